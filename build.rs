@@ -0,0 +1,23 @@
+use std::env;
+use std::process::Command;
+
+/// What `--version --verbose` reports about the build.
+fn main() {
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|commit| commit.trim().to_string())
+        .filter(|commit| !commit.is_empty())
+        // a source tarball, or no git
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=BUILD_GIT_COMMIT={}", commit);
+    println!(
+        "cargo:rustc-env=BUILD_TARGET={}",
+        env::var("TARGET").expect("set by cargo")
+    );
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
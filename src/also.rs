@@ -0,0 +1,61 @@
+use std::fs;
+use std::path::Path;
+
+use failure::bail;
+use failure::format_err;
+use failure::ResultExt;
+use log::info;
+
+use crate::backup;
+use crate::restage::EXDEV;
+
+/// Make `dest` another name for the installed output, replacing whatever was there atomically.
+///
+/// A hard link can't cross filesystems; then it's an error, unless `copy` allows a copy.
+pub fn link(output: &Path, dest: &Path, copy: bool) -> Result<(), failure::Error> {
+    let staging = backup::suffixed(dest, &format!(".fetch-maybe-{}", std::process::id()));
+    let _ = fs::remove_file(&staging);
+
+    match fs::hard_link(output, &staging) {
+        Ok(()) => (),
+        Err(ref e) if Some(EXDEV) == e.raw_os_error() => {
+            if !copy {
+                bail!(
+                    "{:?} is on another filesystem, so can't be a hard link, see --also-copy",
+                    dest
+                );
+            }
+            fs::copy(output, &staging)
+                .with_context(|_| format_err!("copying output to {:?}", staging))?;
+        }
+        Err(e) => Err(e).with_context(|_| format_err!("linking output to {:?}", staging))?,
+    }
+
+    if let Err(e) = fs::rename(&staging, dest) {
+        let _ = fs::remove_file(&staging);
+        Err(e).with_context(|_| format_err!("moving {:?} into place", dest))?;
+    }
+
+    info!("     also-link: {:?}", dest);
+    Ok(())
+}
+
+#[test]
+fn test_link() {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let latest = dir.path().join("latest");
+    fs::write(&output, "new").unwrap();
+    fs::write(&latest, "old").unwrap();
+
+    link(&output, &latest, false).unwrap();
+    assert_eq!(
+        fs::metadata(&output).unwrap().ino(),
+        fs::metadata(&latest).unwrap().ino()
+    );
+
+    let err = link(&output, &dir.path().join("missing/latest"), false).unwrap_err();
+    assert!(err.to_string().contains("missing"), "{}", err);
+}
@@ -0,0 +1,147 @@
+use std::ffi::OsString;
+use std::fs;
+use std::path::PathBuf;
+
+use failure::format_err;
+use failure::ResultExt;
+
+use fetch_maybe::exit;
+
+/// A command line argument, and the args file line it came from, if it did.
+pub struct Arg {
+    pub value: OsString,
+    pub from: Option<(PathBuf, usize)>,
+}
+
+/// `args`, with each `@FILE`, `--args-file FILE` and `--args-file=FILE` replaced by FILE's
+/// arguments, as if they'd been given there; the program's name, and anything after a `--`, are
+/// left alone.
+pub fn expand<I: IntoIterator<Item = OsString>>(args: I) -> Result<Vec<Arg>, failure::Error> {
+    let mut args = args.into_iter();
+    let mut expanded: Vec<Arg> = args
+        .next()
+        .map(|value| Arg { value, from: None })
+        .into_iter()
+        .collect();
+    while let Some(arg) = args.next() {
+        let file = match arg.to_str() {
+            Some("--") => {
+                expanded.push(Arg {
+                    value: arg,
+                    from: None,
+                });
+                expanded.extend(args.by_ref().map(|value| Arg { value, from: None }));
+                break;
+            }
+            Some("--args-file") => match args.next() {
+                Some(file) => PathBuf::from(file),
+                None => {
+                    return Err(exit::classified(
+                        exit::Kind::Usage,
+                        "--args-file needs a FILE",
+                    ))
+                }
+            },
+            Some(s) if s.starts_with("--args-file=") => PathBuf::from(&s["--args-file=".len()..]),
+            Some(s) if s.starts_with('@') && s.len() > 1 => PathBuf::from(&s[1..]),
+            _ => {
+                expanded.push(Arg {
+                    value: arg,
+                    from: None,
+                });
+                continue;
+            }
+        };
+        let text = fs::read_to_string(&file)
+            .with_context(|_| format_err!("reading args file {:?}", file))?;
+        for (line, value) in parse(&text).map_err(|(line, message)| {
+            exit::classified(
+                exit::Kind::Usage,
+                format!("args file {:?} line {}: {}", file, line, message),
+            )
+        })? {
+            expanded.push(Arg {
+                value: OsString::from(value),
+                from: Some((file.clone(), line)),
+            });
+        }
+    }
+    Ok(expanded)
+}
+
+/// The arguments, one a line, with their line numbers; blank lines and `#` comments are
+/// skipped, and the rest is taken whole, less the whitespace around it.
+fn parse(text: &str) -> Result<Vec<(usize, &str)>, (usize, &'static str)> {
+    let mut args = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if (line.starts_with('@') && line.len() > 1) || line.starts_with("--args-file") {
+            return Err((i + 1, "args files can't include others"));
+        }
+        args.push((i + 1, line));
+    }
+    Ok(args)
+}
+
+/// Which args file line clap's complaint is about, if it can tell.
+pub fn blame(args: &[Arg], e: &clap::Error) -> Option<String> {
+    let info = e.info.as_ref()?;
+    args.iter().find_map(|arg| {
+        let (file, line) = arg.from.as_ref()?;
+        let value = arg.value.to_str()?;
+        let about = info.iter().any(|item| {
+            value == item
+                || value.ends_with(&format!("={}", item))
+                || value == format!("--{}", item)
+                || value.starts_with(&format!("--{}=", item))
+        });
+        if about {
+            Some(format!("from args file {:?} line {}", file, line))
+        } else {
+            None
+        }
+    })
+}
+
+#[test]
+fn test_parse() {
+    assert_eq!(
+        Ok(vec![(2, "--header"), (3, "X-A: b c"), (5, "--min-age=1h")]),
+        parse("# headers\n--header\n  X-A: b c  \n\n--min-age=1h\n")
+    );
+    assert_eq!(
+        Err((2, "args files can't include others")),
+        parse("-v\n@more\n")
+    );
+}
+
+#[test]
+fn test_expand() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("args");
+    fs::write(&file, "--header\nX-A: b\n").unwrap();
+    let values = |args: Vec<&str>| -> Vec<OsString> {
+        expand(args.into_iter().map(OsString::from))
+            .unwrap()
+            .into_iter()
+            .map(|arg| arg.value)
+            .collect()
+    };
+    let at = format!("@{}", file.display());
+    assert_eq!(
+        vec!["fetch-maybe", "--header", "X-A: b", "url", "--", &at],
+        values(vec!["fetch-maybe", &at, "url", "--", &at])
+    );
+    assert_eq!(
+        vec!["@me", "--header", "X-A: b", "--header", "X-A: b"],
+        values(vec![
+            "@me",
+            "--args-file",
+            file.to_str().unwrap(),
+            &format!("--args-file={}", file.display())
+        ])
+    );
+}
@@ -0,0 +1,154 @@
+use std::ffi::OsString;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use failure::format_err;
+use failure::ResultExt;
+use log::debug;
+
+/// `output` with `suffix` on the end of its file name, e.g. `foo~`.
+pub fn suffixed(output: &Path, suffix: &str) -> PathBuf {
+    let mut name: OsString = output.as_os_str().to_os_string();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Leave a copy of `output` at `dest`, replacing whatever was there, without disturbing `output`.
+///
+/// A hard link is made under a temporary name, then renamed into place, so `output` is never
+/// missing, and `dest` is always either the old backup or the new one. Without `link`, for
+/// outputs about to be overwritten in place, it's always a copy.
+pub fn preserve_copy(output: &Path, dest: &Path, link: bool) -> Result<(), failure::Error> {
+    let staging = suffixed(dest, &format!(".fetch-maybe-{}", std::process::id()));
+    let _ = fs::remove_file(&staging);
+
+    let linked = link
+        && match fs::hard_link(output, &staging) {
+            Ok(()) => true,
+            Err(e) => {
+                debug!("        backup: can't hard link ({}), copying", e);
+                false
+            }
+        };
+
+    if !linked {
+        fs::copy(output, &staging)
+            .with_context(|_| format_err!("copying {:?} to {:?}", output, staging))?;
+    }
+
+    if let Err(e) = fs::rename(&staging, dest) {
+        let _ = fs::remove_file(&staging);
+        Err(e).with_context(|_| format_err!("moving backup into place at {:?}", dest))?;
+    }
+
+    Ok(())
+}
+
+/// Basic ISO 8601, so names sort chronologically and need no colons.
+const VERSION_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+const VERSION_LEN: usize = 16;
+
+/// Keep `output` as `output.<its mtime>`, then prune all but the newest `keep` such versions.
+pub fn keep_version(output: &Path, keep: usize, link: bool) -> Result<PathBuf, failure::Error> {
+    let mtime = output
+        .metadata()
+        .and_then(|m| m.modified())
+        .with_context(|_| format_err!("reading {:?}'s modification time", output))?;
+    let stamp = chrono::DateTime::<chrono::Utc>::from(mtime).format(VERSION_FORMAT);
+    let version = suffixed(output, &format!(".{}", stamp));
+    preserve_copy(output, &version, link)?;
+    prune(output, keep)?;
+    Ok(version)
+}
+
+fn is_version_stamp(s: &str) -> bool {
+    VERSION_LEN == s.len() && chrono::NaiveDateTime::parse_from_str(s, VERSION_FORMAT).is_ok()
+}
+
+/// Only ever considers `output.<stamp>`, so nothing else in the directory can be touched.
+fn prune(output: &Path, keep: usize) -> Result<(), failure::Error> {
+    let dir = crate::dir_of::dir_of(output, std::env::current_dir)?;
+    let prefix = match output.file_name().and_then(|n| n.to_str()) {
+        Some(name) => format!("{}.", name),
+        None => return Ok(()),
+    };
+
+    let mut versions: Vec<String> = Vec::new();
+    for entry in fs::read_dir(&dir).with_context(|_| format_err!("listing {:?}", dir))? {
+        let entry = entry.with_context(|_| format_err!("listing {:?}", dir))?;
+        if let Some(name) = entry.file_name().to_str() {
+            if let Some(stamp) = name.strip_prefix(&prefix) {
+                if is_version_stamp(stamp) {
+                    versions.push(name.to_string());
+                }
+            }
+        }
+    }
+
+    versions.sort();
+    let excess = versions.len().saturating_sub(keep);
+    for name in &versions[..excess] {
+        let path = dir.join(name);
+        fs::remove_file(&path).with_context(|_| format_err!("pruning old version {:?}", path))?;
+        debug!("      versions: pruned {:?}", path);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_keep_version() {
+    use std::time::Duration;
+    use std::time::UNIX_EPOCH;
+
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    fs::write(dir.path().join("out.unrelated"), b"").unwrap();
+    fs::write(dir.path().join("out.20000101T000000Z.bak"), b"").unwrap();
+
+    for day in 0..4 {
+        fs::write(&output, format!("{}", day)).unwrap();
+        let mtime = UNIX_EPOCH + Duration::from_secs(1_000_000_000 + day * 86_400);
+        filetime::set_file_mtime(&output, filetime::FileTime::from(mtime)).unwrap();
+        keep_version(&output, 2, true).unwrap();
+    }
+
+    let mut names: Vec<String> = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(
+        vec![
+            "out",
+            "out.20000101T000000Z.bak",
+            "out.20010911T014640Z",
+            "out.20010912T014640Z",
+            "out.unrelated",
+        ],
+        names
+    );
+    assert_eq!(
+        "3",
+        fs::read_to_string(dir.path().join("out.20010912T014640Z")).unwrap()
+    );
+}
+
+#[test]
+fn test_preserve_copy() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let backup = suffixed(&output, "~");
+    assert_eq!(dir.path().join("out~"), backup);
+
+    fs::write(&output, b"one").unwrap();
+    preserve_copy(&output, &backup, true).unwrap();
+    fs::write(dir.path().join("replacement"), b"two").unwrap();
+    fs::rename(dir.path().join("replacement"), &output).unwrap();
+    preserve_copy(&output, &backup, true).unwrap();
+
+    assert_eq!("two", fs::read_to_string(&backup).unwrap());
+    assert_eq!("two", fs::read_to_string(&output).unwrap());
+    assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+}
@@ -0,0 +1,219 @@
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use chrono::DateTime;
+use chrono::Utc;
+
+use failure::format_err;
+use failure::ResultExt;
+use log::debug;
+use sha2::Digest as _;
+use sha2::Sha256;
+use url::Url;
+
+use crate::backup;
+use crate::compare;
+use crate::digest;
+use crate::outcome::Outcome;
+use crate::output;
+
+/// Where `--cache-dir` keeps `url`: a hash of the URL, then its file name, if it has one,
+/// so a listing of the directory is still somewhat readable.
+pub fn path(dir: &Path, url: &Url) -> PathBuf {
+    let key = digest::hex(&Sha256::digest(url.as_str().as_bytes()))[..16].to_string();
+    match output::name_from_url(url) {
+        Ok(name) => dir.join(format!("{}-{}", key, name)),
+        Err(_) => dir.join(key),
+    }
+}
+
+/// Whether `name` is one `path` might have made: the hash, and perhaps a file name after it.
+pub fn is_entry_name(name: &str) -> bool {
+    let (key, rest) = match (name.get(..16), name.get(16..)) {
+        (Some(key), Some(rest)) => (key, rest),
+        _ => return false,
+    };
+    key.chars()
+        .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+        && (rest.is_empty() || (rest.len() > 1 && rest.starts_with('-')))
+}
+
+pub fn meta_path(entry: &Path) -> PathBuf {
+    backup::suffixed(entry, ".meta")
+}
+
+/// What `record` put in an entry's `.meta`; all but the URL as they were written, and missing
+/// from those written before they were kept.
+pub struct Meta {
+    pub url: String,
+    pub final_url: Option<String>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// RFC 3339, as are the other times.
+    pub expires: Option<String>,
+    pub fetched: Option<String>,
+    pub sha256: Option<String>,
+    /// The entry's size and mtime, as `compare::stamp` has them.
+    pub output: Option<String>,
+}
+
+/// `entry`'s `.meta`, if it has one that's ours.
+pub fn meta(entry: &Path) -> Option<Meta> {
+    let text = fs::read_to_string(meta_path(entry)).ok()?;
+    let field = |name: &str| {
+        text.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+            .map(str::to_string)
+    };
+    Some(Meta {
+        url: field("url")?,
+        final_url: field("final_url"),
+        etag: field("etag"),
+        last_modified: field("last_modified"),
+        expires: field("expires"),
+        fetched: field("fetched"),
+        sha256: field("sha256"),
+        output: field("output"),
+    })
+}
+
+/// The ETag that `entry` was last fetched with, if the server sent one.
+pub fn etag(entry: &Path) -> Option<String> {
+    let meta = fs::read_to_string(meta_path(entry)).ok()?;
+    meta.lines()
+        .find_map(|line| line.strip_prefix("etag "))
+        .map(str::to_string)
+}
+
+/// Whether `entry` is one `--cache-dir` fetched, as its `.meta` says: the URL there is the one
+/// its name was made from.
+pub fn is_entry(entry: &Path) -> bool {
+    let meta = match fs::read_to_string(meta_path(entry)) {
+        Ok(meta) => meta,
+        Err(_) => return false,
+    };
+    let url = match meta
+        .lines()
+        .find_map(|line| line.strip_prefix("url "))
+        .and_then(|url| Url::parse(url).ok())
+    {
+        Some(url) => url,
+        None => return false,
+    };
+    match entry.parent() {
+        Some(dir) => path(dir, &url) == entry,
+        None => false,
+    }
+}
+
+/// Describe a freshly fetched `entry` in `ENTRY.meta`, next to it: where it came from, and
+/// as far as `outcome` knows them, where that redirected to, its ETag and Last-Modified, when
+/// it stops being fresh, and the address that served it; and when that was.
+///
+/// With the digest of what was written, that's there too, as `sha256`, with the entry's size
+/// and mtime as of now as `output`, so `verify` can tell if it's been changed since. Older
+/// records don't have these, but for the URL, ETag and address.
+pub fn record(
+    entry: &Path,
+    url: &str,
+    outcome: &Outcome,
+    written: Option<&digest::Digest>,
+) -> Result<(), failure::Error> {
+    let meta = meta_path(entry);
+    let rfc3339 = |time: SystemTime| {
+        DateTime::<Utc>::from(time).to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+    };
+    let mut text = format!("url {}\n", url);
+    if let Some(final_url) = outcome.final_url.as_deref().filter(|&f| f != url) {
+        text.push_str(&format!("final_url {}\n", final_url));
+    }
+    if let Some(etag) = &outcome.etag {
+        text.push_str(&format!("etag {}\n", etag.trim()));
+    }
+    if let Some(ip) = outcome.remote.map(|r| r.ip()) {
+        text.push_str(&format!("remote_ip {}\n", ip));
+    }
+    if let Some(last_modified) = outcome.last_modified {
+        text.push_str(&format!("last_modified {}\n", rfc3339(last_modified)));
+    }
+    if let Some(expires) = outcome.expires {
+        text.push_str(&format!("expires {}\n", rfc3339(expires)));
+    }
+    text.push_str(&format!("fetched {}\n", rfc3339(SystemTime::now())));
+    if let Some(written) = written {
+        let metadata =
+            fs::metadata(entry).with_context(|_| format_err!("reading {:?}'s info", entry))?;
+        text.push_str(&format!("sha256 {}\n", digest::hex(&written.sha256)));
+        text.push_str(&format!("output {}\n", compare::stamp(&metadata)));
+    }
+    fs::write(&meta, text).with_context(|_| format_err!("describing cache entry in {:?}", meta))?;
+    debug!("         cache: described in {:?}", meta);
+    Ok(())
+}
+
+#[test]
+fn test_path() {
+    let dir = Path::new("/cache");
+    let named = path(
+        dir,
+        &Url::parse("https://example.com/dist/foo.tar.gz").unwrap(),
+    );
+    let name = named.file_name().unwrap().to_str().unwrap();
+    assert_eq!(Some(dir), named.parent());
+    assert!(name.ends_with("-foo.tar.gz"), "{}", name);
+    assert_eq!(16 + "-foo.tar.gz".len(), name.len());
+
+    let other = path(
+        dir,
+        &Url::parse("https://example.org/dist/foo.tar.gz").unwrap(),
+    );
+    assert_ne!(named, other);
+
+    let bare = path(dir, &Url::parse("https://example.com/").unwrap());
+    assert_eq!(16, bare.file_name().unwrap().len());
+
+    for entry in &[named, other, bare] {
+        assert!(is_entry_name(entry.file_name().unwrap().to_str().unwrap()));
+    }
+    assert!(!is_entry_name("foo.tar.gz"));
+    assert!(!is_entry_name("0123456789abcdef-"));
+    assert!(!is_entry_name("0123456789ABCDEF-foo"));
+    assert!(!is_entry_name("0123456789abcdefg"));
+}
+
+#[test]
+fn test_record() {
+    let dir = tempfile::tempdir().unwrap();
+    let entry = dir.path().join("entry");
+    assert_eq!(None, etag(&entry));
+
+    let outcome = Outcome {
+        final_url: Some("https://example.com/moved".to_string()),
+        etag: Some("\"abc\"".to_string()),
+        remote: Some(([192, 0, 2, 1], 443).into()),
+        expires: Some(SystemTime::UNIX_EPOCH),
+        ..Outcome::default()
+    };
+    record(&entry, "https://example.com/", &outcome, None).unwrap();
+    assert_eq!(Some("\"abc\"".to_string()), etag(&entry));
+    let text = fs::read_to_string(meta_path(&entry)).unwrap();
+    assert!(text.contains("remote_ip 192.0.2.1\n"), "{}", text);
+    let meta = meta(&entry).unwrap();
+    assert_eq!("https://example.com/", meta.url);
+    assert_eq!(Some("https://example.com/moved"), meta.final_url.as_deref());
+    assert_eq!(Some("1970-01-01T00:00:00Z"), meta.expires.as_deref());
+    assert!(meta.fetched.is_some());
+    assert_eq!(None, meta.sha256);
+
+    record(&entry, "https://example.com/", &Outcome::default(), None).unwrap();
+    assert_eq!(None, etag(&entry));
+    // not made from that URL
+    assert!(!is_entry(&entry));
+
+    let url = Url::parse("https://example.com/a.lock").unwrap();
+    let entry = path(dir.path(), &url);
+    record(&entry, url.as_str(), &Outcome::default(), None).unwrap();
+    assert!(is_entry(&entry));
+}
@@ -0,0 +1,310 @@
+use std::net::SocketAddr;
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::DateTime;
+use chrono::NaiveDateTime;
+use chrono::Utc;
+use failure::format_err;
+use failure::ResultExt;
+use log::debug;
+use log::warn;
+use rustls::Session;
+use url::Host;
+use url::Url;
+
+use crate::exit;
+use crate::outcome::Outcome;
+use crate::period;
+
+/// How long the handshake's connection may take to be made, and then to answer.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What's shown of the server's certificate.
+#[derive(Debug, PartialEq)]
+pub struct Leaf {
+    /// Its subject's common name, organisation and so on, as `CN=example.com, O=Example`.
+    pub subject: String,
+    pub not_after: DateTime<Utc>,
+}
+
+/// `--cert-expiry-warn` and `--cert-expiry-fail`: how close to its expiry a certificate may be.
+pub struct Expiry {
+    warn: Option<chrono::Duration>,
+    fail: Option<chrono::Duration>,
+}
+
+impl Expiry {
+    pub fn from_matches(matches: &clap::ArgMatches) -> Result<Option<Expiry>, failure::Error> {
+        let window = |name: &str| -> Result<Option<chrono::Duration>, failure::Error> {
+            match matches.value_of(name) {
+                Some(v) => {
+                    Ok(Some(period::parse_duration(v).with_context(|_| {
+                        format_err!("parsing --{}: {:?}", name, v)
+                    })?))
+                }
+                None => Ok(None),
+            }
+        };
+        let (warn, fail) = (window("cert-expiry-warn")?, window("cert-expiry-fail")?);
+        if warn.is_none() && fail.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(Expiry { warn, fail }))
+    }
+
+    /// Once there's been a final response over https, shake hands with where it came from
+    /// again, to see the certificate, and record when it expires in `outcome`.
+    ///
+    /// Only `--cert-expiry-fail` makes it an error; not being able to see it is a warning.
+    pub fn check(&self, outcome: &mut Outcome) -> Result<(), failure::Error> {
+        let url = match outcome.final_url.as_deref().map(Url::parse) {
+            Some(Ok(url)) if "https" == url.scheme() && outcome.status.is_some() => url,
+            _ => return Ok(()),
+        };
+        let (host, address) = match (url.host(), outcome.remote) {
+            (Some(Host::Domain(host)), Some(address)) => (host.to_string(), address),
+            _ => return Ok(()),
+        };
+        let Leaf { subject, not_after } = match leaf(&host, address) {
+            Ok(leaf) => leaf,
+            Err(e) => {
+                warn!(
+                    "couldn't see {}'s certificate, to check its expiry: {}",
+                    host, e
+                );
+                return Ok(());
+            }
+        };
+        let now = Utc::now();
+        let left = not_after - now;
+        let within = |window: Option<chrono::Duration>| window.is_some_and(|w| left <= w);
+        outcome.cert_expires = Some(not_after.into());
+        outcome.cert_expiring = Some(within(self.warn) || within(self.fail));
+        let subject = if subject.is_empty() { host } else { subject };
+        let shown = not_after.to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+        let message = |flag: &str, window: chrono::Duration| {
+            format!(
+                "the certificate for {} {} {}, within --{} {}",
+                subject,
+                if left > chrono::Duration::zero() {
+                    "expires"
+                } else {
+                    "expired"
+                },
+                shown,
+                flag,
+                period::format_duration(window)
+            )
+        };
+        match (self.fail, self.warn) {
+            (Some(fail), _) if left <= fail => Err(exit::classified(
+                exit::Kind::Tls,
+                message("cert-expiry-fail", fail),
+            )),
+            (_, Some(warn)) if left <= warn => {
+                warn!("{}", message("cert-expiry-warn", warn));
+                Ok(())
+            }
+            _ => {
+                debug!("   certificate: {} expires {}", subject, shown);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The certificate `host` presents at `address`, trusted as ureq trusts it.
+fn leaf(host: &str, address: SocketAddr) -> Result<Leaf, failure::Error> {
+    let mut config = rustls::ClientConfig::new();
+    config
+        .root_store
+        .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+    let name = webpki::DNSNameRef::try_from_ascii_str(host)
+        .map_err(|_| format_err!("{:?} isn't a name a certificate can be for", host))?;
+    let mut session = rustls::ClientSession::new(&Arc::new(config), name);
+
+    let mut socket = TcpStream::connect_timeout(&address, TIMEOUT)
+        .with_context(|_| format_err!("connecting to {}", address))?;
+    socket.set_read_timeout(Some(TIMEOUT))?;
+    socket.set_write_timeout(Some(TIMEOUT))?;
+    while session.is_handshaking() {
+        session
+            .complete_io(&mut socket)
+            .with_context(|_| format_err!("shaking hands with {}", address))?;
+    }
+
+    let certificates = session
+        .get_peer_certificates()
+        .ok_or_else(|| format_err!("{} sent no certificates", address))?;
+    let first = certificates
+        .first()
+        .ok_or_else(|| format_err!("{} sent no certificates", address))?;
+    parse(&first.0).ok_or_else(|| format_err!("couldn't read {}'s certificate", address))
+}
+
+/// One DER element: its tag, what's in it, and what comes after it.
+fn element(der: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = der.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let bytes = (first & 0x7f) as usize;
+        if 0 == bytes || bytes > 4 || rest.len() < bytes {
+            return None;
+        }
+        let len = rest[..bytes]
+            .iter()
+            .fold(0, |len, &b| (len << 8) | b as usize);
+        (len, &rest[bytes..])
+    };
+    if rest.len() < len {
+        return None;
+    }
+    Some((tag, &rest[..len], &rest[len..]))
+}
+
+const SEQUENCE: u8 = 0x30;
+const SET: u8 = 0x31;
+
+/// The `notAfter` and subject of an X.509 certificate, and nothing else of it.
+fn parse(der: &[u8]) -> Option<Leaf> {
+    let sequence = |der| match element(der)? {
+        (SEQUENCE, inside, rest) => Some((inside, rest)),
+        _ => None,
+    };
+    let (certificate, _) = sequence(der)?;
+    let (tbs, _) = sequence(certificate)?;
+    // the version's explicitly tagged, and only there when it isn't v1
+    let rest = match element(tbs)? {
+        (0xa0, _, rest) => rest,
+        _ => tbs,
+    };
+    let (_serial, _, rest) = element(rest)?;
+    let (_signature, _, rest) = element(rest)?;
+    let (_issuer, _, rest) = element(rest)?;
+    let (validity, rest) = sequence(rest)?;
+    let (_not_before, _, after) = element(validity)?;
+    let (tag, not_after, _) = element(after)?;
+    let (subject, _) = sequence(rest)?;
+    Some(Leaf {
+        subject: name(subject)?,
+        not_after: time(tag, not_after)?,
+    })
+}
+
+/// UTCTime's two digit years are 1950 to 2049; after that it's GeneralizedTime.
+fn time(tag: u8, value: &[u8]) -> Option<DateTime<Utc>> {
+    let value = std::str::from_utf8(value).ok()?;
+    let full = match tag {
+        0x17 if value.get(..2)? < "50" => format!("20{}", value),
+        0x17 => format!("19{}", value),
+        0x18 => value.to_string(),
+        _ => return None,
+    };
+    NaiveDateTime::parse_from_str(&full, "%Y%m%d%H%M%SZ")
+        .ok()
+        .map(|t| t.and_utc())
+}
+
+/// The attributes of a distinguished name that are worth showing, in the order they're given.
+fn name(mut der: &[u8]) -> Option<String> {
+    let mut parts = Vec::new();
+    while !der.is_empty() {
+        let (tag, set, rest) = element(der)?;
+        der = rest;
+        if SET != tag {
+            return None;
+        }
+        let (tag, attribute, _) = element(set)?;
+        if SEQUENCE != tag {
+            return None;
+        }
+        let (_, oid, rest) = element(attribute)?;
+        let (_, value, _) = element(rest)?;
+        let key = match oid {
+            [0x55, 0x04, 0x03] => "CN",
+            [0x55, 0x04, 0x06] => "C",
+            [0x55, 0x04, 0x07] => "L",
+            [0x55, 0x04, 0x08] => "ST",
+            [0x55, 0x04, 0x0a] => "O",
+            [0x55, 0x04, 0x0b] => "OU",
+            _ => continue,
+        };
+        parts.push(format!("{}={}", key, String::from_utf8_lossy(value)));
+    }
+    Some(parts.join(", "))
+}
+
+#[test]
+fn test_parse() {
+    fn der(tag: u8, inside: &[&[u8]]) -> Vec<u8> {
+        let inside = inside.concat();
+        let mut out = vec![tag];
+        if inside.len() < 0x80 {
+            out.push(inside.len() as u8);
+        } else {
+            out.push(0x82);
+            out.extend_from_slice(&(inside.len() as u16).to_be_bytes());
+        }
+        out.extend_from_slice(&inside);
+        out
+    }
+    let attribute = |oid: u8, value: &str| {
+        der(
+            SET,
+            &[&der(
+                SEQUENCE,
+                &[
+                    &der(0x06, &[&[0x55, 0x04, oid]]),
+                    &der(0x0c, &[value.as_bytes()]),
+                ],
+            )],
+        )
+    };
+    let certificate = |version: bool, not_after: &[u8]| {
+        let algorithm = der(SEQUENCE, &[&der(0x06, &[&[0x2a, 0x86]])]);
+        let mut tbs = Vec::new();
+        if version {
+            tbs.push(der(0xa0, &[&der(0x02, &[&[2]])]));
+        }
+        tbs.push(der(0x02, &[&[1]]));
+        tbs.push(algorithm.clone());
+        tbs.push(der(SEQUENCE, &[&attribute(0x03, "Example CA")]));
+        tbs.push(der(SEQUENCE, &[&der(0x17, &[b"250101000000Z"]), not_after]));
+        tbs.push(der(
+            SEQUENCE,
+            &[
+                &attribute(0x03, "example.com"),
+                &attribute(0x09, "ignored"),
+                &attribute(0x0a, "Example"),
+            ],
+        ));
+        der(
+            SEQUENCE,
+            &[
+                &der(SEQUENCE, &[&tbs.concat()]),
+                &algorithm,
+                &der(0x03, &[&[0]]),
+            ],
+        )
+    };
+
+    let expected = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+    assert_eq!(
+        Some(Leaf {
+            subject: "CN=example.com, O=Example".to_string(),
+            not_after: expected("2026-10-28T12:30:00Z"),
+        }),
+        parse(&certificate(true, &der(0x17, &[b"261028123000Z"])))
+    );
+    let v1 = parse(&certificate(false, &der(0x18, &[b"20510101000000Z"]))).unwrap();
+    assert_eq!(expected("2051-01-01T00:00:00Z"), v1.not_after);
+    assert_eq!(
+        expected("1999-12-31T23:59:59Z"),
+        time(0x17, b"991231235959Z").unwrap()
+    );
+    assert_eq!(None, parse(&[SEQUENCE, 0x05, 0x30]));
+}
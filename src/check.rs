@@ -0,0 +1,147 @@
+//! Validators for clap, so a bad value is refused up front, with the usage, before anything's
+//! been done. The values are parsed again where they're used.
+
+use crate::method;
+use crate::parse_header;
+use crate::period;
+use crate::perms;
+use crate::size;
+use crate::statuses;
+use crate::target;
+use crate::write_out;
+
+fn refuse<T>(result: Result<T, failure::Error>) -> Result<(), String> {
+    result.map(|_| ()).map_err(|e| {
+        e.iter_chain()
+            .map(|f| f.to_string())
+            .collect::<Vec<_>>()
+            .join(": ")
+    })
+}
+
+/// `period::parse_duration`'s `30s`, `2h`, `1d` and so on.
+pub fn duration(v: String) -> Result<(), String> {
+    refuse(period::parse_duration(&v))
+}
+
+/// `size::parse_size`'s `512`, `10M` and so on.
+pub fn size(v: String) -> Result<(), String> {
+    refuse(size::parse_size(&v))
+}
+
+/// For `--buffer-size`: some bytes, but not so many that every run takes a large allocation.
+pub fn buffer_size(v: String) -> Result<(), String> {
+    match size::parse_size(&v) {
+        Ok(0) => Err("a buffer needs at least one byte".to_string()),
+        Ok(bytes) if bytes > crate::MAX_BUFFER_SIZE => Err(format!(
+            "at most {}",
+            size::format_size(crate::MAX_BUFFER_SIZE)
+        )),
+        other => refuse(other),
+    }
+}
+
+pub fn count(v: String) -> Result<(), String> {
+    v.parse::<usize>()
+        .map(|_| ())
+        .map_err(|e| format!("expected a whole number: {}", e))
+}
+
+/// A whole number, with or without a `%`.
+pub fn percent(v: String) -> Result<(), String> {
+    v.trim_end_matches('%')
+        .parse::<u64>()
+        .map(|_| ())
+        .map_err(|e| format!("expected a percentage: {}", e))
+}
+
+/// `Name: value`.
+pub fn header(v: String) -> Result<(), String> {
+    refuse(parse_header(&v))
+}
+
+/// Octal, like `chmod`'s.
+pub fn mode(v: String) -> Result<(), String> {
+    refuse(perms::parse_mode(&v))
+}
+
+pub fn method(v: String) -> Result<(), String> {
+    refuse(method::parse(&v))
+}
+
+pub fn url(v: String) -> Result<(), String> {
+    refuse(target::parse(&v))
+}
+
+/// Status codes and ranges, like `200,203-209`.
+pub fn statuses(v: String) -> Result<(), String> {
+    refuse(statuses::Statuses::parse(&v))
+}
+
+/// Only the variables `write_out` knows.
+pub fn write_out(v: String) -> Result<(), String> {
+    refuse(write_out::Template::parse(&v))
+}
+
+#[cfg(test)]
+fn parse(args: &[&str]) -> Result<clap::ArgMatches<'static>, String> {
+    crate::app()
+        .get_matches_from_safe(
+            ["fetch-maybe", "https://example.com/a", "out"]
+                .iter()
+                .chain(args),
+        )
+        .map_err(|e| e.message)
+}
+
+#[test]
+fn test_accepted() {
+    let matches = parse(&[
+        "--min-age",
+        "2h",
+        "--min-size",
+        "10k",
+        "--max-shrink",
+        "50%",
+        "--mode",
+        "0644",
+        "-H",
+        "Accept: text/plain",
+        "--retry",
+        "3",
+        "--rename-retries",
+        "0",
+        "--rename-retry-for",
+        "10s",
+    ])
+    .unwrap();
+    assert_eq!(Some("2h"), matches.value_of("min-age"));
+    assert_eq!(Some("Accept: text/plain"), matches.value_of("header"));
+}
+
+#[test]
+fn test_refused() {
+    let refused = |args: &[&str], expected: &str| {
+        let message = parse(args).unwrap_err();
+        assert!(
+            message.contains(args[0]) && message.contains(expected),
+            "{}",
+            message
+        );
+    };
+    refused(&["--min-age", "soon"], "Invalid value");
+    refused(&["--lock-wait", "1 fortnight"], "Invalid value");
+    refused(&["--min-size", "lots"], "Invalid value");
+    refused(&["--max-shrink", "half"], "expected a percentage");
+    refused(&["--retry", "some"], "expected a whole number");
+    refused(&["--rename-retries", "a few"], "expected a whole number");
+    refused(&["--mode", "rw-r--r--"], "Invalid value");
+    refused(&["--header", "Accept text/plain"], "missing a colon");
+    refused(&["--method", "GET /"], "not a method name");
+
+    let message = crate::app()
+        .get_matches_from_safe(["fetch-maybe", "ftp://example.com/a", "out"])
+        .unwrap_err()
+        .message;
+    assert!(message.contains("unsupported scheme"), "{}", message);
+}
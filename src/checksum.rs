@@ -0,0 +1,139 @@
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use failure::bail;
+use failure::format_err;
+use failure::ResultExt;
+use log::debug;
+use url::Url;
+
+use crate::exit;
+use crate::interim;
+use crate::redirect;
+
+/// Decode a hex SHA-256.
+pub fn parse_hex(s: &str) -> Result<[u8; 32], failure::Error> {
+    let s = s.trim();
+    if s.len() != 64 || !s.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("expected a 64-character hex sha256, got {:?}", s);
+    }
+
+    let mut out = [0u8; 32];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).expect("checked hex");
+    }
+    Ok(out)
+}
+
+/// A bare digest, or `sha256sum` output, picking the line for one of `names`.
+pub fn parse_sums(text: &str, names: &[&str]) -> Result<[u8; 32], failure::Error> {
+    let lines: Vec<&str> = text
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .collect();
+
+    if let [only] = lines.as_slice() {
+        if !only.contains(char::is_whitespace) {
+            return parse_hex(only);
+        }
+    }
+
+    for line in &lines {
+        let mut parts = line.splitn(2, char::is_whitespace);
+        let digest = parts.next().unwrap_or_default();
+        // binary-mode lines mark the name with a '*'
+        let name = parts.next().unwrap_or_default().trim_start();
+        let name = name.strip_prefix('*').unwrap_or(name);
+        let name = Path::new(name)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or(name);
+
+        if names.contains(&name) {
+            return parse_hex(digest);
+        }
+    }
+
+    bail!("no checksum for any of {:?} found", names)
+}
+
+/// The largest checksum file worth reading; they're usually a line or two.
+const FETCH_LIMIT: u64 = 1024 * 1024;
+
+/// Fetch a (small) checksum file, following redirects; `new_request` sets up auth and headers.
+pub fn fetch<F>(url: &Url, new_request: F) -> Result<String, failure::Error>
+where
+    F: Fn(&Url) -> ureq::Request,
+{
+    let mut chain = redirect::Chain::new(url.clone(), 10);
+
+    let (response, body) = loop {
+        debug!("      checksum: requesting {:?}", chain.current().as_str());
+        let (response, body) = interim::skip(new_request(chain.current()).call());
+
+        if let Some(err) = response.synthetic_error() {
+            return Err(crate::ureq_error(err));
+        }
+
+        let location = match redirect::location(&response) {
+            Some(location) => location,
+            None => break (response, body),
+        };
+
+        chain.follow_location(location)?;
+    };
+
+    if !(200..=299).contains(&response.status()) {
+        return Err(exit::classified(
+            exit::Kind::Status(response.status()),
+            format!("unhappy response: {:?}", response.status_line()),
+        ));
+    }
+
+    let mut text = String::new();
+    interim::reader(response, body)
+        .take(FETCH_LIMIT)
+        .read_to_string(&mut text)
+        .with_context(|_| format_err!("reading checksum file"))?;
+    Ok(text)
+}
+
+/// `--sha256 HEX` or `--sha256 @FILE`.
+pub fn from_arg(arg: &str, output: Option<&Path>) -> Result<[u8; 32], failure::Error> {
+    let file = match arg.strip_prefix('@') {
+        Some(file) => file,
+        None => return parse_hex(arg),
+    };
+
+    let text = fs::read_to_string(file).with_context(|_| format_err!("reading {:?}", file))?;
+    let names: Vec<&str> = output
+        .and_then(|o| o.file_name())
+        .and_then(|n| n.to_str())
+        .into_iter()
+        .collect();
+    Ok(parse_sums(&text, &names).with_context(|_| format_err!("parsing {:?}", file))?)
+}
+
+#[cfg(test)]
+const ABC: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+#[test]
+fn test_parse_hex() {
+    assert_eq!(0xba, parse_hex(ABC).unwrap()[0]);
+    assert_eq!(0xba, parse_hex(&ABC.to_uppercase()).unwrap()[0]);
+    assert!(parse_hex("abc").is_err());
+    assert!(parse_hex(&ABC.replace('b', "g")).is_err());
+}
+
+#[test]
+fn test_parse_sums() {
+    assert!(parse_sums(&format!("{}\n", ABC), &["foo"]).is_ok());
+
+    let zeros = "0".repeat(64);
+    let sums = format!("{}  other.tar.gz\n{} *dist/foo.tar.gz\n", zeros, ABC);
+    assert_eq!(0xba, parse_sums(&sums, &["foo.tar.gz"]).unwrap()[0]);
+    assert_eq!(0, parse_sums(&sums, &["other.tar.gz"]).unwrap()[0]);
+    assert!(parse_sums(&sums, &["missing"]).is_err());
+}
@@ -0,0 +1,251 @@
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use failure::bail;
+use failure::format_err;
+use failure::ResultExt;
+use log::debug;
+use sha2::Digest as _;
+use sha2::Sha256;
+use url::Url;
+
+use crate::backup;
+use crate::digest;
+use crate::dir_of;
+use crate::exit;
+use crate::interim;
+use crate::redirect;
+use crate::sink;
+
+/// The largest sentinel worth reading; they're meant to be tiny, like a `SHA256SUMS`.
+const FETCH_LIMIT: u64 = 1024 * 1024;
+
+/// What `--compare-url` had, last time the output was brought up to date.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sentinel {
+    pub url: String,
+    pub sha256: [u8; 32],
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+/// Where it's kept: `OUTPUT.compare`.
+pub fn path(output: &Path) -> PathBuf {
+    backup::suffixed(output, ".compare")
+}
+
+/// The output as it was when the sentinel was recorded: anything else replacing it, or the
+/// record being left behind by a run that crashed before the rename, and the record's stale.
+pub fn stamp(metadata: &fs::Metadata) -> String {
+    let mtime = filetime::FileTime::from_last_modification_time(metadata);
+    format!(
+        "{} {}.{:09}",
+        metadata.len(),
+        mtime.unix_seconds(),
+        mtime.nanoseconds()
+    )
+}
+
+/// The sentinel recorded for `output`, if it was from `url`, and `output` hasn't changed since.
+///
+/// A record that's unreadable, or doesn't parse, is only worth a debug note: without it, the
+/// output is fetched as if there were no `--compare-url`.
+pub fn load(output: &Path, url: &str, metadata: &fs::Metadata) -> Option<Sentinel> {
+    let record = path(output);
+    let text = fs::read_to_string(&record).ok()?;
+    match parse(&text) {
+        Some((sentinel, _)) if sentinel.url != url => {
+            debug!(
+                "       compare: {:?} is for {}, not {}, ignoring it",
+                record, sentinel.url, url
+            );
+            None
+        }
+        Some((_, for_output)) if for_output != stamp(metadata) => {
+            debug!(
+                "       compare: {:?} is for an output that's since changed, ignoring it",
+                record
+            );
+            None
+        }
+        Some((sentinel, _)) => Some(sentinel),
+        None => {
+            debug!("       compare: {:?} is corrupt, ignoring it", record);
+            None
+        }
+    }
+}
+
+fn parse(text: &str) -> Option<(Sentinel, String)> {
+    let field = |name: &str| {
+        text.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+            .map(str::to_string)
+    };
+    let sha256 = field("sha256")?;
+    if 64 != sha256.len() {
+        return None;
+    }
+    let mut digest = [0u8; 32];
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(sha256.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    let sentinel = Sentinel {
+        url: field("url")?,
+        sha256: digest,
+        etag: field("etag"),
+        last_modified: field("last-modified"),
+    };
+    Some((sentinel, field("output")?))
+}
+
+/// Record `sentinel` against `output` as it now is, replacing the old record by a rename, so
+/// it's never half written.
+///
+/// This comes after the output's rename, so a crash in between can only leave the old record,
+/// which no longer matches the output, and costs one more download, rather than vouching for
+/// an output that never arrived.
+pub fn record(output: &Path, sentinel: &Sentinel) -> Result<(), failure::Error> {
+    let metadata = output
+        .metadata()
+        .with_context(|_| format_err!("reading output's info: {:?}", output))?;
+    let mut text = format!(
+        "url {}\nsha256 {}\n",
+        sentinel.url,
+        digest::hex(&sentinel.sha256)
+    );
+    if let Some(etag) = &sentinel.etag {
+        text.push_str(&format!("etag {}\n", etag));
+    }
+    if let Some(last_modified) = &sentinel.last_modified {
+        text.push_str(&format!("last-modified {}\n", last_modified));
+    }
+    text.push_str(&format!("output {}\n", stamp(&metadata)));
+
+    let record = path(output);
+    replace(&record, &text)?;
+    debug!("       compare: recorded in {:?}", record);
+    Ok(())
+}
+
+/// Write a record next to the output by a rename, so it's never half written.
+pub fn replace(record: &Path, text: &str) -> Result<(), failure::Error> {
+    let mut temp = sink::temp_in(&dir_of::dir_of(record, env::current_dir)?)?;
+    temp.write_all(text.as_bytes())
+        .with_context(|_| format_err!("writing {:?}", record))?;
+    temp.persist_by_rename(record)
+        .map_err(|e| e.error)
+        .with_context(|_| format_err!("replacing {:?}", record))?;
+    Ok(())
+}
+
+/// Fetch the sentinel at `url`, following redirects, and conditionally on `last`, which a `304`
+/// then stands for; `new_request` sets up auth and headers.
+pub fn fetch<F>(
+    url: &Url,
+    last: Option<&Sentinel>,
+    new_request: F,
+) -> Result<Sentinel, failure::Error>
+where
+    F: Fn(&Url) -> ureq::Request,
+{
+    let mut chain = redirect::Chain::new(url.clone(), 10);
+
+    let (response, interim_body) = loop {
+        debug!("       compare: requesting {:?}", chain.current().as_str());
+        let mut req = new_request(chain.current());
+        if let Some(last) = last {
+            if let Some(etag) = &last.etag {
+                req.set("If-None-Match", etag);
+            }
+            if let Some(last_modified) = &last.last_modified {
+                req.set("If-Modified-Since", last_modified);
+            }
+        }
+        let (response, interim_body) = interim::skip(req.call());
+
+        if let Some(err) = response.synthetic_error() {
+            return Err(crate::ureq_error(err));
+        }
+
+        let location = match redirect::location(&response) {
+            Some(location) => location,
+            None => break (response, interim_body),
+        };
+
+        chain.follow_location(location)?;
+    };
+
+    if let (304, Some(last)) = (response.status(), last) {
+        debug!("       compare: not modified on the server");
+        return Ok(last.clone());
+    }
+
+    if !(200..=299).contains(&response.status()) {
+        return Err(exit::classified(
+            exit::Kind::Status(response.status()),
+            format!("unhappy response: {:?}", response.status_line()),
+        ));
+    }
+
+    let etag = response.header("ETag").map(|v| v.trim().to_string());
+    let last_modified = response
+        .header("Last-Modified")
+        .map(|v| v.trim().to_string());
+    let mut body = Vec::new();
+    interim::reader(response, interim_body)
+        .take(FETCH_LIMIT + 1)
+        .read_to_end(&mut body)
+        .with_context(|_| format_err!("reading {}", url))?;
+    if body.len() as u64 > FETCH_LIMIT {
+        bail!(
+            "{} is more than {} bytes, too big to compare",
+            url,
+            FETCH_LIMIT
+        );
+    }
+
+    Ok(Sentinel {
+        url: url.to_string(),
+        sha256: Sha256::digest(&body).into(),
+        etag,
+        last_modified,
+    })
+}
+
+#[test]
+fn test_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    fs::write(&output, b"data").unwrap();
+    let sentinel = Sentinel {
+        url: "https://example.com/MANIFEST".to_string(),
+        sha256: [7; 32],
+        etag: Some("\"v1\"".to_string()),
+        last_modified: None,
+    };
+    record(&output, &sentinel).unwrap();
+    let metadata = output.metadata().unwrap();
+    assert_eq!(
+        Some(sentinel.clone()),
+        load(&output, &sentinel.url, &metadata)
+    );
+    assert_eq!(None, load(&output, "https://example.com/other", &metadata));
+
+    // replaced by something else since
+    fs::write(&output, b"other data").unwrap();
+    assert_eq!(
+        None,
+        load(&output, &sentinel.url, &output.metadata().unwrap())
+    );
+
+    fs::write(path(&output), "sha256 zz\n").unwrap();
+    assert_eq!(
+        None,
+        load(&output, &sentinel.url, &output.metadata().unwrap())
+    );
+}
@@ -0,0 +1,78 @@
+use std::io;
+use std::io::Write;
+
+use failure::bail;
+use failure::format_err;
+use failure::ResultExt;
+
+/// `gzip` or `gzip:LEVEL`, as `--compress-output` takes it.
+pub fn from_arg(arg: &str) -> Result<flate2::Compression, failure::Error> {
+    let mut parts = arg.splitn(2, ':');
+    match parts.next() {
+        Some("gzip") => (),
+        _ => bail!(
+            "unrecognised --compress-output format: {:?}, expected gzip",
+            arg
+        ),
+    }
+
+    let level = match parts.next() {
+        None => return Ok(flate2::Compression::default()),
+        Some(level) => level
+            .parse::<u32>()
+            .with_context(|_| format_err!("parsing gzip level {:?}", level))?,
+    };
+
+    if level > 9 {
+        bail!("gzip level must be 0-9, not {}", level);
+    }
+
+    Ok(flate2::Compression::new(level))
+}
+
+/// Compresses everything written to it into `inner`, if asked to.
+pub enum Packer<W: Write> {
+    Plain(W),
+    Gzip(flate2::write::GzEncoder<W>),
+}
+
+impl<W: Write> Packer<W> {
+    pub fn new(inner: W, level: Option<flate2::Compression>) -> Packer<W> {
+        match level {
+            Some(level) => Packer::Gzip(flate2::write::GzEncoder::new(inner, level)),
+            None => Packer::Plain(inner),
+        }
+    }
+
+    /// The inner writer, with the compressed stream completed.
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Packer::Plain(inner) => Ok(inner),
+            Packer::Gzip(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Packer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Packer::Plain(inner) => inner.write(buf),
+            Packer::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Packer::Plain(inner) => inner.flush(),
+            Packer::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
+
+#[test]
+fn test_from_arg() {
+    assert_eq!(flate2::Compression::default(), from_arg("gzip").unwrap());
+    assert_eq!(flate2::Compression::best(), from_arg("gzip:9").unwrap());
+    assert!(from_arg("gzip:10").is_err());
+    assert!(from_arg("zstd").is_err());
+}
@@ -0,0 +1,659 @@
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::iter::Peekable;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::Chars;
+
+use failure::bail;
+use failure::format_err;
+use failure::ResultExt;
+
+use fetch_maybe::exit;
+
+/// Options that only make sense on the command line, as they decide which config applies.
+const COMMAND_LINE_ONLY: &[&str] = &[
+    "config",
+    "no-config",
+    "profile",
+    "generate-completions",
+    "args-file",
+];
+
+/// A setting's value: a flag is given or not; anything else is passed as the option's value,
+/// once for each item of a list.
+#[derive(Debug, PartialEq)]
+pub enum Value {
+    Flag(bool),
+    Text(String),
+    List(Vec<String>),
+}
+
+/// The defaults for long options read from a config file, with any profile's on top.
+#[derive(Debug)]
+pub struct Config {
+    pub path: PathBuf,
+    pub settings: Vec<(String, Value)>,
+    /// Things worth mentioning once the logger is up.
+    pub warnings: Vec<String>,
+}
+
+/// `$XDG_CONFIG_HOME/fetch-maybe/config.toml`, or under `~/.config` if that's unset.
+pub fn default_path() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME").filter(|v| !v.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("fetch-maybe").join("config.toml"))
+}
+
+/// Read the config, or `None` if it's the default path and there's no file there.
+pub fn load(
+    explicit: Option<&Path>,
+    profile: Option<&str>,
+) -> Result<Option<Config>, failure::Error> {
+    let path = match explicit.map(Path::to_path_buf).or_else(default_path) {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(ref e) if explicit.is_none() && std::io::ErrorKind::NotFound == e.kind() => {
+            return Ok(None)
+        }
+        Err(e) => Err(e).with_context(|_| format_err!("reading config {:?}", path))?,
+    };
+
+    let mut config = parse(&text, profile).with_context(|_| format_err!("in config {:?}", path))?;
+    for warning in &mut config.warnings {
+        *warning = format!("config {:?}: {}", path, warning);
+    }
+    Ok(Some(Config { path, ..config }))
+}
+
+fn parse(text: &str, profile: Option<&str>) -> Result<Config, failure::Error> {
+    let mut config = Config {
+        path: PathBuf::new(),
+        settings: Vec::new(),
+        warnings: Vec::new(),
+    };
+
+    let mut found_profile = false;
+    // the top level's settings apply, and so do the selected profile's
+    let mut applies = true;
+
+    for (number, line) in lines(text)? {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[') {
+            let name = header
+                .strip_suffix(']')
+                .ok_or_else(|| format_err!("line {}: unclosed table header", number))?
+                .trim();
+            applies = match name.strip_prefix("profile.") {
+                Some(name) if Some(name) == profile => {
+                    found_profile = true;
+                    true
+                }
+                Some(_) => false,
+                None => {
+                    config
+                        .warnings
+                        .push(format!("unknown table [{}], ignoring it", name));
+                    false
+                }
+            };
+            continue;
+        }
+
+        let mut chars = line.chars().peekable();
+        let key = parse_key(&mut chars).with_context(|_| format_err!("line {}", number))?;
+        skip_spaces(&mut chars);
+        if Some('=') != chars.next() {
+            bail!("line {}: expected '=' after {:?}", number, key);
+        }
+        skip_spaces(&mut chars);
+        let value = parse_value(&mut chars).with_context(|_| format_err!("line {}", number))?;
+        skip_spaces(&mut chars);
+        if let Some(c) = chars.next() {
+            bail!("line {}: unexpected {:?} after the value", number, c);
+        }
+
+        if !applies {
+            continue;
+        }
+
+        if COMMAND_LINE_ONLY.contains(&key.as_str()) {
+            config.warnings.push(format!(
+                "{:?} can only be given on the command line, ignoring it",
+                key
+            ));
+            continue;
+        }
+
+        // a profile's setting replaces the top level's
+        config.settings.retain(|(k, _)| *k != key);
+        config.settings.push((key, value));
+    }
+
+    if let (Some(profile), false) = (profile, found_profile) {
+        bail!("there's no [profile.{}]", profile);
+    }
+
+    Ok(config)
+}
+
+/// Logical lines, without comments, numbered from one; an array can span several.
+fn lines(text: &str) -> Result<Vec<(usize, String)>, failure::Error> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    let mut start = 1;
+    let mut depth = 0usize;
+
+    for (i, raw) in text.lines().enumerate() {
+        if current.is_empty() {
+            start = i + 1;
+        }
+        let mut quote = None;
+        let mut escaped = false;
+        for c in raw.chars() {
+            match (quote, c) {
+                (Some('"'), '\\') if !escaped => {
+                    escaped = true;
+                    current.push(c);
+                    continue;
+                }
+                (Some(q), c) if c == q && !escaped => quote = None,
+                (None, '"') | (None, '\'') => quote = Some(c),
+                (None, '#') => break,
+                (None, '[') => depth += 1,
+                (None, ']') => depth = depth.saturating_sub(1),
+                _ => (),
+            }
+            escaped = false;
+            current.push(c);
+        }
+
+        // a table header's brackets balance on its own line, so only arrays carry on
+        if 0 == depth {
+            lines.push((start, current.split_off(0)));
+        } else {
+            current.push(' ');
+        }
+    }
+
+    if !current.trim().is_empty() {
+        bail!("line {}: unclosed array", start);
+    }
+    Ok(lines)
+}
+
+fn skip_spaces(chars: &mut Peekable<Chars>) {
+    while let Some(' ') | Some('\t') = chars.peek() {
+        chars.next();
+    }
+}
+
+fn parse_key(chars: &mut Peekable<Chars>) -> Result<String, failure::Error> {
+    if let Some('"') | Some('\'') = chars.peek() {
+        return parse_string(chars);
+    }
+
+    let mut key = String::new();
+    while let Some(&c) = chars.peek() {
+        if !(c.is_ascii_alphanumeric() || '-' == c || '_' == c) {
+            break;
+        }
+        key.push(c);
+        chars.next();
+    }
+    if key.is_empty() {
+        bail!("expected a key");
+    }
+    Ok(key)
+}
+
+fn parse_value(chars: &mut Peekable<Chars>) -> Result<Value, failure::Error> {
+    match chars.peek() {
+        Some('"') | Some('\'') => Ok(Value::Text(parse_string(chars)?)),
+        Some('[') => {
+            chars.next();
+            let mut items = Vec::new();
+            loop {
+                skip_spaces(chars);
+                match chars.peek() {
+                    Some(']') => {
+                        chars.next();
+                        return Ok(Value::List(items));
+                    }
+                    Some(_) => match parse_value(chars)? {
+                        Value::Text(item) => items.push(item),
+                        Value::Flag(b) => items.push(b.to_string()),
+                        Value::List(_) => bail!("arrays can't contain arrays"),
+                    },
+                    None => bail!("unclosed array"),
+                }
+                skip_spaces(chars);
+                match chars.next() {
+                    Some(',') => (),
+                    Some(']') => return Ok(Value::List(items)),
+                    other => bail!("expected ',' or ']' in an array, not {:?}", other),
+                }
+            }
+        }
+        _ => {
+            let mut bare = String::new();
+            while let Some(&c) = chars.peek() {
+                if ',' == c || ']' == c || c.is_whitespace() {
+                    break;
+                }
+                bare.push(c);
+                chars.next();
+            }
+            match bare.as_str() {
+                "true" => Ok(Value::Flag(true)),
+                "false" => Ok(Value::Flag(false)),
+                // numbers, which options take as text anyway
+                n if !n.is_empty()
+                    && n.chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "+-._:".contains(c)) =>
+                {
+                    Ok(Value::Text(n.replace('_', "")))
+                }
+                other => bail!(
+                    "expected a string, number, boolean or array, not {:?}",
+                    other
+                ),
+            }
+        }
+    }
+}
+
+/// A basic `"string"`, with escapes, or a literal `'string'`, without.
+pub fn parse_string(chars: &mut Peekable<Chars>) -> Result<String, failure::Error> {
+    let quote = chars.next().expect("peeked");
+    let mut out = String::new();
+    loop {
+        match chars.next() {
+            None => bail!("unclosed string"),
+            Some(c) if c == quote => return Ok(out),
+            Some('\\') if '"' == quote => match chars.next() {
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some('r') => out.push('\r'),
+                Some('"') => out.push('"'),
+                Some('\\') => out.push('\\'),
+                Some('u') => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    let c = u32::from_str_radix(&hex, 16)
+                        .ok()
+                        .and_then(std::char::from_u32)
+                        .ok_or_else(|| format_err!("bad escape \\u{}", hex))?;
+                    out.push(c);
+                }
+                other => bail!("unsupported escape {:?}", other),
+            },
+            Some(c) => out.push(c),
+        }
+    }
+}
+
+impl Value {
+    /// As command line arguments for `--key`.
+    fn args(&self, key: &str) -> Vec<OsString> {
+        let flag = OsString::from(format!("--{}", key));
+        match self {
+            Value::Flag(true) => vec![flag],
+            Value::Flag(false) => Vec::new(),
+            Value::Text(text) => vec![flag, OsString::from(text)],
+            Value::List(items) => items
+                .iter()
+                .flat_map(|item| vec![flag.clone(), OsString::from(item)])
+                .collect(),
+        }
+    }
+}
+
+/// A default for a long option, and where it came from, for the logs.
+#[derive(Debug, PartialEq)]
+pub struct Setting {
+    pub key: String,
+    pub value: Value,
+    pub source: String,
+}
+
+impl Config {
+    pub fn into_settings(self) -> Vec<Setting> {
+        let source = format!("config {:?}", self.path);
+        self.settings
+            .into_iter()
+            .map(|(key, value)| Setting {
+                key,
+                value,
+                source: source.clone(),
+            })
+            .collect()
+    }
+}
+
+/// What hooks are given, which a fetch-maybe run by a hook mustn't take as its own options, and
+/// the seed for `--splay` and `--watch-jitter`.
+const NOT_SETTINGS: &[&str] = &[
+    "FETCH_MAYBE_URL",
+    "FETCH_MAYBE_SIZE",
+    "FETCH_MAYBE_SHA256",
+    "FETCH_MAYBE_SEED",
+];
+
+const TRUTHY: &[&str] = &["1", "true", "yes", "on"];
+const FALSY: &[&str] = &["", "0", "false", "no", "off"];
+
+/// The long options, and whether each takes values, and many of them; clap 2 has no public
+/// way to list an `App`'s arguments, only these hidden fields.
+pub fn long_options(app: &clap::App<'static, 'static>) -> Vec<(&'static str, Option<bool>)> {
+    let flags = app
+        .p
+        .flags
+        .iter()
+        .filter_map(|f| f.s.long)
+        .map(|l| (l, None));
+    let opts = app.p.opts.iter().filter_map(|o| {
+        o.s.long
+            .map(|l| (l, Some(o.b.is_set(clap::ArgSettings::Multiple))))
+    });
+    flags.chain(opts).collect()
+}
+
+/// `FETCH_MAYBE_MIN_AGE` for `--min-age`, and so on, replacing any of the same from `settings`.
+///
+/// Flags take one of `TRUTHY` or `FALSY`; options which can be repeated take one value per
+/// line.
+pub fn from_env<I>(
+    app: &clap::App<'static, 'static>,
+    vars: I,
+    settings: &mut Vec<Setting>,
+    warnings: &mut Vec<String>,
+) -> Result<(), failure::Error>
+where
+    I: IntoIterator<Item = (OsString, OsString)>,
+{
+    let options = long_options(app);
+    let mut vars: Vec<(String, String)> = vars
+        .into_iter()
+        .filter_map(|(k, v)| Some((k.into_string().ok()?, v)))
+        .filter(|(k, _)| k.starts_with("FETCH_MAYBE_") && !NOT_SETTINGS.contains(&k.as_str()))
+        .map(|(k, v)| {
+            let v = v
+                .into_string()
+                .map_err(|v| format_err!("{} isn't UTF-8: {:?}", k, v))?;
+            Ok((k, v))
+        })
+        .collect::<Result<_, failure::Error>>()?;
+    vars.sort();
+
+    for (var, value) in vars {
+        let key = var["FETCH_MAYBE_".len()..]
+            .to_ascii_lowercase()
+            .replace('_', "-");
+        let takes = match options.iter().find(|(long, _)| *long == key) {
+            Some((_, takes)) if !COMMAND_LINE_ONLY.contains(&key.as_str()) => *takes,
+            Some(_) => {
+                warnings.push(format!(
+                    "{} can only be given on the command line, ignoring it",
+                    var
+                ));
+                continue;
+            }
+            None => {
+                warnings.push(format!("unknown setting {}, ignoring it", var));
+                continue;
+            }
+        };
+
+        let value = match takes {
+            None if TRUTHY.contains(&value.to_ascii_lowercase().as_str()) => Value::Flag(true),
+            None if FALSY.contains(&value.to_ascii_lowercase().as_str()) => Value::Flag(false),
+            None => bail!(
+                "{} is a flag, so should be one of {}, or {}; not {:?}",
+                var,
+                TRUTHY.join(", "),
+                FALSY[1..].join(", "),
+                value
+            ),
+            Some(true) => Value::List(
+                value
+                    .lines()
+                    .filter(|line| !line.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+            ),
+            Some(false) => Value::Text(value),
+        };
+
+        settings.retain(|s| s.key != key);
+        settings.push(Setting {
+            key,
+            value,
+            source: format!("environment {}", var),
+        });
+    }
+
+    Ok(())
+}
+
+impl Setting {
+    /// For the logs: header values and passwords can be secrets, so only their shape is shown.
+    pub fn describe(&self) -> String {
+        let mask = |v: &str| match self.key.as_str() {
+            "header" | "require-header" => match v.find(':') {
+                Some(colon) => format!("{}: ***", &v[..colon]),
+                None => "***".to_string(),
+            },
+            "user" => match v.find(':') {
+                Some(colon) => format!("{}:***", &v[..colon]),
+                None => v.to_string(),
+            },
+            _ => v.to_string(),
+        };
+        let value = match &self.value {
+            Value::Flag(set) => set.to_string(),
+            Value::Text(text) => format!("{:?}", mask(text)),
+            Value::List(items) => {
+                format!("{:?}", items.iter().map(|i| mask(i)).collect::<Vec<_>>())
+            }
+        };
+        format!("--{} {}, from {}", self.key, value, self.source)
+    }
+}
+
+/// Re-parse the command line with `settings` as defaults, returning those that were used.
+///
+/// Anything given on the command line wins; settings which aren't options are dropped, with
+/// a warning naming them.
+pub fn apply<F>(
+    settings: Vec<Setting>,
+    warnings: &mut Vec<String>,
+    app: F,
+    args: &[OsString],
+    matches: clap::ArgMatches<'static>,
+) -> Result<(clap::ArgMatches<'static>, Vec<Setting>), failure::Error>
+where
+    F: Fn() -> clap::App<'static, 'static>,
+{
+    let mut settings: Vec<Setting> = settings
+        .into_iter()
+        .filter(|s| 0 == matches.occurrences_of(&s.key))
+        .collect();
+
+    if settings.is_empty() {
+        return Ok((matches, settings));
+    }
+
+    loop {
+        // straight after the program name, so they're before any `--`
+        let mut all = args[..1].to_vec();
+        all.extend(settings.iter().flat_map(|s| s.value.args(&s.key)));
+        all.extend_from_slice(&args[1..]);
+
+        match app().get_matches_from_safe(all) {
+            Ok(matches) => return Ok((matches, settings)),
+            Err(e) if clap::ErrorKind::UnknownArgument == e.kind => {
+                let unknown = e.info.as_ref().and_then(|info| info.first()).cloned();
+                let (dropped, kept) = settings
+                    .into_iter()
+                    .partition::<Vec<_>, _>(|s| Some(format!("--{}", s.key)) == unknown);
+                settings = kept;
+                match dropped.first() {
+                    Some(s) => warnings.push(format!(
+                        "unknown setting {:?} in {}, ignoring it",
+                        s.key, s.source
+                    )),
+                    // not one of ours, so it's a problem with the command line itself
+                    None => e.exit(),
+                }
+            }
+            Err(e) => {
+                return Err(exit::classified(
+                    exit::Kind::Usage,
+                    format!(
+                        "applying defaults from {}: {}",
+                        settings
+                            .iter()
+                            .map(|s| s.source.as_str())
+                            .collect::<std::collections::BTreeSet<_>>()
+                            .into_iter()
+                            .collect::<Vec<_>>()
+                            .join(" and "),
+                        e.message.trim_start_matches("error: ")
+                    ),
+                ))
+            }
+        }
+    }
+}
+
+#[test]
+fn test_parse() {
+    let text = r#"
+# shared by everything
+header = ["Accept: application/json", 'X-Literal: \n']
+min-age = "1h" # trailing comment
+fsync = true
+retry = 3
+"no-mtime" = false
+
+[profile.github]
+header = [
+    "Authorization: token \"x\"",  # multi-line
+]
+
+[profile.other]
+retry = 9
+
+[misc]
+stray = 1
+"#;
+
+    let config = parse(text, Some("github")).unwrap();
+    assert_eq!(
+        vec![
+            ("min-age".to_string(), Value::Text("1h".to_string())),
+            ("fsync".to_string(), Value::Flag(true)),
+            ("retry".to_string(), Value::Text("3".to_string())),
+            ("no-mtime".to_string(), Value::Flag(false)),
+            (
+                "header".to_string(),
+                Value::List(vec!["Authorization: token \"x\"".to_string()])
+            ),
+        ],
+        config.settings
+    );
+    assert_eq!(1, config.warnings.len(), "{:?}", config.warnings);
+
+    let config = parse(text, None).unwrap();
+    assert_eq!(
+        (
+            "header".to_string(),
+            Value::List(vec![
+                "Accept: application/json".to_string(),
+                "X-Literal: \\n".to_string()
+            ])
+        ),
+        config.settings[0]
+    );
+
+    assert!(parse(text, Some("missing")).is_err());
+    assert!(parse("retry = ", None).is_err());
+    assert!(parse("retry = [1, 2", None).is_err());
+    assert!(parse("url = \"x\" y", None).is_err());
+}
+
+#[test]
+fn test_from_env() {
+    let app = clap::App::new("test")
+        .arg(clap::Arg::with_name("fsync").long("fsync"))
+        .arg(
+            clap::Arg::with_name("min-age")
+                .long("min-age")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("header")
+                .long("header")
+                .takes_value(true)
+                .multiple(true),
+        )
+        .arg(
+            clap::Arg::with_name("sha256")
+                .long("sha256")
+                .takes_value(true),
+        );
+
+    let var = |k: &str, v: &str| (OsString::from(k), OsString::from(v));
+    let mut settings = vec![Setting {
+        key: "min-age".to_string(),
+        value: Value::Text("1d".to_string()),
+        source: "config".to_string(),
+    }];
+    let mut warnings = Vec::new();
+    from_env(
+        &app,
+        vec![
+            var("FETCH_MAYBE_MIN_AGE", "1h"),
+            var("FETCH_MAYBE_FSYNC", "Yes"),
+            var("FETCH_MAYBE_HEADER", "A: 1\nAuthorization: secret\n"),
+            var("FETCH_MAYBE_SHA256", "from a hook"),
+            var("FETCH_MAYBE_NOPE", "1"),
+            var("PATH", "/bin"),
+        ],
+        &mut settings,
+        &mut warnings,
+    )
+    .unwrap();
+
+    assert_eq!(
+        vec![
+            "--fsync true, from environment FETCH_MAYBE_FSYNC",
+            "--header [\"A: ***\", \"Authorization: ***\"], from environment FETCH_MAYBE_HEADER",
+            "--min-age \"1h\", from environment FETCH_MAYBE_MIN_AGE",
+        ],
+        settings.iter().map(Setting::describe).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        vec!["unknown setting FETCH_MAYBE_NOPE, ignoring it"],
+        warnings
+    );
+
+    assert!(from_env(
+        &app,
+        vec![var("FETCH_MAYBE_FSYNC", "maybe")],
+        &mut settings,
+        &mut warnings
+    )
+    .is_err());
+}
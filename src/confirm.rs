@@ -0,0 +1,94 @@
+use std::fs;
+use std::io;
+use std::io::BufRead;
+use std::io::Write;
+use std::time::SystemTime;
+
+use chrono::DateTime;
+use chrono::Local;
+use failure::format_err;
+use failure::ResultExt;
+
+use crate::exit;
+use crate::size::format_size;
+
+/// For `--interactive`, which would otherwise wait for an answer nobody can give.
+pub fn check_terminal() -> Result<(), failure::Error> {
+    if 1 == unsafe { libc::isatty(libc::STDIN_FILENO) } {
+        return Ok(());
+    }
+    Err(exit::classified(
+        exit::Kind::Usage,
+        "--interactive needs stdin to be a terminal, to ask on",
+    ))
+}
+
+/// What's about to happen, as a question: the old output's size and time, and the new size.
+pub fn question(
+    output: &str,
+    old_len: u64,
+    old_mtime: Option<SystemTime>,
+    new_len: u64,
+    changes: Option<&str>,
+) -> String {
+    format!(
+        "replace {} ({}, modified {}) with {}{}?",
+        output,
+        size(old_len),
+        old_mtime.map_or_else(
+            || "at an unknown time".to_string(),
+            |t| DateTime::<Local>::from(t)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string()
+        ),
+        size(new_len),
+        changes.map(|c| format!(" ({})", c)).unwrap_or_default(),
+    )
+}
+
+fn size(bytes: u64) -> String {
+    match format_size(bytes) {
+        exact if bytes < 1024 => format!("{} bytes", exact),
+        rounded => rounded,
+    }
+}
+
+/// Ask on the controlling terminal, like `cp -i`; only `y` or `yes` is yes.
+pub fn ask(question: &str) -> Result<bool, failure::Error> {
+    let tty = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .with_context(|_| format_err!("opening the terminal, to ask {:?}", question))?;
+    (&tty)
+        .write_all(format!("fetch-maybe: {} [y/N] ", question).as_bytes())
+        .with_context(|_| format_err!("asking {:?}", question))?;
+    let mut answer = String::new();
+    io::BufReader::new(&tty)
+        .read_line(&mut answer)
+        .with_context(|_| format_err!("reading the answer to {:?}", question))?;
+    Ok(is_yes(&answer))
+}
+
+fn is_yes(answer: &str) -> bool {
+    let answer = answer.trim().to_ascii_lowercase();
+    "y" == answer || "yes" == answer
+}
+
+#[test]
+fn test_is_yes() {
+    assert!(is_yes("y\n"));
+    assert!(is_yes(" YES\n"));
+    assert!(!is_yes("\n"));
+    assert!(!is_yes("yeah\n"));
+    assert!(!is_yes(""));
+}
+
+#[test]
+fn test_question() {
+    assert_eq!(
+        "replace \"out\" (1.5k, modified at an unknown time) with 2.0k (+3 -1 lines)?",
+        question("\"out\"", 1536, None, 2048, Some("+3 -1 lines"))
+    );
+    assert!(question("out", 3, None, 4, None).ends_with(" with 4 bytes?"));
+}
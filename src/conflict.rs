@@ -0,0 +1,82 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use log::debug;
+
+/// How many times `--on-conflict retry` starts over before giving up.
+pub const ATTEMPTS: usize = 3;
+
+/// The output changed under us, so renaming over it would lose someone else's write.
+pub struct Changed {
+    output: String,
+}
+
+// main prints errors with Debug
+impl fmt::Debug for Changed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl fmt::Display for Changed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} changed while downloading, see --on-conflict",
+            self.output
+        )
+    }
+}
+
+impl failure::Fail for Changed {}
+
+/// Fail with `Changed` unless the output is still the file, or absence, we started from.
+pub fn check(output: &Path, before: Option<&fs::Metadata>) -> Result<(), failure::Error> {
+    use std::os::unix::fs::MetadataExt;
+
+    let now = match output.metadata() {
+        Ok(metadata) => Some(metadata),
+        Err(ref e) if std::io::ErrorKind::NotFound == e.kind() => None,
+        Err(e) => Err(e)?,
+    };
+
+    let same = match (before, &now) {
+        (None, None) => true,
+        (Some(before), Some(now)) => {
+            before.dev() == now.dev()
+                && before.ino() == now.ino()
+                && before.len() == now.len()
+                && before.modified().ok() == now.modified().ok()
+        }
+        _ => false,
+    };
+
+    if !same {
+        debug!("      conflict: was {:?}, now {:?}", before, now);
+        return Err(Changed {
+            output: format!("{:?}", output),
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_check() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    check(&output, None).unwrap();
+
+    fs::write(&output, "one").unwrap();
+    let err = check(&output, None).unwrap_err();
+    assert!(err.downcast_ref::<Changed>().is_some());
+
+    let before = output.metadata().unwrap();
+    check(&output, Some(&before)).unwrap();
+
+    fs::write(&output, "three").unwrap();
+    let err = check(&output, Some(&before)).unwrap_err();
+    assert!(err.to_string().contains("changed while downloading"));
+}
@@ -0,0 +1,196 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use clap::Arg;
+use failure::format_err;
+use failure::ResultExt;
+
+use crate::exit;
+
+/// The headers which `-A` and `-e` set.
+const HEADERS: &[(&str, &str)] = &[("user-agent", "User-Agent"), ("referer", "Referer")];
+
+/// curl's spellings, for those used to typing them; hidden, so ours are the ones documented.
+pub fn args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("curl-output")
+            .short("o")
+            .takes_value(true)
+            .value_name("FILE")
+            .conflicts_with_all(&["output", "output-fd", "cache-dir", "manifest", "stdin"])
+            .hidden(true)
+            .help("the same as giving the output after the URL"),
+        Arg::with_name("location")
+            .short("L")
+            .long("location")
+            .hidden(true)
+            .help("does nothing: redirects are always followed"),
+        Arg::with_name("user-agent")
+            .short("A")
+            .long("user-agent")
+            .takes_value(true)
+            .number_of_values(1)
+            .hidden(true)
+            .help("the same as --header 'User-Agent: VALUE'"),
+        Arg::with_name("referer")
+            .short("e")
+            .long("referer")
+            .takes_value(true)
+            .number_of_values(1)
+            .hidden(true)
+            .help("the same as --header 'Referer: VALUE'"),
+        Arg::with_name("time-cond")
+            .short("z")
+            .long("time-cond")
+            .takes_value(true)
+            .value_name("FILE")
+            .hidden(true)
+            .help("the output, if given: its time is what's sent as If-Modified-Since anyway; otherwise, the file whose time is"),
+        Arg::with_name("etag-compare")
+            .long("etag-compare")
+            .takes_value(true)
+            .value_name("FILE")
+            .conflicts_with("cache-dir")
+            .hidden(true)
+            .help("with no output file, a file holding the ETag to send as If-None-Match"),
+    ]
+}
+
+/// The output, whether it was given after the URL or with `-o`.
+pub fn output<'m>(matches: &'m clap::ArgMatches) -> Option<&'m OsStr> {
+    matches
+        .value_of_os("output")
+        .or_else(|| matches.value_of_os("curl-output"))
+}
+
+/// The `--header`s, then those for `-A` and `-e`, which can't also be given as a `--header`.
+pub fn headers<'m>(
+    matches: &'m clap::ArgMatches,
+) -> Result<Vec<(&'m str, &'m str)>, failure::Error> {
+    let mut headers = match matches.values_of("header") {
+        Some(headers) => headers
+            .map(crate::parse_header)
+            .collect::<Result<Vec<_>, _>>()?,
+        None => Vec::new(),
+    };
+    for (arg, name) in HEADERS {
+        let value = match matches.value_of(arg) {
+            Some(value) => value,
+            None => continue,
+        };
+        if headers
+            .iter()
+            .any(|(key, _)| key.eq_ignore_ascii_case(name))
+        {
+            return Err(exit::classified(
+                exit::Kind::Usage,
+                format!("--{} and a --header both set {}", arg, name),
+            ));
+        }
+        headers.push((name, value));
+    }
+    Ok(headers)
+}
+
+/// `-z` is only understood when it names the output, as other files and dates have no
+/// equivalent here, unless there's no output file to compare, as when writing to stdout or
+/// discarding. It's the same for `--etag-compare`, whose ETag is otherwise `--cache-dir`'s.
+pub fn check_conditions(
+    matches: &clap::ArgMatches,
+    no_output_file: bool,
+) -> Result<(), failure::Error> {
+    if let (Some(given), false) = (matches.value_of_os("etag-compare"), no_output_file) {
+        return Err(exit::classified(
+            exit::Kind::Usage,
+            format!(
+                "--etag-compare {:?}: only without an output file, see --cache-dir",
+                given
+            ),
+        ));
+    }
+    let given = match matches.value_of_os("time-cond") {
+        Some(given) => Path::new(given),
+        None => return Ok(()),
+    };
+    match output(matches) {
+        _ if no_output_file => Ok(()),
+        Some(output) if Path::new(output) == given => Ok(()),
+        _ => Err(exit::classified(
+            exit::Kind::Usage,
+            format!(
+                "--time-cond {:?}: only the output's own time can be compared, see --reference-time",
+                given
+            ),
+        )),
+    }
+}
+
+/// The ETag in `--etag-compare`'s file; like curl, a missing or empty file just means there's
+/// nothing to compare yet.
+pub fn etag_compare(file: &Path) -> Result<Option<String>, failure::Error> {
+    let text = match fs::read_to_string(file) {
+        Ok(text) => text,
+        Err(ref e) if io::ErrorKind::NotFound == e.kind() => return Ok(None),
+        Err(e) => Err(e).with_context(|_| format_err!("reading --etag-compare {:?}", file))?,
+    };
+    Ok(text
+        .lines()
+        .next()
+        .map(str::trim)
+        .filter(|etag| !etag.is_empty())
+        .map(str::to_string))
+}
+
+#[test]
+fn test_same_as_native() {
+    let table: &[(&[&str], &[&str])] = &[
+        (&["-o", "out", "http://x/"], &["http://x/", "out"]),
+        (&["-L", "http://x/", "out"], &["http://x/", "out"]),
+        (
+            &["-A", "tool/1", "http://x/", "out"],
+            &["-H", "User-Agent: tool/1", "http://x/", "out"],
+        ),
+        (
+            &["-e", "http://y/", "http://x/", "out"],
+            &["-H", "Referer: http://y/", "http://x/", "out"],
+        ),
+        (&["-z", "out", "http://x/", "out"], &["http://x/", "out"]),
+    ];
+    let parse = |args: &[&str]| {
+        crate::app()
+            .get_matches_from_safe(Some(&"fetch-maybe").into_iter().chain(args))
+            .unwrap_or_else(|e| panic!("{:?}: {}", args, e))
+    };
+    for (alias, native) in table {
+        let (alias_matches, native_matches) = (parse(alias), parse(native));
+        assert_eq!(
+            (output(&native_matches), headers(&native_matches).unwrap()),
+            (output(&alias_matches), headers(&alias_matches).unwrap()),
+            "{:?}",
+            alias
+        );
+        assert!(
+            check_conditions(&alias_matches, false).is_ok(),
+            "{:?}",
+            alias
+        );
+    }
+
+    let err = |args: &[&str]| {
+        crate::app()
+            .get_matches_from_safe(Some(&"fetch-maybe").into_iter().chain(args))
+            .map_err(|e| e.to_string())
+            .and_then(|m| {
+                headers(&m)
+                    .and_then(|_| check_conditions(&m, false))
+                    .map_err(|e| e.to_string())
+            })
+            .unwrap_err()
+    };
+    assert!(err(&["-o", "out", "http://x/", "out"]).contains("cannot be used with"));
+    assert!(err(&["-A", "a", "-H", "user-agent: b", "http://x/", "out"]).contains("both set"));
+    assert!(err(&["-z", "other", "http://x/", "out"]).contains("--reference-time"));
+    assert!(err(&["--etag-compare", "tag", "http://x/", "out"]).contains("--cache-dir"));
+}
@@ -0,0 +1,547 @@
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::path::Path;
+use std::path::PathBuf;
+
+use failure::bail;
+use failure::format_err;
+use failure::ResultExt;
+use log::debug;
+use sha2::Digest as _;
+use sha2::Sha256;
+use url::Url;
+
+use crate::backup;
+use crate::compare;
+use crate::digest;
+use crate::exit;
+use crate::interim;
+use crate::range;
+use crate::redirect;
+use crate::target;
+
+/// What `OUTPUT.blocks` is kept in, unless the `--delta-index` says otherwise.
+pub const BLOCK_SIZE: u64 = 1024 * 1024;
+
+/// The largest index worth reading; at a line a block, one for 8 GB is under a megabyte.
+const INDEX_LIMIT: u64 = 64 * 1024 * 1024;
+
+/// `--delta-index`, until a run finds the index can't be trusted, and downloads it all.
+pub struct Delta {
+    index: Url,
+    given_up: Cell<bool>,
+}
+
+impl Delta {
+    pub fn from_matches(matches: &clap::ArgMatches) -> Result<Option<Delta>, failure::Error> {
+        Ok(match matches.value_of("delta-index") {
+            Some(url) => Some(Delta {
+                index: target::parse(url)?.url,
+                given_up: Cell::new(false),
+            }),
+            None => None,
+        })
+    }
+
+    pub fn active(&self) -> bool {
+        !self.given_up.get()
+    }
+
+    pub fn give_up(&self) {
+        self.given_up.set(true);
+    }
+
+    pub fn index(&self) -> &Url {
+        &self.index
+    }
+}
+
+/// The digests of a file's fixed-size blocks, and of the whole thing.
+///
+/// As text, for `--delta-index` and `OUTPUT.blocks` alike, it's `size`, `block-size` and
+/// `sha256` lines, then a `block` line with each block's SHA-256, in order; the last block is
+/// whatever's left. A sidecar also has an `output` line, for the file it describes:
+///
+/// ```text
+/// size 2621440
+/// block-size 1048576
+/// sha256 9f86d08...
+/// block 2c26b46...
+/// block fcde2b2...
+/// block baa5a09...
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Map {
+    pub size: u64,
+    pub block_size: u64,
+    pub sha256: [u8; 32],
+    pub blocks: Vec<[u8; 32]>,
+}
+
+impl Map {
+    fn block_len(&self, block: usize) -> u64 {
+        let start = block as u64 * self.block_size;
+        self.block_size.min(self.size - start)
+    }
+
+    /// How many of the blocks aren't the same in `other`.
+    pub fn differing(&self, other: &Map) -> usize {
+        (0..self.blocks.len())
+            .filter(|&block| !self.same(other, block))
+            .count()
+    }
+
+    fn same(&self, other: &Map, block: usize) -> bool {
+        self.block_size == other.block_size && other.blocks.get(block) == Some(&self.blocks[block])
+    }
+}
+
+pub fn parse(text: &str) -> Option<(Map, Option<String>)> {
+    let field = |name: &str| {
+        text.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+    };
+    let map = Map {
+        size: field("size")?.parse().ok()?,
+        block_size: field("block-size")?.parse().ok().filter(|&bs| bs > 0)?,
+        sha256: unhex(field("sha256")?)?,
+        blocks: text
+            .lines()
+            .filter_map(|line| line.strip_prefix("block "))
+            .map(unhex)
+            .collect::<Option<_>>()?,
+    };
+    if map.blocks.len() as u64 != map.size.div_ceil(map.block_size) {
+        return None;
+    }
+    Some((map, field("output").map(str::to_string)))
+}
+
+fn unhex(hex: &str) -> Option<[u8; 32]> {
+    if 64 != hex.len() {
+        return None;
+    }
+    let mut digest = [0u8; 32];
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(digest)
+}
+
+pub fn format(map: &Map) -> String {
+    let mut text = format!(
+        "size {}\nblock-size {}\nsha256 {}\n",
+        map.size,
+        map.block_size,
+        digest::hex(&map.sha256)
+    );
+    for block in &map.blocks {
+        text.push_str(&format!("block {}\n", digest::hex(block)));
+    }
+    text
+}
+
+/// Where an output's map is kept: `OUTPUT.blocks`.
+pub fn path(output: &Path) -> PathBuf {
+    backup::suffixed(output, ".blocks")
+}
+
+/// The output's map, in blocks of `block_size`; from `OUTPUT.blocks`, if that's for the output
+/// as it is, or else by reading it all.
+pub fn of_output(
+    output: &Path,
+    metadata: &fs::Metadata,
+    block_size: u64,
+) -> Result<Map, failure::Error> {
+    let record = path(output);
+    if let Some((map, Some(for_output))) =
+        fs::read_to_string(&record).ok().as_deref().and_then(parse)
+    {
+        if map.block_size == block_size && for_output == compare::stamp(metadata) {
+            return Ok(map);
+        }
+        debug!(
+            "         delta: {:?} is out of date, reading the output",
+            record
+        );
+    }
+    let mut builder = Builder::new(block_size);
+    let file = fs::File::open(output).with_context(|_| format_err!("opening {:?}", output))?;
+    io::copy(&mut recording(file, Some(&mut builder)), &mut io::sink())
+        .with_context(|_| format_err!("reading {:?} for its blocks", output))?;
+    Ok(builder.finish())
+}
+
+/// Record `map` against `output` as it now is; like `compare::record`, it comes after the
+/// output's rename, so a crash in between leaves a map that no longer matches.
+pub fn record(output: &Path, map: &Map) -> Result<(), failure::Error> {
+    let metadata = output
+        .metadata()
+        .with_context(|_| format_err!("reading output's info: {:?}", output))?;
+    let text = format!("{}output {}\n", format(map), compare::stamp(&metadata));
+    compare::replace(&path(output), &text)?;
+    debug!("         delta: recorded {} blocks", map.blocks.len());
+    Ok(())
+}
+
+/// Fetch and parse the index at `url`, following redirects; `new_request` sets up auth and
+/// headers.
+pub fn fetch_index<F>(url: &Url, new_request: F) -> Result<Map, failure::Error>
+where
+    F: Fn(&Url) -> ureq::Request,
+{
+    let mut chain = redirect::Chain::new(url.clone(), 10);
+    let (response, interim_body) = loop {
+        debug!("         delta: requesting {:?}", chain.current().as_str());
+        let (response, interim_body) = interim::skip(new_request(chain.current()).call());
+        if let Some(err) = response.synthetic_error() {
+            return Err(crate::ureq_error(err));
+        }
+        match redirect::location(&response) {
+            Some(location) => chain.follow_location(location)?,
+            None => break (response, interim_body),
+        }
+    };
+
+    if !(200..=299).contains(&response.status()) {
+        return Err(exit::classified(
+            exit::Kind::Status(response.status()),
+            format!("unhappy response: {:?}", response.status_line()),
+        ));
+    }
+
+    let mut text = String::new();
+    interim::reader(response, interim_body)
+        .take(INDEX_LIMIT + 1)
+        .read_to_string(&mut text)
+        .with_context(|_| format_err!("reading {}", url))?;
+    if text.len() as u64 > INDEX_LIMIT {
+        bail!(
+            "{} is more than {} bytes, too big for an index",
+            url,
+            INDEX_LIMIT
+        );
+    }
+    match parse(&text) {
+        Some((map, _)) => Ok(map),
+        None => bail!("{} isn't a block index", url),
+    }
+}
+
+/// Digests the blocks of what's read through `recording`.
+pub struct Builder {
+    block_size: u64,
+    size: u64,
+    block: Sha256,
+    whole: Sha256,
+    blocks: Vec<[u8; 32]>,
+}
+
+impl Builder {
+    pub fn new(block_size: u64) -> Builder {
+        Builder {
+            block_size,
+            size: 0,
+            block: Sha256::new(),
+            whole: Sha256::new(),
+            blocks: Vec::new(),
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.whole.update(data);
+        while !data.is_empty() {
+            let room = (self.block_size - self.size % self.block_size) as usize;
+            let (now, later) = data.split_at(room.min(data.len()));
+            self.block.update(now);
+            self.size += now.len() as u64;
+            if self.size.is_multiple_of(self.block_size) {
+                self.blocks.push(self.block.finalize_reset().into());
+            }
+            data = later;
+        }
+    }
+
+    pub fn finish(mut self) -> Map {
+        if !self.size.is_multiple_of(self.block_size) {
+            self.blocks.push(self.block.finalize_reset().into());
+        }
+        Map {
+            size: self.size,
+            block_size: self.block_size,
+            sha256: self.whole.finalize().into(),
+            blocks: self.blocks,
+        }
+    }
+}
+
+pub struct Recording<'b, R> {
+    inner: R,
+    builder: Option<&'b mut Builder>,
+}
+
+/// Feeds what's read from `inner` to `builder`, if there is one.
+pub fn recording<R: Read>(inner: R, builder: Option<&mut Builder>) -> Recording<'_, R> {
+    Recording { inner, builder }
+}
+
+impl<R: Read> Read for Recording<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if let Some(builder) = &mut self.builder {
+            builder.update(&buf[..read]);
+        }
+        Ok(read)
+    }
+}
+
+/// The index and the resource disagree, or the resource changed under us; the read fails with
+/// this inside, so the run can start again without `--delta-index`.
+#[derive(Debug)]
+pub struct Inconsistent(String);
+
+impl fmt::Display for Inconsistent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "--delta-index: {}", self.0)
+    }
+}
+
+impl Error for Inconsistent {}
+
+pub fn is_inconsistent(e: &io::Error) -> bool {
+    e.get_ref().is_some_and(|e| e.is::<Inconsistent>())
+}
+
+fn inconsistent(message: String) -> io::Error {
+    io::Error::other(Inconsistent(message))
+}
+
+/// A run of blocks, `start..end`, read from the old output, or from the remote.
+struct Run {
+    start: usize,
+    end: usize,
+    local: bool,
+}
+
+/// Where the run under way is coming from, and how much of it is left to read.
+enum Current {
+    Local(u64),
+    Remote(io::Take<interim::Body>),
+}
+
+/// Gets a range of the resource, or `None` if it's changed since the index was fetched.
+pub type Fetch<'f> = &'f dyn Fn(range::ByteRange) -> Result<Option<interim::Body>, failure::Error>;
+
+/// The new body, as `remote` describes it, from the `old` output where its blocks are the
+/// same as in `local`, and from ranges `fetch` gets for the rest.
+///
+/// Each fetched block is checked against the index as it comes, and the whole against its
+/// digest at the end, before it can be renamed into place.
+pub struct Assembled<'f> {
+    remote: Map,
+    runs: Vec<Run>,
+    next: usize,
+    current: Option<Current>,
+    old: fs::File,
+    fetch: Fetch<'f>,
+    /// Where the fetched bytes are up to, and the digest of their block so far.
+    position: u64,
+    block: Sha256,
+    whole: Sha256,
+    done: bool,
+}
+
+pub fn assemble<'f>(remote: Map, local: &Map, old: fs::File, fetch: Fetch<'f>) -> Assembled<'f> {
+    let mut runs: Vec<Run> = Vec::new();
+    for block in 0..remote.blocks.len() {
+        let local = remote.same(local, block);
+        match runs.last_mut() {
+            Some(run) if run.local == local => run.end = block + 1,
+            _ => runs.push(Run {
+                start: block,
+                end: block + 1,
+                local,
+            }),
+        }
+    }
+    Assembled {
+        remote,
+        runs,
+        next: 0,
+        current: None,
+        old,
+        fetch,
+        position: 0,
+        block: Sha256::new(),
+        whole: Sha256::new(),
+        done: false,
+    }
+}
+
+impl Assembled<'_> {
+    fn start(&mut self, run: usize) -> io::Result<Current> {
+        let Run { start, end, local } = self.runs[run];
+        let offset = start as u64 * self.remote.block_size;
+        let len: u64 = (start..end).map(|block| self.remote.block_len(block)).sum();
+        if local {
+            self.old.seek(SeekFrom::Start(offset))?;
+            return Ok(Current::Local(len));
+        }
+        let wanted = range::ByteRange {
+            start: offset,
+            end: Some(offset + len - 1),
+        };
+        debug!("         delta: fetching {}", wanted.header_value());
+        self.position = offset;
+        match (self.fetch)(wanted) {
+            Ok(Some(body)) => Ok(Current::Remote(body.take(len))),
+            Ok(None) => Err(inconsistent(
+                "the resource changed while its blocks were being fetched".to_string(),
+            )),
+            Err(e) => Err(io::Error::other(e.to_string())),
+        }
+    }
+
+    /// Check the fetched bytes against the index as each block of them is finished.
+    fn check_blocks(&mut self, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            let block = (self.position / self.remote.block_size) as usize;
+            let block_end = block as u64 * self.remote.block_size + self.remote.block_len(block);
+            let room = (block_end - self.position) as usize;
+            let (now, later) = data.split_at(room.min(data.len()));
+            self.block.update(now);
+            self.position += now.len() as u64;
+            if self.position == block_end {
+                let digest: [u8; 32] = self.block.finalize_reset().into();
+                if digest != self.remote.blocks[block] {
+                    return Err(inconsistent(format!(
+                        "block {} isn't what the index says",
+                        block
+                    )));
+                }
+            }
+            data = later;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let whole: [u8; 32] = self.whole.finalize_reset().into();
+        if whole != self.remote.sha256 {
+            return Err(inconsistent(format!(
+                "the assembled file's SHA-256 is {}, not {}",
+                digest::hex(&whole),
+                digest::hex(&self.remote.sha256)
+            )));
+        }
+        self.done = true;
+        Ok(())
+    }
+}
+
+impl Read for Assembled<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.done {
+                return Ok(0);
+            }
+            let read = match &mut self.current {
+                None if self.next == self.runs.len() => {
+                    self.finish()?;
+                    continue;
+                }
+                None => {
+                    self.current = Some(self.start(self.next)?);
+                    self.next += 1;
+                    continue;
+                }
+                Some(Current::Local(0)) => {
+                    self.current = None;
+                    continue;
+                }
+                Some(Current::Local(left)) => {
+                    let want = (*left).min(buf.len() as u64) as usize;
+                    let read = self.old.read(&mut buf[..want])?;
+                    if 0 == read {
+                        return Err(inconsistent("the output got shorter".to_string()));
+                    }
+                    *left -= read as u64;
+                    read
+                }
+                Some(Current::Remote(body)) => {
+                    let read = body.read(buf)?;
+                    if 0 == read {
+                        if 0 != body.limit() {
+                            return Err(io::ErrorKind::UnexpectedEof.into());
+                        }
+                        self.current = None;
+                        continue;
+                    }
+                    self.check_blocks(&buf[..read])?;
+                    read
+                }
+            };
+            self.whole.update(&buf[..read]);
+            return Ok(read);
+        }
+    }
+}
+
+#[test]
+fn test_map() {
+    let mut builder = Builder::new(4);
+    io::copy(
+        &mut recording(&b"aaaabbbbcc"[..], Some(&mut builder)),
+        &mut io::sink(),
+    )
+    .unwrap();
+    let map = builder.finish();
+    assert_eq!(10, map.size);
+    assert_eq!(3, map.blocks.len());
+    assert_eq!(<[u8; 32]>::from(Sha256::digest(b"cc")), map.blocks[2]);
+    assert_eq!(Some((map.clone(), None)), parse(&format(&map)));
+    assert_eq!(
+        None,
+        parse(&format(&map).replace("block-size 4", "block-size 8"))
+    );
+}
+
+#[test]
+fn test_assemble() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    fs::write(&output, b"aaaabbbbcc").unwrap();
+    let map = |data: &[u8]| {
+        let mut builder = Builder::new(4);
+        builder.update(data);
+        builder.finish()
+    };
+    let local = map(b"aaaabbbbcc");
+    let fetched = std::cell::RefCell::new(Vec::new());
+    let fetch = |wanted: range::ByteRange| {
+        fetched.borrow_mut().push(wanted.header_value());
+        let body: interim::Body = Box::new(io::Cursor::new(b"XXXXdd".to_vec()));
+        Ok(Some(body))
+    };
+
+    let mut assembled = Vec::new();
+    let old = fs::File::open(&output).unwrap();
+    assemble(map(b"aaaaXXXXdd"), &local, old, &fetch)
+        .read_to_end(&mut assembled)
+        .unwrap();
+    assert_eq!(b"aaaaXXXXdd", &assembled[..]);
+    assert_eq!(vec!["bytes=4-9"], *fetched.borrow());
+
+    // the server's blocks aren't what the index says
+    let old = fs::File::open(&output).unwrap();
+    let e = assemble(map(b"aaaaYYYYdd"), &local, old, &fetch)
+        .read_to_end(&mut Vec::new())
+        .unwrap_err();
+    assert!(is_inconsistent(&e), "{}", e);
+}
@@ -0,0 +1,197 @@
+use std::fs;
+use std::io;
+use std::io::BufRead;
+use std::io::Read;
+use std::io::Seek;
+use std::path::Path;
+
+use failure::err_msg;
+use failure::format_err;
+use failure::ResultExt;
+
+/// Larger files are reported as differing, without reading them all into memory to diff.
+const TEXT_LIMIT: u64 = 1024 * 1024;
+
+/// A unified diff between the existing output and the new download, or `None` if they match.
+pub fn against_output(
+    output: &Path,
+    mut temp: &fs::File,
+    max_lines: usize,
+) -> Result<Option<String>, failure::Error> {
+    let old = read_limited(
+        fs::File::open(output).with_context(|_| format_err!("opening {:?} to diff", output))?,
+    )
+    .with_context(|_| format_err!("reading {:?} to diff", output))?;
+
+    temp.seek(io::SeekFrom::Start(0))
+        .with_context(|_| err_msg("rewinding download to diff"))?;
+    let new = read_limited(temp).with_context(|_| err_msg("reading download to diff"))?;
+
+    let name = output.to_string_lossy();
+    Ok(describe(old, new, &name, max_lines))
+}
+
+/// A diff from `against_output` in a few words: the lines added and removed, or that they're
+/// binary.
+pub fn changes(diff: &str) -> String {
+    if !diff.starts_with("--- ") {
+        return diff.trim_end().to_string();
+    }
+    let body = diff.lines().skip(2);
+    let added = body.clone().filter(|l| l.starts_with('+')).count();
+    let removed = body.clone().filter(|l| l.starts_with('-')).count();
+    let truncated = body.clone().any(|l| l.starts_with("... diff truncated"));
+    format!(
+        "{}+{} -{} lines",
+        if truncated { "at least " } else { "" },
+        added,
+        removed
+    )
+}
+
+/// Whether the download has exactly the existing output's content.
+pub fn identical(output: &Path, mut temp: &fs::File) -> Result<bool, failure::Error> {
+    let mut old = io::BufReader::new(
+        fs::File::open(output).with_context(|_| format_err!("opening {:?} to compare", output))?,
+    );
+    let old_len = old
+        .get_ref()
+        .metadata()
+        .with_context(|_| format_err!("reading {:?}'s info", output))?
+        .len();
+    let new_len = temp
+        .metadata()
+        .with_context(|_| err_msg("reading temporary file's info"))?
+        .len();
+    if old_len != new_len {
+        return Ok(false);
+    }
+
+    temp.seek(io::SeekFrom::Start(0))
+        .with_context(|_| err_msg("rewinding download to compare"))?;
+    let mut new = io::BufReader::new(temp);
+
+    loop {
+        let old_buf = old
+            .fill_buf()
+            .with_context(|_| format_err!("reading {:?} to compare", output))?;
+        if old_buf.is_empty() {
+            // same length, so the download is done too
+            return Ok(true);
+        }
+        let new_buf = new
+            .fill_buf()
+            .with_context(|_| err_msg("reading download to compare"))?;
+        let n = old_buf.len().min(new_buf.len());
+        if 0 == n {
+            return Ok(false);
+        }
+        if old_buf[..n] != new_buf[..n] {
+            return Ok(false);
+        }
+        old.consume(n);
+        new.consume(n);
+    }
+}
+
+/// The content, or just its length if it's too large to bother with.
+enum Side {
+    Content(Vec<u8>),
+    TooLarge(u64),
+}
+
+fn read_limited<R: Read>(mut file: R) -> io::Result<Side> {
+    let mut buf = Vec::new();
+    (&mut file).take(TEXT_LIMIT + 1).read_to_end(&mut buf)?;
+    if buf.len() as u64 > TEXT_LIMIT {
+        let rest = io::copy(&mut file, &mut io::sink())?;
+        return Ok(Side::TooLarge(buf.len() as u64 + rest));
+    }
+    Ok(Side::Content(buf))
+}
+
+impl Side {
+    fn len(&self) -> u64 {
+        match self {
+            Side::Content(v) => v.len() as u64,
+            Side::TooLarge(len) => *len,
+        }
+    }
+
+    fn text(&self) -> Option<&str> {
+        match self {
+            Side::Content(v) => std::str::from_utf8(v).ok(),
+            Side::TooLarge(_) => None,
+        }
+    }
+}
+
+fn describe(old: Side, new: Side, name: &str, max_lines: usize) -> Option<String> {
+    if let (Side::Content(old), Side::Content(new)) = (&old, &new) {
+        if old == new {
+            return None;
+        }
+    }
+
+    let (old_text, new_text) = match (old.text(), new.text()) {
+        (Some(old_text), Some(new_text)) => (old_text, new_text),
+        _ => {
+            return Some(format!(
+                "binary files differ ({} bytes -> {} bytes)\n",
+                old.len(),
+                new.len()
+            ))
+        }
+    };
+
+    let diff = similar::TextDiff::from_lines(old_text, new_text)
+        .unified_diff()
+        .context_radius(3)
+        .header(name, &format!("{} (new)", name))
+        .to_string();
+
+    let lines = diff.lines().count();
+    if lines <= max_lines {
+        return Some(diff);
+    }
+
+    let mut out: String = diff
+        .split_inclusive('\n')
+        .take(max_lines)
+        .collect::<String>();
+    out.push_str(&format!(
+        "... diff truncated: {} more lines, see --diff-lines\n",
+        lines - max_lines
+    ));
+    Some(out)
+}
+
+#[test]
+fn test_describe() {
+    let text = |s: &str| Side::Content(s.as_bytes().to_vec());
+
+    assert!(describe(text("a\n"), text("a\n"), "f", 10).is_none());
+
+    let diff = describe(text("a\nb\nc\n"), text("a\nB\nc\n"), "f", 10).unwrap();
+    assert!(diff.starts_with("--- f\n+++ f (new)\n"), "{}", diff);
+    assert!(diff.contains("\n-b\n+B\n"), "{}", diff);
+
+    let long = describe(text("a\nb\nc\n"), text("a\nB\nc\n"), "f", 3).unwrap();
+    assert!(
+        long.ends_with("... diff truncated: 4 more lines, see --diff-lines\n"),
+        "{}",
+        long
+    );
+
+    assert_eq!("+1 -1 lines", changes(&diff));
+    assert_eq!("at least +0 -0 lines", changes(&long));
+
+    assert_eq!(
+        Some("binary files differ (2 bytes -> 3 bytes)\n".to_string()),
+        describe(Side::Content(vec![0xff, 0]), text("abc"), "f", 10)
+    );
+    assert_eq!(
+        Some("binary files differ (1 bytes -> 2000000 bytes)\n".to_string()),
+        describe(text("a"), Side::TooLarge(2_000_000), "f", 10)
+    );
+}
@@ -0,0 +1,172 @@
+use std::fmt;
+use std::io;
+use std::io::Write;
+
+use md5::Md5;
+use sha2::Digest as _;
+use sha2::Sha256;
+
+/// What we learnt about a body while writing it out.
+pub struct Digest {
+    pub sha256: [u8; 32],
+    pub bytes: u64,
+    pub md5: Option<[u8; 16]>,
+    pub crc32c: Option<u32>,
+}
+
+impl Digest {
+    pub fn sha256_hex(&self) -> String {
+        hex(&self.sha256)
+    }
+}
+
+impl fmt::Debug for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "sha256:{}", self.sha256_hex())?;
+        if let Some(md5) = &self.md5 {
+            write!(f, " md5:{}", hex(md5))?;
+        }
+        if let Some(crc32c) = self.crc32c {
+            write!(f, " crc32c:{:08x}", crc32c)?;
+        }
+        write!(f, " ({} bytes)", self.bytes)
+    }
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Hashes everything written through it, on the way to `inner`, without holding any of it.
+///
+/// Made without a hasher, it's a plain pass-through, so the default path doesn't pay for hashing.
+pub struct DigestWriter<W> {
+    inner: W,
+    hasher: Option<Sha256>,
+    md5: Option<Md5>,
+    crc32c: Option<u32>,
+    bytes: u64,
+}
+
+impl<W: Write> DigestWriter<W> {
+    pub fn new(inner: W, hashing: bool) -> DigestWriter<W> {
+        DigestWriter {
+            inner,
+            hasher: if hashing { Some(Sha256::new()) } else { None },
+            md5: None,
+            crc32c: None,
+            bytes: 0,
+        }
+    }
+
+    /// Also compute the md5 and/or crc32c, which only object stores still trade in.
+    ///
+    /// Only meaningful on a hashing writer, and before anything has been written.
+    pub fn also(mut self, md5: bool, crc32c: bool) -> DigestWriter<W> {
+        assert!(self.hasher.is_some() && 0 == self.bytes);
+        if md5 {
+            self.md5 = Some(Md5::new());
+        }
+        if crc32c {
+            self.crc32c = Some(0);
+        }
+        self
+    }
+
+    /// The inner writer, and the digest, if one was being computed.
+    pub fn finish(self) -> (W, Option<Digest>) {
+        let bytes = self.bytes;
+        let md5 = self.md5.map(|hasher| hasher.finalize().into());
+        let crc32c = self.crc32c;
+        let digest = self.hasher.map(|hasher| Digest {
+            sha256: hasher.finalize().into(),
+            bytes,
+            md5,
+            crc32c,
+        });
+        (self.inner, digest)
+    }
+}
+
+impl<W: Write> Write for DigestWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..written]);
+        }
+        if let Some(hasher) = &mut self.md5 {
+            hasher.update(&buf[..written]);
+        }
+        if let Some(crc) = &mut self.crc32c {
+            *crc = crc32c::crc32c_append(*crc, &buf[..written]);
+        }
+        self.bytes += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[test]
+fn test_digest_writer() {
+    let mut writer = DigestWriter::new(Vec::new(), true);
+    writer.write_all(b"ab").unwrap();
+    writer.write_all(b"c").unwrap();
+    let (inner, digest) = writer.finish();
+    assert_eq!(b"abc".to_vec(), inner);
+    let digest = digest.unwrap();
+    assert_eq!(3, digest.bytes);
+    assert_eq!(
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+        digest.sha256_hex()
+    );
+
+    let mut writer = DigestWriter::new(Vec::new(), true).also(true, true);
+    writer.write_all(b"ab").unwrap();
+    writer.write_all(b"c").unwrap();
+    let digest = writer.finish().1.unwrap();
+    assert_eq!(
+        "900150983cd24fb0d6963f7d28e17f72",
+        hex(&digest.md5.unwrap())
+    );
+    assert_eq!(Some(0x364b3fb7), digest.crc32c);
+
+    let (_, digest) = DigestWriter::new(io::sink(), false).finish();
+    assert!(digest.is_none());
+}
+
+#[test]
+fn test_digest_writer_streams() {
+    /// Counts bytes, and checks each write arrives in the same size it was made.
+    struct Counting(u64);
+    impl Write for Counting {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0 += buf.len() as u64;
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let len = 16 << 20;
+    let mut writer = DigestWriter::new(Counting(0), true);
+    let chunk = vec![0u8; 128 * 1024];
+    let mut sent = 0;
+    while sent < len {
+        let n = chunk.len().min(len - sent);
+        writer.write_all(&chunk[..n]).unwrap();
+        sent += n;
+        // nothing is held back: everything written has already reached the inner writer
+        assert_eq!(sent as u64, writer.inner.0);
+    }
+
+    let (inner, digest) = writer.finish();
+    assert_eq!(len as u64, inner.0);
+    assert_eq!(
+        "080acf35a507ac9849cfcba47dc2ad83e01b75663a516279c8b9d243b719643e",
+        digest.unwrap().sha256_hex()
+    );
+}
@@ -1,24 +1,41 @@
 use std::io;
+use std::path::Component;
 use std::path::Path;
 use std::path::PathBuf;
 
 use failure::err_msg;
+use failure::format_err;
 use failure::ResultExt;
 
+/// The absolute directory `path` is in, where its temporary file has to go to be renamed over it.
+///
+/// A root, `/`, `C:\` or `\\server\share\`, is its own directory. Relative paths are taken from
+/// `current_dir`, except on Windows for a drive-relative `C:out.bin`, which is from C:'s own
+/// current directory, and only Windows knows that.
 pub fn dir_of<F>(path: &Path, current_dir: F) -> Result<PathBuf, failure::Error>
 where
     F: FnOnce() -> Result<PathBuf, io::Error>,
 {
-    Ok(match path.parent() {
-        None => PathBuf::from("/"),
-        Some(path) if path.is_absolute() => path.to_path_buf(),
-        Some(path) => {
-            let mut cwd =
-                current_dir().with_context(|_| err_msg("determining current working directory"))?;
-            cwd.push(path);
-            cwd
-        }
-    })
+    let mut components = path.components();
+    let dir = match components.next_back() {
+        None | Some(Component::RootDir) | Some(Component::Prefix(_)) => path,
+        Some(_) => components.as_path(),
+    };
+
+    if dir.is_absolute() {
+        return Ok(dir.to_path_buf());
+    }
+
+    if let Some(Component::Prefix(_)) = dir.components().next() {
+        return Ok(std::path::absolute(dir)
+            .with_context(|_| format_err!("determining the current directory on {:?}", dir))?);
+    }
+
+    // `\out.bin`, on Windows, replaces all but the current directory's drive
+    let mut cwd =
+        current_dir().with_context(|_| err_msg("determining current working directory"))?;
+    cwd.push(dir);
+    Ok(cwd)
 }
 
 #[test]
@@ -42,4 +59,74 @@ fn test_dir_of() {
         PathBuf::from("/"),
         dir_of(Path::new("/bar"), || panic!()).unwrap()
     );
+
+    assert_eq!(
+        PathBuf::from("/foo"),
+        dir_of(Path::new("/foo/bar/"), || panic!()).unwrap()
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_dir_of_components() {
+    let root = dir_of(Path::new("/"), || panic!()).unwrap();
+    assert_eq!(
+        vec![Component::RootDir],
+        root.components().collect::<Vec<_>>()
+    );
+
+    // only a drive on Windows; here, a file name like any other
+    let cwd = dir_of(Path::new("C:out.bin"), || Ok(PathBuf::from("/home/quux"))).unwrap();
+    assert_eq!(
+        vec![
+            Component::RootDir,
+            Component::Normal("home".as_ref()),
+            Component::Normal("quux".as_ref())
+        ],
+        cwd.components().collect::<Vec<_>>()
+    );
+}
+
+#[cfg(windows)]
+#[test]
+fn test_dir_of_windows() {
+    assert_eq!(
+        PathBuf::from(r"C:\data"),
+        dir_of(Path::new(r"C:\data\out.bin"), || panic!()).unwrap()
+    );
+
+    assert_eq!(
+        PathBuf::from(r"C:\"),
+        dir_of(Path::new(r"C:\out.bin"), || panic!()).unwrap()
+    );
+
+    assert_eq!(
+        PathBuf::from(r"\\server\share\data"),
+        dir_of(Path::new(r"\\server\share\data\out.bin"), || panic!()).unwrap()
+    );
+
+    assert_eq!(
+        PathBuf::from(r"\\server\share\"),
+        dir_of(Path::new(r"\\server\share\out.bin"), || panic!()).unwrap()
+    );
+
+    assert_eq!(
+        PathBuf::from(r"C:\"),
+        dir_of(Path::new(r"C:\"), || panic!()).unwrap()
+    );
+
+    assert_eq!(
+        PathBuf::from(r"D:\"),
+        dir_of(Path::new(r"\out.bin"), || Ok(PathBuf::from(r"D:\work"))).unwrap()
+    );
+
+    assert_eq!(
+        PathBuf::from(r"D:\work\data"),
+        dir_of(Path::new(r"data\out.bin"), || Ok(PathBuf::from(r"D:\work"))).unwrap()
+    );
+
+    // C:'s current directory is whatever it is, but it's absolute, and on C:
+    let drive_relative = dir_of(Path::new(r"C:out.bin"), || panic!()).unwrap();
+    assert!(drive_relative.is_absolute(), "{:?}", drive_relative);
+    assert_eq!(std::path::absolute("C:").unwrap(), drive_relative);
 }
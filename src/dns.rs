@@ -0,0 +1,353 @@
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::ffi::CString;
+use std::fmt;
+use std::io;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::ptr;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use log::debug;
+use url::Host;
+use url::Url;
+
+/// How long a name that doesn't exist is remembered as not existing, at most; it might yet.
+const NEGATIVE_TTL: Duration = Duration::from_secs(5);
+
+/// Why a name didn't resolve, as far as the resolver will say.
+#[derive(Clone, Debug)]
+pub enum Why {
+    /// NXDOMAIN: there's no such name, and asking again won't make one.
+    NoSuchHost,
+    /// The name exists, but has no addresses we could connect to.
+    NoAddresses,
+    /// SERVFAIL, or the resolver couldn't be reached; maybe only for now.
+    Temporary(String),
+    /// Nothing came back within `--dns-timeout`.
+    TimedOut(Duration),
+    /// Anything else getaddrinfo had to say.
+    Other(String),
+}
+
+/// A host name that didn't resolve; `exit` counts it as a `Kind::Dns`.
+pub struct Failed {
+    pub host: String,
+    pub why: Why,
+}
+
+impl Failed {
+    /// Whether it's worth trying again, under `--retry`.
+    pub fn is_transient(&self) -> bool {
+        match self.why {
+            Why::Temporary(_) | Why::TimedOut(_) => true,
+            Why::NoSuchHost | Why::NoAddresses | Why::Other(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for Failed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.why {
+            Why::NoSuchHost => write!(f, "host {:?} doesn't exist (NXDOMAIN)", self.host),
+            Why::NoAddresses => write!(f, "host {:?} exists, but has no addresses", self.host),
+            Why::Temporary(detail) => write!(
+                f,
+                "looking up {:?} failed, maybe only for now: {}",
+                self.host, detail
+            ),
+            Why::TimedOut(after) => write!(
+                f,
+                "looking up {:?} timed out after {:?}, see --dns-timeout",
+                self.host, after
+            ),
+            Why::Other(detail) => write!(f, "looking up {:?} failed: {}", self.host, detail),
+        }
+    }
+}
+
+// main prints errors with Debug
+impl fmt::Debug for Failed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl failure::Fail for Failed {}
+
+/// The addresses `url`'s host resolves to, in the resolver's order, which is the order ureq
+/// tries them in; an address in the URL is taken as it is.
+///
+/// getaddrinfo can't be told to give up, so with a `timeout` it's asked on a thread of its own,
+/// which is left to finish alone if it's slower than that.
+pub fn resolve(url: &Url, timeout: Option<Duration>) -> Result<Vec<SocketAddr>, Failed> {
+    let port = url.port_or_known_default().unwrap_or(0);
+    let host = match url.host() {
+        Some(Host::Domain(host)) => host.to_string(),
+        Some(Host::Ipv4(ip)) => return Ok(vec![SocketAddr::new(ip.into(), port)]),
+        Some(Host::Ipv6(ip)) => return Ok(vec![SocketAddr::new(ip.into(), port)]),
+        None => String::new(),
+    };
+
+    let looked_up = match timeout {
+        None => lookup(&host, port),
+        Some(timeout) => {
+            let (send, recv) = mpsc::channel();
+            let name = host.clone();
+            thread::spawn(move || {
+                // nobody's listening for a late answer
+                let _ = send.send(lookup(&name, port));
+            });
+            match recv.recv_timeout(timeout) {
+                Ok(looked_up) => looked_up,
+                Err(_) => Err(Why::TimedOut(timeout)),
+            }
+        }
+    };
+
+    match looked_up {
+        Ok(addresses) if addresses.is_empty() => Err(Failed {
+            host,
+            why: Why::NoAddresses,
+        }),
+        Ok(addresses) => {
+            debug!(
+                "      resolved: {} to {}",
+                host,
+                addresses
+                    .iter()
+                    .map(|a| a.ip().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            Ok(addresses)
+        }
+        Err(why) => Err(Failed { host, why }),
+    }
+}
+
+/// How many of an attempt's lookups the `Cache` answered, and how many it had to make.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Lookups {
+    pub hits: u32,
+    pub misses: u32,
+}
+
+/// What a name was found to be, and when.
+struct Known {
+    at: Instant,
+    answer: Result<Vec<IpAddr>, Why>,
+}
+
+impl Known {
+    fn fresh(&self, ttl: Duration) -> bool {
+        let ttl = match self.answer {
+            Ok(_) => ttl,
+            Err(_) => ttl.min(NEGATIVE_TTL),
+        };
+        self.at.elapsed() < ttl
+    }
+}
+
+/// The answers to a run's lookups, shared by all its entries, for as long as `--dns-cache-ttl`
+/// says; and the names that don't exist, for a few seconds, if it's that long. A failure that
+/// might only be for now isn't kept, so `--retry` asks again.
+///
+/// Every lookup asks for any family, so the name's all there is to key on.
+#[derive(Clone, Default)]
+pub struct Cache {
+    known: Arc<Mutex<HashMap<String, Known>>>,
+}
+
+impl Cache {
+    /// `resolve`, unless `url`'s host was looked up within `ttl`; counted in `lookups`.
+    pub fn resolve(
+        &self,
+        url: &Url,
+        timeout: Option<Duration>,
+        ttl: Duration,
+        lookups: &mut Lookups,
+    ) -> Result<Vec<SocketAddr>, Failed> {
+        let host = match url.host() {
+            Some(Host::Domain(host)) => host.to_string(),
+            _ => return resolve(url, timeout),
+        };
+        let port = url.port_or_known_default().unwrap_or(0);
+        let with_port = |ips: &[IpAddr]| -> Vec<SocketAddr> {
+            ips.iter().map(|&ip| SocketAddr::new(ip, port)).collect()
+        };
+
+        let cached = self
+            .known
+            .lock()
+            .expect("dns cache poisoned")
+            .get(&host)
+            .filter(|known| known.fresh(ttl))
+            .map(|known| known.answer.clone());
+        if let Some(answer) = cached {
+            lookups.hits += 1;
+            debug!("      resolved: {} from the cache", host);
+            return match answer {
+                Ok(ips) => Ok(with_port(&ips)),
+                Err(why) => Err(Failed { host, why }),
+            };
+        }
+
+        // not held while looking up, so other entries' lookups aren't held up behind this one
+        lookups.misses += 1;
+        let looked_up = resolve(url, timeout);
+        let answer = match &looked_up {
+            Ok(addresses) => Ok(addresses.iter().map(SocketAddr::ip).collect()),
+            Err(e) if !e.is_transient() => Err(e.why.clone()),
+            Err(_) => return looked_up,
+        };
+        self.known.lock().expect("dns cache poisoned").insert(
+            host,
+            Known {
+                at: Instant::now(),
+                answer,
+            },
+        );
+        looked_up
+    }
+}
+
+fn lookup(host: &str, port: u16) -> Result<Vec<SocketAddr>, Why> {
+    let name = CString::new(host).map_err(|_| Why::NoSuchHost)?;
+    let mut hints: libc::addrinfo = unsafe { std::mem::zeroed() };
+    hints.ai_socktype = libc::SOCK_STREAM;
+    let mut found: *mut libc::addrinfo = ptr::null_mut();
+    let status = unsafe { libc::getaddrinfo(name.as_ptr(), ptr::null(), &hints, &mut found) };
+    if 0 != status {
+        return Err(why(status));
+    }
+
+    let mut addresses = Vec::new();
+    let mut next = found;
+    while let Some(info) = unsafe { next.as_ref() } {
+        if let Some(address) = socket_addr(info, port) {
+            if !addresses.contains(&address) {
+                addresses.push(address);
+            }
+        }
+        next = info.ai_next;
+    }
+    unsafe { libc::freeaddrinfo(found) };
+    Ok(addresses)
+}
+
+fn socket_addr(info: &libc::addrinfo, port: u16) -> Option<SocketAddr> {
+    let address = match info.ai_family {
+        libc::AF_INET => {
+            let sin = unsafe { &*(info.ai_addr as *const libc::sockaddr_in) };
+            SocketAddr::new(sin.sin_addr.s_addr.to_ne_bytes().into(), port)
+        }
+        libc::AF_INET6 => {
+            let sin6 = unsafe { &*(info.ai_addr as *const libc::sockaddr_in6) };
+            SocketAddr::new(sin6.sin6_addr.s6_addr.into(), port)
+        }
+        _ => return None,
+    };
+    Some(address)
+}
+
+fn why(status: libc::c_int) -> Why {
+    match status {
+        libc::EAI_NONAME => Why::NoSuchHost,
+        libc::EAI_NODATA => Why::NoAddresses,
+        libc::EAI_AGAIN | libc::EAI_FAIL => Why::Temporary(gai_message(status)),
+        libc::EAI_SYSTEM => Why::Other(io::Error::last_os_error().to_string()),
+        _ => Why::Other(gai_message(status)),
+    }
+}
+
+fn gai_message(status: libc::c_int) -> String {
+    unsafe { CStr::from_ptr(libc::gai_strerror(status)) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[test]
+fn test_resolve() {
+    let literal = Url::parse("http://[::1]:8080/").unwrap();
+    assert_eq!(
+        vec!["[::1]:8080".parse::<SocketAddr>().unwrap()],
+        resolve(&literal, None).unwrap()
+    );
+
+    let local = Url::parse("https://localhost/").unwrap();
+    let addresses = resolve(&local, Some(Duration::from_secs(10))).unwrap();
+    assert!(addresses
+        .iter()
+        .all(|a| a.ip().is_loopback() && 443 == a.port()));
+}
+
+#[test]
+fn test_cache() {
+    let cache = Cache::default();
+    let minute = Duration::from_secs(60);
+    let mut lookups = Lookups::default();
+    let local = Url::parse("https://localhost/").unwrap();
+    let first = cache.resolve(&local, None, minute, &mut lookups).unwrap();
+    let other_port = Url::parse("http://localhost:8080/").unwrap();
+    let second = cache
+        .resolve(&other_port, None, minute, &mut lookups)
+        .unwrap();
+    assert_eq!(Lookups { hits: 1, misses: 1 }, lookups);
+    assert_eq!(
+        first.iter().map(SocketAddr::ip).collect::<Vec<_>>(),
+        second.iter().map(SocketAddr::ip).collect::<Vec<_>>()
+    );
+    assert!(second.iter().all(|a| 8080 == a.port()));
+
+    cache
+        .resolve(&local, None, Duration::from_secs(0), &mut lookups)
+        .unwrap();
+    assert_eq!(Lookups { hits: 1, misses: 2 }, lookups);
+
+    // literals aren't looked up at all
+    let literal = Url::parse("http://127.0.0.1/").unwrap();
+    cache.resolve(&literal, None, minute, &mut lookups).unwrap();
+    assert_eq!(Lookups { hits: 1, misses: 2 }, lookups);
+
+    // a name that doesn't exist is only remembered briefly
+    cache.known.lock().unwrap().insert(
+        "gone.example.com".to_string(),
+        Known {
+            at: Instant::now(),
+            answer: Err(Why::NoSuchHost),
+        },
+    );
+    let gone = Url::parse("https://gone.example.com/").unwrap();
+    let failed = cache
+        .resolve(&gone, None, minute, &mut lookups)
+        .unwrap_err();
+    assert!(matches!(failed.why, Why::NoSuchHost));
+    assert_eq!(Lookups { hits: 2, misses: 2 }, lookups);
+    let known = &mut cache.known.lock().unwrap();
+    let known = known.get_mut("gone.example.com").unwrap();
+    known.at -= NEGATIVE_TTL;
+    assert!(!known.fresh(minute));
+    known.answer = Ok(vec![]);
+    assert!(known.fresh(minute));
+}
+
+#[test]
+fn test_failed() {
+    let failed = |why| Failed {
+        host: "example.com".to_string(),
+        why,
+    };
+    assert!(failed(Why::TimedOut(Duration::from_secs(2))).is_transient());
+    assert!(failed(Why::Temporary("SERVFAIL".to_string())).is_transient());
+    assert!(!failed(Why::NoSuchHost).is_transient());
+    assert_eq!(
+        "host \"example.com\" doesn't exist (NXDOMAIN)",
+        failed(Why::NoSuchHost).to_string()
+    );
+}
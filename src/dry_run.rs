@@ -0,0 +1,97 @@
+use std::io;
+use std::io::Write;
+
+use crate::target;
+
+/// What `--dry-run` found a run would do, for printing in a fixed order, so two can be diffed.
+pub struct Plan {
+    pub method: String,
+    /// Without any credentials.
+    pub url: String,
+    /// As sent, in order; left empty if nothing would be.
+    pub headers: Vec<(String, String)>,
+    /// Anything which would be fetched first, like a `--checksum-url`.
+    pub first: Vec<String>,
+    pub decision: String,
+}
+
+impl Plan {
+    /// Nothing would be sent, so there's only the URL, and why.
+    pub fn skipped(method: &str, url: &str, decision: &str) -> Plan {
+        Plan {
+            method: method.to_string(),
+            url: url.to_string(),
+            headers: Vec::new(),
+            first: Vec::new(),
+            decision: format!("would skip: {}", decision),
+        }
+    }
+
+    /// Set `name` as ureq does: replacing any of the same name, whatever its case.
+    pub fn set(&mut self, name: &str, value: &str) {
+        self.headers.retain(|(k, _)| !k.eq_ignore_ascii_case(name));
+        self.headers.push((name.to_string(), value.to_string()));
+    }
+
+    /// With the headers ureq adds unless they're already there, at the front, as it puts them.
+    pub fn with_defaults(mut self, host: &str) -> Plan {
+        let mut defaults = Vec::new();
+        for (name, value) in &[("Host", host), ("User-Agent", "ureq"), ("Accept", "*/*")] {
+            if !self
+                .headers
+                .iter()
+                .any(|(k, _)| k.eq_ignore_ascii_case(name))
+            {
+                defaults.push((name.to_string(), value.to_string()));
+            }
+        }
+        defaults.append(&mut self.headers);
+        self.headers = defaults;
+        self
+    }
+
+    /// Values of sensitive headers are shown as `***`.
+    pub fn print<W: Write>(&self, mut out: W) -> io::Result<()> {
+        for first in &self.first {
+            writeln!(out, "first: GET {}", first)?;
+        }
+        writeln!(out, "{} {}", self.method, self.url)?;
+        for (name, value) in &self.headers {
+            if target::is_sensitive_header(name) {
+                writeln!(out, "{}: ***", name)?;
+            } else {
+                writeln!(out, "{}: {}", name, value)?;
+            }
+        }
+        writeln!(out, "decision: {}", self.decision)
+    }
+}
+
+#[test]
+fn test_print() {
+    let mut plan = Plan {
+        method: "GET".to_string(),
+        url: "https://example.com/a".to_string(),
+        headers: Vec::new(),
+        first: Vec::new(),
+        decision: "would send GET".to_string(),
+    };
+    plan.set("Authorization", "Basic c2VjcmV0");
+    plan.set("user-agent", "one");
+    plan.set("User-Agent", "two");
+    let plan = plan.with_defaults("example.com");
+
+    let mut out = Vec::new();
+    plan.print(&mut out).unwrap();
+    assert_eq!(
+        concat!(
+            "GET https://example.com/a\n",
+            "Host: example.com\n",
+            "Accept: */*\n",
+            "Authorization: ***\n",
+            "User-Agent: two\n",
+            "decision: would send GET\n",
+        ),
+        String::from_utf8(out).unwrap()
+    );
+}
@@ -0,0 +1,78 @@
+use std::env;
+use std::ffi::OsStr;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+use failure::format_err;
+use failure::ResultExt;
+use log::info;
+
+use crate::dir_of;
+use crate::sink;
+
+/// A response's status line and headers, as `curl -D` writes them, ending in a blank line.
+///
+/// ureq only keeps the names lowercased, so that's how they come out. Cookie values are
+/// replaced with `***` if `mask_cookies`, leaving the name and attributes.
+pub fn head(response: &ureq::Response, mask_cookies: bool) -> String {
+    let mut out = format!("{}\r\n", response.status_line());
+
+    // names repeat for repeated headers, so take each name's values in turn
+    let mut seen: Vec<String> = Vec::new();
+    for name in response.headers_names() {
+        let nth = seen.iter().filter(|n| **n == name).count();
+        let value = response.all(&name).get(nth).copied().unwrap_or_default();
+        if mask_cookies && "set-cookie" == name {
+            out.push_str(&format!("{}: {}\r\n", name, mask_cookie(value)));
+        } else {
+            out.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        seen.push(name);
+    }
+
+    out.push_str("\r\n");
+    out
+}
+
+/// `name=value; Path=/` to `name=***; Path=/`.
+fn mask_cookie(value: &str) -> String {
+    let (pair, attributes) = match value.find(';') {
+        Some(end) => value.split_at(end),
+        None => (value, ""),
+    };
+    match pair.find('=') {
+        Some(eq) => format!("{}=***{}", &pair[..eq], attributes),
+        None => format!("***{}", attributes),
+    }
+}
+
+/// Write the dumped heads to `dest`, replacing it atomically, or to stderr for `-`.
+pub fn write(dest: &OsStr, text: &str) -> Result<(), failure::Error> {
+    if dest == "-" {
+        io::stderr().write_all(text.as_bytes())?;
+        return Ok(());
+    }
+
+    let dest = Path::new(dest);
+    let dir = dir_of::dir_of(dest, env::current_dir)?;
+    let mut temp = sink::temp_in(&dir)?;
+    temp.write_all(text.as_bytes())
+        .with_context(|_| format_err!("writing headers for {:?}", dest))?;
+    temp.persist_by_rename(dest)
+        .map_err(|e| e.error)
+        .with_context(|_| format_err!("replacing {:?}", dest))?;
+
+    info!("  dump-headers: written to {:?}", dest);
+    Ok(())
+}
+
+#[test]
+fn test_mask_cookie() {
+    assert_eq!(
+        "id=***; Path=/; Secure",
+        mask_cookie("id=abc; Path=/; Secure")
+    );
+    assert_eq!("id=***", mask_cookie("id=abc"));
+    assert_eq!("***", mask_cookie("abc"));
+}
@@ -0,0 +1,61 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+
+use failure::format_err;
+use failure::ResultExt;
+use log::info;
+
+/// How much of an error response's body is worth keeping.
+const LIMIT: u64 = 64 * 1024;
+
+/// Save (the start of) an error response's body to `dest`, or stderr for `-`.
+pub fn save(response: impl Read, dest: &OsStr) -> Result<(), failure::Error> {
+    let mut body = Vec::new();
+    response
+        .take(LIMIT + 1)
+        .read_to_end(&mut body)
+        .with_context(|_| format_err!("reading error response body"))?;
+
+    let truncated = body.len() as u64 > LIMIT;
+    body.truncate(LIMIT as usize);
+
+    if dest == "-" {
+        let stderr = io::stderr();
+        let mut stderr = stderr.lock();
+        stderr.write_all(&summarise(&body))?;
+        if truncated {
+            writeln!(stderr, "\n[error body truncated after {} bytes]", LIMIT)?;
+        }
+        return Ok(());
+    }
+
+    fs::write(dest, &body).with_context(|_| format_err!("writing error body to {:?}", dest))?;
+    if truncated {
+        info!("error body: truncated after {} bytes", LIMIT);
+    }
+
+    Ok(())
+}
+
+/// Text passes through untouched; anything else is escaped, so it can't mess up a terminal.
+fn summarise(body: &[u8]) -> Vec<u8> {
+    match std::str::from_utf8(body) {
+        Ok(text) if !text.contains(|c: char| c.is_control() && !c.is_whitespace()) => body.to_vec(),
+        _ => body
+            .iter()
+            .flat_map(|&b| std::ascii::escape_default(b))
+            .collect(),
+    }
+}
+
+#[test]
+fn test_summarise() {
+    assert_eq!(
+        b"{\"error\": \"expired\"}\n".to_vec(),
+        summarise(b"{\"error\": \"expired\"}\n")
+    );
+    assert_eq!(b"\\x1f\\x8b\\x00ab".to_vec(), summarise(b"\x1f\x8b\x00ab"));
+}
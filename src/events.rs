@@ -0,0 +1,222 @@
+//! `--events`: what a run does, as it does it, as JSON objects on lines of their own on stdout.
+//!
+//! Every event has `event`, its name; `sequence`, counting up from 1 across the whole run, so
+//! from every entry of a batch; `timestamp`, as RFC 3339; and in a batch, `entry`, the line it
+//! came from, for telling apart those running at once. Then, by name:
+//!
+//! - `start`: `url`, and `output`, `null` if it's named by the response; once per attempt.
+//! - `decision`: `decision`, `skip`, `conditional` or `unconditional`, and `reason`, for a skip:
+//!   `min-age`, `no-clobber`, `stored` or `compare-url`.
+//! - `response`: `status`, and `headers`, an object of those in `HEADERS` it had, lowercased.
+//! - `progress`: `bytes` of the body so far, `total`, if it's known, and `rate`, in bytes a
+//!   second since the body started; at most one a second.
+//! - `verified`: `checks`, from `sha256` and `storage`, and the body's `sha256`, in hex.
+//! - `persisted`: `output`, once it's in place.
+//! - `done`: `outcome`, as in `--json`, and `error`, `null` or its kind.
+
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::json::string;
+
+/// The response headers worth passing on.
+pub const HEADERS: &[&str] = &["Content-Type", "Content-Length", "ETag", "Last-Modified"];
+
+/// Where events go; clones share the sequence. The default is off, and says nothing.
+#[derive(Clone, Default)]
+pub struct Events {
+    /// The last sequence number used, held while writing so the lines come out in order.
+    sequence: Option<Arc<Mutex<u64>>>,
+    entry: Option<usize>,
+}
+
+impl Events {
+    pub fn stdout() -> Events {
+        Events {
+            sequence: Some(Arc::new(Mutex::new(0))),
+            entry: None,
+        }
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.sequence.is_some()
+    }
+
+    /// The same stream, for the batch entry from line `entry`.
+    pub fn for_entry(&self, entry: usize) -> Events {
+        Events {
+            sequence: self.sequence.clone(),
+            entry: Some(entry),
+        }
+    }
+
+    /// `fields` are already JSON, as `value` makes them.
+    pub fn emit(&self, event: &str, fields: &[(&str, String)]) {
+        let sequence = match &self.sequence {
+            Some(sequence) => sequence,
+            None => return,
+        };
+        let mut sequence = sequence.lock().expect("poisoned");
+        *sequence += 1;
+        let line = line(
+            event,
+            *sequence,
+            &chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            self.entry,
+            fields,
+        );
+        // a supervisor that's stopped listening is no reason to fail the download
+        let mut stdout = io::stdout().lock();
+        let _ = stdout
+            .write_all(line.as_bytes())
+            .and_then(|()| stdout.flush());
+    }
+
+    /// For counting the body's bytes, of `total`, if that's known.
+    pub fn progress(&self, total: Option<u64>) -> Progress<'_> {
+        Progress {
+            events: self,
+            bytes: 0,
+            total,
+            started: Instant::now(),
+            last: None,
+        }
+    }
+}
+
+/// A JSON string, or `null`.
+pub fn value(s: Option<&str>) -> String {
+    s.map_or_else(|| "null".to_string(), string)
+}
+
+fn line(
+    event: &str,
+    sequence: u64,
+    timestamp: &str,
+    entry: Option<usize>,
+    fields: &[(&str, String)],
+) -> String {
+    let mut line = format!(
+        "{{\"event\":{},\"sequence\":{},\"timestamp\":{}",
+        string(event),
+        sequence,
+        string(timestamp)
+    );
+    if let Some(entry) = entry {
+        line.push_str(&format!(",\"entry\":{}", entry));
+    }
+    for (key, value) in fields {
+        line.push_str(&format!(",{}:{}", string(key), value));
+    }
+    line.push_str("}\n");
+    line
+}
+
+/// The body's bytes so far, and when they were last reported.
+pub struct Progress<'e> {
+    events: &'e Events,
+    bytes: u64,
+    total: Option<u64>,
+    started: Instant,
+    last: Option<Instant>,
+}
+
+impl<'e> Progress<'e> {
+    fn add(&mut self, bytes: usize) {
+        self.bytes += bytes as u64;
+        let now = Instant::now();
+        if !self.events.is_on()
+            || self
+                .last
+                .is_some_and(|last| now.duration_since(last) < Duration::from_secs(1))
+        {
+            return;
+        }
+        self.last = Some(now);
+        let seconds = now.duration_since(self.started).as_secs_f64();
+        let rate = if seconds > 0.0 {
+            (self.bytes as f64 / seconds) as u64
+        } else {
+            0
+        };
+        self.events.emit(
+            "progress",
+            &[
+                ("bytes", self.bytes.to_string()),
+                (
+                    "total",
+                    self.total
+                        .map_or_else(|| "null".to_string(), |t| t.to_string()),
+                ),
+                ("rate", rate.to_string()),
+            ],
+        );
+    }
+
+    /// Count up to `bytes` in all, for a body that's arriving other than through `reader`.
+    pub fn reached(&mut self, bytes: u64) {
+        if bytes > self.bytes {
+            self.add((bytes - self.bytes) as usize);
+        }
+    }
+
+    /// The body's bytes so far.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Reads from `inner`, counting what comes through.
+    pub fn reader<R: Read>(&mut self, inner: R) -> Counted<'_, 'e, R> {
+        Counted {
+            inner,
+            progress: self,
+        }
+    }
+}
+
+pub struct Counted<'p, 'e, R> {
+    inner: R,
+    progress: &'p mut Progress<'e>,
+}
+
+impl<R: Read> Read for Counted<'_, '_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.progress.add(read);
+        Ok(read)
+    }
+}
+
+#[test]
+fn test_line() {
+    assert_eq!(
+        concat!(
+            "{\"event\":\"start\",\"sequence\":3,\"timestamp\":\"2019-10-03T12:00:00.000Z\",",
+            "\"entry\":2,\"url\":\"https://example.com/\",\"output\":null}\n"
+        ),
+        line(
+            "start",
+            3,
+            "2019-10-03T12:00:00.000Z",
+            Some(2),
+            &[
+                ("url", value(Some("https://example.com/"))),
+                ("output", value(None))
+            ]
+        )
+    );
+}
+
+#[test]
+fn test_progress_counts() {
+    let events = Events::default();
+    let mut progress = events.progress(Some(5));
+    let mut out = Vec::new();
+    io::copy(&mut progress.reader(&b"hello"[..]), &mut out).unwrap();
+    assert_eq!(5, progress.bytes);
+}
@@ -0,0 +1,205 @@
+use std::fmt;
+use std::io;
+
+use crate::dns;
+use crate::lock;
+
+/// The exit status for a failure that isn't one of the kinds below.
+pub const FAILURE: i32 = 1;
+
+/// With `--fail-if-unchanged`, when there was nothing new; well clear of the failures.
+pub const UNCHANGED: i32 = 100;
+
+/// For `--help`; `lock::ALREADY_RUNNING` and `UNCHANGED` are there too.
+pub const STATUSES: &str = "EXIT STATUS:
+    0   done, including when there was nothing new
+    1   failed, for any reason not listed here
+    2   bad arguments, or bad settings from the config file or environment
+    3   the host name didn't resolve
+    4   couldn't connect, or the connection failed part way
+    5   TLS failed, as far as can be told
+    6   timed out
+    7   the server said no: a 4xx status
+    8   the server failed: a 5xx, or any other status we can't use
+    9   reading or writing local files failed
+    10  the download didn't verify: a checksum, size or validation check
+    75  another run holds the lock
+    100 with --fail-if-unchanged, there was nothing new: a 304, a skip, or the same content";
+
+/// The run went fine, but `--fail-if-unchanged` wants to hear that nothing changed.
+pub struct Unchanged;
+
+impl fmt::Display for Unchanged {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "nothing changed, see --fail-if-unchanged")
+    }
+}
+
+impl fmt::Debug for Unchanged {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl failure::Fail for Unchanged {}
+
+/// Ways of failing that get their own exit status.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Usage,
+    Dns,
+    Connect,
+    Tls,
+    Timeout,
+    Status(u16),
+    Filesystem,
+    Verification,
+    /// `--quota` had no room for the download.
+    Quota,
+}
+
+impl Kind {
+    /// For `--json`'s report.
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Usage => "usage",
+            Kind::Dns => "dns",
+            Kind::Connect => "connect",
+            Kind::Tls => "tls",
+            Kind::Timeout => "timeout",
+            Kind::Status(400..=499) => "client-error",
+            Kind::Status(_) => "server-error",
+            Kind::Filesystem => "filesystem",
+            Kind::Verification => "verification",
+            Kind::Quota => "quota",
+        }
+    }
+
+    pub fn code(self) -> i32 {
+        match self {
+            Kind::Usage => 2,
+            Kind::Dns => 3,
+            Kind::Connect => 4,
+            Kind::Tls => 5,
+            Kind::Timeout => 6,
+            Kind::Status(400..=499) => 7,
+            Kind::Status(_) => 8,
+            Kind::Filesystem => 9,
+            Kind::Verification => 10,
+            Kind::Quota => 11,
+        }
+    }
+}
+
+/// An error that knows its `Kind`; otherwise just its message.
+pub struct Classified {
+    pub kind: Kind,
+    message: String,
+}
+
+impl fmt::Display for Classified {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl fmt::Debug for Classified {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl failure::Fail for Classified {}
+
+pub fn classified<S: Into<String>>(kind: Kind, message: S) -> failure::Error {
+    Classified {
+        kind,
+        message: message.into(),
+    }
+    .into()
+}
+
+/// The exit status for the error that ended a run, given how far it got.
+///
+/// Anything marked with a `Kind` wins, as does a `dns::Failed`; then an `io::Error` anywhere in the chain is a network
+/// failure if it looks like one, and a local one otherwise; and then anything that went wrong
+/// while verifying the download is a verification failure.
+pub fn code(e: &failure::Error, phase: &str) -> i32 {
+    if e.downcast_ref::<lock::Held>().is_some() {
+        return lock::ALREADY_RUNNING;
+    }
+    if e.downcast_ref::<Unchanged>().is_some() {
+        return UNCHANGED;
+    }
+    kind(e, phase).map_or(FAILURE, Kind::code)
+}
+
+/// The error's kind, for reports: a `Kind`'s name, `locked` for `lock::Held`, or `other`.
+pub fn kind_name(e: &failure::Error, phase: &str) -> &'static str {
+    if e.downcast_ref::<lock::Held>().is_some() {
+        return "locked";
+    }
+    kind(e, phase).map_or("other", Kind::name)
+}
+
+/// The `Kind` behind `code`, if there is one.
+pub fn kind(e: &failure::Error, phase: &str) -> Option<Kind> {
+    if let Some(c) = e.iter_chain().find_map(|f| f.downcast_ref::<Classified>()) {
+        return Some(c.kind);
+    }
+    if e.iter_chain()
+        .any(|f| f.downcast_ref::<dns::Failed>().is_some())
+    {
+        return Some(Kind::Dns);
+    }
+    if let Some(io) = e.iter_chain().find_map(|f| f.downcast_ref::<io::Error>()) {
+        return Some(io_kind(io));
+    }
+    if "verifying" == phase {
+        return Some(Kind::Verification);
+    }
+    None
+}
+
+pub fn io_kind(e: &io::Error) -> Kind {
+    use io::ErrorKind::*;
+    match e.kind() {
+        TimedOut | WouldBlock => Kind::Timeout,
+        ConnectionRefused | ConnectionReset | ConnectionAborted | NotConnected | BrokenPipe
+        | UnexpectedEof => Kind::Connect,
+        _ => Kind::Filesystem,
+    }
+}
+
+#[test]
+fn test_code() {
+    use failure::ResultExt;
+
+    let wrapped = Err::<(), _>(classified(Kind::Status(404), "unhappy response"))
+        .with_context(|_| "requesting")
+        .unwrap_err()
+        .into();
+    assert_eq!(7, code(&wrapped, "checking the response"));
+    assert_eq!(8, code(&classified(Kind::Status(503), ""), ""));
+    assert_eq!(8, code(&classified(Kind::Status(101), ""), ""));
+
+    let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+    let denied = Err::<(), _>(denied)
+        .with_context(|_| "creating temporary file")
+        .unwrap_err()
+        .into();
+    assert_eq!(9, code(&denied, "preparing"));
+    assert_eq!(
+        6,
+        code(&io::Error::from(io::ErrorKind::TimedOut).into(), "")
+    );
+    assert_eq!(
+        4,
+        code(&io::Error::from(io::ErrorKind::ConnectionReset).into(), "")
+    );
+
+    assert_eq!(10, code(&failure::err_msg("sha256 mismatch"), "verifying"));
+    assert_eq!(1, code(&failure::err_msg("odd"), "downloading"));
+    assert_eq!(75, code(&lock::Held.into(), "preparing"));
+    assert_eq!(100, code(&Unchanged.into(), "checking the response"));
+}
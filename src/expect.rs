@@ -0,0 +1,322 @@
+use failure::bail;
+use log::debug;
+
+/// The `type/subtype` of a Content-Type header, lowercased, without parameters.
+pub fn media_type(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+fn is_html_type(media_type: &str) -> bool {
+    "text/html" == media_type || "application/xhtml+xml" == media_type
+}
+
+/// Does the start of a body look like an HTML document?
+pub fn sniffs_as_html(prefix: &[u8]) -> bool {
+    let text = String::from_utf8_lossy(prefix);
+    let text = text.trim_start_matches('\u{feff}').trim_start();
+    let start: String = text
+        .chars()
+        .take(14)
+        .collect::<String>()
+        .to_ascii_lowercase();
+    start.starts_with("<!doctype html") || start.starts_with("<html")
+}
+
+/// Is the user actually after an HTML page, going by `--expect-content-type` or the URL?
+fn wants_html(expected: &[&str], url_path: &str) -> bool {
+    if !expected.is_empty() {
+        return expected.iter().any(|p| {
+            let p = media_type(p);
+            p != "*" && p != "*/*" && pattern_matches(&p, Some("text/html"))
+        });
+    }
+
+    let last = url_path.rsplit('/').next().unwrap_or_default();
+    if last.is_empty() {
+        // a directory-style URL, which is an index page more often than not
+        return true;
+    }
+
+    match last.rfind('.') {
+        Some(dot) => ["html", "htm", "xhtml", "shtml"]
+            .contains(&last[dot + 1..].to_ascii_lowercase().as_str()),
+        None => false,
+    }
+}
+
+/// `--reject-html`: fail if an HTML page arrived when something else was expected.
+pub fn reject_html(
+    expected: &[&str],
+    url_path: &str,
+    content_type: Option<&str>,
+    prefix: &[u8],
+) -> Result<(), failure::Error> {
+    if wants_html(expected, url_path) {
+        debug!("    html check: skipped, expecting html");
+        return Ok(());
+    }
+
+    let claims = content_type
+        .map(|t| is_html_type(&media_type(t)))
+        .unwrap_or(false);
+    let sniffs = sniffs_as_html(prefix);
+
+    debug!(
+        "    html check: Content-Type {:?} says html: {}, body sniffs as html: {}",
+        content_type, claims, sniffs
+    );
+
+    if claims || sniffs {
+        bail!(
+            "received an HTML page, probably an error or a login portal \
+             (Content-Type: {:?}, body looks like html: {})",
+            content_type,
+            sniffs
+        );
+    }
+
+    Ok(())
+}
+
+fn pattern_matches(pattern: &str, actual: Option<&str>) -> bool {
+    let pattern = media_type(pattern);
+    if pattern == "*" || pattern == "*/*" {
+        return true;
+    }
+
+    let actual = match actual {
+        Some(actual) => media_type(actual),
+        None => return false,
+    };
+
+    match pattern.strip_suffix("/*") {
+        Some(main) => actual.split('/').next() == Some(main),
+        None => pattern == actual,
+    }
+}
+
+/// Check the response's Content-Type against any of the `--expect-content-type` patterns.
+pub fn content_type(expected: &[&str], actual: Option<&str>) -> Result<(), failure::Error> {
+    if expected.is_empty() || expected.iter().any(|p| pattern_matches(p, actual)) {
+        return Ok(());
+    }
+
+    match actual {
+        Some(actual) => bail!(
+            "unexpected Content-Type: got {:?}, expected one of {:?}",
+            actual,
+            expected
+        ),
+        None => bail!(
+            "response had no Content-Type, expected one of {:?}",
+            expected
+        ),
+    }
+}
+
+/// A `--require-header`: `Name` for presence, or `Name: value` for an exact value.
+pub struct HeaderRequirement<'s> {
+    name: &'s str,
+    value: Option<&'s str>,
+}
+
+impl<'s> HeaderRequirement<'s> {
+    pub fn parse(arg: &'s str) -> HeaderRequirement<'s> {
+        match arg.find(':') {
+            Some(colon) => {
+                let (name, value) = arg.split_at(colon);
+                let value = &value[1..];
+                HeaderRequirement {
+                    name: name.trim(),
+                    value: Some(value.strip_prefix(' ').unwrap_or(value)),
+                }
+            }
+            None => HeaderRequirement {
+                name: arg.trim(),
+                value: None,
+            },
+        }
+    }
+}
+
+/// Check every requirement against the response, reporting all of the failures together.
+///
+/// `lookup` returns all the values of a (case-insensitive) header name.
+pub fn headers<'r, F>(requirements: &[HeaderRequirement], lookup: F) -> Result<(), failure::Error>
+where
+    F: Fn(&str) -> Vec<&'r str>,
+{
+    let mut failures = Vec::new();
+
+    for requirement in requirements {
+        let actual = lookup(requirement.name);
+        match requirement.value {
+            None if actual.is_empty() => {
+                failures.push(format!("{}: missing", requirement.name));
+            }
+            Some(expected) if !actual.contains(&expected) => {
+                failures.push(format!(
+                    "{}: expected {:?}, got {:?}",
+                    requirement.name, expected, actual
+                ));
+            }
+            _ => (),
+        }
+    }
+
+    if !failures.is_empty() {
+        bail!(
+            "response failed header requirements:\n  {}",
+            failures.join("\n  ")
+        );
+    }
+
+    Ok(())
+}
+
+/// `--min-size` and `--max-shrink`: catch truncated exports that still came back as a 200.
+pub fn size(
+    new_len: u64,
+    old_len: Option<u64>,
+    min_size: Option<u64>,
+    max_shrink_percent: Option<u64>,
+) -> Result<(), failure::Error> {
+    if let Some(min_size) = min_size {
+        if new_len < min_size {
+            bail!(
+                "download failed --min-size: new size {} bytes, old size {:?} bytes, minimum {} bytes",
+                new_len,
+                old_len,
+                min_size
+            );
+        }
+    }
+
+    if let (Some(percent), Some(old_len)) = (max_shrink_percent, old_len) {
+        if u128::from(new_len) * 100 < u128::from(old_len) * u128::from(percent) {
+            bail!(
+                "download failed --max-shrink: new size {} bytes, old size {} bytes, \
+                 must be at least {}% of the old size",
+                new_len,
+                old_len,
+                percent
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Fail if a response's headers are larger, or more numerous, than is plausible.
+pub fn header_limits(
+    response: &ureq::Response,
+    max_bytes: u64,
+    max_count: usize,
+) -> Result<(), failure::Error> {
+    let mut names = response.headers_names();
+    let count = names.len();
+
+    names.sort();
+    names.dedup();
+
+    // as they appeared on the wire: "name: value\r\n"
+    let bytes: u64 = names
+        .iter()
+        .flat_map(|name| {
+            response
+                .all(name)
+                .into_iter()
+                .map(move |value| (name.len() + value.len() + 4) as u64)
+        })
+        .sum();
+
+    if count > max_count {
+        bail!(
+            "response headers exceed limit: {} headers, limit {}",
+            count,
+            max_count
+        );
+    }
+
+    if bytes > max_bytes {
+        bail!(
+            "response headers exceed limit: {} bytes, limit {}",
+            bytes,
+            max_bytes
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_size() {
+    assert!(size(10, None, None, None).is_ok());
+    assert!(size(10, None, Some(10), None).is_ok());
+    assert!(size(9, Some(100), Some(10), None).is_err());
+    assert!(size(50, Some(100), None, Some(50)).is_ok());
+    assert!(size(49, Some(100), None, Some(50)).is_err());
+    assert!(size(49, None, None, Some(50)).is_ok());
+}
+
+#[test]
+fn test_headers() {
+    let lookup = |name: &str| match name.to_ascii_lowercase().as_str() {
+        "server" => vec!["artifactory"],
+        "x-checksum-sha256" => vec!["abc"],
+        _ => vec![],
+    };
+
+    let reqs = |args: &[&'static str]| -> Vec<HeaderRequirement<'static>> {
+        args.iter().map(|a| HeaderRequirement::parse(a)).collect()
+    };
+
+    assert!(headers(&reqs(&["X-Checksum-Sha256", "server: artifactory"]), lookup).is_ok());
+
+    let err = headers(&reqs(&["Server: nginx", "X-Missing", "Server"]), lookup)
+        .err()
+        .unwrap()
+        .to_string();
+    assert!(err.contains("Server: expected \"nginx\""), "{}", err);
+    assert!(err.contains("X-Missing: missing"), "{}", err);
+}
+
+#[test]
+fn test_content_type() {
+    assert!(content_type(&[], None).is_ok());
+    assert!(content_type(&["application/gzip"], Some("application/gzip")).is_ok());
+    assert!(content_type(&["application/gzip"], Some("Application/GZip; q=1")).is_ok());
+    assert!(content_type(&["text/*"], Some("text/plain; charset=utf-8")).is_ok());
+    assert!(content_type(&["text/*"], Some("texts/plain")).is_err());
+    assert!(content_type(
+        &["application/gzip", "application/x-gzip"],
+        Some("application/x-gzip")
+    )
+    .is_ok());
+    assert!(content_type(&["application/gzip"], Some("text/html")).is_err());
+    assert!(content_type(&["application/gzip"], None).is_err());
+    assert!(content_type(&["*"], None).is_ok());
+    assert!(content_type(&["*/*"], None).is_ok());
+}
+
+#[test]
+fn test_reject_html() {
+    let page = b"\n  <!DOCTYPE html>\n<html><head>";
+    assert!(sniffs_as_html(page));
+    assert!(sniffs_as_html(b"<HTML>"));
+    assert!(!sniffs_as_html(b"\x1f\x8b\x08"));
+
+    assert!(reject_html(&[], "/foo.tar.gz", Some("application/gzip"), b"\x1f\x8b").is_ok());
+    assert!(reject_html(&[], "/foo.tar.gz", Some("text/html; charset=utf-8"), b"").is_err());
+    assert!(reject_html(&[], "/foo.tar.gz", None, page).is_err());
+
+    assert!(reject_html(&[], "/index.html", Some("text/html"), page).is_ok());
+    assert!(reject_html(&[], "/docs/", Some("text/html"), page).is_ok());
+    assert!(reject_html(&["text/*"], "/report", Some("text/html"), page).is_ok());
+    assert!(reject_html(&["*"], "/report", Some("text/html"), page).is_err());
+}
@@ -0,0 +1,1059 @@
+use std::env;
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use failure::bail;
+use failure::format_err;
+use failure::ResultExt;
+use log::debug;
+use log::info;
+use log::warn;
+
+use crate::cache;
+use crate::dir_of;
+use crate::outcome::Outcome;
+use crate::output;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Tar,
+    TarGz,
+    TarXz,
+    TarBz2,
+    TarZst,
+    Zip,
+}
+
+pub const FORMATS: &[&str] = &[
+    "auto", "tar", "tar.gz", "tar.xz", "tar.bz2", "tar.zst", "zip",
+];
+
+/// What `--extract` asked for: a known format, or `None` to work it out from the archive.
+pub fn from_arg(arg: &str) -> Result<Option<Format>, failure::Error> {
+    let format = match arg {
+        "auto" => return Ok(None),
+        "tar" => Format::Tar,
+        "tar.gz" => Format::TarGz,
+        "tar.xz" => Format::TarXz,
+        "tar.bz2" => Format::TarBz2,
+        "tar.zst" => Format::TarZst,
+        "zip" => Format::Zip,
+        other => bail!("unrecognised --extract format: {:?}", other),
+    };
+    supported(format)?;
+    Ok(Some(format))
+}
+
+fn supported(format: Format) -> Result<(), failure::Error> {
+    match format {
+        Format::TarXz if !cfg!(feature = "xz") => {
+            bail!("built without xz support (feature \"xz\")")
+        }
+        Format::TarBz2 if !cfg!(feature = "bzip2") => {
+            bail!("built without bzip2 support (feature \"bzip2\")")
+        }
+        Format::TarZst => {
+            bail!("there's no zstd decoder in this build, so tar.zst can't be extracted")
+        }
+        _ => Ok(()),
+    }
+}
+
+/// From the first bytes of the archive, as `--extract auto` does.
+fn detect(start: &[u8]) -> Option<Format> {
+    if start.starts_with(&[0x1f, 0x8b]) {
+        Some(Format::TarGz)
+    } else if start.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0]) {
+        Some(Format::TarXz)
+    } else if start.starts_with(b"BZh") {
+        Some(Format::TarBz2)
+    } else if start.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Some(Format::TarZst)
+    } else if start.starts_with(b"PK\x03\x04") || start.starts_with(b"PK\x05\x06") {
+        Some(Format::Zip)
+    } else if start.get(257..262) == Some(b"ustar") {
+        Some(Format::Tar)
+    } else {
+        None
+    }
+}
+
+/// What's known of the tree at `output`, for deciding whether to ask: the `OUTPUT.meta` that
+/// was written beside it when it was extracted, whose mtime is the archive's.
+///
+/// A directory that's there without one wasn't made by `--extract`, so it's only replaced if
+/// it's empty.
+pub fn recorded(output: &Path) -> Result<Option<fs::Metadata>, failure::Error> {
+    match fs::symlink_metadata(output) {
+        Ok(metadata) if metadata.is_dir() => (),
+        Ok(_) => bail!(
+            "--extract needs a directory to extract into, and {:?} isn't one",
+            output
+        ),
+        Err(ref e) if io::ErrorKind::NotFound == e.kind() => return Ok(None),
+        Err(e) => Err(e).with_context(|_| format_err!("reading {:?}'s info", output))?,
+    }
+    let meta = cache::meta_path(output);
+    match fs::metadata(&meta) {
+        Ok(metadata) => Ok(Some(metadata)),
+        Err(ref e) if io::ErrorKind::NotFound == e.kind() => {
+            let empty = fs::read_dir(output)
+                .with_context(|_| format_err!("listing {:?}", output))?
+                .next()
+                .is_none();
+            if !empty {
+                bail!(
+                    "{:?} isn't empty, and has no {:?} to say it was extracted here, so it's not replaced",
+                    output,
+                    meta
+                );
+            }
+            Ok(None)
+        }
+        Err(e) => Err(e).with_context(|_| format_err!("reading {:?}'s info", meta))?,
+    }
+}
+
+/// Record the archive in `OUTPUT.meta`, dated as the archive is, so the next run can ask
+/// whether it's changed.
+pub fn record(
+    output: &Path,
+    url: &str,
+    outcome: &Outcome,
+    mtime: Option<SystemTime>,
+) -> Result<(), failure::Error> {
+    cache::record(output, url, outcome, None)?;
+    if let Some(mtime) = mtime {
+        let meta = cache::meta_path(output);
+        if let Err(e) = filetime::set_file_mtime(&meta, mtime.into()) {
+            warn!("failed to set {:?}'s time: {:?}", meta, e);
+        }
+    }
+    Ok(())
+}
+
+/// Extract the archive that's been downloaded into `archive` into a new directory beside
+/// `output`, then swap that in for whatever's there.
+///
+/// Nothing of the old tree's touched until the new one's complete, so a corrupt or truncated
+/// archive, or an entry that would land outside the directory, leaves it as it was.
+pub fn install(
+    format: Option<Format>,
+    archive: &fs::File,
+    output: &Path,
+) -> Result<(), failure::Error> {
+    let mut file = archive
+        .try_clone()
+        .with_context(|_| format_err!("reopening the archive"))?;
+    file.seek(SeekFrom::Start(0))?;
+    let format = match format {
+        Some(format) => format,
+        None => {
+            let mut start = Vec::with_capacity(512);
+            (&mut file).take(512).read_to_end(&mut start)?;
+            file.seek(SeekFrom::Start(0))?;
+            let format = detect(&start).ok_or_else(|| {
+                format_err!("can't tell what kind of archive this is; give --extract FORMAT")
+            })?;
+            supported(format)?;
+            format
+        }
+    };
+    debug!("       extract: as {:?}", format);
+
+    let dir = dir_of::dir_of(output, env::current_dir)?;
+    let name = output
+        .file_name()
+        .ok_or_else(|| format_err!("{:?} has no name to extract beside", output))?
+        .to_string_lossy()
+        .into_owned();
+    let unique = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    let staging = dir.join(format!(
+        ".{}.{}-{}.extracting",
+        name,
+        std::process::id(),
+        unique
+    ));
+    let aside = dir.join(format!(".{}.{}-{}.old", name, std::process::id(), unique));
+    fs::create_dir(&staging).with_context(|_| format_err!("creating {:?}", staging))?;
+
+    let mut tree = Tree::new(&staging);
+    let extracted = extract(format, file, &mut tree).and_then(|()| tree.finish());
+    let entries = match extracted {
+        Ok(entries) => entries,
+        Err(e) => {
+            if let Err(e) = fs::remove_dir_all(&staging) {
+                warn!(
+                    "couldn't remove the partial extraction {:?}: {}",
+                    staging, e
+                );
+            }
+            return Err(e.context("extracting the archive").into());
+        }
+    };
+    info!("       extract: {} entries", entries);
+
+    swap(&staging, output, &aside)?;
+    output::sync_directory(&dir);
+    Ok(())
+}
+
+fn extract(format: Format, file: fs::File, tree: &mut Tree) -> Result<(), failure::Error> {
+    let mut reader = io::BufReader::new(file);
+    match format {
+        Format::Tar => tar(&mut reader, tree),
+        Format::TarGz => tar(
+            &mut io::BufReader::new(flate2::bufread::MultiGzDecoder::new(reader)),
+            tree,
+        ),
+        #[cfg(feature = "xz")]
+        Format::TarXz => tar(
+            &mut io::BufReader::new(xz2::bufread::XzDecoder::new(reader)),
+            tree,
+        ),
+        #[cfg(feature = "bzip2")]
+        Format::TarBz2 => tar(
+            &mut io::BufReader::new(bzip2::bufread::MultiBzDecoder::new(reader)),
+            tree,
+        ),
+        Format::Zip => zip(&mut reader.into_inner(), tree),
+        other => unreachable!("checked supported: {:?}", other),
+    }
+}
+
+/// Move the old tree aside, the new one in, and then remove the old; if the new one can't go
+/// in, the old one's put back.
+fn swap(staging: &Path, output: &Path, aside: &Path) -> Result<(), failure::Error> {
+    let previous = match fs::symlink_metadata(output) {
+        Ok(metadata) if metadata.is_dir() => {
+            fs::rename(output, aside)
+                .with_context(|_| format_err!("moving {:?} aside, to {:?}", output, aside))?;
+            true
+        }
+        Ok(_) => bail!("{:?} isn't a directory, so isn't replaced", output),
+        Err(ref e) if io::ErrorKind::NotFound == e.kind() => false,
+        Err(e) => Err(e).with_context(|_| format_err!("reading {:?}'s info", output))?,
+    };
+
+    if let Err(e) = fs::rename(staging, output) {
+        if previous {
+            if let Err(e) = fs::rename(aside, output) {
+                warn!(
+                    "couldn't put the previous tree back from {:?}: {}",
+                    aside, e
+                );
+            }
+        }
+        if let Err(e) = fs::remove_dir_all(staging) {
+            warn!("couldn't remove the extraction {:?}: {}", staging, e);
+        }
+        return Err(e).with_context(|_| format_err!("renaming {:?} to {:?}", staging, output))?;
+    }
+    info!("       extract: {:?} replaced", output);
+
+    // the new tree's in place, so this is only tidying
+    if previous {
+        if let Err(e) = fs::remove_dir_all(aside) {
+            warn!("couldn't remove the previous tree, at {:?}: {}", aside, e);
+        }
+    }
+    Ok(())
+}
+
+/// Where an archive's entries are written: under `root`, and nowhere else.
+struct Tree {
+    root: PathBuf,
+    /// Given their modes at the end, so one that isn't writable can still be filled.
+    dirs: Vec<(PathBuf, u32)>,
+    /// Under the root, looked at again once everything's there.
+    links: Vec<PathBuf>,
+    entries: u64,
+}
+
+impl Tree {
+    fn new(root: &Path) -> Tree {
+        Tree {
+            root: root.to_path_buf(),
+            dirs: Vec::new(),
+            links: Vec::new(),
+            entries: 0,
+        }
+    }
+
+    /// Where `name` goes under the root, with its parents made; refusing absolute names, `..`,
+    /// and anywhere that's through a symlink, so no entry can be written outside the root; and
+    /// the root itself, where replacing what's there would take everything extracted with it.
+    fn place(&self, name: &Path) -> Result<PathBuf, failure::Error> {
+        let relative = relative(name)?;
+        if relative.as_os_str().is_empty() {
+            bail!("refusing {:?}, which would be the directory itself", name);
+        }
+        let mut at = self.root.clone();
+        let mut parts = relative.components().peekable();
+        while let Some(part) = parts.next() {
+            at.push(part);
+            match fs::symlink_metadata(&at) {
+                Ok(metadata) if metadata.file_type().is_symlink() && parts.peek().is_some() => {
+                    bail!("refusing {:?}, which is through the symlink {:?}", name, at)
+                }
+                Ok(metadata) if !metadata.is_dir() && parts.peek().is_some() => {
+                    bail!("refusing {:?}, which is inside a file", name)
+                }
+                Ok(_) => (),
+                Err(ref e) if io::ErrorKind::NotFound == e.kind() => {
+                    if parts.peek().is_some() {
+                        fs::create_dir(&at).with_context(|_| format_err!("creating {:?}", at))?;
+                    }
+                }
+                Err(e) => Err(e).with_context(|_| format_err!("reading {:?}'s info", at))?,
+            }
+        }
+        Ok(at)
+    }
+
+    /// The last of an archive's entries for a name is the one that's kept, as with tar.
+    fn clear(at: &Path) -> Result<(), failure::Error> {
+        match fs::symlink_metadata(at) {
+            Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(at),
+            Ok(_) => fs::remove_file(at),
+            Err(ref e) if io::ErrorKind::NotFound == e.kind() => Ok(()),
+            Err(e) => Err(e),
+        }
+        .with_context(|_| format_err!("replacing {:?}", at))?;
+        Ok(())
+    }
+
+    fn dir(&mut self, name: &Path, mode: u32) -> Result<(), failure::Error> {
+        self.entries += 1;
+        if relative(name)?.as_os_str().is_empty() {
+            return Ok(());
+        }
+        let at = self.place(name)?;
+        match fs::symlink_metadata(&at) {
+            Ok(metadata) if metadata.is_dir() => (),
+            _ => {
+                Tree::clear(&at)?;
+                fs::create_dir(&at).with_context(|_| format_err!("creating {:?}", at))?;
+            }
+        }
+        self.dirs.push((at, mode & 0o777));
+        Ok(())
+    }
+
+    fn file(
+        &mut self,
+        name: &Path,
+        mode: u32,
+        mtime: Option<SystemTime>,
+        data: &mut dyn Read,
+        size: u64,
+    ) -> Result<(), failure::Error> {
+        self.entries += 1;
+        let at = self.place(name)?;
+        Tree::clear(&at)?;
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&at)
+            .with_context(|_| format_err!("creating {:?}", at))?;
+        let copied = io::copy(data, &mut file).with_context(|_| format_err!("writing {:?}", at))?;
+        if copied != size {
+            bail!(
+                "the archive ends inside {:?}, after {} of its {} bytes",
+                name,
+                copied,
+                size
+            );
+        }
+        file.set_permissions(fs::Permissions::from_mode(mode & 0o777))
+            .with_context(|_| format_err!("setting {:?}'s mode", at))?;
+        if let Some(mtime) = mtime {
+            let time = filetime::FileTime::from(mtime);
+            if let Err(e) = filetime::set_file_handle_times(&file, None, Some(time)) {
+                warn!("failed to set {:?}'s time: {:?}", at, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Only a relative link that stays under the root is made, and not one that goes through
+    /// another symlink to get there.
+    fn symlink(&mut self, name: &Path, target: &Path) -> Result<(), failure::Error> {
+        self.entries += 1;
+        let link = relative(name)?;
+        if !self.resolves_inside(&link, target, false) {
+            bail!(
+                "refusing the symlink {:?} -> {:?}, which points outside the directory",
+                name,
+                target
+            );
+        }
+        let at = self.place(name)?;
+        Tree::clear(&at)?;
+        std::os::unix::fs::symlink(target, &at)
+            .with_context(|_| format_err!("creating the symlink {:?}", at))?;
+        self.links.push(link);
+        Ok(())
+    }
+
+    /// Whether `target`, from a link at `link` under the root, resolves to under the root, in
+    /// the tree as it's been extracted so far. Without `follow`, going through a symlink is
+    /// refused outright; with it, they're followed, as the kernel would.
+    fn resolves_inside(&self, link: &Path, target: &Path, follow: bool) -> bool {
+        // as the kernel's ELOOP
+        const MAX_HOPS: usize = 40;
+        // what's still to be walked, last first; ".." can't be a name, so it stands for itself
+        fn unwalked(pending: &mut Vec<OsString>, target: &Path) -> bool {
+            let start = pending.len();
+            for component in target.components() {
+                match component {
+                    Component::Normal(_) | Component::ParentDir => {
+                        pending.push(component.as_os_str().to_os_string())
+                    }
+                    Component::CurDir => (),
+                    Component::RootDir | Component::Prefix(_) => return false,
+                }
+            }
+            pending[start..].reverse();
+            !target.as_os_str().is_empty()
+        }
+
+        let mut at: Vec<OsString> = link
+            .parent()
+            .into_iter()
+            .flat_map(Path::components)
+            .map(|c| c.as_os_str().to_os_string())
+            .collect();
+        let mut pending = Vec::new();
+        if !unwalked(&mut pending, target) {
+            return false;
+        }
+        let mut hops = 0;
+        while let Some(part) = pending.pop() {
+            if ".." == part {
+                if at.pop().is_none() {
+                    return false;
+                }
+                continue;
+            }
+            at.push(part);
+            let here = self.root.join(at.iter().collect::<PathBuf>());
+            match fs::symlink_metadata(&here) {
+                Ok(metadata) if metadata.file_type().is_symlink() => (),
+                _ => continue,
+            }
+            // a link to a link is fine; it's going through one that can lead out
+            if !follow {
+                if pending.is_empty() {
+                    continue;
+                }
+                return false;
+            }
+            hops += 1;
+            at.pop();
+            match fs::read_link(&here) {
+                Ok(next) if hops <= MAX_HOPS && unwalked(&mut pending, &next) => (),
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    /// To a file that's already been extracted.
+    fn hard_link(&mut self, name: &Path, target: &Path) -> Result<(), failure::Error> {
+        self.entries += 1;
+        let original = self.place(target)?;
+        match fs::symlink_metadata(&original) {
+            Ok(metadata) if metadata.is_file() => (),
+            _ => bail!(
+                "refusing the hard link {:?} -> {:?}, which isn't to a file in the archive",
+                name,
+                target
+            ),
+        }
+        let at = self.place(name)?;
+        Tree::clear(&at)?;
+        fs::hard_link(&original, &at)
+            .with_context(|_| format_err!("creating the hard link {:?}", at))?;
+        Ok(())
+    }
+
+    /// Each link's looked at again, following the others, as one made later can be something
+    /// an earlier one goes through; then the directories' modes, deepest first. How many
+    /// entries there were.
+    fn finish(&mut self) -> Result<u64, failure::Error> {
+        for link in &self.links {
+            // replaced since by a later entry
+            let target = match fs::read_link(self.root.join(link)) {
+                Ok(target) => target,
+                Err(_) => continue,
+            };
+            if !self.resolves_inside(link, &target, true) {
+                bail!(
+                    "refusing the symlink {:?} -> {:?}, which leads outside the directory through another",
+                    link,
+                    target
+                );
+            }
+        }
+        for (dir, mode) in self.dirs.iter().rev() {
+            fs::set_permissions(dir, fs::Permissions::from_mode(*mode))
+                .with_context(|_| format_err!("setting {:?}'s mode", dir))?;
+        }
+        Ok(self.entries)
+    }
+}
+
+/// `name` as a path under the root, without any `.`; an error if it's absolute or has a `..`.
+fn relative(name: &Path) -> Result<PathBuf, failure::Error> {
+    let mut relative = PathBuf::new();
+    for component in name.components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir => (),
+            _ => bail!("refusing {:?}, which would be outside the directory", name),
+        }
+    }
+    Ok(relative)
+}
+
+const BLOCK: usize = 512;
+
+/// A ustar, GNU or pax archive: regular files, directories, and links, with GNU's and pax's
+/// long names; anything else is skipped, with a warning.
+fn tar(reader: &mut dyn Read, tree: &mut Tree) -> Result<(), failure::Error> {
+    let mut long_name: Option<Vec<u8>> = None;
+    let mut long_link: Option<Vec<u8>> = None;
+    let mut long_size: Option<u64> = None;
+    loop {
+        let mut header = [0u8; BLOCK];
+        reader
+            .read_exact(&mut header)
+            .with_context(|_| format_err!("the archive ends without its end marker"))?;
+        if header.iter().all(|&b| 0 == b) {
+            return Ok(());
+        }
+        let expected = number(&header[148..156])?;
+        let actual: u64 = header
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| if (148..156).contains(&i) { b' ' } else { b } as u64)
+            .sum();
+        if expected != actual {
+            bail!(
+                "a tar header is corrupt: its checksum's {}, not {}",
+                expected,
+                actual
+            );
+        }
+
+        let size = match long_size.take() {
+            Some(size) => size,
+            None => number(&header[124..136])?,
+        };
+        let name = match long_name.take() {
+            Some(name) => name,
+            None if &header[257..263] == b"ustar\0" && 0 != header[345] => {
+                let mut name = field(&header[345..500]).to_vec();
+                name.push(b'/');
+                name.extend_from_slice(field(&header[..100]));
+                name
+            }
+            None => field(&header[..100]).to_vec(),
+        };
+        let link = long_link
+            .take()
+            .unwrap_or_else(|| field(&header[157..257]).to_vec());
+        let name = Path::new(OsStr::from_bytes(&name));
+        let link = Path::new(OsStr::from_bytes(&link));
+        let mode = number(&header[100..108])? as u32;
+        let mtime = Some(SystemTime::UNIX_EPOCH + Duration::from_secs(number(&header[136..148])?));
+
+        let mut data = (&mut *reader).take(size);
+        match header[156] {
+            // as old tars gave directories
+            b'0' | 0 if name.as_os_str().as_bytes().ends_with(b"/") => tree.dir(name, mode)?,
+            b'0' | b'7' | 0 => tree.file(name, mode, mtime, &mut data, size)?,
+            b'5' => tree.dir(name, mode)?,
+            b'2' => tree.symlink(name, link)?,
+            b'1' => tree.hard_link(name, link)?,
+            b'L' => long_name = Some(trailer(&mut data)?),
+            b'K' => long_link = Some(trailer(&mut data)?),
+            b'x' => {
+                for (key, value) in pax(&trailer(&mut data)?)? {
+                    match key.as_str() {
+                        "path" => long_name = Some(value),
+                        "linkpath" => long_link = Some(value),
+                        "size" => {
+                            long_size = Some(
+                                String::from_utf8_lossy(&value)
+                                    .parse()
+                                    .map_err(|_| format_err!("a pax header's size is corrupt"))?,
+                            )
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            b'g' => (),
+            other => warn!(
+                "skipping {:?}, a kind of entry ({:?}) that isn't extracted",
+                name, other as char
+            ),
+        }
+        io::copy(&mut data, &mut io::sink())?;
+        if data.limit() > 0 {
+            bail!("the archive ends inside {:?}", name);
+        }
+        let padding = (BLOCK - (size % BLOCK as u64) as usize) % BLOCK;
+        reader
+            .read_exact(&mut header[..padding])
+            .with_context(|_| format_err!("the archive ends inside {:?}", name))?;
+    }
+}
+
+/// A header's field, up to its first NUL.
+fn field(bytes: &[u8]) -> &[u8] {
+    let end = bytes.iter().position(|&b| 0 == b).unwrap_or(bytes.len());
+    &bytes[..end]
+}
+
+/// Octal, space or NUL padded, or GNU's base-256 for what doesn't fit.
+fn number(bytes: &[u8]) -> Result<u64, failure::Error> {
+    if 0 != bytes[0] & 0x80 {
+        return Ok(bytes[1..]
+            .iter()
+            .fold(u64::from(bytes[0] & 0x7f), |n, &b| (n << 8) | u64::from(b)));
+    }
+    let text = String::from_utf8_lossy(field(bytes));
+    let text = text.trim_matches(|c| ' ' == c);
+    if text.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(text, 8).map_err(|_| format_err!("a tar header is corrupt: {:?}", text))
+}
+
+/// The most a long name, or a pax header, may be, as their size is only what the header says.
+const MAX_TRAILER: u64 = 1 << 20;
+
+/// The body of a long name, or of a pax header, without the padding NUL at its end.
+fn trailer<R: Read>(data: &mut io::Take<R>) -> Result<Vec<u8>, failure::Error> {
+    if data.limit() > MAX_TRAILER {
+        bail!(
+            "a long name or pax header is too big, at {} bytes",
+            data.limit()
+        );
+    }
+    let mut text = Vec::new();
+    data.read_to_end(&mut text)?;
+    while text.last() == Some(&0) {
+        text.pop();
+    }
+    Ok(text)
+}
+
+/// pax's `LENGTH KEY=VALUE\n` records, the length counting the whole record.
+fn pax(mut text: &[u8]) -> Result<Vec<(String, Vec<u8>)>, failure::Error> {
+    let corrupt = || format_err!("a pax header is corrupt");
+    let mut records = Vec::new();
+    while !text.is_empty() {
+        let space = text.iter().position(|&b| b' ' == b).ok_or_else(corrupt)?;
+        let len: usize = String::from_utf8_lossy(&text[..space])
+            .parse()
+            .map_err(|_| corrupt())?;
+        if len <= space + 1 || len > text.len() || b'\n' != text[len - 1] {
+            bail!(corrupt());
+        }
+        let record = &text[space + 1..len - 1];
+        let equals = record.iter().position(|&b| b'=' == b).ok_or_else(corrupt)?;
+        records.push((
+            String::from_utf8_lossy(&record[..equals]).into_owned(),
+            record[equals + 1..].to_vec(),
+        ));
+        text = &text[len..];
+    }
+    Ok(records)
+}
+
+const END_OF_DIRECTORY: u32 = 0x0605_4b50;
+const CENTRAL: u32 = 0x0201_4b50;
+const LOCAL: u32 = 0x0403_4b50;
+const S_IFMT: u32 = 0o170_000;
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+/// A zip of stored and deflated entries, as its central directory lists them; without zip64,
+/// or encryption. Each entry's CRC is checked as it's written.
+fn zip(file: &mut fs::File, tree: &mut Tree) -> Result<(), failure::Error> {
+    let len = file.seek(SeekFrom::End(0))?;
+    let tail_len = len.min(22 + 0xffff);
+    file.seek(SeekFrom::Start(len - tail_len))?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail)?;
+    let end = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&i| END_OF_DIRECTORY == u32_at(&tail, i))
+        .ok_or_else(|| format_err!("the zip has no end of central directory"))?;
+    let end = &tail[end..];
+    let (count, size, offset) = (u16_at(end, 10), u32_at(end, 12), u32_at(end, 16));
+    if 0xffff == count || 0xffff_ffff == offset {
+        bail!("zip64 archives aren't supported");
+    }
+    // it says where it is, and how big, so that's checked before anything's allocated for it
+    if u64::from(offset) + u64::from(size) > len {
+        bail!("the zip's central directory is said to be past the end of it");
+    }
+
+    let mut central = vec![0; size as usize];
+    file.seek(SeekFrom::Start(offset.into()))?;
+    file.read_exact(&mut central)
+        .with_context(|_| format_err!("the zip's central directory is truncated"))?;
+    let mut at = 0;
+    for _ in 0..count {
+        let corrupt = || format_err!("the zip's central directory is corrupt");
+        let entry = central.get(at..at + 46).ok_or_else(corrupt)?;
+        if CENTRAL != u32_at(entry, 0) {
+            bail!(corrupt());
+        }
+        let made_by = u16_at(entry, 4);
+        let flags = u16_at(entry, 8);
+        let method = u16_at(entry, 10);
+        let (time, date) = (u16_at(entry, 12), u16_at(entry, 14));
+        let crc = u32_at(entry, 16);
+        let (compressed, size) = (u32_at(entry, 20), u32_at(entry, 24));
+        let name_len = u16_at(entry, 28) as usize;
+        let skip = name_len + u16_at(entry, 30) as usize + u16_at(entry, 32) as usize;
+        let external = u32_at(entry, 38);
+        let local = u32_at(entry, 42);
+        let name = central
+            .get(at + 46..at + 46 + name_len)
+            .ok_or_else(corrupt)?;
+        let name = Path::new(OsStr::from_bytes(name));
+        at += 46 + skip;
+
+        if 0 != flags & 1 {
+            bail!("{:?} is encrypted", name);
+        }
+        if [compressed, size, local].contains(&0xffff_ffff) {
+            bail!("zip64 archives aren't supported");
+        }
+        // only unix zips say what a mode is, and whether an entry's a link
+        let mode = if 3 == made_by >> 8 { external >> 16 } else { 0 };
+        let is_dir = name.as_os_str().as_bytes().ends_with(b"/") || 0o040_000 == mode & S_IFMT;
+        if is_dir {
+            tree.dir(name, if 0 == mode { 0o755 } else { mode })?;
+            continue;
+        }
+
+        let mut header = [0; 30];
+        file.seek(SeekFrom::Start(local.into()))?;
+        file.read_exact(&mut header)
+            .with_context(|_| format_err!("the archive ends inside {:?}", name))?;
+        if LOCAL != u32_at(&header, 0) {
+            bail!("{:?}'s local header is corrupt", name);
+        }
+        let data_at =
+            u64::from(local) + 30 + u64::from(u16_at(&header, 26)) + u64::from(u16_at(&header, 28));
+        if data_at + u64::from(compressed) > len {
+            bail!("the archive ends inside {:?}", name);
+        }
+        file.seek(SeekFrom::Start(data_at))?;
+        let raw = (&mut *file).take(compressed.into());
+        let data: Box<dyn Read + '_> = match method {
+            0 => Box::new(raw),
+            8 => Box::new(flate2::read::DeflateDecoder::new(raw)),
+            other => bail!(
+                "{:?} is compressed in a way that isn't supported ({})",
+                name,
+                other
+            ),
+        };
+        let mut data = flate2::CrcReader::new(data);
+
+        if 0o120_000 == mode & S_IFMT {
+            let mut target = Vec::new();
+            (&mut data).take(4096).read_to_end(&mut target)?;
+            if crc != data.crc().sum() {
+                bail!("{:?} is corrupt: its CRC doesn't match", name);
+            }
+            tree.symlink(name, Path::new(OsStr::from_bytes(&target)))?;
+        } else {
+            let mode = if 0 == mode { 0o644 } else { mode };
+            tree.file(name, mode, dos_time(date, time), &mut data, size.into())?;
+            if crc != data.crc().sum() {
+                bail!("{:?} is corrupt: its CRC doesn't match", name);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// MS-DOS's two second resolution local time; taken as UTC, as there's no saying which zone.
+fn dos_time(date: u16, time: u16) -> Option<SystemTime> {
+    let date = chrono::NaiveDate::from_ymd_opt(
+        1980 + i32::from(date >> 9),
+        u32::from((date >> 5) & 0xf),
+        u32::from(date & 0x1f),
+    )?;
+    let time = date.and_hms_opt(
+        u32::from(time >> 11),
+        u32::from((time >> 5) & 0x3f),
+        2 * u32::from(time & 0x1f),
+    )?;
+    Some(time.and_utc().into())
+}
+
+#[cfg(test)]
+fn tar_entry(kind: u8, name: &str, link: &str, data: &[u8]) -> Vec<u8> {
+    let mut header = [0u8; BLOCK];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[100..107].copy_from_slice(b"0000644");
+    header[124..135].copy_from_slice(format!("{:011o}", data.len()).as_bytes());
+    header[136..147].copy_from_slice(b"14000000000");
+    header[148..156].copy_from_slice(b"        ");
+    header[156] = kind;
+    header[157..157 + link.len()].copy_from_slice(link.as_bytes());
+    header[257..263].copy_from_slice(b"ustar\0");
+    let sum: u64 = header.iter().map(|&b| u64::from(b)).sum();
+    header[148..155].copy_from_slice(format!("{:06o}\0", sum).as_bytes());
+    let mut entry = header.to_vec();
+    entry.extend_from_slice(data);
+    entry.resize(entry.len() + (BLOCK - data.len() % BLOCK) % BLOCK, 0);
+    entry
+}
+
+#[test]
+fn test_relative() {
+    assert_eq!(Path::new("a/b"), relative(Path::new("./a/./b")).unwrap());
+    assert_eq!(Path::new(""), relative(Path::new("./")).unwrap());
+    assert!(relative(Path::new("/etc/passwd")).is_err());
+    assert!(relative(Path::new("a/../../b")).is_err());
+
+    let dir = tempfile::tempdir().unwrap();
+    let tree = Tree::new(dir.path());
+    let inside =
+        |link: &str, target: &str| tree.resolves_inside(Path::new(link), Path::new(target), true);
+    assert!(inside("a/b", "../c"));
+    assert!(inside("a", "./b/c"));
+    assert!(!inside("a/b", "../../c"));
+    assert!(!inside("a", "b/../../c"));
+    assert!(!inside("a", "/etc"));
+    assert!(!inside("a", ""));
+
+    // through links that are there: only followed, and then only while it stays inside
+    fs::create_dir_all(dir.path().join("a/b")).unwrap();
+    std::os::unix::fs::symlink("../..", dir.path().join("a/b/c")).unwrap();
+    std::os::unix::fs::symlink("a/b", dir.path().join("up")).unwrap();
+    assert!(!tree.resolves_inside(Path::new("x"), Path::new("up/c"), false));
+    assert!(tree.resolves_inside(Path::new("x"), Path::new("up/c"), true));
+    assert!(!inside("x", "a/b/c/.."));
+    assert!(tree.resolves_inside(Path::new("x"), Path::new("up"), false));
+    std::os::unix::fs::symlink("loop", dir.path().join("loop")).unwrap();
+    assert!(!inside("x", "loop/y"));
+}
+
+#[test]
+fn test_tar() {
+    let dir = tempfile::tempdir().unwrap();
+    let extract = |name: &str, entries: &[Vec<u8>]| {
+        let root = dir.path().join(name);
+        fs::create_dir(&root).unwrap();
+        let mut archive = entries.concat();
+        archive.extend_from_slice(&[0; 2 * BLOCK]);
+        let mut tree = Tree::new(&root);
+        tar(&mut archive.as_slice(), &mut tree).and_then(|()| tree.finish())
+    };
+
+    let long = format!("{}/file", "d".repeat(120));
+    let good = [
+        tar_entry(b'5', "top/", "", b""),
+        tar_entry(b'0', "top/a.txt", "", b"hello"),
+        tar_entry(b'2', "top/link", "a.txt", b""),
+        tar_entry(b'1', "top/hard", "top/a.txt", b""),
+        tar_entry(b'L', "././@LongLink", "", long.as_bytes()),
+        tar_entry(b'0', "ignored", "", b"long"),
+    ];
+    assert_eq!(5, extract("good", &good).unwrap());
+    let root = dir.path().join("good");
+    assert_eq!("hello", fs::read_to_string(root.join("top/a.txt")).unwrap());
+    assert_eq!("hello", fs::read_to_string(root.join("top/hard")).unwrap());
+    assert_eq!(
+        Path::new("a.txt"),
+        fs::read_link(root.join("top/link")).unwrap()
+    );
+    assert_eq!("long", fs::read_to_string(root.join(&long)).unwrap());
+    let mtime = fs::metadata(root.join("top/a.txt"))
+        .unwrap()
+        .modified()
+        .unwrap();
+    assert_eq!(
+        SystemTime::UNIX_EPOCH + Duration::from_secs(0o14000000000),
+        mtime
+    );
+
+    for (name, entries) in &[
+        ("absolute", vec![tar_entry(b'0', "/tmp/evil", "", b"x")]),
+        ("parent", vec![tar_entry(b'0', "a/../../evil", "", b"x")]),
+        ("escape", vec![tar_entry(b'2', "a/link", "../../evil", b"")]),
+        (
+            "through",
+            vec![
+                tar_entry(b'2', "link", ".", b""),
+                tar_entry(b'0', "link/evil", "", b"x"),
+            ],
+        ),
+        (
+            "through a link",
+            vec![
+                tar_entry(b'2', "a/b/c", "../..", b""),
+                tar_entry(b'2', "escape", "a/b/c/..", b""),
+            ],
+        ),
+        (
+            "through a later link",
+            vec![
+                tar_entry(b'2', "x", "d/..", b""),
+                tar_entry(b'2', "d", ".", b""),
+            ],
+        ),
+    ] {
+        let err = extract(name, entries).unwrap_err();
+        assert!(err.to_string().starts_with("refusing"), "{}: {}", name, err);
+    }
+
+    // the root's only ever a directory entry, and isn't cleared for anything else
+    for (name, entry) in &[
+        ("dot", tar_entry(b'0', ".", "", b"x")),
+        ("dot slash", tar_entry(b'7', "./", "", b"x")),
+        ("link at the root", tar_entry(b'2', "./", "kept", b"")),
+        ("hard link at the root", tar_entry(b'1', ".", "kept", b"")),
+    ] {
+        let entries = [tar_entry(b'0', "kept", "", b"kept"), entry.clone()];
+        let err = extract(name, &entries).unwrap_err();
+        assert!(err.to_string().starts_with("refusing"), "{}: {}", name, err);
+        let kept = dir.path().join(name).join("kept");
+        assert_eq!("kept", fs::read_to_string(kept).unwrap(), "{}", name);
+    }
+    for (name, entry) in &[
+        ("dot dir", tar_entry(b'5', ".", "", b"")),
+        ("old dot dir", tar_entry(b'0', "./", "", b"")),
+    ] {
+        let entries = [tar_entry(b'0', "kept", "", b"kept"), entry.clone()];
+        assert_eq!(2, extract(name, &entries).unwrap(), "{}", name);
+        let kept = dir.path().join(name).join("kept");
+        assert_eq!("kept", fs::read_to_string(kept).unwrap(), "{}", name);
+    }
+
+    let mut truncated = tar_entry(b'0', "a", "", &[b'x'; 1000]);
+    truncated.truncate(BLOCK + 100);
+    let root = dir.path().join("truncated");
+    fs::create_dir(&root).unwrap();
+    assert!(tar(&mut truncated.as_slice(), &mut Tree::new(&root)).is_err());
+
+    assert_eq!(
+        vec![("path".to_string(), b"a b".to_vec())],
+        pax(b"12 path=a b\n").unwrap()
+    );
+    assert!(pax(b"99 path=a\n").is_err());
+}
+
+#[test]
+fn test_detect() {
+    assert_eq!(Some(Format::TarGz), detect(&[0x1f, 0x8b, 8]));
+    assert_eq!(Some(Format::Zip), detect(b"PK\x03\x04"));
+    assert_eq!(Some(Format::TarZst), detect(&[0x28, 0xb5, 0x2f, 0xfd]));
+    assert_eq!(Some(Format::Tar), detect(&tar_entry(b'0', "a", "", b"")));
+    assert_eq!(None, detect(b"<html>"));
+    assert!(from_arg("tar.zst").is_err());
+}
+
+#[test]
+fn test_zip() {
+    use std::io::Write;
+
+    fn stored(name: &str, data: &[u8]) -> Vec<u8> {
+        let mut crc = flate2::Crc::new();
+        crc.update(data);
+        let sizes = [crc.sum(), data.len() as u32, data.len() as u32];
+        let mut local = LOCAL.to_le_bytes().to_vec();
+        local.extend_from_slice(&[20, 0, 0, 0, 0, 0, 0, 0, 0x21, 0]);
+        sizes
+            .iter()
+            .for_each(|v| local.extend_from_slice(&v.to_le_bytes()));
+        local.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        local.extend_from_slice(&[0, 0]);
+        local.extend_from_slice(name.as_bytes());
+        local.extend_from_slice(data);
+
+        let mut central = CENTRAL.to_le_bytes().to_vec();
+        central.extend_from_slice(&[20, 3, 20, 0, 0, 0, 0, 0, 0, 0, 0x21, 0]);
+        sizes
+            .iter()
+            .for_each(|v| central.extend_from_slice(&v.to_le_bytes()));
+        central.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        central.extend_from_slice(&[0; 8]);
+        central.extend_from_slice(&(0o100_640u32 << 16).to_le_bytes());
+        central.extend_from_slice(&0u32.to_le_bytes());
+        central.extend_from_slice(name.as_bytes());
+
+        let mut end = END_OF_DIRECTORY.to_le_bytes().to_vec();
+        end.extend_from_slice(&[0, 0, 0, 0, 1, 0, 1, 0]);
+        end.extend_from_slice(&(central.len() as u32).to_le_bytes());
+        end.extend_from_slice(&(local.len() as u32).to_le_bytes());
+        end.extend_from_slice(&[0, 0]);
+        [local, central, end].concat()
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let extract = |name: &str, archive: &[u8]| {
+        let path = dir.path().join(format!("{}.zip", name));
+        fs::File::create(&path).unwrap().write_all(archive).unwrap();
+        let root = dir.path().join(name);
+        fs::create_dir(&root).unwrap();
+        zip(&mut fs::File::open(&path).unwrap(), &mut Tree::new(&root))
+    };
+
+    extract("good", &stored("d/a.txt", b"hello")).unwrap();
+    let a = dir.path().join("good/d/a.txt");
+    assert_eq!("hello", fs::read_to_string(&a).unwrap());
+    assert_eq!(
+        0o640,
+        fs::metadata(&a).unwrap().permissions().mode() & 0o777
+    );
+
+    let mut corrupt = stored("a.txt", b"hello");
+    let at = corrupt.len() - 22 - 46 - 5 - 5;
+    corrupt[at] = b'j';
+    let err = extract("corrupt", &corrupt).unwrap_err().to_string();
+    assert!(err.contains("CRC"), "{}", err);
+
+    // a central directory said to be 4GiB, in a file of 22 bytes, isn't allocated for
+    let mut huge = END_OF_DIRECTORY.to_le_bytes().to_vec();
+    huge.extend_from_slice(&[0, 0, 0, 0, 1, 0, 1, 0]);
+    huge.extend_from_slice(&0xffff_fffeu32.to_le_bytes());
+    huge.extend_from_slice(&[0; 6]);
+    let err = extract("huge", &huge).unwrap_err().to_string();
+    assert!(err.contains("past the end"), "{}", err);
+}
@@ -0,0 +1,299 @@
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use failure::format_err;
+use failure::ResultExt;
+use log::debug;
+use log::info;
+use log::warn;
+
+use crate::cache;
+use crate::lock;
+use crate::period;
+use crate::signals;
+use crate::size;
+
+/// What `--cache-dir` keeps next to an entry, longest first, so a `.part.meta` isn't only a
+/// `.meta`.
+const SIDECARS: &[&str] = &[
+    ".part.meta",
+    ".part",
+    ".meta",
+    ".compare",
+    ".blocks",
+    ".lock",
+];
+
+/// An entry, or what's left of one, and the files that go with it.
+#[derive(Debug)]
+struct Group {
+    primary: PathBuf,
+    /// Whether the entry itself is there; without it, the rest are orphans.
+    present: bool,
+    /// The entry, if it's there, then its sidecars, and their sizes.
+    files: Vec<(PathBuf, u64)>,
+    /// When any of them was last read or written.
+    used: SystemTime,
+}
+
+impl Group {
+    fn size(&self) -> u64 {
+        self.files.iter().map(|(_, len)| len).sum()
+    }
+}
+
+/// `--gc`: prune `--cache-dir` of entries unused for `--cache-max-age`, then of the least
+/// recently used until it fits in `--cache-max-size`, and of sidecars whose entry is gone.
+///
+/// Only files `--cache-dir` could have made are looked at: an entry is one whose `.meta` names
+/// the URL it was named after, and a sidecar is one named for an entry, so anything else in the
+/// directory is left alone. Entries a run has locked are skipped. With `--dry-run`, what would
+/// be removed is printed instead.
+pub fn run(matches: &clap::ArgMatches) -> Result<(), failure::Error> {
+    let dir = Path::new(matches.value_of_os("cache-dir").expect("required"));
+    let max_age = match matches.value_of("cache-max-age") {
+        Some(v) => Some(
+            period::parse_duration(v)?
+                .to_std()
+                .with_context(|_| format_err!("--cache-max-age must be positive: {:?}", v))?,
+        ),
+        None => None,
+    };
+    let max_size = match matches.value_of("cache-max-size") {
+        Some(v) => Some(size::parse_size(v)?),
+        None => None,
+    };
+    let dry_run = matches.is_present("dry-run");
+
+    let doomed = plan(dir, max_age, max_size, SystemTime::now())?;
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    let (mut files, mut freed) = (0, 0);
+    for (group, why) in &doomed {
+        // held for the removal, so a run can't start on the entry meanwhile
+        let _claim = match lock::try_existing(&lock::default_path(&group.primary))? {
+            lock::Existing::Held => {
+                info!("            gc: {:?} is in use, leaving it", group.primary);
+                continue;
+            }
+            claim => claim,
+        };
+        for (path, len) in &group.files {
+            if dry_run {
+                writeln!(stdout, "would remove {:?}: {}", path, why)?;
+            } else if let Err(e) = fs::remove_file(path) {
+                warn!("couldn't remove {:?}: {}", path, e);
+                continue;
+            } else {
+                info!("            gc: removed {:?}: {}", path, why);
+            }
+            files += 1;
+            freed += len;
+        }
+    }
+
+    if dry_run {
+        writeln!(
+            stdout,
+            "would free {} in {} files",
+            size::format_size(freed),
+            files
+        )?;
+    } else {
+        info!(
+            "            gc: freed {} in {} files",
+            size::format_size(freed),
+            files
+        );
+    }
+    Ok(())
+}
+
+/// What's to go from `dir`, and why, in the order it's to go: orphans, the too old, then the
+/// least recently used.
+fn plan(
+    dir: &Path,
+    max_age: Option<Duration>,
+    max_size: Option<u64>,
+    now: SystemTime,
+) -> Result<Vec<(Group, &'static str)>, failure::Error> {
+    let mut names = BTreeMap::new();
+    for entry in fs::read_dir(dir).with_context(|_| format_err!("listing cache {:?}", dir))? {
+        let entry = entry.with_context(|_| format_err!("listing cache {:?}", dir))?;
+        let metadata = match fs::symlink_metadata(entry.path()) {
+            Ok(metadata) if metadata.is_file() => metadata,
+            _ => continue,
+        };
+        if let Ok(name) = entry.file_name().into_string() {
+            names.insert(name, metadata);
+        }
+    }
+
+    let entries: BTreeSet<&str> = names
+        .keys()
+        .map(String::as_str)
+        .filter(|name| cache::is_entry_name(name) && cache::is_entry(&dir.join(name)))
+        .collect();
+
+    let mut groups: BTreeMap<&str, Group> = BTreeMap::new();
+    for (name, metadata) in &names {
+        let primary = if entries.contains(name.as_str()) {
+            name.as_str()
+        } else {
+            let primaries: Vec<&str> = SIDECARS
+                .iter()
+                .filter_map(|suffix| name.strip_suffix(suffix))
+                .filter(|primary| cache::is_entry_name(primary))
+                .collect();
+            match primaries
+                .iter()
+                .copied()
+                .find(|primary| entries.contains(primary))
+                .or_else(|| primaries.first().copied())
+            {
+                Some(primary) => primary,
+                None => {
+                    debug!("            gc: not ours, leaving {:?}", name);
+                    continue;
+                }
+            }
+        };
+        let used = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        let used = match metadata.accessed() {
+            Ok(accessed) if name == primary => used.max(accessed),
+            _ => used,
+        };
+        let group = groups.entry(primary).or_insert_with(|| Group {
+            primary: dir.join(primary),
+            present: entries.contains(primary),
+            files: Vec::new(),
+            used,
+        });
+        group.used = group.used.max(used);
+        let file = (dir.join(name), metadata.len());
+        if name == primary {
+            group.files.insert(0, file);
+        } else {
+            group.files.push(file);
+        }
+    }
+
+    let mut doomed = Vec::new();
+    let mut kept = Vec::new();
+    for (primary, group) in groups {
+        let age = now.duration_since(group.used).unwrap_or_default();
+        if !group.present {
+            // an entry that's never existed might be being fetched for the first time
+            if names.contains_key(primary) || age < signals::STALE_AFTER {
+                continue;
+            }
+            doomed.push((group, "orphaned, as its entry is gone"));
+        } else if max_age.is_some_and(|max_age| age > max_age) {
+            doomed.push((group, "unused for longer than --cache-max-age"));
+        } else {
+            kept.push(group);
+        }
+    }
+
+    if let Some(max_size) = max_size {
+        kept.sort_by_key(|group| group.used);
+        let mut total: u64 = kept.iter().map(Group::size).sum();
+        for group in kept {
+            if total <= max_size {
+                break;
+            }
+            total -= group.size();
+            doomed.push((group, "the least recently used, over --cache-max-size"));
+        }
+    }
+    Ok(doomed)
+}
+
+#[test]
+fn test_plan() {
+    let dir = tempfile::tempdir().unwrap();
+    let now = SystemTime::now();
+    let day = Duration::from_secs(24 * 60 * 60);
+    let entry = |url: &str, len: usize, used: SystemTime| {
+        let url = url::Url::parse(url).unwrap();
+        let path = cache::path(dir.path(), &url);
+        fs::write(&path, vec![b'x'; len]).unwrap();
+        cache::record(
+            &path,
+            url.as_str(),
+            &crate::outcome::Outcome::default(),
+            None,
+        )
+        .unwrap();
+        for file in &[
+            path.clone(),
+            PathBuf::from(format!("{}.meta", path.display())),
+        ] {
+            filetime::set_file_times(file, used.into(), used.into()).unwrap();
+        }
+        path
+    };
+    let old = entry("https://example.com/old.tar.gz", 10, now - 30 * day);
+    let big = entry("https://example.com/big.tar.gz", 100, now - 2 * day);
+    let new = entry("https://example.com/new.tar.gz", 10, now);
+
+    // the entry's gone, and long since
+    let orphan = dir.path().join("0123456789abcdef-gone.part");
+    fs::write(&orphan, b"part").unwrap();
+    filetime::set_file_mtime(&orphan, (now - day).into()).unwrap();
+    // perhaps still being fetched for the first time
+    fs::write(dir.path().join("0123456789abcdef-fresh.part"), b"part").unwrap();
+    // not ours at all
+    fs::write(dir.path().join("notes.txt"), b"mine").unwrap();
+    fs::write(dir.path().join("0123456789abcdef-unknown"), b"mine").unwrap();
+
+    let doomed = |max_age, max_size| -> Vec<(String, &str)> {
+        plan(dir.path(), max_age, max_size, now)
+            .unwrap()
+            .into_iter()
+            .map(|(group, why)| {
+                let name = group.primary.file_name().unwrap().to_str().unwrap();
+                (name.to_string(), why)
+            })
+            .collect()
+    };
+    let name = |path: &Path| path.file_name().unwrap().to_str().unwrap().to_string();
+
+    assert_eq!(
+        vec![(
+            "0123456789abcdef-gone".to_string(),
+            "orphaned, as its entry is gone"
+        )],
+        doomed(None, None)
+    );
+    let by_age = doomed(Some(7 * day), None);
+    assert_eq!(2, by_age.len(), "{:?}", by_age);
+    assert_eq!(name(&old), by_age[1].0);
+
+    let by_size = doomed(None, Some(150));
+    assert_eq!(
+        vec![name(&old), name(&big)],
+        by_size[1..].iter().map(|d| d.0.clone()).collect::<Vec<_>>()
+    );
+    assert!(by_size.iter().all(|d| d.0 != name(&new)));
+
+    let group = plan(dir.path(), Some(7 * day), None, now)
+        .unwrap()
+        .into_iter()
+        .find(|(group, _)| group.primary == old)
+        .unwrap()
+        .0;
+    assert_eq!(
+        vec![
+            old.clone(),
+            PathBuf::from(format!("{}.meta", old.display()))
+        ],
+        group.files.iter().map(|f| f.0.clone()).collect::<Vec<_>>()
+    );
+}
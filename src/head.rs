@@ -0,0 +1,42 @@
+use crate::exit;
+use crate::statuses::Statuses;
+
+/// For `--head`: whether the server won't answer HEAD, so a GET is worth sending instead, and its
+/// body left unread.
+pub fn refused(status: u16) -> bool {
+    405 == status || 501 == status
+}
+
+/// How `--head` exits: as a fetch would, except that there's no body to be unhappy with.
+pub fn verdict(status: u16, status_line: &str, ok_status: &Statuses) -> Result<(), failure::Error> {
+    let complaint = match status {
+        304 => return Ok(()),
+        _ if ok_status.contains(status) => return Ok(()),
+        300..=399 => "confused by redirection",
+        400..=599 => "unhappy response",
+        _ => "unexpected response",
+    };
+    Err(exit::classified(
+        exit::Kind::Status(status),
+        format!("{}: {:?}", complaint, status_line),
+    ))
+}
+
+#[test]
+fn test_verdict() {
+    let ok = Statuses::default();
+    assert!(verdict(200, "HTTP/1.1 200 OK", &ok).is_ok());
+    assert!(verdict(304, "HTTP/1.1 304 Not Modified", &ok).is_ok());
+    let err = verdict(404, "HTTP/1.1 404 Not Found", &ok).unwrap_err();
+    assert_eq!(
+        "unhappy response: \"HTTP/1.1 404 Not Found\"",
+        err.to_string()
+    );
+    assert!(verdict(
+        404,
+        "HTTP/1.1 404 Not Found",
+        &Statuses::parse("404").unwrap()
+    )
+    .is_ok());
+    assert!(refused(405) && !refused(404));
+}
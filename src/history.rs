@@ -0,0 +1,126 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use failure::bail;
+use failure::format_err;
+use failure::ResultExt;
+use log::debug;
+
+use crate::digest;
+use crate::json::string;
+use crate::outcome::Outcome;
+
+/// Append one JSON line describing the run to `path`.
+///
+/// It's a single `write` to a file opened for appending, so lines from concurrent runs
+/// interleave, but never mix.
+pub fn append(
+    path: &Path,
+    outcome: &Outcome,
+    at: chrono::DateTime<chrono::Utc>,
+) -> Result<(), failure::Error> {
+    let line = line(outcome, at);
+
+    let mut file = fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)
+        .with_context(|_| format_err!("opening history file {:?}", path))?;
+
+    let written = file
+        .write(line.as_bytes())
+        .with_context(|_| format_err!("appending to history file {:?}", path))?;
+    if written != line.len() {
+        bail!(
+            "short write to history file {:?}: {} of {} bytes",
+            path,
+            written,
+            line.len()
+        );
+    }
+
+    debug!("       history: appended to {:?}", path);
+    Ok(())
+}
+
+fn line(outcome: &Outcome, at: chrono::DateTime<chrono::Utc>) -> String {
+    let optional = |value: Option<String>| value.unwrap_or_else(|| "null".to_string());
+    format!(
+        concat!(
+            "{{\"time\":{},\"result\":{},\"url\":{},\"final_url\":{},",
+            "\"status\":{},\"bytes\":{},\"sha256\":{},\"output\":{}}}\n"
+        ),
+        string(&at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)),
+        string(outcome.kind.name()),
+        string(&outcome.url),
+        optional(outcome.final_url.as_deref().map(string)),
+        optional(outcome.status.map(|s| s.to_string())),
+        optional(outcome.bytes.map(|b| b.to_string())),
+        optional(outcome.sha256.as_ref().map(|s| string(&digest::hex(s)))),
+        optional(
+            outcome
+                .output
+                .as_ref()
+                .map(|p| string(&p.to_string_lossy()))
+        ),
+    )
+}
+
+#[test]
+fn test_line() {
+    use chrono::TimeZone;
+
+    use crate::outcome::Kind;
+
+    let at = chrono::Utc.with_ymd_and_hms(2019, 10, 3, 12, 0, 0).unwrap();
+    let outcome = Outcome {
+        kind: Kind::Fetched,
+        phase: "installing",
+        url: "https://example.com/a".to_string(),
+        final_url: Some("https://example.com/b".to_string()),
+        remote: None,
+        lookups: Default::default(),
+        status: Some(200),
+        last_modified: None,
+        etag: None,
+        expires: None,
+        cert_expires: None,
+        cert_expiring: None,
+        bytes: Some(3),
+        sha256: Some([0xab; 32]),
+        buffer: None,
+        output: Some("dir/\"odd\"\n".into()),
+        identical: false,
+        skipped: None,
+        headers: String::new(),
+        first_byte: None,
+        elapsed: None,
+    };
+    assert_eq!(
+        format!(
+            concat!(
+                "{{\"time\":\"2019-10-03T12:00:00Z\",\"result\":\"fetched\",",
+                "\"url\":\"https://example.com/a\",\"final_url\":\"https://example.com/b\",",
+                "\"status\":200,\"bytes\":3,\"sha256\":\"{}\",\"output\":\"dir/\\\"odd\\\"\\n\"}}\n"
+            ),
+            "ab".repeat(32)
+        ),
+        line(&outcome, at)
+    );
+
+    assert_eq!(
+        concat!(
+            "{\"time\":\"2019-10-03T12:00:00Z\",\"result\":\"skipped\",",
+            "\"url\":\"https://example.com/a\",\"final_url\":null,",
+            "\"status\":null,\"bytes\":null,\"sha256\":null,\"output\":null}\n"
+        ),
+        line(
+            &Outcome {
+                url: "https://example.com/a".to_string(),
+                ..Outcome::default()
+            },
+            at
+        )
+    );
+}
@@ -0,0 +1,69 @@
+use std::ffi::OsStr;
+use std::path::PathBuf;
+use std::process::Command;
+
+use failure::bail;
+use failure::format_err;
+use failure::ResultExt;
+use log::debug;
+
+/// A path at which other processes can read the temporary file, which may not have a name.
+pub fn temp_path(temp: &tempfile_fast::PersistableTempFile) -> PathBuf {
+    match temp {
+        tempfile_fast::PersistableTempFile::Fallback(named) => named.path().to_path_buf(),
+        tempfile_fast::PersistableTempFile::Linux(file) => {
+            use std::os::unix::io::AsRawFd;
+            // /proc/self would be the child's view, which doesn't have our descriptors
+            PathBuf::from(format!(
+                "/proc/{}/fd/{}",
+                std::process::id(),
+                file.as_raw_fd()
+            ))
+        }
+    }
+}
+
+/// Run a user's shell command, with `path` substituted for `{}`, or appended if there's none.
+///
+/// The path is passed as a positional parameter, rather than pasted in, so it needs no quoting;
+/// and mustn't have any: `{}` becomes `"$1"`, which inside `'...'` is taken literally. The
+/// command shares our stdout and stderr, so what it says is seen, not swallowed.
+pub fn run(cmd: &str, path: &OsStr, env: &[(&str, String)]) -> Result<(), failure::Error> {
+    let script = if cmd.contains("{}") {
+        cmd.replace("{}", "\"$1\"")
+    } else {
+        format!("{} \"$1\"", cmd)
+    };
+
+    debug!("       command: {:?} with {:?}", cmd, path);
+
+    let status = Command::new("/bin/sh")
+        .arg("-c")
+        .arg(&script)
+        .arg("fetch-maybe")
+        .arg(path)
+        .envs(env.iter().map(|(k, v)| (k, v)))
+        .status()
+        .with_context(|_| format_err!("starting {:?}", cmd))?;
+
+    if !status.success() {
+        bail!("{:?} failed ({})", cmd, status);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_run() {
+    assert!(run("test -n", OsStr::new("x"), &[]).is_ok());
+    assert!(run("test {} = x", OsStr::new("x"), &[]).is_ok());
+    assert!(run(
+        "test \"$FOO\" = bar && true",
+        OsStr::new("x"),
+        &[("FOO", "bar".to_string())]
+    )
+    .is_ok());
+
+    let err = run("exit 3", OsStr::new("x"), &[]).unwrap_err().to_string();
+    assert!(err.contains("exit status: 3"), "{}", err);
+}
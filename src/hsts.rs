@@ -0,0 +1,374 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use chrono::DateTime;
+use chrono::Utc;
+use failure::bail;
+use failure::format_err;
+use failure::ResultExt;
+use log::debug;
+use log::info;
+use log::warn;
+use url::Host;
+use url::Url;
+
+use crate::dir_of;
+use crate::lock;
+use crate::output;
+use crate::redirect;
+use crate::sink;
+
+/// How long to wait for another run to finish updating the file; it only holds it for a read,
+/// and a write and a rename.
+const LOCK_WAIT: Duration = Duration::from_secs(30);
+
+/// A host's `Strict-Transport-Security`, as it was last sent.
+#[derive(Clone, Debug, PartialEq)]
+struct Policy {
+    host: String,
+    subdomains: bool,
+    expires: DateTime<Utc>,
+}
+
+impl Policy {
+    fn covers(&self, host: &str) -> bool {
+        host == self.host
+            || (self.subdomains
+                && host
+                    .strip_suffix(self.host.as_str())
+                    .is_some_and(|rest| rest.ends_with('.')))
+    }
+}
+
+/// What a `Strict-Transport-Security` header says: how long for, and whether subdomains too.
+#[derive(Debug, PartialEq)]
+struct Header {
+    max_age: u64,
+    subdomains: bool,
+}
+
+/// The hosts that have said they're only to be asked over https, kept between runs in
+/// `$XDG_STATE_HOME/fetch-maybe/hsts`; an http URL for one of them is asked over https instead,
+/// as a browser, or curl's `--hsts`, would.
+pub struct Hsts {
+    path: PathBuf,
+    policies: Vec<Policy>,
+}
+
+impl Hsts {
+    /// `None` with `--no-hsts`, or if there's nowhere to keep it.
+    pub fn from_matches(matches: &clap::ArgMatches) -> Result<Option<Hsts>, failure::Error> {
+        if matches.is_present("no-hsts") {
+            return Ok(None);
+        }
+        let path = match default_path() {
+            Some(path) => path,
+            None => {
+                debug!("          hsts: neither XDG_STATE_HOME nor HOME is set, so off");
+                return Ok(None);
+            }
+        };
+        let policies = load(&path)?;
+        Ok(Some(Hsts { path, policies }))
+    }
+
+    /// Ask for `chain`'s current URL over https, if it's http and its host has said it's not
+    /// to be asked any other way.
+    pub fn upgrade(&self, chain: &mut redirect::Chain) -> Result<(), failure::Error> {
+        let url = chain.current();
+        if "http" != url.scheme() {
+            return Ok(());
+        }
+        let host = match url.host() {
+            Some(Host::Domain(host)) => host,
+            _ => return Ok(()),
+        };
+        let now = Utc::now();
+        if !self
+            .policies
+            .iter()
+            .any(|policy| policy.expires > now && policy.covers(host))
+        {
+            return Ok(());
+        }
+        let mut upgraded = url.clone();
+        // an explicit port's kept; the default goes with the scheme
+        upgraded.set_scheme("https").expect("http is special");
+        info!(
+            "          hsts: {} is only to be asked over https, so asking {}",
+            host, upgraded
+        );
+        chain.replace(upgraded)
+    }
+
+    /// Remember, or forget, what an https response from `url` said about its host.
+    ///
+    /// The file's a cache, so failing to update it is only a warning.
+    pub fn observe(&mut self, url: &Url, header: Option<&str>) {
+        let (host, header) = match (url.scheme(), url.host(), header) {
+            ("https", Some(Host::Domain(host)), Some(header)) => (host, header),
+            _ => return,
+        };
+        let parsed = match parse(header) {
+            Some(parsed) => parsed,
+            None => {
+                warn!(
+                    "{} sent an unusable Strict-Transport-Security: {:?}",
+                    host, header
+                );
+                return;
+            }
+        };
+        if let Err(e) = self.update(host, &parsed) {
+            warn!(
+                "couldn't remember {}'s Strict-Transport-Security: {}",
+                host, e
+            );
+        }
+    }
+
+    /// Under the lock, with what other runs have written since this one read the file.
+    fn update(&mut self, host: &str, header: &Header) -> Result<(), failure::Error> {
+        let dir = dir_of::dir_of(&self.path, env::current_dir)?;
+        fs::create_dir_all(&dir).with_context(|_| format_err!("creating {:?}", dir))?;
+        let _lock = lock_for(&self.path)?;
+        let now = Utc::now();
+        let mut policies = load(&self.path)?;
+        policies.retain(|policy| policy.host != host && policy.expires > now);
+        if header.max_age > 0 {
+            let max_age = chrono::Duration::seconds(header.max_age.min(i64::MAX as u64) as i64);
+            policies.push(Policy {
+                host: host.to_string(),
+                subdomains: header.subdomains,
+                expires: now
+                    .checked_add_signed(max_age)
+                    .unwrap_or(DateTime::<Utc>::MAX_UTC),
+            });
+            debug!(
+                "          hsts: {} is https only for {}s{}",
+                host,
+                header.max_age,
+                if header.subdomains {
+                    ", subdomains too"
+                } else {
+                    ""
+                }
+            );
+        } else {
+            debug!("          hsts: {} is no longer https only", host);
+        }
+        store(&self.path, &policies)?;
+        self.policies = policies;
+        Ok(())
+    }
+}
+
+/// `$XDG_STATE_HOME/fetch-maybe/hsts`, or under `~/.local/state` if that's unset.
+fn default_path() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_STATE_HOME").filter(|v| !v.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?)
+            .join(".local")
+            .join("state"),
+    };
+    Some(base.join("fetch-maybe").join("hsts"))
+}
+
+/// RFC 6797's directives: a `max-age` is needed, and none may be given twice, or the whole
+/// header's ignored.
+fn parse(header: &str) -> Option<Header> {
+    let mut max_age = None;
+    let mut subdomains = false;
+    for directive in header.split(';').map(str::trim).filter(|d| !d.is_empty()) {
+        let (name, value) = match directive.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+            None => (directive, None),
+        };
+        if name.eq_ignore_ascii_case("max-age") {
+            if max_age.is_some() {
+                return None;
+            }
+            max_age = Some(value?.parse().ok()?);
+        } else if name.eq_ignore_ascii_case("includeSubDomains") {
+            if subdomains {
+                return None;
+            }
+            subdomains = true;
+        }
+    }
+    Some(Header {
+        max_age: max_age?,
+        subdomains,
+    })
+}
+
+fn lock_for(path: &Path) -> Result<fs::File, failure::Error> {
+    let lock_path = lock::default_path(path);
+    match lock::acquire(&lock_path, LOCK_WAIT)? {
+        Some(file) => Ok(file),
+        None => bail!(
+            "{:?} was held for more than {:?}, updating HSTS policies",
+            lock_path,
+            LOCK_WAIT
+        ),
+    }
+}
+
+/// A line that doesn't parse is dropped, with a warning; it's only a cache, and the host will
+/// say it again.
+fn load(path: &Path) -> Result<Vec<Policy>, failure::Error> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(ref e) if io::ErrorKind::NotFound == e.kind() => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|_| format_err!("reading {:?}", path))?,
+    };
+    let mut policies = Vec::new();
+    for line in text
+        .lines()
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+    {
+        match parse_line(line) {
+            Some(policy) => policies.push(policy),
+            None => warn!("ignoring a corrupt line of {:?}: {:?}", path, line),
+        }
+    }
+    Ok(policies)
+}
+
+fn parse_line(line: &str) -> Option<Policy> {
+    let mut fields = line.split(' ');
+    let host = fields.next()?.to_string();
+    let expires = DateTime::parse_from_rfc3339(fields.next()?)
+        .ok()?
+        .with_timezone(&Utc);
+    let subdomains = match fields.next() {
+        None => false,
+        Some("includeSubDomains") => true,
+        Some(_) => return None,
+    };
+    Some(Policy {
+        host,
+        subdomains,
+        expires,
+    })
+}
+
+/// Replaced by a rename, so it's never half written.
+fn store(path: &Path, policies: &[Policy]) -> Result<(), failure::Error> {
+    let mut text = "# host, until when, and whether its subdomains are included\n".to_string();
+    for policy in policies {
+        text.push_str(&policy.host);
+        text.push(' ');
+        text.push_str(&policy.expires.to_rfc3339());
+        if policy.subdomains {
+            text.push_str(" includeSubDomains");
+        }
+        text.push('\n');
+    }
+    let dir = dir_of::dir_of(path, env::current_dir)?;
+    let mut temp = sink::temp_in(&dir)?;
+    temp.write_all(text.as_bytes())
+        .with_context(|_| format_err!("writing {:?}", path))?;
+    temp.persist_by_rename(path)
+        .map_err(|e| e.error)
+        .with_context(|_| format_err!("replacing {:?}", path))?;
+    output::sync_directory(&dir);
+    Ok(())
+}
+
+#[test]
+fn test_parse() {
+    assert_eq!(
+        Some(Header {
+            max_age: 31536000,
+            subdomains: true
+        }),
+        parse("max-age=31536000; includeSubDomains; preload")
+    );
+    assert_eq!(
+        Some(Header {
+            max_age: 0,
+            subdomains: false
+        }),
+        parse("MAX-AGE=\"0\"")
+    );
+    assert_eq!(None, parse("includeSubDomains"));
+    assert_eq!(None, parse("max-age=1; max-age=2"));
+    assert_eq!(None, parse("max-age=soon"));
+}
+
+#[test]
+fn test_upgrade() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut hsts = Hsts {
+        path: dir.path().join("state").join("hsts"),
+        policies: Vec::new(),
+    };
+    let url = |s: &str| Url::parse(s).unwrap();
+    let upgraded = |hsts: &Hsts, s: &str| {
+        let mut chain = redirect::Chain::new(url(s), 10);
+        hsts.upgrade(&mut chain).unwrap();
+        chain.current().to_string()
+    };
+
+    hsts.observe(
+        &url("https://example.com/"),
+        Some("max-age=600; includeSubDomains"),
+    );
+    // only said over https counts
+    hsts.observe(&url("http://plain.example.org/"), Some("max-age=600"));
+    assert_eq!(
+        "https://example.com/a",
+        upgraded(&hsts, "http://example.com:80/a")
+    );
+    assert_eq!(
+        "https://www.example.com:8080/",
+        upgraded(&hsts, "http://www.example.com:8080/")
+    );
+    assert_eq!(
+        "http://notexample.com/",
+        upgraded(&hsts, "http://notexample.com/")
+    );
+    assert_eq!(
+        "http://plain.example.org/",
+        upgraded(&hsts, "http://plain.example.org/")
+    );
+
+    // kept for the next run, which can be told to forget it
+    let next = Hsts {
+        policies: load(&hsts.path).unwrap(),
+        path: hsts.path.clone(),
+    };
+    assert_eq!(hsts.policies, next.policies);
+    hsts.observe(&url("https://example.com/"), Some("max-age=0"));
+    assert_eq!(
+        "http://example.com/",
+        upgraded(&hsts, "http://example.com/")
+    );
+
+    // an expired one's no use, and goes with the next write
+    hsts.policies.push(Policy {
+        host: "old.example.net".to_string(),
+        subdomains: false,
+        expires: Utc::now() - chrono::Duration::seconds(1),
+    });
+    store(&hsts.path, &hsts.policies).unwrap();
+    assert_eq!(
+        "http://old.example.net/",
+        upgraded(&hsts, "http://old.example.net/")
+    );
+    hsts.observe(&url("https://example.net/"), Some("max-age=600"));
+    assert_eq!(
+        vec!["example.net"],
+        load(&hsts.path)
+            .unwrap()
+            .iter()
+            .map(|p| p.host.as_str())
+            .collect::<Vec<_>>()
+    );
+}
@@ -0,0 +1,37 @@
+use std::fs;
+use std::io;
+use std::io::Seek;
+use std::path::Path;
+
+use failure::err_msg;
+use failure::format_err;
+use failure::ResultExt;
+use log::debug;
+
+/// Copy the finished download over the existing output's contents, keeping its inode.
+///
+/// Not atomic: readers may see a mix of old and new, or a truncated file, until this returns.
+/// The download is complete and checked before this starts, so only a failure in this local
+/// copy (e.g. a full disk) can leave the output damaged.
+pub fn overwrite(temp: &fs::File, output: &Path) -> Result<fs::File, failure::Error> {
+    let mut dest = fs::OpenOptions::new()
+        .write(true)
+        .open(output)
+        .with_context(|_| format_err!("opening {:?} to overwrite in place", output))?;
+
+    let mut source = temp;
+    source
+        .seek(io::SeekFrom::Start(0))
+        .with_context(|_| err_msg("rewinding the download"))?;
+
+    let len = io::copy(&mut source, &mut dest)
+        .with_context(|_| format_err!("overwriting {:?} in place", output))?;
+
+    dest.set_len(len)
+        .with_context(|_| format_err!("truncating {:?} after overwriting it", output))?;
+    dest.sync_all()
+        .with_context(|_| format_err!("flushing {:?} to disk", output))?;
+
+    debug!("      in-place: wrote {} bytes into {:?}", len, output);
+    Ok(dest)
+}
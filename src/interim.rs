@@ -0,0 +1,133 @@
+use std::io;
+use std::io::Read;
+
+use log::debug;
+
+/// The final response's body, read from what came after the interim responses.
+pub type Body = Box<dyn Read>;
+
+/// Past any interim `1xx` responses, like `103 Early Hints`, to the final one.
+///
+/// ureq 0.11 takes the first response it reads as the answer, and can't be handed a body
+/// back, so the final response's comes separately; `None` when there were no interims, and
+/// the response reads its own. It's framed by the final response's headers, and the connection
+/// isn't reused.
+pub fn skip(response: ureq::Response) -> (ureq::Response, Option<Body>) {
+    if !is_interim(response.status()) {
+        return (response, None);
+    }
+    debug!("       interim: skipping {:?}", response.status_line());
+
+    // with no Content-Length, what ureq thinks is the body is everything left on the connection
+    let mut rest: Body = Box::new(response.into_reader());
+    loop {
+        let next = ureq::Response::from_read(&mut rest);
+        if next.synthetic_error().is_some() {
+            return (next, None);
+        }
+        if is_interim(next.status()) {
+            debug!("       interim: skipping {:?}", next.status_line());
+            continue;
+        }
+        let body = framed(&next, rest);
+        return (next, Some(body));
+    }
+}
+
+/// The response's body, whether it's still its own, or came after interim responses.
+pub fn reader(response: ureq::Response, body: Option<Body>) -> Body {
+    match body {
+        Some(body) => body,
+        None => Box::new(response.into_reader()),
+    }
+}
+
+// 101 Switching Protocols would be final, but is only ever sent to those asking to switch
+fn is_interim(status: u16) -> bool {
+    (100..=199).contains(&status) && 101 != status
+}
+
+fn framed(response: &ureq::Response, rest: Body) -> Body {
+    if response.header("Transfer-Encoding").is_some() {
+        return Box::new(Chunked(chunked_transfer::Decoder::new(rest)));
+    }
+    match response
+        .header("Content-Length")
+        .and_then(|len| len.trim().parse::<u64>().ok())
+    {
+        Some(len) => Box::new(rest.take(len)),
+        None => rest,
+    }
+}
+
+/// A chunked body, which only ends with its final, empty chunk: the decoder takes the
+/// connection closing inside a chunk for the end of the body.
+struct Chunked(chunked_transfer::Decoder<Body>);
+
+impl Read for Chunked {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.0.read(buf)?;
+        if 0 == read && !buf.is_empty() && self.0.remaining_chunks_size().is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed inside a chunk",
+            ));
+        }
+        Ok(read)
+    }
+}
+
+#[test]
+fn test_skip() {
+    let parse = |text: &str| text.parse::<ureq::Response>().unwrap();
+
+    let (response, body) = skip(parse("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nhi"));
+    assert_eq!(200, response.status());
+    assert!(body.is_none());
+
+    let (response, body) = skip(parse(
+        "HTTP/1.1 100 Continue\r\n\r\n\
+         HTTP/1.1 103 Early Hints\r\nLink: </style.css>; rel=preload\r\n\r\n\
+         HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Length: 4\r\n\r\nbody, and the next response",
+    ));
+    assert_eq!(200, response.status());
+    assert_eq!(Some("\"v1\""), response.header("ETag"));
+    let mut text = String::new();
+    reader(response, body).read_to_string(&mut text).unwrap();
+    assert_eq!("body", text);
+
+    let (response, body) = skip(parse(
+        "HTTP/1.1 103 Early Hints\r\n\r\n\
+         HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n",
+    ));
+    let mut text = String::new();
+    reader(response, body).read_to_string(&mut text).unwrap();
+    assert_eq!("abc", text);
+
+    assert!(!is_interim(101));
+}
+
+#[test]
+fn test_chunked_cut_short() {
+    let parse = |text: &str| text.parse::<ureq::Response>().unwrap();
+    let read = |text: &str| {
+        let (response, body) = skip(parse(text));
+        let mut out = Vec::new();
+        reader(response, body).read_to_end(&mut out).map(|_| out)
+    };
+    let early =
+        "HTTP/1.1 103 Early Hints\r\n\r\nHTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n";
+
+    assert_eq!(
+        b"hello world".to_vec(),
+        read(&format!("{}5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n", early)).unwrap()
+    );
+    for cut in &[
+        "5\r\nhello\r\n6\r\n wo",
+        "5\r\nhel",
+        "5\r\nhello\r\n",
+        "5\r\nhello",
+    ] {
+        assert!(read(&format!("{}{}", early, cut)).is_err(), "{:?}", cut);
+    }
+}
@@ -0,0 +1,4171 @@
+use std::env;
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time;
+
+use clap::Arg;
+use clap::ArgGroup;
+use failure::bail;
+use failure::err_msg;
+use failure::format_err;
+use failure::ResultExt;
+use log::debug;
+use log::info;
+use log::warn;
+use std::io::Read;
+use std::io::Write;
+
+// without one, every https URL would only fail once it was fetched
+#[cfg(not(feature = "tls-rustls"))]
+compile_error!("fetch-maybe needs a TLS backend: build with the tls-rustls feature");
+
+mod also;
+mod backup;
+pub mod cache;
+mod certificate;
+mod check;
+mod checksum;
+mod compare;
+mod compress;
+mod confirm;
+mod conflict;
+pub mod curl;
+mod delta;
+mod diff;
+mod digest;
+pub mod dir_of;
+mod dns;
+mod dry_run;
+mod dump;
+mod error_body;
+pub mod events;
+pub mod exit;
+mod expect;
+mod extract;
+pub mod gc;
+mod head;
+mod history;
+mod hook;
+mod hsts;
+mod in_place;
+mod interim;
+pub mod json;
+pub mod lock;
+mod method;
+mod metrics;
+#[cfg(feature = "async")]
+mod nonblocking;
+pub mod outcome;
+mod output;
+mod partial;
+pub mod period;
+mod perms;
+mod preflight;
+mod proxy;
+mod pump;
+mod quota;
+mod range;
+pub mod rate;
+mod readback;
+mod redirect;
+pub mod report;
+mod request;
+mod restage;
+mod retry;
+mod segments;
+pub mod signals;
+mod sink;
+pub mod size;
+mod space;
+mod stats;
+pub mod status;
+mod statuses;
+mod storage;
+mod store;
+pub mod target;
+mod template;
+mod timestamp;
+mod unpack;
+pub mod verify;
+mod write_out;
+mod xattrs;
+
+/// For `--buffer-size`, which a typo shouldn't make the size of memory.
+const MAX_BUFFER_SIZE: u64 = 64 << 20;
+
+pub fn app() -> clap::App<'static, 'static> {
+    clap::App::new(clap::crate_name!())
+        .arg(
+            Arg::with_name("fail-if-unchanged")
+                .long("fail-if-unchanged")
+                .conflicts_with_all(&["dry-run", "head"])
+                .help("exit with status 100 unless the output changed: after a 304, a --min-age or --no-clobber skip, or a download of the same content; with a --manifest, unless any entry's did. Failures keep their own statuses"),
+        )
+        .arg(
+            Arg::with_name("fail-with-body")
+                .long("fail-with-body")
+                .takes_value(true)
+                .number_of_values(1)
+                .help("on an error status, save the response body to this file (or - for stderr)"),
+        )
+        .arg(
+            Arg::with_name("fsync")
+                .long("fsync")
+                .help("flush the output and the rename to disk before finishing, so a crash can't leave it empty; costs a couple of fsyncs per file: around a millisecond on SSDs, tens on spinning or network disks"),
+        )
+        .arg(
+            Arg::with_name("header")
+                .validator(check::header)
+                .short("H")
+                .long("header")
+                .takes_value(true)
+                .number_of_values(1)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("allow-empty")
+                .long("allow-empty")
+                .help("allow an empty response to replace a non-empty output"),
+        )
+        .arg(
+            Arg::with_name("allow-insecure-redirect")
+                .long("allow-insecure-redirect")
+                .help("follow a redirect from https to http, rather than failing; credentials, cookies and sensitive --header values aren't sent over it"),
+        )
+        .arg(
+            Arg::with_name("also-copy")
+                .long("also-copy")
+                .requires("also-link")
+                .help("copy to --also-link paths on other filesystems, instead of failing"),
+        )
+        .arg(
+            Arg::with_name("also-link")
+                .long("also-link")
+                .takes_value(true)
+                .value_name("PATH")
+                .multiple(true)
+                .number_of_values(1)
+                .help("after installing the output, hard link it at PATH too, e.g. a 'latest' name"),
+        )
+        .arg(
+            Arg::with_name("append")
+                .long("append")
+                .conflicts_with("range")
+                .help("fetch only what has been added since the output was last fetched, and append it"),
+        )
+        .arg(
+            Arg::with_name("args-file")
+                .long("args-file")
+                .takes_value(true)
+                .value_name("FILE")
+                .multiple(true)
+                .number_of_values(1)
+                .help("read more arguments from FILE, one a line, as if they were given in its place; lines aren't split on spaces, and blank lines and # comments are skipped. @FILE is the same. These, and the rest of the command line, win over FETCH_MAYBE_* variables, which win over the config file"),
+        )
+        .arg(
+            Arg::with_name("backup")
+                .long("backup")
+                .takes_value(true)
+                .min_values(0)
+                .require_equals(true)
+                .value_name("SUFFIX")
+                .help("keep the replaced output at OUTPUT~, or OUTPUT.old with --backup=.old"),
+        )
+        .arg(
+            Arg::with_name("buffer-size")
+                .long("buffer-size")
+                .takes_value(true)
+                .value_name("BYTES")
+                .validator(check::buffer_size)
+                .default_value("128k")
+                .help("read the body, and write the file, this much at a time, up to 64M"),
+        )
+        .arg(
+            Arg::with_name("checksum-url")
+                .long("checksum-url")
+                .takes_value(true)
+                .number_of_values(1)
+                .conflicts_with("sha256")
+                .help("fetch the expected sha256 from this URL first, as a bare digest or sha256sum output"),
+        )
+        .arg(
+            Arg::with_name("compare-url")
+                .long("compare-url")
+                .takes_value(true)
+                .number_of_values(1)
+                .validator(check::url)
+                .conflicts_with_all(&["dry-run", "head"])
+                .help("fetch this small URL first, conditionally, and leave the output alone unless its body's changed since the output was last brought up to date, as recorded in OUTPUT.compare; if it fails, the output's fetched as usual"),
+        )
+        .arg(
+            Arg::with_name("cache-dir")
+                .long("cache-dir")
+                .takes_value(true)
+                .value_name("DIR")
+                .conflicts_with_all(&["output", "output-fd"])
+                .help("fetch into DIR, under a name made from the URL, and print the file's path"),
+        )
+        .arg(
+            Arg::with_name("cache-max-age")
+                .long("cache-max-age")
+                .takes_value(true)
+                .value_name("DURATION")
+                .validator(check::duration)
+                .requires("gc")
+                .help("with --gc, remove entries that haven't been read or fetched for this long"),
+        )
+        .arg(
+            Arg::with_name("cache-max-size")
+                .long("cache-max-size")
+                .takes_value(true)
+                .value_name("SIZE")
+                .validator(check::size)
+                .requires("gc")
+                .help("with --gc, then remove the least recently used entries until what's left is no bigger than this"),
+        )
+        .arg(
+            Arg::with_name("chown")
+                .long("chown")
+                .takes_value(true)
+                .value_name("USER[:GROUP]")
+                .help("owner for the output, as names or ids, set before it's installed"),
+        )
+        .arg(
+            Arg::with_name("clean-stale-temps")
+                .long("clean-stale-temps")
+                .help("first remove temporary files, untouched for an hour, left next to the output by killed runs"),
+        )
+        .arg(
+            Arg::with_name("compress-output")
+                .long("compress-output")
+                .takes_value(true)
+                .value_name("gzip[:LEVEL]")
+                .conflicts_with("append")
+                .help("gzip the output; --sha256 and storage checksums still apply to the body as sent"),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("defaults for any long option, as TOML, instead of $XDG_CONFIG_HOME/fetch-maybe/config.toml; the command line wins"),
+        )
+        .arg(
+            Arg::with_name("content-disposition")
+                .long("content-disposition")
+                .conflicts_with("append")
+                .help("for a directory output, use the file name from the server's Content-Disposition"),
+        )
+        .arg(
+            Arg::with_name("create-dirs")
+                .long("create-dirs")
+                .help("create the output's directory, and any missing parents"),
+        )
+        .arg(
+            Arg::with_name("data")
+                .long("data")
+                .takes_value(true)
+                .value_name("DATA")
+                .help("send DATA as the request's body, or the contents of FILE for @FILE, read again for each attempt; the method is then POST, unless --method says otherwise"),
+        )
+        .arg(
+            Arg::with_name("data-content-type")
+                .long("data-content-type")
+                .takes_value(true)
+                .value_name("TYPE")
+                .requires("data")
+                .help("the Content-Type to send --data with; a --header overrides it"),
+        )
+        .arg(
+            Arg::with_name("delta-index")
+                .long("delta-index")
+                .takes_value(true)
+                .number_of_values(1)
+                .validator(check::url)
+                .conflicts_with_all(&["append", "range", "unpack", "compress-output"])
+                .help("when the output's changed on the server, fetch this index of the new one's block digests, and fetch only the blocks that aren't the same in the output, as ranges; the whole is checked against the index's SHA-256 before the rename, and anything amiss means downloading it all. The index is size, block-size and sha256 lines, then a block line with each block's SHA-256; OUTPUT.blocks, kept for the output, is one"),
+        )
+        .arg(
+            Arg::with_name("diff")
+                .long("diff")
+                .help("print a unified diff to stderr if the output changes"),
+        )
+        .arg(
+            Arg::with_name("diff-lines")
+                .validator(check::count)
+                .long("diff-lines")
+                .takes_value(true)
+                .default_value("200")
+                .help("truncate --diff output after this many lines"),
+        )
+        .arg(
+            Arg::with_name("dirs-mode")
+                .long("dirs-mode")
+                .takes_value(true)
+                .default_value("0755")
+                .help("octal mode, less the umask, for directories made by --create-dirs"),
+        )
+        .arg(
+            Arg::with_name("cert-expiry-fail")
+                .long("cert-expiry-fail")
+                .takes_value(true)
+                .value_name("DURATION")
+                .validator(check::duration)
+                .help("like --cert-expiry-warn, but fail the run, though the fetch itself is done"),
+        )
+        .arg(
+            Arg::with_name("cert-expiry-warn")
+                .long("cert-expiry-warn")
+                .takes_value(true)
+                .value_name("DURATION")
+                .validator(check::duration)
+                .help("after an https fetch, shake hands with the server again to see its certificate, and warn if it expires within DURATION, e.g. 14d; it's in --json and --metrics-file either way"),
+        )
+        .arg(
+            Arg::with_name("dns-cache-ttl")
+                .long("dns-cache-ttl")
+                .takes_value(true)
+                .value_name("DURATION")
+                .default_value("60s")
+                .validator(check::duration)
+                .help("reuse a host name's addresses for this long, across a --manifest's entries too, and remember one that doesn't exist for a few seconds of it; 0s to always look up"),
+        )
+        .arg(
+            Arg::with_name("dns-timeout")
+                .long("dns-timeout")
+                .takes_value(true)
+                .value_name("DURATION")
+                .validator(check::duration)
+                .help("give up looking up a host name after this long, e.g. 5s; a retry under --retry"),
+        )
+        .arg(
+            Arg::with_name("dump-headers")
+                .long("dump-headers")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("once the run succeeds, write the response's status line and headers to FILE, or - for stderr"),
+        )
+        .arg(
+            Arg::with_name("dump-headers-all")
+                .long("dump-headers-all")
+                .requires("dump-headers")
+                .help("include each redirect's headers in --dump-headers, before the final response's"),
+        )
+        .arg(
+            Arg::with_name("gc")
+                .long("gc")
+                .requires("cache-dir")
+                .conflicts_with_all(&["url", "output", "manifest", "stdin", "head", "discard"])
+                .help("instead of fetching, tidy --cache-dir: remove what --cache-max-age and --cache-max-size say to, and the .part, .lock and other files of entries that are gone; only files named as --cache-dir names them are touched, and entries in use are skipped. With --dry-run, list what would be removed"),
+        )
+        .arg(
+            Arg::with_name("head")
+                .long("head")
+                .short("I")
+                .conflicts_with_all(&["dry-run", "manifest", "stdin", "output-fd", "json", "porcelain", "events", "min-age", "no-clobber", "method", "data"])
+                .help("like curl -I: print the response's status line and headers to stdout, and nothing to the output, which can be left out; with it, the request is as conditional as a fetch's. A server refusing HEAD with 405 gets a GET, whose body is ignored"),
+        )
+        .arg(
+            Arg::with_name("interactive")
+                .long("interactive")
+                .short("i")
+                .conflicts_with_all(&["manifest", "stdin", "cron"])
+                .help("like cp -i: once the download is checked, and before an existing output is replaced, say how it would change and ask on the terminal; anything but yes leaves it alone. stdin must be a terminal"),
+        )
+        .arg(
+            Arg::with_name("json")
+                .long("json")
+                .conflicts_with_all(&["write-out", "dry-run", "cache-dir"])
+                .help("once done, even after a failure, print a JSON object describing the run to stdout, and nothing else; its fields are documented in fetch_maybe::report"),
+        )
+        .arg(
+            Arg::with_name("porcelain")
+                .long("porcelain")
+                .conflicts_with_all(&["json", "write-out", "dry-run", "cache-dir"])
+                .help("once done, even after a failure, print one line to stdout, and nothing else; this format is kept stable:
+    downloaded BYTES SHA256   a body was received and written
+    stored SHA256             installed from --store, without downloading
+    not-modified              the server had nothing new
+    skipped REASON            not asked: min-age or no-clobber
+    error KIND                failed: usage, dns, connect, tls, timeout,
+                              client-error, server-error, filesystem,
+                              verification, locked or other"),
+        )
+        .arg(
+            Arg::with_name("events")
+                .long("events")
+                .conflicts_with_all(&["json", "porcelain", "write-out", "dry-run", "cache-dir"])
+                .help("as the run goes, print events to stdout as JSON objects, one a line, and nothing else: start, decision, response, progress, verified, persisted and done; their fields are documented in fetch_maybe::events"),
+        )
+        .arg(
+            Arg::with_name("metrics-file")
+                .long("metrics-file")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("after every run, even a failed one, replace FILE with Prometheus gauges for node_exporter's textfile collector: the last run, last success, outcome, bytes and duration, labelled with the output"),
+        )
+        .arg(
+            Arg::with_name("stats")
+                .long("stats")
+                .help("print the summary line logged at info level (-vv) to stderr, whatever the verbosity: the outcome, status, size, rate, how many lookups --dns-cache-ttl saved, timings, and whether the output changed"),
+        )
+        .arg(
+            Arg::with_name("write-out")
+                .validator(check::write_out)
+                .short("w")
+                .long("write-out")
+                .takes_value(true)
+                .value_name("TEMPLATE")
+                .help("once done, print TEMPLATE to stdout, with %{status}, %{size_download}, %{time_starttransfer}, %{time_total}, %{final_url}, %{remote_ip}, %{outcome} or %{output} filled in, even after a failure; \\n is a newline. %{time_namelookup} and %{time_connect} are accepted, but left empty, as they can't be told apart"),
+        )
+        .arg(
+            Arg::with_name("dry-run")
+                .long("dry-run")
+                .help("print the request that would be sent, and whether it would be, to stdout; the output is looked at, but nothing is written, or asked of the network"),
+        )
+        .arg(Arg::with_name("empty-on-204").long("empty-on-204").help(
+            "on 204 No Content, replace the output with an empty file, instead of leaving it",
+        ))
+        .arg(
+            Arg::with_name("expect-content-type")
+                .long("expect-content-type")
+                .takes_value(true)
+                .number_of_values(1)
+                .multiple(true)
+                .help("fail unless the response's media type matches, e.g. 'application/*'"),
+        )
+        .arg(
+            Arg::with_name("extract")
+                .long("extract")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values(extract::FORMATS)
+                .conflicts_with_all(&[
+                    "also-link",
+                    "append",
+                    "backup",
+                    "cache-dir",
+                    "compare-url",
+                    "compress-output",
+                    "content-disposition",
+                    "delta-index",
+                    "diff",
+                    "in-place",
+                    "interactive",
+                    "keep-versions",
+                    "max-shrink",
+                    "paranoid",
+                    "range",
+                    "store",
+                    "unpack",
+                ])
+                .help("unpack the archive into OUTPUT, a directory, replacing the tree that's there only once all of it's extracted; tar, tar.gz, tar.xz, tar.bz2 or zip, or with auto, whichever it looks like. What was fetched is kept in OUTPUT.meta, so an unchanged archive isn't fetched again"),
+        )
+        .arg(
+            Arg::with_name("min-size")
+                .validator(check::size)
+                .long("min-size")
+                .takes_value(true)
+                .number_of_values(1)
+                .help("fail if the output (after any --unpack) is smaller than this many bytes"),
+        )
+        .arg(
+            Arg::with_name("ok-status")
+                .long("ok-status")
+                .takes_value(true)
+                .value_name("CODES")
+                .validator(check::statuses)
+                .help("the statuses to take the body of, as codes and ranges like 200,203,207-209, instead of any 2xx; a 304 always means not modified"),
+        )
+        .arg(
+            Arg::with_name("on-change")
+                .long("on-change")
+                .takes_value(true)
+                .value_name("CMD")
+                .help("shell command to run after new content is installed; the output is {}, unquoted, or the last argument"),
+        )
+        .arg(
+            Arg::with_name("on-change-failure")
+                .long("on-change-failure")
+                .takes_value(true)
+                .possible_values(&["warn", "fail"])
+                .default_value("fail")
+                .help("whether a failing --on-change or --on-unchanged command fails the run"),
+        )
+        .arg(
+            Arg::with_name("on-unchanged")
+                .long("on-unchanged")
+                .takes_value(true)
+                .value_name("CMD")
+                .help("shell command to run when the run succeeds without new content, like --on-change"),
+        )
+        .arg(
+            Arg::with_name("on-conflict")
+                .long("on-conflict")
+                .takes_value(true)
+                .possible_values(&["fail", "retry", "overwrite"])
+                .default_value("overwrite")
+                .help("if the output changes while we're downloading: fail, start again, or replace it anyway"),
+        )
+        .arg(
+            Arg::with_name("discard")
+                .long("discard")
+                .conflicts_with_all(&["output", "curl-output", "output-fd", "cache-dir", "manifest", "stdin", "head"])
+                .help("fetch and verify the body, then throw it away, as for health checks and warming caches; the same as an output of /dev/null. --time-cond FILE and --etag-compare FILE make it conditional"),
+        )
+        .arg(
+            Arg::with_name("output-fd")
+                .long("output-fd")
+                .takes_value(true)
+                .value_name("FD")
+                .help("write the body to this already open, inherited file descriptor, instead of an output file"),
+        )
+        .arg(
+            Arg::with_name("output-symlink")
+                .long("output-symlink")
+                .takes_value(true)
+                .possible_values(&["follow", "replace"])
+                .default_value("replace")
+                .help("if the output is a symlink, replace the file it points to (keeping the link), or the link itself"),
+        )
+        .arg(
+            Arg::with_name("parallel-segments")
+                .long("parallel-segments")
+                .takes_value(true)
+                .value_name("N")
+                .validator(check::count)
+                .conflicts_with_all(&["append", "range", "unpack", "compress-output", "delta-index"])
+                .help("when the response has Accept-Ranges and a Content-Length, download it as N ranges at once, each written in its place in the temporary file, and each retried on its own; the whole is then checked as usual, before the rename. Otherwise, it's downloaded as one stream. --limit-rate and progress events are for all the segments together"),
+        )
+        .arg(
+            Arg::with_name("paranoid")
+                .long("paranoid")
+                .help("re-read the output after installing it, and check it's what was written"),
+        )
+        .arg(
+            Arg::with_name("preallocate")
+                .long("preallocate")
+                .takes_value(true)
+                .possible_values(&["fallocate", "truncate"])
+                .help("reserve the Content-Length on disk before downloading, so a full disk fails immediately, and the file is less fragmented"),
+        )
+        .arg(
+            Arg::with_name("preserve-xattrs")
+                .long("preserve-xattrs")
+                .help("also copy the replaced output's user.* extended attributes"),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .takes_value(true)
+                .value_name("NAME")
+                .help("also apply the config's [profile.NAME] table, over its top level"),
+        )
+        .arg(
+            Arg::with_name("range")
+                .long("range")
+                .takes_value(true)
+                .number_of_values(1)
+                .help("fetch only bytes START-END (or START- to the end) of the resource"),
+        )
+        .arg(
+            Arg::with_name("range-fallback")
+                .long("range-fallback")
+                .takes_value(true)
+                .possible_values(&["fail", "truncate"])
+                .default_value("fail")
+                .help("what to do if the server ignores --range and sends the whole file"),
+        )
+        .arg(
+            Arg::with_name("reference-time")
+                .long("reference-time")
+                .takes_value(true)
+                .possible_values(&["mtime", "ctime", "btime"])
+                .default_value("mtime")
+                .help("which of the output's times to send as If-Modified-Since and check --min-age against; ctime also moves on chmod, chown and the like, and btime falls back to mtime where unsupported"),
+        )
+        .arg(Arg::with_name("reject-html").long("reject-html").help(
+            "fail if an HTML page arrives, unless the URL or --expect-content-type wants one",
+        ))
+        .arg(
+            Arg::with_name("rename-retries")
+                .validator(check::count)
+                .long("rename-retries")
+                .takes_value(true)
+                .value_name("N")
+                .default_value("4")
+                .help("if replacing the output fails as something else briefly has it open or locked, as virus scanners do, try again up to N times"),
+        )
+        .arg(
+            Arg::with_name("rename-retry-for")
+                .validator(check::duration)
+                .long("rename-retry-for")
+                .takes_value(true)
+                .value_name("DURATION")
+                .default_value("2")
+                .help("how long --rename-retries are spread over, evenly"),
+        )
+        .arg(
+            Arg::with_name("require-header")
+                .long("require-header")
+                .takes_value(true)
+                .number_of_values(1)
+                .multiple(true)
+                .help("fail unless the response has this header, or 'Name: value' exactly"),
+        )
+        .arg(
+            Arg::with_name("history-file")
+                .long("history-file")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("append a JSON line to FILE for each run that downloads: the time, URLs, status, size, sha256 and output"),
+        )
+        .arg(
+            Arg::with_name("history-all")
+                .long("history-all")
+                .requires("history-file")
+                .help("also record runs that found nothing new, or skipped asking"),
+        )
+        .arg(
+            Arg::with_name("in-place")
+                .long("in-place")
+                .help("write into the existing output, keeping its inode for hard links and bind mounts; unlike the usual rename, readers can see a partly written file while it's copied in, and it's fsynced"),
+        )
+        .arg(
+            Arg::with_name("keep-partial")
+                .long("keep-partial")
+                .conflicts_with_all(&["append", "range", "unpack", "compress-output"])
+                .help("if the download fails part way, keep what we got as OUTPUT.part, and carry on from there next time, if the server still has the same file"),
+        )
+        .arg(
+            Arg::with_name("keep-versions")
+                .validator(check::count)
+                .long("keep-versions")
+                .takes_value(true)
+                .value_name("N")
+                .help("keep replaced outputs as OUTPUT.<their mtime>, pruning all but the newest N"),
+        )
+        .arg(
+            Arg::with_name("lock")
+                .long("lock")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("flock this file for the whole run, instead of OUTPUT.lock"),
+        )
+        .arg(
+            Arg::with_name("lock-wait")
+                .validator(check::duration)
+                .long("lock-wait")
+                .takes_value(true)
+                .value_name("DURATION")
+                .default_value("0")
+                .help("how long to wait for another run holding the lock, before exiting with status 75"),
+        )
+        .arg(
+            Arg::with_name("mask-cookies")
+                .long("mask-cookies")
+                .requires("dump-headers")
+                .help("replace Set-Cookie values with *** in --dump-headers"),
+        )
+        .arg(
+            Arg::with_name("limit-rate")
+                .validator(check::size)
+                .long("limit-rate")
+                .takes_value(true)
+                .value_name("RATE")
+                .help("download no faster than this many bytes a second, e.g. 500k"),
+        )
+        .arg(
+            Arg::with_name("limit-rate-total")
+                .validator(check::size)
+                .long("limit-rate-total")
+                .takes_value(true)
+                .value_name("RATE")
+                .help("download no faster than this many bytes a second, across all of a --manifest's entries together"),
+        )
+        .arg(
+            Arg::with_name("max-header-bytes")
+                .validator(check::size)
+                .long("max-header-bytes")
+                .takes_value(true)
+                .number_of_values(1)
+                .default_value("256k")
+                .help("fail if a response's headers are larger than this"),
+        )
+        .arg(
+            Arg::with_name("max-headers")
+                .validator(check::count)
+                .long("max-headers")
+                .takes_value(true)
+                .number_of_values(1)
+                .default_value("300")
+                .help("fail if a response has more headers than this"),
+        )
+        .arg(
+            Arg::with_name("max-size")
+                .validator(check::size)
+                .long("max-size")
+                .takes_value(true)
+                .number_of_values(1)
+                .help("fail, keeping nothing, as soon as the output (after any --unpack) is larger than this many bytes"),
+        )
+        .arg(
+            Arg::with_name("quota-file")
+                .long("quota-file")
+                .takes_value(true)
+                .value_name("PATH")
+                .requires("quota")
+                .help("keep count in PATH of the bytes downloaded, by every run sharing it, including those that failed part way, for --quota"),
+        )
+        .arg(
+            Arg::with_name("quota")
+                .validator(check::size)
+                .long("quota")
+                .takes_value(true)
+                .value_name("SIZE")
+                .requires("quota-file")
+                .help("fail, keeping nothing, exit status 11, rather than take --quota-file's count past this many bytes in a --quota-period; a Content-Length says so before the download starts"),
+        )
+        .arg(
+            Arg::with_name("quota-period")
+                .validator(check::duration)
+                .long("quota-period")
+                .takes_value(true)
+                .value_name("DURATION")
+                .default_value("30d")
+                .help("how long --quota lasts; the count starts again at zero every this long after the --quota-file was first written"),
+        )
+        .arg(
+            Arg::with_name("max-unpack-ratio")
+                .validator(check::count)
+                .long("max-unpack-ratio")
+                .takes_value(true)
+                .number_of_values(1)
+                .default_value("100")
+                .help("with --unpack, fail, keeping nothing, if the body unpacks to more than this many times its size, once it's past a megabyte; 0 to allow any"),
+        )
+        .arg(
+            Arg::with_name("max-shrink")
+                .validator(check::percent)
+                .long("max-shrink")
+                .takes_value(true)
+                .number_of_values(1)
+                .help("fail if the new file is smaller than this PERCENT of the existing one"),
+        )
+        .arg(
+            Arg::with_name("method")
+                .long("method")
+                .takes_value(true)
+                .validator(check::method)
+                .help("send METHOD instead of GET; the response is handled as a GET's is, so If-Modified-Since is still sent, and a 304 still means nothing changed. A 303, or a 301 or 302 after a POST, is followed with a GET"),
+        )
+        .arg(
+            Arg::with_name("min-age")
+                .validator(check::duration)
+                .long("min-age")
+                .takes_value(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("min-free")
+                .validator(check::size)
+                .long("min-free")
+                .takes_value(true)
+                .value_name("BYTES")
+                .help("fail rather than leave less than this free on the disk the download is written to"),
+        )
+        .arg(
+            Arg::with_name("mode")
+                .validator(check::mode)
+                .long("mode")
+                .takes_value(true)
+                .help("octal permissions for the output, e.g. 0644, set before it's installed"),
+        )
+        .arg(
+            Arg::with_name("mtime-from")
+                .long("mtime-from")
+                .takes_value(true)
+                .possible_values(&["server", "download", "keep"])
+                .default_value("server")
+                .help("the output's times: the server's Last-Modified, the download time, or kept from an identical previous output"),
+        )
+        .arg(
+            Arg::with_name("future-mtime")
+                .long("future-mtime")
+                .takes_value(true)
+                .possible_values(timestamp::FUTURE_POLICIES)
+                .default_value("clamp")
+                .help("what to make of a Last-Modified, or an existing output's time, that's after now: take it as now, keep it, or reject it, using the download time or fetching unconditionally"),
+        )
+        .arg(
+            Arg::with_name("no-clobber")
+                .long("no-clobber")
+                .conflicts_with("min-age")
+                .help("if the output exists, leave it alone and don't even ask the server; only fetch missing outputs"),
+        )
+        .arg(
+            Arg::with_name("no-config")
+                .long("no-config")
+                .conflicts_with_all(&["config", "profile"])
+                .help("ignore the config file"),
+        )
+        .arg(
+            Arg::with_name("no-hsts")
+                .long("no-hsts")
+                .help("neither ask over https for an http URL whose host has sent Strict-Transport-Security, nor remember any that do, in $XDG_STATE_HOME/fetch-maybe/hsts"),
+        )
+        .arg(
+            Arg::with_name("no-lock")
+                .long("no-lock")
+                .conflicts_with_all(&["lock", "lock-wait"])
+                .help("don't lock, e.g. where the output's directory doesn't support flock"),
+        )
+        .arg(
+            Arg::with_name("no-mtime")
+                .long("no-mtime")
+                .conflicts_with("mtime-from")
+                .help("the same as --mtime-from download; leave the output's times as when it was downloaded; later If-Modified-Since requests then rely on the server and our clocks agreeing"),
+        )
+        .arg(
+            Arg::with_name("no-preflight")
+                .long("no-preflight")
+                .help("don't check, before downloading, that the output can be replaced: that it isn't immutable, and that we may rename in its directory"),
+        )
+        .arg(
+            Arg::with_name("no-preserve")
+                .long("no-preserve")
+                .help("treat the output as new: 0666 less the umask, owned by us, instead of copying the old one's"),
+        )
+        .arg(
+            Arg::with_name("no-preserve-xattr")
+                .long("no-preserve-xattr")
+                .conflicts_with("preserve-xattrs")
+                .help("don't copy the replaced output's SELinux context and ACL onto the new one"),
+        )
+        .arg(
+            Arg::with_name("no-verify-storage-checksums")
+                .long("no-verify-storage-checksums")
+                .help("ignore checksums claimed by S3/GCS headers and ETags"),
+        )
+        .arg(
+            Arg::with_name("retry")
+                .validator(check::count)
+                .long("retry")
+                .takes_value(true)
+                .value_name("N")
+                .default_value("0")
+                .help("if the body fails part way, try again up to N times, carrying on from where it stopped if the server supports ranges; a host name that fails to resolve for now is tried again too"),
+        )
+        .arg(
+            Arg::with_name("retry-non-idempotent")
+                .long("retry-non-idempotent")
+                .help("let --retry, and --on-conflict retry, send a POST, or any other method that mightn't be safe to send twice, again; they're otherwise only for GET, HEAD, PUT, DELETE, OPTIONS and TRACE"),
+        )
+        .arg(
+            Arg::with_name("sha256")
+                .long("sha256")
+                .takes_value(true)
+                .number_of_values(1)
+                .help("refuse the download unless its sha256 is HEX, or is listed in @FILE"),
+        )
+        .arg(
+            Arg::with_name("store")
+                .long("store")
+                .takes_value(true)
+                .value_name("DIR")
+                .conflicts_with_all(&["append", "compress-output", "in-place", "keep-partial", "unpack"])
+                .help("keep downloads in DIR by sha256, hard linking identical outputs to one copy; with a known sha256, a stored copy is used without downloading"),
+        )
+        .arg(
+            Arg::with_name("temp-dir")
+                .long("temp-dir")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("download into a temporary file here, instead of next to the output; if it's another filesystem, the result is copied across before the rename"),
+        )
+        .arg(
+            Arg::with_name("ttfb-timeout")
+                .long("ttfb-timeout")
+                .takes_value(true)
+                .value_name("DURATION")
+                .validator(check::duration)
+                .help("give up on a request if its response's headers haven't come within this long of sending it, e.g. 30s; the body may then go quiet for as long at most, too"),
+        )
+        .arg(
+            Arg::with_name("unpack")
+                .long("unpack")
+                .takes_value(true)
+                .possible_values(unpack::FORMATS)
+                .conflicts_with_all(&["append", "range"])
+                .help("decompress the body into the output; --sha256 and storage checksums still apply to the body as sent"),
+        )
+        .arg(
+            Arg::with_name("user")
+                .short("u")
+                .long("user")
+                .takes_value(true)
+                .number_of_values(1)
+                .help("USER[:PASSWORD] for Basic auth, overriding any in the URL"),
+        )
+        .arg(
+            Arg::with_name("validate-cmd")
+                .long("validate-cmd")
+                .takes_value(true)
+                .number_of_values(1)
+                .help("shell command to check the download before it's installed; the file is {}, unquoted, or the last argument"),
+        )
+        .arg(
+            Arg::with_name("verbose")
+                .short("v")
+                .multiple(true)
+                .help("log more, up to -vvvv; RUST_LOG, if set, wins for the modules it names, or everywhere for a bare level"),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .short("q")
+                .long("quiet")
+                .multiple(true)
+                .help("only a one-line error on failure; twice for nothing but the exit status"),
+        )
+        .arg(
+            Arg::with_name("log-format")
+                .long("log-format")
+                .takes_value(true)
+                .possible_values(&["human", "json"])
+                .default_value("human")
+                .help("json: each log record as an object on a line, with the URL, status and so on as fields"),
+        )
+        .arg(
+            Arg::with_name("log-timestamps")
+                .long("log-timestamps")
+                .takes_value(true)
+                .possible_values(&["local", "utc", "none"])
+                .default_value("local")
+                .help("how log lines are timed; utc is ISO 8601"),
+        )
+        .arg(
+            Arg::with_name("log-show-target")
+                .long("log-show-target")
+                .takes_value(true)
+                .possible_values(&["on", "off"])
+                .default_value("on")
+                .help("whether log lines name the module they're from"),
+        )
+        .arg(
+            Arg::with_name("color")
+                .long("color")
+                .takes_value(true)
+                .possible_values(&["auto", "always", "never"])
+                .default_value("auto")
+                .help("colour the log; auto is when stderr is a terminal, and NO_COLOR or CLICOLOR_FORCE aren't set"),
+        )
+        .arg(
+            Arg::with_name("log-target")
+                .long("log-target")
+                .takes_value(true)
+                .possible_values(&["stderr", "syslog", "journald"])
+                .default_value("stderr")
+                .help("where to log: journald gets the URL, status and so on as fields"),
+        )
+        .arg(
+            Arg::with_name("log-file")
+                .long("log-file")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("also append the log to this file, reopening it on SIGHUP"),
+        )
+        .arg(
+            Arg::with_name("log-file-only")
+                .long("log-file-only")
+                .requires("log-file")
+                .help("log only to --log-file, not stderr"),
+        )
+        .arg(
+            Arg::with_name("cron")
+                .long("cron")
+                .help("nothing on success, and one self-contained paragraph on failure"),
+        )
+        .arg(
+            Arg::with_name("generate-completions")
+                .long("generate-completions")
+                .takes_value(true)
+                .value_name("SHELL")
+                .possible_values(&["bash", "zsh", "fish", "powershell", "elvish"])
+                .hidden(true)
+                .help("print the completion script for a shell, and exit"),
+        )
+        .arg(
+            Arg::with_name("manifest")
+                .long("manifest")
+                .takes_value(true)
+                .value_name("FILE")
+                .conflicts_with_all(&["url", "output", "output-fd", "cache-dir"])
+                .help("fetch each line's URL OUTPUT [--OPTION VALUE...] in turn, sharing connections; the other options apply to every line"),
+        )
+        .arg(
+            Arg::with_name("stdin")
+                .long("stdin")
+                .conflicts_with_all(&["url", "output", "output-fd", "cache-dir"])
+                .help("as --manifest, but reading the lines from stdin, starting each as it arrives; a bad line is a failure, not the end"),
+        )
+        .arg(
+            Arg::with_name("null")
+                .short("0")
+                .long("null")
+                .requires("stdin")
+                .help("with --stdin, read NUL-terminated URL, then OUTPUT, then URL..., with no quoting or options"),
+        )
+        .group(ArgGroup::with_name("batch").args(&["manifest", "stdin"]))
+        .arg(
+            Arg::with_name("jobs")
+                .validator(check::count)
+                .long("jobs")
+                .takes_value(true)
+                .value_name("N")
+                .requires("batch")
+                .help("fetch up to N of the --manifest's entries at once, rather than one, with each log line starting with its entry's line number"),
+        )
+        .arg(
+            Arg::with_name("fail-fast")
+                .long("fail-fast")
+                .requires("batch")
+                .help("once a --manifest entry fails, start no more; those under way are finished"),
+        )
+        .arg(
+            Arg::with_name("per-host-delay")
+                .validator(check::duration)
+                .long("per-host-delay")
+                .takes_value(true)
+                .value_name("DURATION")
+                .requires("batch")
+                .help("start a --manifest entry's request at least this long after the last to the same host; others go meanwhile"),
+        )
+        .arg(
+            Arg::with_name("per-host-max")
+                .validator(check::count)
+                .long("per-host-max")
+                .takes_value(true)
+                .value_name("N")
+                .requires("batch")
+                .help("with --jobs, have at most N of the --manifest's entries for any one host under way at once"),
+        )
+        .arg(
+            Arg::with_name("splay")
+                .validator(check::duration)
+                .long("splay")
+                .takes_value(true)
+                .value_name("DURATION")
+                .help("first wait a random time up to this long, so the same crontab line on many machines doesn't hit the server all at once; FETCH_MAYBE_SEED fixes the choice"),
+        )
+        .arg(
+            Arg::with_name("watch")
+                .validator(check::duration)
+                .long("watch")
+                .takes_value(true)
+                .value_name("DURATION")
+                .conflicts_with_all(&["stdin", "gc", "fail-if-unchanged"])
+                .help("stay running, fetching again this long after each fetch started, or the --manifest's entries; SIGHUP checks now, SIGTERM stops after the fetch under way"),
+        )
+        .arg(
+            Arg::with_name("watch-jitter")
+                .validator(check::duration)
+                .long("watch-jitter")
+                .takes_value(true)
+                .value_name("DURATION")
+                .requires("watch")
+                .help("with --watch, wait up to this much longer each time, at random, so a fleet's checks spread out"),
+        )
+        .arg(
+            Arg::with_name("watch-fail-fast")
+                .long("watch-fail-fast")
+                .requires("watch")
+                .help("with --watch, exit after a failed fetch, rather than trying again next time"),
+        )
+        .arg(
+            Arg::with_name("url")
+                .index(1)
+                .validator(check::url)
+                .required_unless_one(&["generate-completions", "manifest", "stdin", "gc"]),
+        )
+        .arg(
+            Arg::with_name("output")
+                .index(2)
+                .required_unless_one(&["output-fd", "cache-dir", "generate-completions", "manifest", "stdin", "curl-output", "head", "discard"])
+                .conflicts_with("output-fd")
+                .help("file to write, a directory to write the URL's file name in, or - for stdout; an existing FIFO or device is written into as it is, with --time-cond and --etag-compare for conditions, as its times mean nothing"),
+        )
+        .args(&curl::args())
+        .subcommand(
+            clap::SubCommand::with_name("status")
+                .about("print what's known of an output, without the network: its age; for a --cache-dir entry, the URL, final URL, ETag, Last-Modified and Cache-Control or Expires expiry its .meta recorded, and when it was fetched; the last run, success and outcome from --metrics-file; and what a run would decide. Always a success, however stale it is")
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("print a JSON object instead, with null for what isn't known"),
+                )
+                .arg(
+                    Arg::with_name("remote")
+                        .long("remote")
+                        .help("also send the URL a HEAD, and say whether it looks newer, by its ETag or Last-Modified"),
+                )
+                .arg(
+                    Arg::with_name("min-age")
+                        .long("min-age")
+                        .takes_value(true)
+                        .value_name("DURATION")
+                        .validator(check::duration)
+                        .help("say what a run with this --min-age would decide"),
+                )
+                .arg(
+                    Arg::with_name("metrics-file")
+                        .long("metrics-file")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("the --metrics-file the output's runs keep, for when they were"),
+                )
+                .arg(
+                    Arg::with_name("url")
+                        .index(1)
+                        .required(true)
+                        .value_name("URL")
+                        .help("the URL the output's fetched from; or alone, the output, if its .meta records the URL"),
+                )
+                .arg(Arg::with_name("output").index(2).value_name("OUTPUT")),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("verify")
+                .about("re-hash each output, without the network, and check it against what was recorded when it was written: a --cache-dir entry's .meta, or the .blocks kept for --delta-index. Prints OUTPUT: OK, MODIFIED, MISSING, NO-METADATA, or NO-DIGEST for a record from before digests were kept; any but OK and NO-DIGEST fail the run")
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("print a JSON object for each output instead"),
+                )
+                .arg(
+                    Arg::with_name("outputs")
+                        .value_name("OUTPUT")
+                        .required(true)
+                        .multiple(true),
+                ),
+        )
+        .setting(clap::AppSettings::SubcommandsNegateReqs)
+        .setting(clap::AppSettings::DisableHelpSubcommand)
+        .version(clap::crate_version!())
+        .version_message("Prints version information; with -v, also the commit, target and features it was built with")
+        .after_help(exit::STATUSES)
+}
+
+#[cfg(feature = "async")]
+pub use nonblocking::fetch_async;
+
+static REQUESTS: AtomicUsize = AtomicUsize::new(0);
+
+/// How many requests this process has made, counting redirects, resumes and checksum fetches.
+pub fn requests_made() -> usize {
+    REQUESTS.load(Ordering::Relaxed)
+}
+
+/// A download, for running from another program rather than the command line.
+///
+/// It's `fetch-maybe URL OUTPUT`, plus `args` for anything without a field here, written
+/// as they would be on the command line: `["--sha256", "..."]`. Config files and the
+/// environment aren't consulted.
+#[derive(Clone, Debug)]
+pub struct FetchRequest {
+    pub url: String,
+    pub output: PathBuf,
+    /// As for `--header`: `Name: value`.
+    pub headers: Vec<String>,
+    pub min_age: Option<time::Duration>,
+    pub args: Vec<OsString>,
+    /// Share one between requests to reuse its connections; it keeps their cookies, too.
+    /// Without one, each request gets a fresh connection, as on the command line.
+    pub agent: Option<ureq::Agent>,
+}
+
+impl FetchRequest {
+    pub fn new<U: Into<String>, O: Into<PathBuf>>(url: U, output: O) -> FetchRequest {
+        FetchRequest {
+            url: url.into(),
+            output: output.into(),
+            headers: Vec::new(),
+            min_age: None,
+            args: Vec::new(),
+            agent: None,
+        }
+    }
+}
+
+/// What a successful `fetch` did to the output.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FetchOutcome {
+    /// The output was replaced, or appended to; `bytes` is `None` if it was installed from
+    /// `--store`, without a download.
+    Downloaded {
+        bytes: Option<u64>,
+        last_modified: Option<time::SystemTime>,
+    },
+    /// The server had nothing newer.
+    NotModified,
+    /// It was left alone before asking the server, e.g. for `min_age`.
+    Skipped,
+}
+
+/// Fetch `request.url` to `request.output`, if it's changed, as the command line would.
+///
+/// Nothing is logged unless the caller has set up a `log` logger, no signal handlers are
+/// installed, and another run holding the output's lock is an error, not an exit.
+pub fn fetch(request: &FetchRequest) -> Result<FetchOutcome, failure::Error> {
+    let mut args: Vec<OsString> = vec![
+        "fetch-maybe".into(),
+        request.url.clone().into(),
+        request.output.clone().into(),
+    ];
+    for header in &request.headers {
+        args.push("--header".into());
+        args.push(header.into());
+    }
+    if let Some(min_age) = request.min_age {
+        args.push("--min-age".into());
+        args.push(format!("{}", min_age.as_secs()).into());
+    }
+    args.extend(request.args.iter().cloned());
+
+    let matches = app()
+        .get_matches_from_safe(&args)
+        .map_err(|e| exit::classified(exit::Kind::Usage, e.message))?;
+
+    let mut outcome = outcome::Outcome::default();
+    let shared = Shared {
+        agent: request.agent.clone(),
+        ..Shared::default()
+    };
+    run(&matches, &shared, &mut outcome)?;
+    Ok(match outcome.kind {
+        outcome::Kind::Fetched | outcome::Kind::Stored => FetchOutcome::Downloaded {
+            bytes: outcome
+                .bytes
+                .filter(|_| outcome::Kind::Fetched == outcome.kind),
+            last_modified: outcome.last_modified,
+        },
+        outcome::Kind::Unchanged => FetchOutcome::NotModified,
+        outcome::Kind::Skipped => FetchOutcome::Skipped,
+    })
+}
+
+/// What the fetches in a run share, when there's more than one.
+#[derive(Clone, Default)]
+pub struct Shared {
+    /// Without one, each request gets a fresh connection.
+    pub agent: Option<ureq::Agent>,
+    /// For `--limit-rate-total`; without one, it only covers this fetch.
+    pub rate: Option<rate::Bucket>,
+    /// For `--events`; without them on, a run with `--events` has a stream of its own.
+    pub events: events::Events,
+    /// For `--dns-cache-ttl`; without one, it only lasts the run.
+    pub dns: dns::Cache,
+}
+
+/// The whole job for parsed arguments, after the config and environment are applied: the
+/// attempts, then the reports and hooks. `outcome` is left with what the last attempt did.
+pub fn run(
+    matches: &clap::ArgMatches,
+    shared: &Shared,
+    outcome: &mut outcome::Outcome,
+) -> Result<(), failure::Error> {
+    let events = if shared.events.is_on() || !matches.is_present("events") {
+        shared.events.clone()
+    } else {
+        events::Events::stdout()
+    };
+    let result = job(matches, shared, &events, outcome);
+    events.emit(
+        "done",
+        &[
+            (
+                "outcome",
+                json::string(if result.is_ok() {
+                    outcome.kind.name()
+                } else {
+                    "failed"
+                }),
+            ),
+            (
+                "error",
+                events::value(
+                    result
+                        .as_ref()
+                        .err()
+                        .map(|e| exit::kind_name(e, outcome.phase)),
+                ),
+            ),
+        ],
+    );
+    // however it ended, so there's always one
+    let report = if matches.is_present("json") {
+        Some(report::json(outcome, result.as_ref().err()))
+    } else if matches.is_present("porcelain") {
+        Some(report::porcelain(outcome, result.as_ref().err()))
+    } else {
+        None
+    };
+    if let Some(report) = report {
+        let mut stdout = io::stdout();
+        stdout.write_all(report.as_bytes())?;
+        stdout.flush()?;
+    }
+    result
+}
+
+fn job(
+    matches: &clap::ArgMatches,
+    shared: &Shared,
+    events: &events::Events,
+    outcome: &mut outcome::Outcome,
+) -> Result<(), failure::Error> {
+    let run_started = time::Instant::now();
+    let mut buckets = Vec::new();
+    if let Some(v) = matches.value_of("limit-rate") {
+        buckets.push(rate::Bucket::new(size::parse_size(v)?));
+    }
+    match (&shared.rate, matches.value_of("limit-rate-total")) {
+        (Some(total), _) => buckets.push(total.clone()),
+        (None, Some(v)) => buckets.push(rate::Bucket::new(size::parse_size(v)?)),
+        (None, None) => (),
+    }
+
+    let mut retries = {
+        let v = matches.value_of("retry").expect("defaulted");
+        v.parse::<usize>()
+            .with_context(|_| format_err!("parsing retry: {:?}", v))?
+    };
+
+    let write_out = match matches.value_of("write-out") {
+        Some(v) => Some(write_out::Template::parse(v)?),
+        None => None,
+    };
+
+    let method = method::from_matches(matches);
+    let resend = method::is_idempotent(method) || matches.is_present("retry-non-idempotent");
+    if !resend && retries > 0 {
+        warn!(
+            "--retry: not sending a {} again without --retry-non-idempotent",
+            method
+        );
+        retries = 0;
+    }
+
+    let quota = if matches.is_present("dry-run") {
+        None
+    } else {
+        quota::Quota::from_matches(matches)?
+    };
+
+    let delta = delta::Delta::from_matches(matches)?;
+    let segments = segments::Segments::from_matches(matches)?;
+    let expiry = certificate::Expiry::from_matches(matches)?;
+
+    let retry = resend && "retry" == matches.value_of("on-conflict").expect("defaulted");
+    let mut attempts = 1;
+    let result = loop {
+        let started = time::Instant::now();
+        let result = attempt(
+            matches,
+            shared.agent.as_ref(),
+            &Carried {
+                buckets: &buckets,
+                quota: quota.as_ref(),
+                delta: delta.as_ref(),
+                segments: segments.as_ref(),
+                dns: &shared.dns,
+            },
+            &mut retries,
+            events,
+            outcome,
+        );
+        outcome.elapsed = Some(started.elapsed());
+        match result {
+            Err(ref e)
+                if retry
+                    && attempts < conflict::ATTEMPTS
+                    && e.downcast_ref::<conflict::Changed>().is_some() =>
+            {
+                warn!("{}, starting again", e);
+                attempts += 1;
+            }
+            // the retry was already counted, and logged why
+            Err(ref e) if e.downcast_ref::<retry::Restart>().is_some() => (),
+            result => break result,
+        }
+    };
+
+    // only once it's done, so the fetch goes as it would without it
+    let result = match (result, expiry.map(|expiry| expiry.check(outcome))) {
+        (Ok(()), Some(Err(e))) => Err(e),
+        (result, _) => result,
+    };
+
+    // whatever came, even of a failure, counts against it
+    let result = match (result, quota.as_ref().map(quota::Quota::record)) {
+        (Ok(()), Some(Err(e))) => Err(e),
+        (result, Some(Err(e))) => {
+            warn!("{}", e);
+            result
+        }
+        (result, _) => result,
+    };
+
+    // a failure counts too, so a stale success can be alerted on
+    let metrics = match matches.value_of_os("metrics-file") {
+        Some(path) if !matches.is_present("dry-run") && !matches.is_present("head") => {
+            let output = match &outcome.output {
+                Some(output) => output.to_string_lossy().into_owned(),
+                None if matches.is_present("discard") => sink::DISCARD.to_string(),
+                None => curl::output(matches)
+                    .unwrap_or_else(|| OsStr::new("-"))
+                    .to_string_lossy()
+                    .into_owned(),
+            };
+            metrics::write(
+                Path::new(path),
+                &output,
+                outcome,
+                result.is_ok(),
+                run_started.elapsed(),
+                chrono::Utc::now(),
+            )
+        }
+        _ => Ok(()),
+    };
+
+    if matches.is_present("stats") {
+        eprintln!("fetch-maybe: {}", stats::line(outcome, result.is_ok()));
+    } else {
+        info!("         stats: {}", stats::line(outcome, result.is_ok()));
+    }
+
+    // what's known even of a failure, for timing those too
+    if let Some(template) = write_out {
+        let mut stdout = io::stdout();
+        stdout.write_all(template.render(outcome).as_bytes())?;
+        stdout.flush()?;
+    }
+    result?;
+    metrics?;
+
+    // it's all been said, and nothing happened
+    if matches.is_present("dry-run") || matches.is_present("head") {
+        return Ok(());
+    }
+
+    // nothing to dump if it was done before asking the server
+    if let (Some(dest), false) = (
+        matches.value_of_os("dump-headers"),
+        outcome.headers.is_empty(),
+    ) {
+        dump::write(dest, &outcome.headers)?;
+    }
+
+    if let Some(path) = matches.value_of_os("history-file") {
+        if outcome::Kind::Fetched == outcome.kind || matches.is_present("history-all") {
+            history::append(Path::new(path), outcome, chrono::Utc::now())?;
+        }
+    }
+
+    let hook = if outcome.changed() {
+        matches.value_of("on-change").map(|cmd| ("on-change", cmd))
+    } else {
+        matches
+            .value_of("on-unchanged")
+            .map(|cmd| ("on-unchanged", cmd))
+    };
+    if let (Some((flag, cmd)), Some(output)) = (hook, &outcome.output) {
+        let mut env = vec![("FETCH_MAYBE_URL", outcome.url.clone())];
+        if let Some(sha256) = &outcome.sha256 {
+            env.push(("FETCH_MAYBE_SHA256", digest::hex(sha256)));
+        }
+        match hook::run(cmd, output.as_os_str(), &env) {
+            Ok(()) => info!("{:>14}: ran {:?}", flag, cmd),
+            Err(e) if "warn" == matches.value_of("on-change-failure").expect("defaulted") => {
+                warn!("--{} failed: {}", flag, e)
+            }
+            Err(e) => return Err(e.context(format!("running --{}", flag)).into()),
+        }
+    }
+
+    Ok(())
+}
+
+/// What a job's attempts carry from one to the next.
+#[derive(Clone, Copy)]
+struct Carried<'j> {
+    buckets: &'j [rate::Bucket],
+    quota: Option<&'j quota::Quota>,
+    /// Given up on once an attempt finds the index can't be trusted.
+    delta: Option<&'j delta::Delta>,
+    /// Given up on once an attempt finds the server won't send ranges.
+    segments: Option<&'j segments::Segments>,
+    dns: &'j dns::Cache,
+}
+
+/// Everything an attempt makes of its arguments, and of the output as it is, before it asks the
+/// server anything.
+struct Prepared<'a> {
+    matches: &'a clap::ArgMatches<'a>,
+    carried: Carried<'a>,
+    events: &'a events::Events,
+    target: target::Target,
+    started: time::Instant,
+    /// Only the output's times are wanted; nothing is written, not even a temporary file.
+    head: bool,
+    method: &'a str,
+    data_content_type: Option<&'a str>,
+    output_fd: Option<fs::File>,
+    cache_entry: Option<PathBuf>,
+    output_arg: OsString,
+    to_stdout: bool,
+    discard: bool,
+    special: bool,
+    no_output_file: bool,
+    template: Option<(PathBuf, template::Template)>,
+    extract: Option<Option<extract::Format>>,
+    content_disposition: bool,
+    /// The path we expect to write, though the response may yet name it differently.
+    provisional: Option<PathBuf>,
+    follow_symlinks: bool,
+    output_dir: PathBuf,
+    /// Held for the rest of the attempt.
+    _lock: Option<fs::File>,
+    dns_timeout: Option<time::Duration>,
+    dns_cache_ttl: time::Duration,
+    hsts: Option<hsts::Hsts>,
+    ttfb_timeout: Option<time::Duration>,
+    rename_retries: output::RenameRetries,
+    max_header_bytes: u64,
+    max_headers: usize,
+    min_size: Option<u64>,
+    unpack_limits: unpack::Limits,
+    min_free: Option<u64>,
+    max_shrink: Option<u64>,
+    expected_sha256: Option<[u8; 32]>,
+    range: Option<range::ByteRange>,
+    ok_status: statuses::Statuses,
+    credentials: Option<(&'static str, target::Credentials)>,
+    headers: Vec<(&'a str, &'a str)>,
+    metadata_before: Option<fs::Metadata>,
+    cached_etag: Option<String>,
+    no_clobber: bool,
+    future_mtime: &'a str,
+    mtime_before: Option<chrono::DateTime<chrono::Utc>>,
+    append_from: Option<u64>,
+    resume: Option<(PathBuf, partial::Partial)>,
+    mode: Option<u32>,
+    default_mode: u32,
+    owner: Option<perms::Owner>,
+    keep_versions: Option<usize>,
+    diff_lines: usize,
+    compress_output: Option<flate2::Compression>,
+    unpack: Option<Option<unpack::Format>>,
+    requested_range: Option<range::ByteRange>,
+}
+
+/// What's known once there's somewhere for the body to go, less the sink itself.
+struct Staged<'a> {
+    /// Until the temporary file's renamed into place.
+    watched: Option<signals::Watched>,
+    expected_sha256: Option<[u8; 32]>,
+    store_dir: Option<&'a Path>,
+    compared: Option<compare::Sentinel>,
+}
+
+/// The final response to the main request, and how it was got.
+struct Sent<'a> {
+    chain: redirect::Chain,
+    /// As it ended up after the redirects, with the body, if it's still sent.
+    method: &'a str,
+    data: Option<Vec<u8>>,
+    requested_range: Option<range::ByteRange>,
+    remote: Option<std::net::SocketAddr>,
+    response: ureq::Response,
+    interim_body: Option<interim::Body>,
+}
+
+/// What the final response says about the body, and what's to be done with it.
+struct Checked<'a> {
+    chain: redirect::Chain,
+    method: &'a str,
+    data: Option<Vec<u8>>,
+    requested_range: Option<range::ByteRange>,
+    status: u16,
+    content_type: Option<String>,
+    output: PathBuf,
+    metadata_before: Option<fs::Metadata>,
+    template_values: template::Values,
+    appending: bool,
+    resuming: Option<&'a Path>,
+    whole_for_range: Option<range::ByteRange>,
+    expected_types: Vec<&'a str>,
+    server_date: Option<time::SystemTime>,
+    content_length: Option<u64>,
+    unpack_format: Option<Option<unpack::Format>>,
+    storage_claims: storage::Claims,
+    hashing: bool,
+    validator: Option<String>,
+    resumable: bool,
+}
+
+/// The finished download.
+struct Copied {
+    temp: sink::Sink,
+    digest: Option<digest::Digest>,
+    written: Option<digest::Digest>,
+    blocks: Option<delta::Builder>,
+}
+
+/// One attempt at the whole job; `retries` is how many more attempts are allowed after a failed
+/// transfer, shared with any restarts. `outcome` is filled in with what this attempt did.
+fn attempt(
+    matches: &clap::ArgMatches,
+    agent: Option<&ureq::Agent>,
+    carried: &Carried,
+    retries: &mut usize,
+    events: &events::Events,
+    outcome: &mut outcome::Outcome,
+) -> Result<(), failure::Error> {
+    let mut prepared = match prepare(matches, carried, events, outcome)? {
+        Some(prepared) => prepared,
+        None => return Ok(()),
+    };
+    let mut hsts = prepared.hsts.take();
+    let output_fd = prepared.output_fd.take();
+
+    if let Some((source, _)) = &prepared.credentials {
+        debug!("   credentials: from {}", source);
+    }
+
+    let sender = request::Sender {
+        agent,
+        start: &prepared.target.url,
+        credentials: prepared
+            .credentials
+            .as_ref()
+            .map(|(_, credentials)| credentials),
+        headers: &prepared.headers,
+    };
+
+    // read now, and again by the next attempt, so a retry sends the file as it is then
+    let data = match matches.value_of("data") {
+        Some(v) => Some(method::data(v)?),
+        None => None,
+    };
+
+    if prepared.head {
+        return fetch_head(&prepared, &sender, &mut hsts, retries, outcome);
+    }
+
+    // no point doing any networking if we aren't going to be able to store the result
+    let (temp, staged) = match stage(&prepared, output_fd, &sender, outcome)? {
+        Some(staged) => staged,
+        None => return Ok(()),
+    };
+    let sent = match send(&prepared, &sender, &mut hsts, data, retries, outcome)? {
+        Some(sent) => sent,
+        None => return Ok(()),
+    };
+    let (checked, body) = match check(&prepared, &staged, &temp, sent, outcome)? {
+        Some(checked) => checked,
+        None => return Ok(()),
+    };
+    let copied = copy_body(&prepared, &sender, &checked, body, temp, retries, outcome)?;
+    verify(&prepared, &staged, &checked, &copied.digest, outcome)?;
+    install(&prepared, staged, checked, copied, outcome)
+}
+
+/// Tell `--events` of skipping the download.
+fn skip(events: &events::Events, reason: &str) {
+    events.emit(
+        "decision",
+        &[
+            ("decision", json::string("skip")),
+            ("reason", json::string(reason)),
+        ],
+    )
+}
+
+/// Everything an attempt makes of its arguments and of the output as it is, before asking the
+/// server anything; or `None` if there's no need to.
+fn prepare<'a>(
+    matches: &'a clap::ArgMatches<'a>,
+    carried: &Carried<'a>,
+    events: &'a events::Events,
+    outcome: &mut outcome::Outcome,
+) -> Result<Option<Prepared<'a>>, failure::Error> {
+    let raw_url = matches.value_of("url").expect("required");
+    let mut target = target::parse(raw_url)?;
+    *outcome = outcome::Outcome {
+        url: target.url.to_string(),
+        phase: "preparing",
+        ..outcome::Outcome::default()
+    };
+    let started = time::Instant::now();
+    let dry_run = matches.is_present("dry-run");
+    // only the output's times are wanted; nothing is written, not even a temporary file
+    let head = matches.is_present("head");
+    let method = method::from_matches(matches);
+    let data_content_type = matches.value_of("data-content-type");
+    let output_fd = match matches.value_of("output-fd") {
+        Some(v) => Some(sink::inherited_fd(v)?),
+        None => None,
+    };
+
+    let cache_entry = matches
+        .value_of_os("cache-dir")
+        .map(|dir| cache::path(Path::new(dir), &target.url));
+
+    // there's no output file with --output-fd, so a placeholder
+    let output_arg = match &cache_entry {
+        Some(entry) => entry.as_os_str(),
+        None if matches.is_present("discard") => OsStr::new(sink::DISCARD),
+        None => curl::output(matches).unwrap_or_else(|| OsStr::new("-")),
+    };
+    if matches.is_present("interactive") {
+        confirm::check_terminal()?;
+    }
+    let to_stdout = "-" == output_arg;
+    // renaming over /dev/null would replace the device, if we were even allowed to
+    let discard = Path::new(output_arg) == Path::new(sink::DISCARD);
+    // nor can a FIFO or a device be renamed over, so the body's written straight into it
+    let special = !to_stdout && !discard && sink::is_special(Path::new(output_arg));
+    let no_output_file = to_stdout || discard || special;
+    curl::check_conditions(matches, no_output_file)?;
+    if let (true, Some(flag)) = (
+        to_stdout,
+        report::FLAGS.iter().find(|flag| matches.is_present(flag)),
+    ) {
+        return Err(exit::classified(
+            exit::Kind::Usage,
+            format!(
+                "--{} has stdout to itself, so the output can't be '-'",
+                flag
+            ),
+        ));
+    }
+    if no_output_file {
+        if let Some(flag) = sink::FILE_ONLY
+            .iter()
+            .find(|flag| matches.occurrences_of(flag) > 0)
+        {
+            bail!(
+                "--{} needs an output file, so can't be used when {}",
+                flag,
+                if discard {
+                    "discarding the body"
+                } else if special {
+                    "writing into a FIFO or device"
+                } else {
+                    "writing to stdout ('-')"
+                }
+            );
+        }
+    }
+
+    // placeholders are filled in once what they stand for is known, so the directory can't have any
+    let template =
+        if !no_output_file && cache_entry.is_none() && template::is_template(output_arg) {
+            let path = Path::new(output_arg);
+            let dir = path.parent().unwrap_or_else(|| Path::new(""));
+            if template::is_template(dir.as_os_str()) {
+                bail!(
+                    "placeholders can only be in the output's file name, not its directory: {:?}",
+                    output_arg
+                );
+            }
+            let name = path.file_name().and_then(OsStr::to_str).ok_or_else(|| {
+                format_err!("output name with placeholders isn't UTF-8: {:?}", path)
+            })?;
+            let template = template::Template::parse(name)?;
+            if template.needs_response() {
+                if let Some(flag) = ["append", "extract", "keep-partial"]
+                    .iter()
+                    .find(|flag| matches.is_present(flag))
+                {
+                    bail!(
+                    "--{} needs the output before asking the server, so can't be used with {:?}",
+                    flag,
+                    name
+                );
+                }
+            }
+            Some((dir.to_path_buf(), template))
+        } else {
+            None
+        };
+
+    let extract = match matches.value_of("extract") {
+        Some(arg) => Some(extract::from_arg(arg)?),
+        None => None,
+    };
+
+    let into_directory = !no_output_file
+        && extract.is_none()
+        && cache_entry.is_none()
+        && template.is_none()
+        && output::is_directory(output_arg);
+    let content_disposition = into_directory && matches.is_present("content-disposition");
+
+    // the path we expect to write, though a Content-Disposition may yet name it differently
+    let provisional = if no_output_file {
+        None
+    } else if let Some((dir, template)) = &template {
+        if template.needs_response() {
+            info!(
+                "   output name: waiting for the response, for {:?}",
+                output_arg
+            );
+            None
+        } else {
+            let values = template::Values::new(&target.url, None, None, None);
+            Some(output::join(dir.as_os_str(), &template.render(&values)?)?)
+        }
+    } else if into_directory {
+        match output::name_from_url(&target.url) {
+            Ok(name) => {
+                let derived = output::join(output_arg, &name)?;
+                info!("   output name: {:?}, from the URL", derived);
+                Some(derived)
+            }
+            Err(e) if content_disposition => {
+                info!("   output name: waiting for a Content-Disposition: {}", e);
+                None
+            }
+            Err(e) => return Err(e),
+        }
+    } else {
+        Some(PathBuf::from(output_arg))
+    };
+
+    // resolved before anything looks at the output, so the checks see the file the rename replaces
+    let follow_symlinks = "follow" == matches.value_of("output-symlink").expect("defaulted");
+    let provisional = match provisional {
+        Some(output) if follow_symlinks => {
+            let resolved = output::follow_symlinks(&output, matches.is_present("create-dirs"))?;
+            if resolved != output {
+                info!("   output path: following symlink to {:?}", resolved);
+            }
+            Some(resolved)
+        }
+        other => other,
+    };
+
+    // relative paths with no directory part have an empty parent, which check_directory accepts
+    let output_dir = match (&provisional, &template) {
+        (Some(output), _) if !into_directory => output.parent().unwrap_or_else(|| Path::new("/")),
+        (None, Some((dir, _))) => dir.as_path(),
+        _ => Path::new(output_arg),
+    };
+    if !no_output_file {
+        let create = matches.is_present("create-dirs") || cache_entry.is_some();
+        if create && !dry_run && !head {
+            let mode = perms::parse_mode(matches.value_of("dirs-mode").expect("defaulted"))
+                .with_context(|_| err_msg("parsing --dirs-mode"))?;
+            output::create_directory(output_dir, mode)?;
+        }
+        // a dry run would have created it, and without it, there's just nothing to be conditional on
+        if !((create && dry_run) || head) || output_dir.exists() {
+            output::check_directory(output_dir)?;
+        }
+    }
+
+    // before anything looks at the output, so a second run decides based on the first's result
+    let lock_path = match (matches.value_of_os("lock"), &provisional) {
+        // a dry run writes nothing, not even the lock
+        _ if matches.is_present("no-lock") || dry_run || head => None,
+        (Some(path), _) => Some(PathBuf::from(path)),
+        (None, Some(output)) => Some(lock::default_path(output)),
+        (None, None) => None,
+    };
+    let lock = match &lock_path {
+        Some(path) => {
+            let wait = duration_arg(matches, "lock-wait")?.expect("defaulted");
+            match lock::acquire(path, wait)? {
+                Some(lock) => Some(lock),
+                None => {
+                    info!("another run holds {:?}, leaving it to that", path);
+                    return Err(lock::Held.into());
+                }
+            }
+        }
+        None => None,
+    };
+
+    debug!("     input URL: {:?}", target::redact(raw_url));
+    debug!("normalised URL: {:?}", target.url.as_str());
+    proxy::warn_if_wanted(&target.url);
+    debug!("   output path: {:?}", provisional);
+
+    let min_age = match matches.value_of("min-age") {
+        Some(v) => Some(
+            period::parse_duration(v).with_context(|_| format_err!("parsing min-age: {:?}", v))?,
+        ),
+        None => None,
+    };
+
+    debug!("       min-age: {:?}", min_age);
+
+    let dns_timeout = duration_arg(matches, "dns-timeout")?;
+    let dns_cache_ttl = duration_arg(matches, "dns-cache-ttl")?.expect("defaulted");
+    let hsts = hsts::Hsts::from_matches(matches)?;
+    let ttfb_timeout = duration_arg(matches, "ttfb-timeout")?;
+
+    let rename_retries = {
+        let retries: u32 = number_arg(matches, "rename-retries")?.expect("defaulted");
+        let over = duration_arg(matches, "rename-retry-for")?.expect("defaulted");
+        output::RenameRetries {
+            retries,
+            delay: over.checked_div(retries).unwrap_or_default(),
+        }
+    };
+
+    let max_header_bytes = size_arg(matches, "max-header-bytes")?.expect("defaulted");
+    let max_headers: usize = number_arg(matches, "max-headers")?.expect("defaulted");
+    let min_size = size_arg(matches, "min-size")?;
+    let unpack_limits = unpack::Limits {
+        max_size: size_arg(matches, "max-size")?,
+        max_ratio: number_arg(matches, "max-unpack-ratio")?.filter(|&ratio: &u64| ratio > 0),
+    };
+    let min_free = size_arg(matches, "min-free")?;
+
+    let max_shrink = match matches.value_of("max-shrink") {
+        Some(v) => Some(
+            v.trim_end_matches('%')
+                .parse::<u64>()
+                .with_context(|_| format_err!("parsing max-shrink: {:?}", v))?,
+        ),
+        None => None,
+    };
+
+    let expected_sha256 = match matches.value_of("sha256") {
+        Some(v) => Some(
+            checksum::from_arg(v, provisional.as_deref())
+                .with_context(|_| err_msg("parsing sha256"))?,
+        ),
+        None => None,
+    };
+
+    let range = match matches.value_of("range") {
+        Some(v) => Some(
+            range::ByteRange::parse(v).with_context(|_| format_err!("parsing range: {:?}", v))?,
+        ),
+        None => None,
+    };
+
+    let ok_status = match matches.value_of("ok-status") {
+        Some(v) => statuses::Statuses::parse(v)
+            .with_context(|_| format_err!("parsing ok-status: {:?}", v))?,
+        None => statuses::Statuses::default(),
+    };
+
+    let credentials = match matches.value_of("user") {
+        Some(user) => Some(("--user", target::Credentials::from_arg(user))),
+        None => target.credentials.take().map(|c| ("URL", c)),
+    };
+
+    let headers = curl::headers(matches)?;
+
+    // an extracted tree is as new as the archive it came from, as its .meta has it
+    let metadata_before = match &provisional {
+        Some(output) if extract.is_some() => extract::recorded(output)?,
+        Some(output) => metadata_of(output)?,
+        None => None,
+    };
+    if let (Some(output), None) = (&provisional, &extract) {
+        output::check_not_directory(output, metadata_before.as_ref())?;
+    }
+    outcome.output = provisional.clone();
+    events.emit(
+        "start",
+        &[
+            ("url", json::string(&outcome.url)),
+            (
+                "output",
+                events::value(provisional.as_deref().and_then(Path::to_str)),
+            ),
+        ],
+    );
+
+    // only worth asking with something on disk that it describes
+    let cached_etag = match (&cache_entry, matches.value_of_os("etag-compare")) {
+        (Some(entry), _) if metadata_before.is_some() => cache::etag(entry),
+        (None, Some(file)) => curl::etag_compare(Path::new(file))?,
+        (None, None) if extract.is_some() && metadata_before.is_some() => {
+            provisional.as_deref().and_then(cache::etag)
+        }
+        _ => None,
+    };
+
+    let no_clobber = matches.is_present("no-clobber");
+    if no_clobber && metadata_before.is_some() {
+        info!("    no-clobber: output exists, done");
+        outcome.skipped = Some("no-clobber");
+        skip(events, "no-clobber");
+        if dry_run {
+            dry_run::Plan::skipped(method, &outcome.url, "output exists, with --no-clobber")
+                .print(io::stdout().lock())?;
+        }
+        return Ok(None);
+    }
+
+    // otherwise we'd only find out at the rename, after the whole download; probing writes
+    if let (Some(output), false) = (
+        &provisional,
+        matches.is_present("no-preflight") || dry_run || head,
+    ) {
+        preflight::check(
+            output,
+            &dir_of::dir_of(output, env::current_dir)?,
+            metadata_before.as_ref(),
+            matches.is_present("in-place"),
+        )?;
+    }
+
+    let now = chrono::Utc::now();
+
+    let reference_time = matches.value_of("reference-time").expect("defaulted");
+    let future_mtime = matches.value_of("future-mtime").expect("defaulted");
+    // with no output file, -z says which file has the time to compare against
+    let time_cond = match matches.value_of_os("time-cond") {
+        Some(file) if no_output_file => Some(
+            fs::metadata(file).with_context(|_| format_err!("reading --time-cond {:?}", file))?,
+        ),
+        _ => None,
+    };
+    let mtime_before = metadata_before
+        .as_ref()
+        .or(time_cond.as_ref())
+        .and_then(|m| timestamp::reference(m, reference_time))
+        .and_then(|t| timestamp::unless_future(t, now.into(), future_mtime, "the output's time"))
+        .map(timestamp::whole_seconds);
+
+    info!("reference time: {:?}", mtime_before);
+
+    if let Some(mtime) = mtime_before {
+        if let Some(min_age) = min_age {
+            // a min-age reaching back past the earliest date covers any time at all
+            let since = now.checked_sub_signed(min_age);
+            if since.is_none_or(|since| mtime > since) {
+                info!("newer than min-age, done");
+                outcome.skipped = Some("min-age");
+                skip(events, "min-age");
+                if dry_run {
+                    dry_run::Plan::skipped(method, &outcome.url, "within min-age")
+                        .print(io::stdout().lock())?;
+                }
+                return Ok(None);
+            }
+        }
+    }
+
+    // an empty or missing output has nothing worth appending to, so is just fetched normally
+    let append_from = match &metadata_before {
+        Some(metadata) if matches.is_present("append") && metadata.len() > 0 => {
+            info!("        append: from offset {}", metadata.len());
+            Some(metadata.len())
+        }
+        _ => None,
+    };
+
+    let resume = match &provisional {
+        Some(output) if matches.is_present("keep-partial") => {
+            partial::load(output, target.url.as_str())
+                .map(|partial| (partial::part_path(output), partial))
+        }
+        _ => None,
+    };
+    if let Some((part, partial)) = &resume {
+        info!(
+            "       partial: resuming from offset {}, in {:?}",
+            partial.offset, part
+        );
+    }
+
+    let mode = match matches.value_of("mode") {
+        Some(v) => Some(perms::parse_mode(v).with_context(|_| err_msg("parsing --mode"))?),
+        None => None,
+    };
+
+    let default_mode = perms::default_mode();
+
+    let owner = match matches.value_of("chown") {
+        Some(v) => {
+            let owner = perms::Owner::from_arg(v).with_context(|_| err_msg("parsing --chown"))?;
+            owner.check_permitted()?;
+            Some(owner)
+        }
+        None => None,
+    };
+
+    let keep_versions: Option<usize> = number_arg(matches, "keep-versions")?;
+
+    let diff_lines = matches
+        .value_of("diff-lines")
+        .expect("defaulted")
+        .parse::<usize>()
+        .with_context(|_| err_msg("parsing --diff-lines"))?;
+
+    let compress_output = match matches.value_of("compress-output") {
+        Some(v) => Some(compress::from_arg(v)?),
+        None => None,
+    };
+
+    let unpack = match matches.value_of("unpack") {
+        Some(v) => Some(unpack::from_arg(v)?),
+        None => None,
+    };
+
+    let requested_range = range.or(append_from
+        .or_else(|| resume.as_ref().map(|(_, partial)| partial.offset))
+        .map(|start| range::ByteRange { start, end: None }));
+
+    if dry_run {
+        let mut plan = dry_run::Plan {
+            method: method.to_string(),
+            url: outcome.url.clone(),
+            headers: Vec::new(),
+            first: Vec::new(),
+            decision: String::new(),
+        };
+        if let (None, Some(checksum_url)) = (&expected_sha256, matches.value_of("checksum-url")) {
+            plan.first
+                .push(target::parse(checksum_url)?.url.to_string());
+        }
+        // in the order the request gets them, as a header set twice is replaced
+        if credentials.is_some() {
+            plan.set("Authorization", "Basic");
+        }
+        if let Some(mtime) = mtime_before {
+            plan.set("If-Modified-Since", &timestamp::http_date(mtime));
+        }
+        if let Some(etag) = &cached_etag {
+            plan.set("If-None-Match", etag);
+        }
+        if let Some(range) = &requested_range {
+            plan.set("Range", &range.header_value());
+            if let Some((_, partial)) = &resume {
+                plan.set("If-Range", &partial.validator);
+            }
+        }
+        if let Some(content_type) = data_content_type {
+            plan.set("Content-Type", content_type);
+        }
+        for (key, value) in &headers {
+            plan.set(key, value);
+        }
+        let conditional = plan.headers.iter().any(|(k, _)| {
+            ["If-Modified-Since", "If-None-Match", "If-Range"]
+                .iter()
+                .any(|c| c.eq_ignore_ascii_case(k))
+        });
+        plan.decision = if conditional {
+            format!("would send conditional {}", method)
+        } else {
+            format!("would send {}", method)
+        };
+        plan.with_defaults(target.url.host_str().unwrap_or_default())
+            .print(io::stdout().lock())?;
+        outcome.skipped = Some("dry-run");
+        return Ok(None);
+    }
+
+    let output_arg = output_arg.to_os_string();
+    let output_dir = output_dir.to_path_buf();
+    Ok(Some(Prepared {
+        matches,
+        carried: *carried,
+        events,
+        target,
+        started,
+        head,
+        method,
+        data_content_type,
+        output_fd,
+        cache_entry,
+        output_arg,
+        to_stdout,
+        discard,
+        special,
+        no_output_file,
+        template,
+        extract,
+        content_disposition,
+        provisional,
+        follow_symlinks,
+        output_dir,
+        _lock: lock,
+        dns_timeout,
+        dns_cache_ttl,
+        hsts,
+        ttfb_timeout,
+        rename_retries,
+        max_header_bytes,
+        max_headers,
+        min_size,
+        unpack_limits,
+        min_free,
+        max_shrink,
+        expected_sha256,
+        range,
+        ok_status,
+        credentials,
+        headers,
+        metadata_before,
+        cached_etag,
+        no_clobber,
+        future_mtime,
+        mtime_before,
+        append_from,
+        resume,
+        mode,
+        default_mode,
+        owner,
+        keep_versions,
+        diff_lines,
+        compress_output,
+        unpack,
+        requested_range,
+    }))
+}
+
+/// `--head`: only the final response's headers, on stdout, and whatever they say about the output.
+fn fetch_head(
+    prepared: &Prepared,
+    sender: &request::Sender,
+    hsts: &mut Option<hsts::Hsts>,
+    retries: &mut usize,
+    outcome: &mut outcome::Outcome,
+) -> Result<(), failure::Error> {
+    let Prepared {
+        matches,
+        carried: Carried { dns, .. },
+        ref target,
+        started,
+        dns_timeout,
+        dns_cache_ttl,
+        ttfb_timeout,
+        max_header_bytes,
+        max_headers,
+        ref ok_status,
+        mtime_before,
+        ref cached_etag,
+        ..
+    } = *prepared;
+    outcome.phase = "requesting";
+    let mut chain = redirect::Chain::new(target.url.clone(), 10);
+    if matches.is_present("allow-insecure-redirect") {
+        chain.allow_downgrade();
+    }
+    let mut method = "HEAD";
+    let mut heads = String::new();
+    let response = loop {
+        if let Some(hsts) = &hsts {
+            hsts.upgrade(&mut chain)?;
+        }
+        let mut req = sender.request(method, chain.current());
+        if let Some(mtime) = mtime_before {
+            req.set("If-Modified-Since", &timestamp::http_date(mtime));
+        }
+        if let Some(etag) = &cached_etag {
+            req.set("If-None-Match", etag);
+        }
+        sender.set_custom_headers(&mut req);
+
+        debug!(
+            "       request: sending {} {:?}...",
+            method,
+            chain.current().as_str()
+        );
+        outcome.remote = match dns.resolve(
+            chain.current(),
+            dns_timeout,
+            dns_cache_ttl,
+            &mut outcome.lookups,
+        ) {
+            Ok(addresses) => addresses.first().cloned(),
+            Err(e) if e.is_transient() && *retries > 0 => {
+                *retries -= 1;
+                warn!("{}; trying again", e);
+                return Err(retry::Restart.into());
+            }
+            Err(e) => Err(e).with_context(|_| err_msg("requesting"))?,
+        };
+
+        let (response, _) =
+            interim::skip(call(req, None, ttfb_timeout).with_context(|_| err_msg("requesting"))?);
+        if let Some(err) = response.synthetic_error() {
+            Err(ureq_error(err)).with_context(|_| err_msg("requesting"))?;
+        }
+        expect::header_limits(&response, max_header_bytes, max_headers)?;
+        if let Some(hsts) = hsts {
+            hsts.observe(
+                chain.current(),
+                response.header("Strict-Transport-Security"),
+            );
+        }
+
+        if "HEAD" == method && head::refused(response.status()) {
+            warn!(
+                "{:?} for HEAD, so sending a GET, and ignoring its body",
+                response.status_line()
+            );
+            method = "GET";
+            continue;
+        }
+
+        let location = match redirect::location(&response) {
+            Some(location) => location,
+            None => break response,
+        };
+        heads.push_str(&dump::head(&response, false));
+        chain.follow_location(location)?;
+    };
+    heads.push_str(&dump::head(&response, false));
+
+    outcome.first_byte = Some(started.elapsed());
+    outcome.final_url = Some(chain.current().to_string());
+    outcome.status = Some(response.status());
+    outcome.kind = outcome::Kind::Unchanged;
+    let mut stdout = io::stdout();
+    stdout.write_all(heads.as_bytes())?;
+    stdout.flush()?;
+    head::verdict(response.status(), response.status_line(), ok_status)
+}
+
+/// Where the body's going, and anything else to know or check before the main request; or `None`
+/// if that's enough.
+fn stage<'a>(
+    prepared: &'a Prepared,
+    output_fd: Option<fs::File>,
+    sender: &request::Sender,
+    outcome: &mut outcome::Outcome,
+) -> Result<Option<(sink::Sink, Staged<'a>)>, failure::Error> {
+    let Prepared {
+        matches,
+        events,
+        ref target,
+        special,
+        to_stdout,
+        discard,
+        ref provisional,
+        content_disposition,
+        expected_sha256,
+        ref metadata_before,
+        ..
+    } = *prepared;
+    let output_arg = prepared.output_arg.as_os_str();
+    let output_dir = prepared.output_dir.as_path();
+
+    // no point doing any networking if we aren't going to be able to store the result
+    let mut watched = None;
+    let temp = if let Some(fd) = output_fd {
+        sink::Sink::Fd(fd)
+    } else if special {
+        sink::Sink::Special(sink::open_special(Path::new(output_arg))?)
+    } else if to_stdout {
+        sink::Sink::Stdout(io::stdout())
+    } else if discard {
+        sink::Sink::Discard(io::sink())
+    } else {
+        let requested = match (matches.value_of_os("temp-dir"), &provisional) {
+            (Some(dir), _) => PathBuf::from(dir),
+            (None, Some(output)) => dir_of::dir_of(output, env::current_dir)?,
+            (None, None) => output_dir.to_path_buf(),
+        };
+        let output_location = output::real_directory(&requested)?;
+        debug!(
+            "output tmp dir: {:?}, resolved to {:?}",
+            requested, output_location
+        );
+
+        if matches.is_present("clean-stale-temps") {
+            signals::clean_stale_temps(&output_location)?;
+            if matches.is_present("temp-dir") {
+                signals::clean_stale_temps(output_dir)?;
+            }
+        }
+
+        let temp = sink::temp_in(&output_location)?;
+        watched = signals::watch(&temp);
+        sink::Sink::Temp(temp)
+    };
+
+    let expected_sha256 = match (expected_sha256, matches.value_of("checksum-url")) {
+        (None, Some(checksum_url)) => {
+            let checksum_url = target::parse(checksum_url)?.url;
+            let names: Vec<&str> = [
+                target.url.path_segments().and_then(|mut s| s.next_back()),
+                provisional
+                    .as_ref()
+                    .and_then(|o| o.file_name())
+                    .and_then(|n| n.to_str()),
+            ]
+            .iter()
+            .flatten()
+            .cloned()
+            .collect();
+
+            let text = checksum::fetch(&checksum_url, |url| {
+                let mut req = sender.request("GET", url);
+                sender.set_custom_headers(&mut req);
+                req
+            })
+            .with_context(|_| format_err!("fetching checksum from {}", checksum_url))?;
+
+            let expected = checksum::parse_sums(&text, &names)
+                .with_context(|_| format_err!("parsing checksum from {}", checksum_url))?;
+            info!(
+                "      checksum: expecting sha256 {}",
+                digest::hex(&expected)
+            );
+            Some(expected)
+        }
+        (expected, _) => expected,
+    };
+
+    let store_dir = matches.value_of_os("store").map(Path::new);
+    if let Some(store) = store_dir {
+        output::create_directory(store, 0o777)?;
+    }
+
+    // with the content already in the store, there's nothing to download
+    if let (Some(store), Some(expected), Some(output), false) = (
+        store_dir,
+        &expected_sha256,
+        &provisional,
+        content_disposition,
+    ) {
+        if store::install(store, expected, output)? {
+            skip(events, "stored");
+            outcome.kind = outcome::Kind::Stored;
+            outcome.sha256 = Some(*expected);
+            return Ok(None);
+        }
+    }
+
+    // a small sentinel that changes whenever the output does, so it's the one worth asking about
+    let compared = match (matches.value_of("compare-url"), &provisional) {
+        (Some(compare_url), Some(output)) => {
+            let compare_url = target::parse(compare_url)?.url;
+            let last = metadata_before
+                .as_ref()
+                .and_then(|metadata| compare::load(output, compare_url.as_str(), metadata));
+            let fetched = compare::fetch(&compare_url, last.as_ref(), |url| {
+                let mut req = sender.request("GET", url);
+                sender.set_custom_headers(&mut req);
+                req
+            });
+            match fetched {
+                Ok(sentinel) if last.as_ref().map(|l| l.sha256) == Some(sentinel.sha256) => {
+                    info!("       compare: {} is unchanged, done", compare_url);
+                    compare::record(output, &sentinel)?;
+                    outcome.kind = outcome::Kind::Unchanged;
+                    skip(events, "compare-url");
+                    return Ok(None);
+                }
+                Ok(sentinel) => {
+                    info!(
+                        "       compare: {} has changed since the output was fetched, or nobody knows",
+                        compare_url
+                    );
+                    Some(sentinel)
+                }
+                Err(e) => {
+                    warn!(
+                        "couldn't fetch --compare-url {}, so asking about the output as usual: {}",
+                        compare_url, e
+                    );
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+
+    Ok(Some((
+        temp,
+        Staged {
+            watched,
+            expected_sha256,
+            store_dir,
+            compared,
+        },
+    )))
+}
+
+/// The main request, following any redirects; or `None` if an answer on the way is enough.
+fn send<'a>(
+    prepared: &'a Prepared,
+    sender: &request::Sender,
+    hsts: &mut Option<hsts::Hsts>,
+    data: Option<Vec<u8>>,
+    retries: &mut usize,
+    outcome: &mut outcome::Outcome,
+) -> Result<Option<Sent<'a>>, failure::Error> {
+    let Prepared {
+        matches,
+        carried: Carried { dns, .. },
+        events,
+        ref target,
+        method,
+        data_content_type,
+        dns_timeout,
+        dns_cache_ttl,
+        ttfb_timeout,
+        max_header_bytes,
+        max_headers,
+        mtime_before,
+        ref cached_etag,
+        append_from,
+        ref resume,
+        mut requested_range,
+        ..
+    } = *prepared;
+
+    let mut chain = redirect::Chain::new(target.url.clone(), 10);
+    if matches.is_present("allow-insecure-redirect") {
+        chain.allow_downgrade();
+    }
+    let dump_all = matches.is_present("dump-headers-all");
+    let mask_cookies = matches.is_present("mask-cookies");
+
+    let conditional = mtime_before.is_some()
+        || cached_etag.is_some()
+        || (requested_range.is_some() && resume.is_some());
+    events.emit(
+        "decision",
+        &[(
+            "decision",
+            json::string(if conditional {
+                "conditional"
+            } else {
+                "unconditional"
+            }),
+        )],
+    );
+
+    outcome.phase = "requesting";
+    let mut remote;
+    let mut method = method;
+    let mut data = data;
+    let (response, interim_body) = loop {
+        if let Some(hsts) = &hsts {
+            hsts.upgrade(&mut chain)?;
+        }
+        let mut req = sender.request(method, chain.current());
+
+        if let Some(mtime) = mtime_before {
+            req.set("If-Modified-Since", &timestamp::http_date(mtime));
+        }
+
+        if let Some(etag) = &cached_etag {
+            req.set("If-None-Match", etag);
+        }
+
+        if let Some(range) = &requested_range {
+            req.set("Range", &range.header_value());
+            // so a changed resource comes back whole, instead of its end stuck onto our start
+            if let Some((_, partial)) = &resume {
+                req.set("If-Range", &partial.validator);
+            }
+        }
+
+        if let (Some(_), Some(content_type)) = (&data, data_content_type) {
+            req.set("Content-Type", content_type);
+        }
+
+        sender.set_custom_headers(&mut req);
+
+        debug!(
+            url = chain.current().as_str();
+            "       request: sending {} {:?}...", method, chain.current().as_str()
+        );
+
+        // ureq looks it up again, but can't say how it failed, or be told to give up
+        remote = match dns.resolve(
+            chain.current(),
+            dns_timeout,
+            dns_cache_ttl,
+            &mut outcome.lookups,
+        ) {
+            Ok(addresses) => addresses.first().cloned(),
+            Err(e) if e.is_transient() && *retries > 0 => {
+                *retries -= 1;
+                warn!("{}; trying again", e);
+                return Err(retry::Restart.into());
+            }
+            Err(e) => Err(e).with_context(|_| err_msg("requesting"))?,
+        };
+
+        let response =
+            call(req, data.as_deref(), ttfb_timeout).with_context(|_| err_msg("requesting"))?;
+        let (response, interim_body) = interim::skip(response);
+
+        if let Some(err) = response.synthetic_error() {
+            Err(ureq_error(err)).with_context(|_| err_msg("requesting"))?;
+        }
+
+        expect::header_limits(&response, max_header_bytes, max_headers)?;
+        if let Some(hsts) = hsts {
+            hsts.observe(
+                chain.current(),
+                response.header("Strict-Transport-Security"),
+            );
+        }
+
+        if 416 == response.status() && append_from.is_some() && requested_range.is_some() {
+            let remote_len = range::unsatisfiable_length(response.header("Content-Range"));
+            if remote_len == append_from {
+                info!("          done: nothing has been appended on the server");
+                outcome.kind = outcome::Kind::Unchanged;
+                outcome.final_url = Some(chain.current().to_string());
+                outcome.status = Some(response.status());
+                return Ok(None);
+            }
+
+            info!(
+                "        append: remote is now {:?} bytes, shorter than ours, fetching it all",
+                remote_len
+            );
+            requested_range = None;
+            continue;
+        }
+
+        if 416 == response.status() && resume.is_some() && requested_range.is_some() {
+            info!("       partial: the server has no more than we kept, fetching it all");
+            requested_range = None;
+            continue;
+        }
+
+        let location = match redirect::location(&response) {
+            Some(location) => location,
+            None => break (response, interim_body),
+        };
+
+        if dump_all {
+            outcome
+                .headers
+                .push_str(&dump::head(&response, mask_cookies));
+        }
+
+        debug!(
+            "      redirect: {:?} to {:?}",
+            response.status_line(),
+            location
+        );
+
+        if !method::kept_after(response.status(), method) {
+            debug!("      redirect: following with a GET, without the body");
+            method = "GET";
+            data = None;
+        }
+
+        chain.follow_location(location)?;
+    };
+
+    Ok(Some(Sent {
+        chain,
+        method,
+        data,
+        requested_range,
+        remote,
+        response,
+        interim_body,
+    }))
+}
+
+/// The checks on the final response, and what it says about where and how the body's written;
+/// or `None` if it says there's nothing to write. The body's handed back, unless it's a 204's.
+fn check<'a>(
+    prepared: &'a Prepared<'a>,
+    staged: &Staged,
+    temp: &sink::Sink,
+    sent: Sent<'a>,
+    outcome: &mut outcome::Outcome,
+) -> Result<Option<(Checked<'a>, Option<interim::Body>)>, failure::Error> {
+    let Prepared {
+        matches,
+        carried: Carried { quota, .. },
+        events,
+        ref target,
+        started,
+        no_output_file,
+        ref template,
+        content_disposition,
+        ref provisional,
+        follow_symlinks,
+        ref metadata_before,
+        no_clobber,
+        ref ok_status,
+        append_from,
+        ref resume,
+        range,
+        mtime_before,
+        unpack,
+        unpack_limits,
+        compress_output,
+        min_free,
+        ..
+    } = *prepared;
+    let output_arg = prepared.output_arg.as_os_str();
+    let output_dir = prepared.output_dir.as_path();
+    let Staged {
+        expected_sha256,
+        store_dir,
+        ref compared,
+        ..
+    } = *staged;
+    let Sent {
+        chain,
+        method,
+        data,
+        requested_range,
+        remote,
+        response,
+        interim_body,
+    } = sent;
+    let mask_cookies = matches.is_present("mask-cookies");
+
+    debug!(
+        url = chain.current().as_str(), status = response.status();
+        "      response: {:?}", response.status_line()
+    );
+    outcome.phase = "checking the response";
+    outcome.first_byte = Some(started.elapsed());
+    outcome.final_url = Some(chain.current().to_string());
+    outcome.remote = remote;
+    if let Some(remote) = outcome.remote {
+        info!("        remote: {}", remote);
+    }
+    outcome.status = Some(response.status());
+    outcome.etag = response.header("ETag").map(str::to_string);
+    outcome.expires = timestamp::expires(
+        response.header("Cache-Control"),
+        response.header("Expires"),
+        response.header("Age"),
+        time::SystemTime::now(),
+    );
+    if events.is_on() {
+        let headers: Vec<String> = events::HEADERS
+            .iter()
+            .filter_map(|name| {
+                response.header(name).map(|value| {
+                    format!(
+                        "{}:{}",
+                        json::string(&name.to_ascii_lowercase()),
+                        json::string(value)
+                    )
+                })
+            })
+            .collect();
+        events.emit(
+            "response",
+            &[
+                ("status", response.status().to_string()),
+                ("headers", format!("{{{}}}", headers.join(","))),
+            ],
+        );
+    }
+
+    if matches.is_present("dump-headers") {
+        outcome
+            .headers
+            .push_str(&dump::head(&response, mask_cookies));
+    }
+
+    // not up to --ok-status: a 304 is the answer to our If-Modified-Since, and a 206 to our Range
+    let status = response.status();
+    match status {
+        304 /* not modified */ => {
+            info!(status = 304; "          done: not modified on the server");
+            outcome.kind = outcome::Kind::Unchanged;
+            if let (Some(sentinel), Some(output)) = (&compared, &provisional) {
+                compare::record(output, sentinel)?;
+            }
+            return Ok(None)
+        },
+        206 /* partial content */ => match &requested_range {
+            Some(range) => range::check_content_range(range, response.header("Content-Range"))?,
+            None => bail!(
+                "received a partial response, but asked for the whole file: Content-Range: {:?}",
+                response.header("Content-Range")
+            ),
+        },
+        300..=399 if !ok_status.contains(status) => return Err(exit::classified(
+            exit::Kind::Status(status),
+            format!("confused by redirection: {:?}", response.status_line()),
+        )),
+        400..=599 if !ok_status.contains(status) => {
+            let status_line = response.status_line().to_string();
+            if let Some(dest) = matches.value_of_os("fail-with-body") {
+                if let Err(e) = error_body::save(interim::reader(response, interim_body), dest) {
+                    warn!("failed to save error body: {}", e);
+                }
+            }
+            return Err(exit::classified(
+                exit::Kind::Status(status),
+                format!("unhappy response: {:?}", status_line),
+            ))
+        },
+        _ if !ok_status.contains(status) => return Err(exit::classified(
+            exit::Kind::Status(status),
+            format!("unexpected response: {:?}", response.status_line()),
+        )),
+        204 /* no content */ => {
+            if response.header("Content-Length").map(|l| l.trim() != "0").unwrap_or(false) {
+                warn!("protocol anomaly: 204 No Content with a Content-Length: {:?}",
+                      response.header("Content-Length"));
+            }
+            if !matches.is_present("empty-on-204") {
+                info!(status = 204; "          done: no content on the server");
+                outcome.kind = outcome::Kind::Unchanged;
+                return Ok(None)
+            }
+        },
+        _ => (),
+    }
+
+    let template_values = template::Values::new(
+        &target.url,
+        Some(chain.current()),
+        response.header("ETag"),
+        response.header("Last-Modified"),
+    );
+    let late_template = template.as_ref().filter(|(_, t)| t.needs_response());
+
+    let late_name = match late_template {
+        Some((dir, t)) if !t.needs_body() => Some((
+            dir.as_os_str(),
+            t.render(&template_values)?,
+            "from the response",
+        )),
+        _ if content_disposition => response
+            .header("Content-Disposition")
+            .and_then(output::name_from_disposition)
+            .map(|name| (output_arg, name, "from the Content-Disposition")),
+        _ => None,
+    };
+
+    let late_output;
+    let (output, metadata_before) = match late_name {
+        Some((dir, name, source)) => {
+            let joined = output::join(dir, &name)?;
+            late_output = if follow_symlinks {
+                output::follow_symlinks(&joined, matches.is_present("create-dirs"))?
+            } else {
+                joined
+            };
+            info!("   output name: {:?}, {}", late_output, source);
+            // the checks against the old output need the right old output
+            let metadata = if Some(late_output.as_path()) == provisional.as_deref() {
+                metadata_before.clone()
+            } else {
+                metadata_of(&late_output)?
+            };
+            output::check_not_directory(&late_output, metadata.as_ref())?;
+            // only now do we know which output might exist
+            if no_clobber && metadata.is_some() {
+                info!("    no-clobber: output exists, done");
+                outcome.skipped = Some("no-clobber");
+                return Ok(None);
+            }
+            (late_output.as_path(), metadata)
+        }
+        None if no_output_file => (Path::new(output_arg), None),
+        None => match &provisional {
+            Some(output) => (output.as_path(), metadata_before.clone()),
+            // named after the body's hash, below; nothing needs the name before then
+            None if late_template.is_some() => (output_dir, None),
+            None => bail!(
+                "the URL has no file name, and the server sent no usable Content-Disposition; name the output explicitly"
+            ),
+        },
+    };
+    if !no_output_file {
+        outcome.output = Some(output.to_path_buf());
+    }
+
+    let appending = 206 == response.status() && append_from.is_some() && range.is_none();
+
+    if append_from.is_some() && !appending {
+        info!("        append: server sent the whole resource, replacing");
+    }
+
+    let resuming = match &resume {
+        Some((part, _)) if 206 == response.status() && range.is_none() => Some(part.as_path()),
+        Some(_) => {
+            info!("       partial: server sent the whole resource, starting again");
+            None
+        }
+        None => None,
+    };
+
+    // the server ignored our Range and is sending everything, from the start
+    let whole_for_range = match range {
+        Some(range) if 206 != response.status() && 204 != response.status() => {
+            if "truncate" != matches.value_of("range-fallback").expect("defaulted") {
+                bail!(
+                    "server ignored the range request ({:?}), see --range-fallback",
+                    response.status_line()
+                );
+            }
+            warn!("server ignored the range request, truncating the full response locally");
+            Some(range)
+        }
+        _ => None,
+    };
+
+    let expected_types: Vec<&str> = matches
+        .values_of("expect-content-type")
+        .map(|v| v.collect())
+        .unwrap_or_default();
+    expect::content_type(&expected_types, response.header("Content-Type"))?;
+
+    let required_headers: Vec<expect::HeaderRequirement> = matches
+        .values_of("require-header")
+        .map(|v| v.map(expect::HeaderRequirement::parse).collect())
+        .unwrap_or_default();
+    expect::headers(&required_headers, |name| response.all(name))?;
+
+    let server_date = if let Some(server_modified) = response.header("Last-Modified") {
+        chrono::DateTime::parse_from_rfc2822(server_modified)
+            .ok()
+            .map(time::SystemTime::from)
+    } else {
+        None
+    };
+
+    info!("server lastmod: {:?}", server_date);
+    outcome.last_modified = server_date;
+
+    if let (Some(server_date), Some(mtime)) = (server_date, mtime_before) {
+        if !timestamp::server_is_newer(server_date, mtime) {
+            info!("server lastmod: no newer than ours, but the server sent it anyway");
+        }
+    }
+
+    // chunked bodies are delimited by the final chunk instead; the decoder errors if it's missing
+    let content_length: Option<u64> =
+        if response.has("Transfer-Encoding") || whole_for_range.is_some() {
+            None
+        } else {
+            response
+                .header("Content-Length")
+                .and_then(|l| l.trim().parse().ok())
+        };
+
+    // claims describe the whole object, which a local truncation or explicit range isn't
+    let unpack_format = unpack.map(|format| {
+        let format = format.or_else(|| unpack::from_extension(chain.current().path()));
+        debug!("        unpack: {:?}", format);
+        format
+    });
+
+    let storage_claims = if matches.is_present("no-verify-storage-checksums")
+        || range.is_some()
+        || 204 == response.status()
+    {
+        storage::Claims::default()
+    } else {
+        storage::Claims::from_headers(|name| response.all(name))
+    };
+
+    if !storage_claims.is_empty() {
+        debug!("       storage: claims {:?}", storage_claims);
+    }
+
+    let hashing = expected_sha256.is_some()
+        || store_dir.is_some()
+        || matches.is_present("on-change")
+        || late_template.map(|(_, t)| t.needs_body()).unwrap_or(false)
+        || matches.is_present("history-file")
+        || matches.is_present("json")
+        || matches.is_present("porcelain")
+        || !storage_claims.is_empty()
+        || log::log_enabled!(log::Level::Debug);
+    // what actually reaches the file, after any unpacking or compression
+    // compression makes the file smaller than the body, but unpacking only ever needs more
+    if let (Some(len), Some(file)) = (content_length, temp.file()) {
+        if 204 != response.status() && compress_output.is_none() {
+            let existing = match (appending, &resume) {
+                (true, _) => metadata_before.as_ref().map(|m| m.len()).unwrap_or(0),
+                (false, Some((_, partial))) if resuming.is_some() => partial.offset,
+                _ => 0,
+            };
+            space::check(file, len.saturating_add(existing), min_free.unwrap_or(0))?;
+        }
+    }
+
+    // as it's not unpacked, the body is all that will be written, so there's no need to wait
+    if let (Some(max), Some(len), None) = (unpack_limits.max_size, content_length, unpack_format) {
+        if 204 != response.status() && !appending && resuming.is_none() && len > max {
+            return Err(exit::classified(
+                exit::Kind::Verification,
+                format!("the body is {} bytes, past --max-size {}", len, max),
+            ));
+        }
+    }
+
+    if let (Some(quota), Some(len)) = (quota, content_length) {
+        if 204 != response.status() {
+            quota.check(len)?;
+        }
+    }
+
+    // only when the body is written as-is, so it's the size of the file we'll end up with
+    if let (Some(how), Some(len), Some(file)) =
+        (matches.value_of("preallocate"), content_length, temp.file())
+    {
+        if 204 != response.status()
+            && !appending
+            && resuming.is_none()
+            && unpack_format.is_none()
+            && compress_output.is_none()
+        {
+            sink::preallocate(file, len, how)?;
+        }
+    }
+
+    let validator = partial::validator(response.header("ETag"), response.header("Last-Modified"));
+    let resumable = range.is_none()
+        && validator.is_some()
+        && (206 == status
+            || response
+                .header("Accept-Ranges")
+                .map(|v| v.split(',').any(|unit| "bytes" == unit.trim()))
+                .unwrap_or(false));
+    let content_type = response.header("Content-Type").map(str::to_string);
+    // a 204 has no body, whatever its headers claim, so don't wait for one
+    let body = if 204 == status {
+        None
+    } else {
+        Some(interim::reader(response, interim_body))
+    };
+
+    Ok(Some((
+        Checked {
+            chain,
+            method,
+            data,
+            requested_range,
+            status,
+            content_type,
+            output: output.to_path_buf(),
+            metadata_before,
+            template_values,
+            appending,
+            resuming,
+            whole_for_range,
+            expected_types,
+            server_date,
+            content_length,
+            unpack_format,
+            storage_claims,
+            hashing,
+            validator,
+            resumable,
+        },
+        body,
+    )))
+}
+
+/// The body, through whatever it's unpacked, compressed, hashed and counted with, into `temp`;
+/// resuming it, or starting again, when it's cut short.
+fn copy_body(
+    prepared: &Prepared,
+    sender: &request::Sender,
+    checked: &Checked,
+    body: Option<interim::Body>,
+    temp: sink::Sink,
+    retries: &mut usize,
+    outcome: &mut outcome::Outcome,
+) -> Result<Copied, failure::Error> {
+    let Prepared {
+        matches,
+        carried:
+            Carried {
+                buckets,
+                quota,
+                delta,
+                segments,
+                ..
+            },
+        events,
+        ref target,
+        data_content_type,
+        ref cache_entry,
+        no_output_file,
+        ttfb_timeout,
+        max_header_bytes,
+        max_headers,
+        min_size,
+        unpack_limits,
+        min_free,
+        max_shrink,
+        range,
+        compress_output,
+        ..
+    } = *prepared;
+    let Checked {
+        ref chain,
+        method,
+        ref data,
+        requested_range,
+        status,
+        ref content_type,
+        ref metadata_before,
+        appending,
+        resuming,
+        whole_for_range,
+        ref expected_types,
+        content_length,
+        unpack_format,
+        ref storage_claims,
+        hashing,
+        ref validator,
+        resumable,
+        ..
+    } = *checked;
+    let output = checked.output.as_path();
+
+    // read back through its descriptor, as the chain of writers below owns the file itself
+    let partial_source = match &temp {
+        sink::Sink::Temp(file) if matches.is_present("keep-partial") => Some(hook::temp_path(file)),
+        _ => None,
+    };
+    let requested_url = target.url.as_str();
+    let keep_partial = |temp: &mut dyn Write| {
+        let source = match &partial_source {
+            Some(source) => source,
+            None => return,
+        };
+        let validator = match &validator {
+            Some(validator) => validator,
+            None => {
+                warn!("no ETag or Last-Modified to resume against, so not keeping the partial download");
+                return;
+            }
+        };
+        let kept = temp
+            .flush()
+            .map_err(failure::Error::from)
+            .and_then(|()| partial::keep(source, output, requested_url, validator));
+        if let Err(e) = kept {
+            warn!("couldn't keep the partial download: {}", e);
+        }
+    };
+
+    // where the body starts in the resource, so a retry knows what to ask for
+    let body_start = match &requested_range {
+        Some(requested) if 206 == status => requested.start,
+        _ => 0,
+    };
+    let resume_url = chain.current().clone();
+    let buffer_size =
+        size::parse_size(matches.value_of("buffer-size").expect("defaulted"))? as usize;
+    let ranged = |rest: range::ByteRange| -> Result<Option<interim::Body>, failure::Error> {
+        let mut req = sender.request(method, &resume_url);
+        req.set("Range", &rest.header_value());
+        req.set("If-Range", validator.as_deref().expect("resumable"));
+        if let (Some(_), Some(content_type)) = (&data, data_content_type) {
+            req.set("Content-Type", content_type);
+        }
+        sender.set_custom_headers(&mut req);
+
+        let (response, body) = interim::skip(
+            call(req, data.as_deref(), ttfb_timeout).with_context(|_| err_msg("resuming"))?,
+        );
+        if let Some(err) = response.synthetic_error() {
+            Err(ureq_error(err)).with_context(|_| err_msg("resuming"))?;
+        }
+        expect::header_limits(&response, max_header_bytes, max_headers)?;
+
+        match response.status() {
+            206 => {
+                range::check_content_range(&rest, response.header("Content-Range"))?;
+                Ok(Some(interim::reader(response, body)))
+            }
+            200 => Ok(None),
+            _ => Err(exit::classified(
+                exit::Kind::Status(response.status()),
+                format!(
+                    "unhappy response while resuming: {:?}",
+                    response.status_line()
+                ),
+            )),
+        }
+    };
+
+    let continue_from = |offset: u64| {
+        ranged(range::ByteRange {
+            start: offset,
+            end: None,
+        })
+    };
+
+    // only a body that's written as it's sent can be made from the output's blocks, or be the
+    // output's map
+    let delta = delta.filter(|_| {
+        !no_output_file
+            && !appending
+            && resuming.is_none()
+            && range.is_none()
+            && requested_range.is_none()
+            && unpack_format.is_none()
+            && compress_output.is_none()
+    });
+    let assembly = match (delta, &metadata_before, content_length) {
+        (Some(delta), Some(metadata), Some(len))
+            if delta.active() && 200 == status && resumable && metadata.is_file() =>
+        {
+            let planned = delta::fetch_index(delta.index(), |url| {
+                let mut req = sender.request("GET", url);
+                sender.set_custom_headers(&mut req);
+                req
+            })
+            .and_then(|index| {
+                if index.size != len {
+                    bail!(
+                        "the index is for {} bytes, but the Content-Length is {}",
+                        index.size,
+                        len
+                    );
+                }
+                let local = delta::of_output(output, metadata, index.block_size)?;
+                Ok((index, local))
+            });
+            match planned {
+                Ok((index, local)) => {
+                    info!(
+                        "         delta: fetching {} of {} blocks",
+                        index.differing(&local),
+                        index.blocks.len()
+                    );
+                    Some((index, local))
+                }
+                Err(e) => {
+                    warn!("--delta-index: {}; downloading it all", e);
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+    let mut blocks = delta.map(|_| {
+        let block_size = assembly.as_ref().map(|(index, _)| index.block_size);
+        delta::Builder::new(block_size.unwrap_or(delta::BLOCK_SIZE))
+    });
+
+    // as with the delta, only a body written as it's sent can be written in pieces
+    let segmented = match (segments, content_length, temp.file()) {
+        (Some(segments), Some(len), Some(file))
+            if segments.active()
+                && delta.is_none()
+                && 200 == status
+                && resumable
+                && !appending
+                && resuming.is_none()
+                && range.is_none()
+                && requested_range.is_none()
+                && unpack_format.is_none()
+                && compress_output.is_none() =>
+        {
+            let ranges = segments.split(len);
+            if ranges.len() > 1 {
+                let file = file
+                    .try_clone()
+                    .with_context(|_| err_msg("opening the temporary file for the segments"))?;
+                Some((segments, ranges, file))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    };
+
+    let paranoid = matches.is_present("paranoid");
+    let temp = segments::Placed::new(space::Reserve::new(temp, min_free), segmented.is_some());
+    // what reached the file is also what a cache entry's recorded as
+    let temp = digest::DigestWriter::new(temp, paranoid || cache_entry.is_some());
+    let temp = compress::Packer::new(temp, compress_output);
+    let temp = match unpack_format {
+        Some(format) => unpack::Unpacker::new(temp, format, unpack_limits),
+        None => unpack::Unpacker::plain(temp, unpack_limits),
+    };
+    let mut temp = digest::DigestWriter::new(temp, hashing);
+    if !storage_claims.is_empty() {
+        temp = temp.also(
+            storage_claims.md5.is_some(),
+            storage_claims.crc32c.is_some(),
+        );
+    }
+
+    debug!("   downloading: started...");
+    outcome.phase = "downloading";
+
+    let has_body = body.is_some();
+    if let Some(body) = body {
+        let mut body: Box<dyn Read + '_> = body;
+        if let Some((index, local)) = assembly {
+            let old = fs::File::open(output)
+                .with_context(|_| format_err!("opening {:?} for its blocks", output))?;
+            body = Box::new(delta::assemble(index, &local, old, &ranged));
+        }
+        // the body, and anything already there, go through this, and only this, on to the file
+        let mut buf = vec![0; buffer_size];
+        outcome.buffer = Some(buffer_size);
+
+        // held back from the file until it's been inspected
+        let mut prefix = Vec::new();
+        if matches.is_present("reject-html") {
+            (&mut body)
+                .take(1024)
+                .read_to_end(&mut prefix)
+                .with_context(|_| err_msg("downloading"))?;
+            expect::reject_html(
+                expected_types,
+                chain.current().path(),
+                content_type.as_deref(),
+                &prefix,
+            )?;
+        }
+
+        let mut body: Box<dyn Read + '_> = Box::new(io::Cursor::new(prefix).chain(body));
+
+        if let Some(range) = whole_for_range {
+            let skipped = io::copy(&mut (&mut body).take(range.start), &mut io::sink())
+                .with_context(|_| err_msg("downloading"))?;
+            if skipped != range.start {
+                bail!("resource ended before the start of the requested range");
+            }
+            if let Some(len) = range.len() {
+                body = Box::new(body.take(len));
+            }
+        }
+
+        if appending {
+            // built up in the temporary file, so a failure part way leaves the output as it was
+            let mut existing = fs::File::open(output)
+                .with_context(|_| format_err!("opening {:?} to append to", output))?;
+            pump::copy(&mut existing, &mut temp, &mut buf, &mut 0)
+                .with_context(|_| format_err!("copying {:?} to append to", output))?;
+        }
+
+        if let Some(part) = resuming {
+            let mut existing = fs::File::open(part)
+                .with_context(|_| format_err!("opening {:?} to resume", part))?;
+            pump::copy(&mut existing, &mut temp, &mut buf, &mut 0)
+                .with_context(|_| format_err!("copying {:?} to resume", part))?;
+        }
+
+        let mut received = 0;
+        let mut progress = events.progress(content_length);
+        if let Some((segments, ranges, file)) = &segmented {
+            let len = content_length.expect("segmented");
+            info!(
+                "      segments: {} of about {}",
+                ranges.len(),
+                size::format_size(len / ranges.len() as u64)
+            );
+            let downloaded = segments::download(
+                file,
+                ranges,
+                &mut body,
+                &ranged,
+                buckets,
+                *retries,
+                &mut progress,
+            );
+            if let Some(quota) = quota {
+                quota.count(progress.bytes());
+            }
+            match downloaded {
+                Ok(()) => (),
+                Err(e) if segments::is_unranged(&e) => {
+                    if 0 == *retries {
+                        return Err(e);
+                    }
+                    *retries -= 1;
+                    warn!("{}; downloading it as one stream instead", e);
+                    segments.give_up();
+                    return Err(retry::Restart.into());
+                }
+                Err(e) => return Err(e),
+            }
+            // the digests, and whatever else is worked out as it's written, are of the whole
+            pump::copy(
+                &mut segments::read_back(file, len),
+                &mut temp,
+                &mut buf,
+                &mut received,
+            )
+            .with_context(|_| err_msg("reading back the segments"))?;
+        } else {
+            loop {
+                let limited = delta::recording(
+                    quota::meter(rate::limit(&mut body, buckets), quota),
+                    blocks.as_mut(),
+                );
+                let copied = pump::copy(
+                    &mut progress.reader(limited),
+                    &mut temp,
+                    &mut buf,
+                    &mut received,
+                );
+                let mut failure = match copied {
+                    Ok(()) => match content_length {
+                        Some(expected) if received < expected => format_err!(
+                            "download truncated: received {} bytes, but Content-Length was {}",
+                            received,
+                            expected
+                        ),
+                        _ => break,
+                    },
+                    // the body is as the server sent it, so there's no point asking again
+                    Err(e) if unpack::is_too_big(&e) => {
+                        return Err(exit::classified(exit::Kind::Verification, e.to_string()))
+                    }
+                    Err(e) if quota::is_exceeded(&e) => {
+                        return Err(exit::classified(exit::Kind::Quota, e.to_string()))
+                    }
+                    Err(e) if sink::is_reader_gone(&e) => {
+                        return Err(exit::classified(exit::Kind::Filesystem, e.to_string()))
+                    }
+                    Err(e) if delta::is_inconsistent(&e) => {
+                        warn!("{}; downloading it all instead", e);
+                        delta.expect("assembling").give_up();
+                        return Err(retry::Restart.into());
+                    }
+                    Err(e) => failure::Error::from(e).context("downloading").into(),
+                };
+
+                // a failed resume request is just another failure to retry
+                body = loop {
+                    if 0 == *retries {
+                        keep_partial(&mut temp);
+                        return Err(failure);
+                    }
+                    *retries -= 1;
+
+                    if !resumable {
+                        warn!("{}; starting again", failure);
+                        return Err(retry::Restart.into());
+                    }
+
+                    let offset = body_start + received;
+                    warn!("{}; resuming from offset {}", failure, offset);
+                    match continue_from(offset) {
+                        Ok(Some(rest)) => break rest,
+                        Ok(None) => {
+                            warn!("the resource changed while we were resuming it; starting again");
+                            return Err(retry::Restart.into());
+                        }
+                        Err(e) => failure = e,
+                    }
+                };
+            }
+        }
+        outcome.bytes = Some(received);
+
+        if 0 == received && !appending && resuming.is_none() && !matches.is_present("allow-empty") {
+            if let Some(previous) = metadata_before.as_ref().map(|m| m.len()).filter(|&l| l > 0) {
+                bail!(
+                    "refusing to replace {:?} ({} bytes) with an empty response, see --allow-empty",
+                    output,
+                    previous
+                );
+            }
+        }
+
+        if let Some(expected) = content_length {
+            if received != expected {
+                bail!(
+                    "download truncated: received {} bytes, but Content-Length was {}",
+                    received,
+                    expected
+                );
+            }
+        }
+    }
+
+    debug!("   downloading: ...read complete...");
+
+    temp.flush()
+        .with_context(|_| err_msg("completing download"))?;
+
+    let (temp, digest) = temp.finish();
+    let (temp, written) = temp
+        .finish()
+        .map_err(|e| {
+            if unpack::is_too_big(&e) {
+                exit::classified(exit::Kind::Verification, e.to_string())
+            } else {
+                failure::Error::from(e)
+                    .context("decompressing download")
+                    .into()
+            }
+        })?
+        .finish()
+        .with_context(|_| err_msg("compressing download"))?
+        .finish();
+    let temp = temp.into_inner().into_inner();
+
+    if let (true, Some(file)) = (has_body, temp.file()) {
+        let old_len = metadata_before.as_ref().map(|m| m.len());
+        let new_len = file
+            .metadata()
+            .with_context(|_| err_msg("reading temporary file's info"))?
+            .len();
+        expect::size(new_len, old_len, min_size, max_shrink)?;
+    }
+
+    if let Some(digest) = &digest {
+        debug!("        digest: {:?}", digest);
+        outcome.bytes = Some(digest.bytes);
+        outcome.sha256 = Some(digest.sha256);
+    }
+
+    Ok(Copied {
+        temp,
+        digest,
+        written,
+        blocks,
+    })
+}
+
+/// The body's digests, against what they were expected or claimed to be.
+fn verify(
+    prepared: &Prepared,
+    staged: &Staged,
+    checked: &Checked,
+    digest: &Option<digest::Digest>,
+    outcome: &mut outcome::Outcome,
+) -> Result<(), failure::Error> {
+    let events = prepared.events;
+    let expected_sha256 = staged.expected_sha256;
+    let storage_claims = &checked.storage_claims;
+
+    outcome.phase = "verifying";
+
+    if let Some(expected) = &expected_sha256 {
+        let digest = digest
+            .as_ref()
+            .expect("hashing when there's an expectation");
+        if expected != &digest.sha256 {
+            bail!(
+                "sha256 mismatch: expected {}, downloaded {}",
+                digest::hex(expected),
+                digest.sha256_hex()
+            );
+        }
+        info!("        sha256: matches");
+    }
+
+    if !storage_claims.is_empty() {
+        let digest = digest.as_ref().expect("hashing when there are claims");
+        storage::verify(storage_claims, digest)?;
+        info!("       storage: checksums match");
+    }
+
+    let checks: Vec<String> = [
+        ("sha256", expected_sha256.is_some()),
+        ("storage", !storage_claims.is_empty()),
+    ]
+    .iter()
+    .filter(|(_, checked)| *checked)
+    .map(|(check, _)| json::string(check))
+    .collect();
+    if let (Some(digest), false) = (&digest, checks.is_empty()) {
+        events.emit(
+            "verified",
+            &[
+                ("checks", format!("[{}]", checks.join(","))),
+                ("sha256", json::string(&digest.sha256_hex())),
+            ],
+        );
+    }
+
+    debug!("   downloading: ...write complete.");
+    Ok(())
+}
+
+/// Everything that's done with the finished download: checking it again, as asked, and putting
+/// it in place of the output, with everything that goes along with that.
+fn install(
+    prepared: &Prepared,
+    staged: Staged,
+    checked: Checked,
+    copied: Copied,
+    outcome: &mut outcome::Outcome,
+) -> Result<(), failure::Error> {
+    let Prepared {
+        matches,
+        events,
+        ref target,
+        started,
+        ref cache_entry,
+        ref template,
+        extract,
+        follow_symlinks,
+        ref rename_retries,
+        no_clobber,
+        future_mtime,
+        mode,
+        default_mode,
+        owner,
+        keep_versions,
+        diff_lines,
+        ..
+    } = *prepared;
+    let output_arg = prepared.output_arg.as_os_str();
+    let Staged {
+        watched,
+        store_dir,
+        compared,
+        ..
+    } = staged;
+    let Checked {
+        chain,
+        template_values,
+        server_date,
+        ..
+    } = checked;
+    let Copied {
+        temp,
+        digest,
+        written,
+        blocks,
+    } = copied;
+    let paranoid = matches.is_present("paranoid");
+
+    let late_template = template.as_ref().filter(|(_, t)| t.needs_response());
+    let hashed_output;
+    let (output, metadata_before) = match late_template {
+        Some((dir, t)) if t.needs_body() => {
+            let mut values = template_values;
+            values.sha256 = digest.as_ref().map(|d| d.sha256);
+            let joined = output::join(dir.as_os_str(), &t.render(&values)?)?;
+            hashed_output = if follow_symlinks {
+                output::follow_symlinks(&joined, matches.is_present("create-dirs"))?
+            } else {
+                joined
+            };
+            info!("   output name: {:?}, from the body", hashed_output);
+            let metadata = metadata_of(&hashed_output)?;
+            if no_clobber && metadata.is_some() {
+                info!("    no-clobber: output exists, done");
+                outcome.skipped = Some("no-clobber");
+                return Ok(());
+            }
+            outcome.output = Some(hashed_output.clone());
+            (hashed_output.as_path(), metadata)
+        }
+        _ => (checked.output.as_path(), checked.metadata_before),
+    };
+
+    outcome.kind = outcome::Kind::Fetched;
+    outcome.phase = "installing";
+    let temp = match temp {
+        sink::Sink::Temp(temp) => temp,
+        sink::Sink::Stdout(_) => {
+            info!("        output: written to stdout");
+            return Ok(());
+        }
+        sink::Sink::Discard(_) => {
+            info!("        output: discarded");
+            return Ok(());
+        }
+        sink::Sink::Fd(fd) => {
+            if matches.is_present("fsync") {
+                // pipes and sockets can't be synced, and there's nothing to lose in them anyway
+                if let Err(e) = fd.sync_all() {
+                    warn!("couldn't fsync the inherited descriptor: {}", e);
+                }
+            }
+            info!("        output: written to the inherited descriptor");
+            return Ok(());
+        }
+        sink::Sink::Special(_) => {
+            info!("        output: written into {:?}", output_arg);
+            return Ok(());
+        }
+    };
+
+    if let Some(cmd) = matches.value_of("validate-cmd") {
+        let size = temp
+            .metadata()
+            .with_context(|_| err_msg("reading temporary file's info"))?
+            .len();
+        hook::run(
+            cmd,
+            hook::temp_path(&temp).as_os_str(),
+            &[
+                ("FETCH_MAYBE_URL", chain.current().to_string()),
+                ("FETCH_MAYBE_SIZE", size.to_string()),
+            ],
+        )
+        .with_context(|_| err_msg("validating download"))?;
+        info!("    validation: passed");
+    }
+
+    if let Some(format) = extract {
+        extract::install(format, temp.as_ref(), output)?;
+        let mtime = server_date.and_then(|t| {
+            timestamp::unless_future(
+                t,
+                time::SystemTime::now(),
+                future_mtime,
+                "the server's Last-Modified",
+            )
+        });
+        extract::record(output, target.url.as_str(), outcome, mtime)?;
+        events.emit("persisted", &[("output", events::value(output.to_str()))]);
+        info!(
+            url = outcome.url.as_str(),
+            output = outcome.output.as_deref().and_then(Path::to_str),
+            status = outcome.status,
+            bytes = outcome.bytes,
+            duration_ms = started.elapsed().as_millis() as u64;
+            "        output: extracted"
+        );
+        return Ok(());
+    }
+
+    let mut changes = None;
+    if matches.is_present("diff") && metadata_before.is_some() {
+        changes = Some(
+            match diff::against_output(output, temp.as_ref(), diff_lines)? {
+                Some(diff) => {
+                    eprint!("{}", diff);
+                    diff::changes(&diff)
+                }
+                None => "identical".to_string(),
+            },
+        );
+    }
+
+    // everything's been checked, and nothing's been touched yet
+    if let (true, Some(previous)) = (matches.is_present("interactive"), &metadata_before) {
+        let new_len = temp
+            .metadata()
+            .with_context(|_| err_msg("reading temporary file's info"))?
+            .len();
+        let question = confirm::question(
+            &format!("{:?}", output),
+            previous.len(),
+            previous.modified().ok(),
+            new_len,
+            changes.as_deref(),
+        );
+        if !confirm::ask(&question)? {
+            bail!("not replacing {:?}, as answered", output);
+        }
+    }
+
+    // replacing a file shouldn't quietly change who can read it
+    let preserved = metadata_before
+        .as_ref()
+        .filter(|_| !matches.is_present("no-preserve"));
+
+    // before the mode, as changing the owner can clear setuid bits
+    match (owner, preserved) {
+        (Some(owner), _) => owner
+            .apply(temp.as_ref())
+            .with_context(|_| format_err!("changing temporary file's owner to {:?}", owner))?,
+        (None, Some(previous)) => perms::preserve_owner(temp.as_ref(), previous),
+        (None, None) => (),
+    }
+
+    // before the mode, which setting an ACL would otherwise overwrite
+    if preserved.is_some() && !matches.is_present("no-preserve-xattr") {
+        xattrs::copy(output, temp.as_ref(), matches.is_present("preserve-xattrs"));
+    }
+
+    // the temporary file is always 0600, which new outputs shouldn't inherit
+    let mode = mode
+        .or_else(|| preserved.map(perms::mode_of))
+        .unwrap_or(default_mode);
+    perms::set_mode(temp.as_ref(), mode)?;
+
+    let mtime_from = if matches.is_present("no-mtime") {
+        "download"
+    } else {
+        matches.value_of("mtime-from").expect("defaulted")
+    };
+
+    let backup_suffix = if matches.is_present("backup") {
+        Some(matches.value_of("backup").unwrap_or("~"))
+    } else {
+        None
+    };
+
+    // a redownload of the same bytes isn't a new version, for times or backups
+    let unchanged = metadata_before.is_some()
+        && ("keep" == mtime_from
+            || backup_suffix.is_some()
+            || keep_versions.is_some()
+            || matches.is_present("on-change")
+            || matches.is_present("on-unchanged")
+            || matches.is_present("fail-if-unchanged"))
+        && diff::identical(output, temp.as_ref())?;
+    outcome.identical = unchanged;
+
+    // a server's clock running fast would otherwise date the output after now
+    let server_mtime = || {
+        server_date.and_then(|t| {
+            timestamp::unless_future(
+                t,
+                time::SystemTime::now(),
+                future_mtime,
+                "the server's Last-Modified",
+            )
+        })
+    };
+    let mtime = match mtime_from {
+        "server" => server_mtime(),
+        "download" => None,
+        "keep" => match metadata_before.as_ref().and_then(|m| m.modified().ok()) {
+            Some(previous) if unchanged => Some(previous),
+            _ => server_mtime(),
+        },
+        other => unreachable!("clap validated: {:?}", other),
+    };
+
+    debug!("     file time: from {}: {:?}", mtime_from, mtime);
+
+    // the access time too, like wget -N; noatime mounts still allow setting it explicitly
+    if let Some(mtime) = mtime {
+        let time = filetime::FileTime::from(mtime);
+        match filetime::set_file_handle_times(temp.as_ref(), Some(time), Some(time)) {
+            Ok(()) => debug!("     file time: set successfully on temporary"),
+            Err(e) => warn!("failed to set temp file times: {:?}", e),
+        }
+    }
+
+    // only something to keep the inode of; a new output is renamed into place as usual
+    let in_place = matches.is_present("in-place") && metadata_before.is_some();
+
+    if let Some(suffix) = backup_suffix {
+        if metadata_before.is_some() && !unchanged {
+            let backup = backup::suffixed(output, suffix);
+            backup::preserve_copy(output, &backup, !in_place)?;
+            info!("        backup: previous version kept at {:?}", backup);
+        }
+    }
+
+    if let Some(keep) = keep_versions {
+        if metadata_before.is_some() && !unchanged {
+            let version = backup::keep_version(output, keep, !in_place)?;
+            info!("      versions: previous version kept at {:?}", version);
+        }
+    }
+
+    // as late as possible, though a write between this and the rename is still lost
+    if "overwrite" != matches.value_of("on-conflict").expect("defaulted") {
+        conflict::check(output, metadata_before.as_ref())?;
+    }
+
+    if in_place {
+        let dest = in_place::overwrite(temp.as_ref(), output)?;
+
+        // it keeps its owner, mode and attributes, unless asked otherwise
+        if let Some(owner) = owner {
+            owner
+                .apply(&dest)
+                .with_context(|_| format_err!("changing {:?}'s owner to {:?}", output, owner))?;
+        }
+        if let Some(mode) = matches.value_of("mode") {
+            perms::set_mode(&dest, perms::parse_mode(mode)?)?;
+        }
+        if let Some(mtime) = mtime {
+            let time = filetime::FileTime::from(mtime);
+            if let Err(e) = filetime::set_file_handle_times(&dest, Some(time), Some(time)) {
+                warn!("failed to set output's times: {:?}", e);
+            }
+        }
+    } else {
+        persist(matches, temp, watched, output, preserved, rename_retries)?;
+    }
+
+    if let (true, Some(written)) = (paranoid, &written) {
+        readback::verify(output, written, mtime)?;
+        info!("      paranoid: output reads back as written");
+    }
+
+    events.emit("persisted", &[("output", events::value(output.to_str()))]);
+
+    partial::remove(output);
+
+    if let Some(store) = store_dir {
+        let digest = digest.as_ref().expect("hashing for the store");
+        store::add(store, &digest.sha256, output)?;
+    }
+
+    if cache_entry.is_some() {
+        cache::record(output, target.url.as_str(), outcome, written.as_ref())?;
+    }
+
+    if let Some(sentinel) = &compared {
+        compare::record(output, sentinel)?;
+    }
+
+    // only worth the time it saves the next run
+    if let Some(map) = blocks.map(delta::Builder::finish) {
+        if let Err(e) = delta::record(output, &map) {
+            warn!("couldn't keep the output's blocks: {}", e);
+        }
+    }
+
+    info!(
+        url = outcome.url.as_str(),
+        output = outcome.output.as_deref().and_then(Path::to_str),
+        status = outcome.status,
+        bytes = outcome.bytes,
+        duration_ms = started.elapsed().as_millis() as u64;
+        "        output: ready"
+    );
+
+    // the output's fine whatever happens here, so try them all
+    let failed: Vec<String> = matches
+        .values_of_os("also-link")
+        .into_iter()
+        .flatten()
+        .filter_map(|dest| {
+            also::link(output, Path::new(dest), matches.is_present("also-copy"))
+                .err()
+                .map(|e| {
+                    let causes: Vec<String> = e.iter_chain().map(|c| c.to_string()).collect();
+                    causes.join(": ")
+                })
+        })
+        .collect();
+    if !failed.is_empty() {
+        bail!(
+            "{:?} is installed, but some --also-link paths aren't: {}",
+            output,
+            failed.join("; ")
+        );
+    }
+
+    Ok(())
+}
+
+/// Rename the finished temporary file over the output, copying it across filesystems if need be.
+fn persist(
+    matches: &clap::ArgMatches,
+    temp: tempfile_fast::PersistableTempFile,
+    watched: Option<signals::Watched>,
+    output: &Path,
+    preserved: Option<&fs::Metadata>,
+    retries: &output::RenameRetries,
+) -> Result<(), failure::Error> {
+    let fsync = matches.is_present("fsync");
+    if fsync {
+        // after the times and mode, which are metadata that needs syncing too
+        temp.as_ref()
+            .sync_all()
+            .with_context(|_| err_msg("flushing download to disk"))?;
+        debug!("         fsync: temporary file synced");
+    }
+
+    // once it might be the output, it mustn't be removed
+    drop(watched);
+    match rename_over(temp, output, retries) {
+        Ok(()) => (),
+        Err(e) if Some(restage::EXDEV) == e.error.raw_os_error() => {
+            let dir = output::real_directory(&dir_of::dir_of(output, env::current_dir)?)?;
+            info!(
+                "       staging: {:?} is on another filesystem, copying across",
+                dir
+            );
+            let xattrs_from = preserved
+                .filter(|_| !matches.is_present("no-preserve-xattr"))
+                .map(|_| (output, matches.is_present("preserve-xattrs")));
+            let copy = restage::restage(e.file.as_ref(), &dir, xattrs_from)?;
+            let watched = signals::watch(&copy);
+            if fsync {
+                copy.as_ref()
+                    .sync_all()
+                    .with_context(|_| err_msg("flushing download to disk"))?;
+            }
+            drop(watched);
+            match rename_over(copy, output, retries) {
+                Ok(()) => (),
+                Err(e) => Err(e.error)
+                    .with_context(|_| format_err!("replacing {:?} with download", output))?,
+            }
+        }
+        Err(e) => {
+            Err(e.error).with_context(|_| format_err!("replacing {:?} with download", output))?
+        }
+    };
+
+    if fsync {
+        output::sync_directory(&dir_of::dir_of(output, env::current_dir)?);
+    }
+
+    Ok(())
+}
+
+/// Rename `temp` over `output`, trying again while something else briefly has it open or locked.
+fn rename_over(
+    temp: tempfile_fast::PersistableTempFile,
+    output: &Path,
+    retries: &output::RenameRetries,
+) -> Result<(), tempfile_fast::PersistError> {
+    let mut temp = temp;
+    let mut retried = 0;
+    loop {
+        match temp.persist_by_rename(output) {
+            Err(e) if retried < retries.retries && output::is_contended(&e.error) => {
+                retried += 1;
+                debug!(
+                    "        output: in use, retrying the rename in {:?} ({} of {}): {}",
+                    retries.delay, retried, retries.retries, e.error
+                );
+                std::thread::sleep(retries.delay);
+                temp = e.file;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// A `DURATION` option, which mayn't be negative.
+fn duration_arg(
+    matches: &clap::ArgMatches,
+    name: &str,
+) -> Result<Option<time::Duration>, failure::Error> {
+    let v = match matches.value_of(name) {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    Ok(Some(
+        period::parse_duration(v)
+            .with_context(|_| format_err!("parsing {}: {:?}", name, v))?
+            .to_std()
+            .with_context(|_| format_err!("negative {}: {:?}", name, v))?,
+    ))
+}
+
+/// A `SIZE` option, in bytes.
+fn size_arg(matches: &clap::ArgMatches, name: &str) -> Result<Option<u64>, failure::Error> {
+    match matches.value_of(name) {
+        Some(v) => {
+            Ok(Some(size::parse_size(v).with_context(|_| {
+                format_err!("parsing {}: {:?}", name, v)
+            })?))
+        }
+        None => Ok(None),
+    }
+}
+
+/// A whole number option.
+fn number_arg<T>(matches: &clap::ArgMatches, name: &str) -> Result<Option<T>, failure::Error>
+where
+    T: std::str::FromStr,
+    T::Err: failure::Fail,
+{
+    match matches.value_of(name) {
+        Some(v) => {
+            Ok(Some(v.parse::<T>().with_context(|_| {
+                format_err!("parsing {}: {:?}", name, v)
+            })?))
+        }
+        None => Ok(None),
+    }
+}
+
+fn metadata_of(output: &Path) -> Result<Option<fs::Metadata>, failure::Error> {
+    match output.metadata() {
+        Ok(metadata) => Ok(Some(metadata)),
+        Err(ref e) if io::ErrorKind::NotFound == e.kind() => {
+            info!("reference time: output file missing, so not available");
+            Ok(None)
+        }
+        Err(e) => Err(e).with_context(|_| format_err!("reading output's info: {:?}", output))?,
+    }
+}
+
+fn parse_header(header: &str) -> Result<(&str, &str), failure::Error> {
+    let colon = header.find(':').ok_or_else(|| {
+        format_err!(
+            "header missing a colon, expected format 'Foo: bar', got {:?}",
+            header
+        )
+    })?;
+
+    let (key, mut value) = header.split_at(colon);
+
+    // colon
+    value = &value[1..];
+
+    if value.starts_with(' ') {
+        value = &value[1..];
+    }
+
+    Ok((key, value))
+}
+
+/// Send `req`, waiting at most `ttfb_timeout` for the response's headers once it's been written.
+///
+/// That's ureq's read timeout, on the socket for as long as it's open: so the TLS handshake's
+/// reads, and each read of the body, get as long again.
+fn call(
+    mut req: ureq::Request,
+    body: Option<&[u8]>,
+    ttfb_timeout: Option<time::Duration>,
+) -> Result<ureq::Response, failure::Error> {
+    if let Some(limit) = ttfb_timeout {
+        // zero is no limit at all, to ureq
+        req.timeout_read((limit.as_millis() as u64).max(1));
+    }
+    let started = time::Instant::now();
+    let response = match body {
+        Some(body) => req.send_bytes(body),
+        None => req.call(),
+    };
+
+    // a read that timed out is only ever a bad status line or header, to ureq
+    let timed_out = match response.synthetic_error() {
+        Some(ureq::Error::BadStatus) | Some(ureq::Error::BadHeader) => true,
+        Some(ureq::Error::Io(e)) => {
+            io::ErrorKind::WouldBlock == e.kind() || io::ErrorKind::TimedOut == e.kind()
+        }
+        _ => false,
+    };
+    match ttfb_timeout {
+        Some(limit) if timed_out && started.elapsed() >= limit => Err(exit::classified(
+            exit::Kind::Timeout,
+            format!(
+                "no response headers within {:?} of sending the request, see --ttfb-timeout",
+                limit
+            ),
+        )),
+        _ => Ok(response),
+    }
+}
+
+fn ureq_error(err: &ureq::Error) -> failure::Error {
+    let kind = match err {
+        ureq::Error::DnsFailed(_) => exit::Kind::Dns,
+        ureq::Error::ConnectionFailed(_) | ureq::Error::BadStatusRead => exit::Kind::Connect,
+        // how rustls' handshake failures come out
+        ureq::Error::Io(e) if io::ErrorKind::InvalidData == e.kind() => exit::Kind::Tls,
+        ureq::Error::Io(e) => exit::io_kind(e),
+        _ => return format_err!("request failed: {:?}", err),
+    };
+    exit::classified(kind, format!("request failed: {:?}", err))
+}
@@ -0,0 +1,123 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use failure::format_err;
+use failure::ResultExt;
+use log::debug;
+
+/// The exit status when another run holds the lock: sysexits' `EX_TEMPFAIL`.
+pub const ALREADY_RUNNING: i32 = 75;
+
+/// Another run holds the lock, so this one left the output to it.
+pub struct Held;
+
+impl fmt::Display for Held {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "another run holds the lock")
+    }
+}
+
+impl fmt::Debug for Held {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl failure::Fail for Held {}
+
+const POLL: Duration = Duration::from_millis(100);
+
+/// The default lock next to an output: `OUTPUT.lock`.
+pub fn default_path(output: &Path) -> PathBuf {
+    let mut name = output.as_os_str().to_os_string();
+    name.push(".lock");
+    PathBuf::from(name)
+}
+
+/// Take an exclusive `flock` on `path`, creating it, waiting up to `wait` for another run.
+///
+/// `None` if it's still held after that. The lock lasts as long as the returned file is
+/// open, so it goes away with a killed process; the file itself is left in place, as
+/// removing it would let a waiting run and a new one lock different files.
+pub fn acquire(path: &Path, wait: Duration) -> Result<Option<fs::File>, failure::Error> {
+    use std::os::unix::io::AsRawFd;
+
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .with_context(|_| format_err!("opening lock file {:?}", path))?;
+
+    let deadline = Instant::now() + wait;
+    loop {
+        if 0 == unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } {
+            debug!("          lock: holding {:?}", path);
+            return Ok(Some(file));
+        }
+
+        let e = io::Error::last_os_error();
+        if io::ErrorKind::WouldBlock != e.kind() {
+            return Err(e).with_context(|_| format_err!("locking {:?}", path))?;
+        }
+
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        thread::sleep(POLL);
+    }
+}
+
+/// An existing lock file, as `try_existing` found it.
+pub enum Existing {
+    Missing,
+    /// Nobody else had it, so it's ours until this is dropped.
+    Free(fs::File),
+    Held,
+}
+
+/// Lock `path` if it's there and nobody else has, without creating it, or waiting.
+pub fn try_existing(path: &Path) -> Result<Existing, failure::Error> {
+    use std::os::unix::io::AsRawFd;
+
+    let file = match fs::OpenOptions::new().write(true).open(path) {
+        Ok(file) => file,
+        Err(ref e) if io::ErrorKind::NotFound == e.kind() => return Ok(Existing::Missing),
+        Err(e) => return Err(e).with_context(|_| format_err!("opening lock file {:?}", path))?,
+    };
+    if 0 == unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } {
+        return Ok(Existing::Free(file));
+    }
+    let e = io::Error::last_os_error();
+    if io::ErrorKind::WouldBlock == e.kind() {
+        return Ok(Existing::Held);
+    }
+    Err(e).with_context(|_| format_err!("locking {:?}", path))?
+}
+
+#[test]
+fn test_acquire() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = default_path(&dir.path().join("out"));
+    assert_eq!(dir.path().join("out.lock"), path);
+
+    let held = acquire(&path, Duration::from_secs(0)).unwrap();
+    assert!(held.is_some());
+    assert!(acquire(&path, Duration::from_millis(150))
+        .unwrap()
+        .is_none());
+    assert!(matches!(try_existing(&path).unwrap(), Existing::Held));
+    drop(held);
+    assert!(acquire(&path, Duration::from_secs(0)).unwrap().is_some());
+    assert!(matches!(try_existing(&path).unwrap(), Existing::Free(_)));
+
+    fs::remove_file(&path).unwrap();
+    assert!(matches!(try_existing(&path).unwrap(), Existing::Missing));
+    assert!(!path.exists());
+}
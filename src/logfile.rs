@@ -0,0 +1,98 @@
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use env_logger::filter::Filter;
+
+use fetch_maybe::json;
+use fetch_maybe::signals;
+
+/// Logs to `--log-file`, and to the `--log-target` too, unless `--log-file-only`.
+struct FileLogger {
+    path: PathBuf,
+    file: Mutex<fs::File>,
+    console: Option<Box<dyn log::Log>>,
+    filter: Filter,
+    json: bool,
+}
+
+/// A logger for `path` as well as `console`, or just `console` and why, if the file can't be
+/// opened.
+pub fn with_file(
+    console: Box<dyn log::Log>,
+    filter: Filter,
+    path: &Path,
+    only: bool,
+    json: bool,
+) -> (Box<dyn log::Log>, Option<io::Error>) {
+    match open(path) {
+        Ok(file) => (
+            Box::new(FileLogger {
+                path: path.to_path_buf(),
+                file: Mutex::new(file),
+                console: if only { None } else { Some(console) },
+                filter,
+                json,
+            }),
+            None,
+        ),
+        Err(e) => (console, Some(e)),
+    }
+}
+
+/// Opened for appending, so logrotate's `copytruncate` works.
+fn open(path: &Path) -> io::Result<fs::File> {
+    fs::OpenOptions::new().append(true).create(true).open(path)
+}
+
+impl log::Log for FileLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+        if let Some(console) = &self.console {
+            console.log(record);
+        }
+
+        // always with the date, whatever stderr's format does
+        let timestamp = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+        let mut line = if self.json {
+            json::record_line(record, timestamp)
+        } else {
+            format!(
+                "{} {:<5} {} > {}",
+                timestamp,
+                record.level(),
+                record.target(),
+                record.args()
+            )
+        };
+        line.push('\n');
+
+        let mut file = match self.file.lock() {
+            Ok(file) => file,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        // after a rotation, carry on with the old file if the new one can't be had
+        if signals::hung_up() {
+            if let Ok(reopened) = open(&self.path) {
+                *file = reopened;
+            }
+        }
+        // one write, so records from concurrent runs don't mix; nowhere to report failure
+        let _ = file.write_all(line.as_bytes());
+    }
+
+    fn flush(&self) {
+        if let Some(console) = &self.console {
+            console.flush();
+        }
+    }
+}
@@ -0,0 +1,354 @@
+use std::cell::RefCell;
+use std::env;
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+use env_logger::filter::Filter;
+use env_logger::fmt::Color;
+use env_logger::WriteStyle;
+use log::warn;
+use log::Level;
+use log::LevelFilter;
+
+use crate::logfile;
+use crate::system_log;
+use fetch_maybe::json;
+
+/// Install the logger the options ask for.
+///
+/// Returns whether there's a `--log-file` in use, in which case a SIGHUP should reopen it.
+pub fn init(matches: &clap::ArgMatches, level: LevelFilter, rust_log: Option<&str>) -> bool {
+    let json = "json" == matches.value_of("log-format").expect("defaulted");
+    let mut logger = env_logger::Builder::new();
+    if json {
+        logger.format(|out, record| json::write_record(out, record));
+    } else {
+        let layout = Layout {
+            timestamps: match matches.value_of("log-timestamps").expect("defaulted") {
+                "utc" => Timestamps::Utc,
+                "none" => Timestamps::None,
+                _ => Timestamps::Local,
+            },
+            show_target: "on" == matches.value_of("log-show-target").expect("defaulted"),
+        };
+        logger.format(move |out, record| {
+            let mut style = out.style();
+            let level = style
+                .set_color(match record.level() {
+                    Level::Trace => Color::Magenta,
+                    Level::Debug => Color::Blue,
+                    Level::Info => Color::Green,
+                    Level::Warn => Color::Yellow,
+                    Level::Error => Color::Red,
+                })
+                .value(level_name(record.level()));
+            let mut style = out.style();
+            let target = style.set_bold(true).value(layout.target(record.target()));
+            let line = layout.line(chrono::Utc::now(), level, target, record.args());
+            writeln!(out, "{}", line)
+        });
+    }
+    directives(&mut logger, level, rust_log);
+    logger.write_style(write_style(
+        matches.value_of("color").expect("defaulted"),
+        |name| env::var_os(name),
+        unsafe { 1 == libc::isatty(libc::STDERR_FILENO) },
+    ));
+    log::set_max_level(filter(level, rust_log).filter());
+
+    let mut fallbacks = Vec::new();
+    let console: Box<dyn log::Log> = match matches.value_of("log-target").expect("defaulted") {
+        "stderr" => Box::new(logger.build()),
+        name => {
+            let target = match name {
+                "syslog" => system_log::Target::Syslog,
+                _ => system_log::Target::Journald,
+            };
+            match system_log::Logger::connect(target, filter(level, rust_log)) {
+                Ok(system) => Box::new(system),
+                Err(e) => {
+                    fallbacks.push(format!("couldn't reach {}, logging to stderr: {}", name, e));
+                    Box::new(logger.build())
+                }
+            }
+        }
+    };
+
+    let (logger, log_file) = match matches.value_of_os("log-file") {
+        Some(path) => {
+            let (logger, failure) = logfile::with_file(
+                console,
+                filter(level, rust_log),
+                Path::new(path),
+                matches.is_present("log-file-only"),
+                json,
+            );
+            let in_use = failure.is_none();
+            if let Some(e) = failure {
+                fallbacks.push(format!(
+                    "couldn't open log file {:?}, logging without it: {}",
+                    path, e
+                ));
+            }
+            (logger, in_use)
+        }
+        None => (console, false),
+    };
+    log::set_boxed_logger(Box::new(Tagged { inner: logger })).expect("only set once");
+
+    for fallback in fallbacks {
+        warn!("{}", fallback);
+    }
+    log_file
+}
+
+thread_local! {
+    static TAG: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Start everything this thread logs with `tag`, e.g. which `--manifest` entry it's working on,
+/// until it's set to `None`.
+pub fn tag(tag: Option<String>) {
+    TAG.with(|t| *t.borrow_mut() = tag);
+}
+
+/// Adds the thread's tag to each record, for whichever logger is really in use.
+struct Tagged {
+    inner: Box<dyn log::Log>,
+}
+
+impl log::Log for Tagged {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        TAG.with(|tag| match &*tag.borrow() {
+            Some(tag) => self.inner.log(
+                &record
+                    .to_builder()
+                    .args(format_args!("{}: {}", tag, record.args()))
+                    .build(),
+            ),
+            None => self.inner.log(record),
+        })
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+/// What gets logged: the `-v` level, unless `RUST_LOG` is set, whose directives then win for
+/// the modules they name, or everywhere for a bare level.
+pub fn filter(level: LevelFilter, rust_log: Option<&str>) -> Filter {
+    let mut builder = env_logger::filter::Builder::new();
+    builder.filter_level(level);
+    if let Some(spec) = rust_log.filter(|spec| !spec.trim().is_empty()) {
+        builder.parse(spec);
+    }
+    builder.build()
+}
+
+/// How a human log line looks: by default, the local time, the level, and the module, padded to
+/// the longest seen so far, so the messages line up.
+#[derive(Clone, Copy)]
+struct Layout {
+    timestamps: Timestamps,
+    show_target: bool,
+}
+
+#[derive(Clone, Copy)]
+enum Timestamps {
+    Local,
+    /// ISO 8601, with a `Z`.
+    Utc,
+    None,
+}
+
+static TARGET_WIDTH: AtomicUsize = AtomicUsize::new(0);
+
+/// All five characters wide.
+fn level_name(level: Level) -> &'static str {
+    match level {
+        Level::Trace => "TRACE",
+        Level::Debug => "DEBUG",
+        Level::Info => "INFO ",
+        Level::Warn => "WARN ",
+        Level::Error => "ERROR",
+    }
+}
+
+impl Layout {
+    /// The module, padded, or nothing if it's not shown.
+    fn target(&self, target: &str) -> String {
+        if !self.show_target {
+            return String::new();
+        }
+        let width = TARGET_WIDTH
+            .fetch_max(target.len(), Ordering::Relaxed)
+            .max(target.len());
+        format!("{: <width$}", target, width = width)
+    }
+
+    fn line(
+        &self,
+        now: chrono::DateTime<chrono::Utc>,
+        level: impl Display,
+        target: impl Display,
+        message: impl Display,
+    ) -> String {
+        let mut line = String::from(" ");
+        match self.timestamps {
+            Timestamps::Local => line.push_str(&format!(
+                "{} ",
+                now.with_timezone(&chrono::Local)
+                    .format("%Y-%m-%dT%H:%M:%S%.3f")
+            )),
+            Timestamps::Utc => line.push_str(&format!(
+                "{} ",
+                now.to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+            )),
+            Timestamps::None => (),
+        }
+        line.push_str(&level.to_string());
+        if self.show_target {
+            line.push_str(&format!(" {}", target));
+        }
+        line.push_str(&format!(" > {}", message));
+        line
+    }
+}
+
+/// Whether to colour the log on stderr: `--color` wins, then `NO_COLOR`, then `CLICOLOR_FORCE`,
+/// and otherwise only if it's a terminal.
+fn write_style(color: &str, var: impl Fn(&str) -> Option<OsString>, tty: bool) -> WriteStyle {
+    let set = |name| var(name).filter(|v| !v.is_empty());
+    match color {
+        "always" => WriteStyle::Always,
+        "never" => WriteStyle::Never,
+        _ if set("NO_COLOR").is_some() => WriteStyle::Never,
+        _ if set("CLICOLOR_FORCE").filter(|v| v != "0").is_some() => WriteStyle::Always,
+        _ if tty => WriteStyle::Always,
+        _ => WriteStyle::Never,
+    }
+}
+
+/// The same as `filter`, for env_logger's own logger.
+fn directives(logger: &mut env_logger::Builder, level: LevelFilter, rust_log: Option<&str>) {
+    logger.filter_level(level);
+    if let Some(spec) = rust_log.filter(|spec| !spec.trim().is_empty()) {
+        logger.parse_filters(spec);
+    }
+}
+
+#[test]
+fn test_filter() {
+    let enabled = |filter: &Filter, level: log::Level, target: &str| {
+        filter.enabled(&log::Metadata::builder().level(level).target(target).build())
+    };
+
+    let plain = filter(LevelFilter::Warn, None);
+    assert_eq!(LevelFilter::Warn, plain.filter());
+    assert!(enabled(&plain, log::Level::Warn, "fetch_maybe"));
+    assert!(!enabled(&plain, log::Level::Info, "fetch_maybe::period"));
+
+    let empty = filter(LevelFilter::Warn, Some(""));
+    assert_eq!(LevelFilter::Warn, empty.filter());
+
+    let targeted = filter(LevelFilter::Warn, Some("fetch_maybe::period=trace"));
+    assert_eq!(LevelFilter::Trace, targeted.filter());
+    assert!(enabled(&targeted, log::Level::Trace, "fetch_maybe::period"));
+    assert!(enabled(&targeted, log::Level::Warn, "fetch_maybe"));
+    assert!(!enabled(&targeted, log::Level::Info, "fetch_maybe"));
+
+    let replaced = filter(LevelFilter::Warn, Some("error,fetch_maybe::lock=off"));
+    assert!(!enabled(&replaced, log::Level::Warn, "fetch_maybe"));
+    assert!(!enabled(&replaced, log::Level::Error, "fetch_maybe::lock"));
+}
+
+#[test]
+fn test_write_style() {
+    let none = |_: &str| None;
+    let no_color = |name: &str| Some(OsString::from(if "NO_COLOR" == name { "1" } else { "" }));
+    let force = |name: &str| {
+        Some(OsString::from(if "CLICOLOR_FORCE" == name {
+            "1"
+        } else {
+            ""
+        }))
+    };
+    let unforced = |name: &str| {
+        Some(OsString::from(if "CLICOLOR_FORCE" == name {
+            "0"
+        } else {
+            ""
+        }))
+    };
+
+    assert_eq!(WriteStyle::Always, write_style("auto", none, true));
+    assert_eq!(WriteStyle::Never, write_style("auto", none, false));
+    assert_eq!(WriteStyle::Never, write_style("auto", no_color, true));
+    assert_eq!(WriteStyle::Always, write_style("auto", force, false));
+    assert_eq!(WriteStyle::Never, write_style("auto", unforced, false));
+    assert_eq!(WriteStyle::Always, write_style("always", no_color, false));
+    assert_eq!(WriteStyle::Never, write_style("never", force, true));
+}
+
+#[test]
+fn test_layout() {
+    use chrono::TimeZone;
+
+    let now = chrono::Utc.with_ymd_and_hms(2019, 10, 3, 12, 0, 0).unwrap();
+    let layout = |timestamps, show_target| Layout {
+        timestamps,
+        show_target,
+    };
+    let args = |layout: Layout| {
+        layout.line(
+            now,
+            level_name(Level::Info),
+            "fetch_maybe::lock",
+            "          lock: holding",
+        )
+    };
+
+    let local = now
+        .with_timezone(&chrono::Local)
+        .format("%Y-%m-%dT%H:%M:%S%.3f");
+    assert_eq!(
+        format!(
+            " {} INFO  fetch_maybe::lock >           lock: holding",
+            local
+        ),
+        args(layout(Timestamps::Local, true))
+    );
+    assert_eq!(
+        " 2019-10-03T12:00:00.000Z INFO  fetch_maybe::lock >           lock: holding",
+        args(layout(Timestamps::Utc, true))
+    );
+    assert_eq!(
+        " 2019-10-03T12:00:00.000Z INFO  >           lock: holding",
+        args(layout(Timestamps::Utc, false))
+    );
+    assert_eq!(
+        " WARN  >           lock: holding",
+        layout(Timestamps::None, false).line(
+            now,
+            level_name(Level::Warn),
+            "",
+            "          lock: holding"
+        )
+    );
+
+    let padded = layout(Timestamps::None, true);
+    assert_eq!("fetch_maybe::lock", padded.target("fetch_maybe::lock"));
+    assert!(padded
+        .target("fetch_maybe")
+        .starts_with("fetch_maybe      "));
+    assert_eq!("", layout(Timestamps::None, false).target("fetch_maybe"));
+}
@@ -1,214 +1,268 @@
 use std::env;
+use std::ffi::OsString;
+use std::fs;
 use std::io;
 use std::path::Path;
-use std::path::PathBuf;
-use std::time;
+use std::thread;
 
-use clap::Arg;
-use failure::bail;
 use failure::err_msg;
 use failure::format_err;
 use failure::ResultExt;
 use log::debug;
-use log::error;
 use log::info;
 use log::warn;
 use log::LevelFilter;
-use std::io::Write;
-
-mod dir_of;
-mod period;
-
-fn main() -> Result<(), failure::Error> {
-    let matches = clap::App::new(clap::crate_name!())
-        .arg(
-            Arg::with_name("headers")
-                .short("H")
-                .long("header")
-                .takes_value(true)
-                .number_of_values(1)
-                .multiple(true),
-        )
-        .arg(
-            Arg::with_name("min-age")
-                .long("min-age")
-                .takes_value(true)
-                .number_of_values(1),
-        )
-        .arg(Arg::with_name("verbose").short("v").multiple(true))
-        .arg(Arg::with_name("url").index(1).required(true))
-        .arg(Arg::with_name("output").index(2).required(true))
-        .version(clap::crate_version!())
-        .get_matches();
-
-    let level = match matches.occurrences_of("verbose") {
-        0 => LevelFilter::Error,
-        1 => LevelFilter::Warn,
-        2 => LevelFilter::Info,
-        3 => LevelFilter::Debug,
-        _ => LevelFilter::Trace,
-    };
-
-    pretty_env_logger::formatted_timed_builder()
-        .filter_level(level)
-        .init();
-
-    let url = matches.value_of("url").expect("required");
-    let output = Path::new(matches.value_of_os("output").expect("required"));
-
-    debug!("     input URL: {:?}", url);
-    debug!("   output path: {:?}", output);
 
-    let min_age = match matches.value_of("min-age") {
-        Some(v) => Some(
-            period::parse_duration(v).with_context(|_| format_err!("parsing min-age: {:?}", v))?,
-        ),
-        None => None,
-    };
-
-    debug!("       min-age: {:?}", min_age);
+use fetch_maybe::app;
+use fetch_maybe::cache;
+use fetch_maybe::curl;
+use fetch_maybe::exit;
+use fetch_maybe::gc;
+use fetch_maybe::lock;
+use fetch_maybe::outcome;
+use fetch_maybe::period;
+use fetch_maybe::signals;
+use fetch_maybe::status;
+use fetch_maybe::target;
+use fetch_maybe::verify;
+
+mod args_file;
+mod config;
+mod logfile;
+mod logging;
+mod manifest;
+mod random;
+mod schedule;
+mod system_log;
+mod version;
+mod watch;
+
+/// How much to say about the error a run failed with.
+enum Report {
+    /// Everything `failure` has: the cause, any backtrace, and the context chain.
+    Full,
+    /// The context chain, on one line.
+    Line,
+    /// Just the exit status.
+    Nothing,
+    /// For `--cron`: what was being fetched, where to, and what went wrong, in one line.
+    Paragraph { url: String, output: String },
+}
 
-    let metadata_before = match output.metadata() {
-        Ok(metadata) => Some(metadata),
-        Err(ref e) if io::ErrorKind::NotFound == e.kind() => {
-            info!("reference time: output file missing, so not available");
-            None
+impl Report {
+    fn from(matches: &clap::ArgMatches) -> Report {
+        if matches.is_present("cron") && matches.occurrences_of("quiet") < 2 {
+            // a manifest's entries are reported one by one, so only the total is left
+            let url = match matches.value_of("url") {
+                Some(url) => url,
+                None => return Report::Line,
+            };
+            return Report::Paragraph {
+                url: target::redact(url),
+                output: match (curl::output(matches), matches.value_of("output-fd")) {
+                    (Some(output), _) => format!("{:?}", output),
+                    (None, Some(fd)) => format!("fd {}", fd),
+                    (None, None) if matches.is_present("discard") => "nowhere".to_string(),
+                    (None, None) => "the cache".to_string(),
+                },
+            };
         }
-        Err(e) => Err(e).with_context(|_| format_err!("reading output's info: {:?}", output))?,
-    };
-
-    let now = chrono::Utc::now();
-
-    let mtime_before = metadata_before
-        .as_ref()
-        // errors only for unsupported platforms, apparently
-        .and_then(|m| m.modified().ok())
-        .map(|t| chrono::DateTime::<chrono::Utc>::from(t))
-        // discard mtimes in the future, which are generally unexpected
-        .filter(|t| t < &now);
-
-    info!("reference time: {:?}", mtime_before);
-
-    if let Some(mtime) = mtime_before {
-        if let Some(min_age) = min_age {
-            if mtime > now - min_age {
-                info!("newer than min-age, done");
-                return Ok(());
-            }
+        match (
+            matches.occurrences_of("quiet"),
+            matches.occurrences_of("verbose"),
+        ) {
+            (0, _) | (_, 1..=u64::MAX) => Report::Full,
+            (1, 0) => Report::Line,
+            (_, 0) => Report::Nothing,
         }
     }
 
-    // no point doing any networking if we aren't going to be able to store the result
-    let temp = {
-        let output_location = dir_of::dir_of(output, env::current_dir)?;
-        if log::log_enabled!(log::Level::Debug) {
-            debug!("output tmp dir: {:?}", output_location.canonicalize());
+    fn print(&self, e: &failure::Error, outcome: &outcome::Outcome) {
+        let chain = e.iter_chain().map(|f| f.to_string()).collect::<Vec<_>>();
+        match self {
+            Report::Full => eprintln!("Error: {:?}", e),
+            Report::Line => eprintln!("fetch-maybe: {}", chain.join(": ")),
+            Report::Nothing => (),
+            // the outermost context says what we were doing, the rest why it didn't work;
+            // without one, go by how far the attempt got
+            Report::Paragraph { url, output } => match chain.split_first() {
+                Some((phase, cause)) if !cause.is_empty() => eprintln!(
+                    "fetch-maybe: fetching {} to {} failed while {}: {}",
+                    url,
+                    output,
+                    phase,
+                    cause.join(": ")
+                ),
+                _ if !outcome.phase.is_empty() => eprintln!(
+                    "fetch-maybe: fetching {} to {} failed while {}: {}",
+                    url,
+                    output,
+                    outcome.phase,
+                    chain.join(": ")
+                ),
+                _ => eprintln!(
+                    "fetch-maybe: fetching {} to {} failed: {}",
+                    url,
+                    output,
+                    chain.join(": ")
+                ),
+            },
         }
-
-        tempfile_fast::PersistableTempFile::new_in(&output_location)
-            .with_context(|_| format_err!("creating temporary file in {:?}", output_location))?
-    };
-
-    let mut temp = io::BufWriter::new(temp);
-
-    let mut req = ureq::get(url);
-    req.redirects(10);
-
-    if let Some(mtime) = mtime_before {
-        req.set("If-Modified-Since", &mtime.to_rfc2822());
     }
+}
 
-    if let Some(headers) = matches.values_of("headers") {
-        for header in headers {
-            let colon = header.find(':').ok_or_else(|| {
-                format_err!(
-                    "header missing a colon, expected format 'Foo: bar', got {:?}",
-                    header
-                )
-            })?;
-
-            let (key, mut value) = header.split_at(colon);
-
-            // colon
-            value = &value[1..];
-
-            if value.starts_with(' ') {
-                value = &value[1..];
-            }
-
-            req.set(key, value);
-            debug!("sending header: {:?}: {:?}", key, value);
+fn main() {
+    let mut report = Report::Full;
+    let mut outcome = outcome::Outcome::default();
+    if let Err(e) = run(&mut report, &mut outcome) {
+        // it's been logged, and isn't a failure as such
+        if e.downcast_ref::<lock::Held>().is_none() && e.downcast_ref::<exit::Unchanged>().is_none()
+        {
+            report.print(&e, &outcome);
         }
+        std::process::exit(exit::code(&e, outcome.phase));
     }
+}
 
-    debug!("       request: sending...");
-
-    let response = req.call();
-
-    if let Some(err) = response.synthetic_error() {
-        Err(ureq_error(err)).with_context(|_| err_msg("requesting"))?;
+/// `report` is updated as soon as it's known how the caller wants a failure reported, and
+/// `outcome` with what the last attempt did.
+fn run(report: &mut Report, outcome: &mut outcome::Outcome) -> Result<(), failure::Error> {
+    let expanded = args_file::expand(env::args_os())?;
+    let args: Vec<OsString> = expanded.iter().map(|arg| arg.value.clone()).collect();
+    if version::wants_verbose(&args) {
+        print!("{}", version::verbose());
+        return Ok(());
     }
+    let matches = match app().get_matches_from_safe(&args) {
+        Ok(matches) => matches,
+        Err(e) if e.use_stderr() => {
+            eprintln!("{}", e.message);
+            if let Some(from) = args_file::blame(&expanded, &e) {
+                eprintln!("({})", from);
+            }
+            std::process::exit(exit::Kind::Usage.code());
+        }
+        // --help and --version
+        Err(e) => e.exit(),
+    };
 
-    debug!("      response: {:?}", response.status_line());
-
-    match response.status() {
-        200..=299 => (),
-        304 /* not modified */ => {
-            info!("          done: not modified on the server");
-            return Ok(())
-        },
-        300..=399 => bail!("confused by redirection: {:?}", response.status_line()),
-        400..=599 => bail!("unhappy response: {:?}", response.status_line()),
-        _ => bail!("unexpected response: {:?}", response.status_line()),
+    // for packaging, so nothing else may go to stdout
+    if let Some(shell) = matches.value_of("generate-completions") {
+        let shell = shell.parse::<clap::Shell>().map_err(err_msg)?;
+        app().gen_completions_to("fetch-maybe", shell, &mut io::stdout());
+        return Ok(());
     }
 
-    let server_date = if let Some(server_modified) = response.header("Last-Modified") {
-        chrono::DateTime::parse_from_rfc2822(server_modified)
-            .ok()
-            .map(time::SystemTime::from)
-    } else {
+    *report = Report::from(&matches);
+
+    // the command line, with any args files, wins over the environment, which wins over the
+    // config file
+    let mut settings = Vec::new();
+    let mut warnings = Vec::new();
+    let config = if matches.is_present("no-config") {
         None
+    } else {
+        config::load(
+            matches.value_of_os("config").map(Path::new),
+            matches.value_of("profile"),
+        )?
+    };
+    if let Some(mut config) = config {
+        warnings.append(&mut config.warnings);
+        settings.extend(config.into_settings());
+    }
+    config::from_env(&app(), env::vars_os(), &mut settings, &mut warnings)?;
+    let (matches, settings) = config::apply(settings, &mut warnings, app, &args, matches)?;
+    *report = Report::from(&matches);
+
+    // each -q cancels out a -v, and anything left over turns off logging entirely;
+    // --cron counts as one
+    let quiet = matches.occurrences_of("quiet") + u64::from(matches.is_present("cron"));
+    let level = match matches.occurrences_of("verbose").checked_sub(quiet) {
+        None => LevelFilter::Off,
+        Some(0) => LevelFilter::Error,
+        Some(1) => LevelFilter::Warn,
+        Some(2) => LevelFilter::Info,
+        Some(3) => LevelFilter::Debug,
+        Some(_) => LevelFilter::Trace,
     };
 
-    info!("server lastmod: {:?}", server_date);
-
-    debug!("   downloading: started...");
+    let log_file = logging::init(&matches, level, env::var("RUST_LOG").ok().as_deref());
 
-    io::copy(&mut io::BufReader::new(response.into_reader()), &mut temp)
-        .with_context(|_| err_msg("downloading"))?;
+    for warning in &warnings {
+        warn!("{}", warning);
+    }
+    for setting in &settings {
+        debug!("       setting: {}", setting.describe());
+    }
 
-    debug!("   downloading: ...read complete...");
+    signals::install();
+    if log_file {
+        signals::reopen_on_hangup();
+    }
 
-    temp.flush()
-        .with_context(|_| err_msg("completing download"))?;
+    // before anything's looked at, but with the signals seen to, so a kill is prompt
+    let mut random = random::Random::from_env()?;
+    if let Some(v) = matches.value_of("splay") {
+        let splay = period::parse_duration(v)?
+            .to_std()
+            .with_context(|_| format_err!("--splay must be positive: {:?}", v))?;
+        let delay = random.below(splay);
+        info!("         splay: waiting {:?}", delay);
+        thread::sleep(delay);
+    }
 
-    let temp = temp.into_inner().expect("just flushed");
+    if matches.is_present("gc") {
+        return gc::run(&matches);
+    }
+    if let Some(status) = matches.subcommand_matches("status") {
+        return status::run(status);
+    }
+    if let Some(verify) = matches.subcommand_matches("verify") {
+        return verify::run(verify);
+    }
+    if matches.is_present("watch") {
+        let report = &*report;
+        return watch::run(&matches, &mut random, |agent| {
+            if let Some(path) = matches.value_of_os("manifest") {
+                let source = manifest::Source::File(Path::new(path));
+                manifest::run(source, &matches, report, agent)?;
+                return Ok("done");
+            }
+            *outcome = outcome::Outcome::default();
+            let shared = fetch_maybe::Shared {
+                agent: Some(agent.clone()),
+                ..fetch_maybe::Shared::default()
+            };
+            fetch_maybe::run(&matches, &shared, outcome)?;
+            Ok(outcome.kind.name())
+        });
+    }
+    if let Some(path) = matches.value_of_os("manifest") {
+        let source = manifest::Source::File(Path::new(path));
+        return manifest::run(source, &matches, report, &ureq::agent());
+    }
+    if matches.is_present("stdin") {
+        let null = matches.is_present("null");
+        let source = manifest::Source::Stdin { null };
+        return manifest::run(source, &matches, report, &ureq::agent());
+    }
 
-    debug!("   downloading: ...write complete.");
+    fetch_maybe::run(&matches, &fetch_maybe::Shared::default(), outcome)?;
 
-    if let Some(server_date) = server_date {
-        match filetime::set_file_handle_times(
-            temp.as_ref(),
-            None,
-            Some(filetime::FileTime::from(server_date)),
-        ) {
-            Ok(()) => debug!("     file time: set successfully on temporary"),
-            Err(e) => warn!("failed to set temp file modified time: {:?}", e),
-        }
+    // only once it's there, so a script's `$(fetch-maybe --cache-dir ...)` can trust it
+    if let (Some(dir), false) = (
+        matches.value_of_os("cache-dir"),
+        matches.is_present("dry-run"),
+    ) {
+        let dir = fs::canonicalize(dir).with_context(|_| format_err!("resolving {:?}", dir))?;
+        let url = target::parse(matches.value_of("url").expect("required"))?.url;
+        println!("{}", cache::path(&dir, &url).display());
     }
 
-    match temp.persist_by_rename(output) {
-        Ok(()) => (),
-        Err(e) => {
-            Err(e.error).with_context(|_| format_err!("replacing {:?} with download", output))?
-        }
-    };
-
-    info!("        output: ready");
+    if matches.is_present("fail-if-unchanged") && !outcome.changed() {
+        return Err(exit::Unchanged.into());
+    }
 
     Ok(())
 }
-
-fn ureq_error(err: &ureq::Error) -> failure::Error {
-    format_err!("request failed: {:?}", err)
-}
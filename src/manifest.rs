@@ -0,0 +1,598 @@
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::BufRead;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use failure::bail;
+use failure::format_err;
+use failure::ResultExt;
+use log::info;
+
+use fetch_maybe::app;
+use fetch_maybe::events::Events;
+use fetch_maybe::exit;
+use fetch_maybe::lock;
+use fetch_maybe::outcome::Outcome;
+use fetch_maybe::period;
+use fetch_maybe::rate;
+use fetch_maybe::size;
+use fetch_maybe::target;
+use fetch_maybe::Shared;
+
+use crate::config;
+use crate::logging;
+use crate::schedule::Schedule;
+use crate::Report;
+
+/// Options for the whole batch, not to be passed on to each entry.
+const BATCH_ONLY: &[&str] = &[
+    "manifest",
+    "stdin",
+    "null",
+    "jobs",
+    "fail-fast",
+    "per-host-delay",
+    "per-host-max",
+];
+
+/// One line of a manifest: `URL OUTPUT [--option value]...`.
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    pub line: usize,
+    pub url: String,
+    pub output: String,
+    pub options: Vec<String>,
+}
+
+/// Blank lines and `#` comments are skipped; words are split on spaces, and can be quoted as
+/// in the config file, `'like this'` or `"like\tthis"`.
+pub fn parse(text: &str) -> Result<Vec<Entry>, failure::Error> {
+    let mut entries = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        if let Some(entry) = parse_line(i + 1, line)? {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+/// Line `number`, or `None` if there's nothing on it.
+fn parse_line(number: usize, line: &str) -> Result<Option<Entry>, failure::Error> {
+    let mut words = words(line)
+        .with_context(|_| format_err!("line {}", number))?
+        .into_iter();
+    let url = match words.next() {
+        Some(url) => url,
+        None => return Ok(None),
+    };
+    let output = match words.next() {
+        Some(output) if !url.starts_with('-') && !output.starts_with('-') => output,
+        _ => bail!(
+            "line {}: expected a URL and an output, then any options",
+            number
+        ),
+    };
+    let options: Vec<String> = words.collect();
+    if let Some(short) = options
+        .iter()
+        .find(|o| o.starts_with('-') && !o.starts_with("--"))
+    {
+        bail!(
+            "line {}: only long options can be given, not {:?}",
+            number,
+            short
+        );
+    }
+    Ok(Some(Entry {
+        line: number,
+        url,
+        output,
+        options,
+    }))
+}
+
+fn words(line: &str) -> Result<Vec<String>, failure::Error> {
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        match chars.peek() {
+            None | Some('#') => return Ok(words),
+            Some(_) => (),
+        }
+        let mut word = String::new();
+        while let Some(&c) = chars.peek() {
+            match c {
+                c if c.is_whitespace() => break,
+                '"' | '\'' => word.push_str(&config::parse_string(&mut chars)?),
+                c => {
+                    word.push(c);
+                    chars.next();
+                }
+            }
+        }
+        words.push(word);
+    }
+}
+
+impl Entry {
+    /// The file it writes, if that's known before asking the server: not for a directory, or
+    /// a name with placeholders.
+    fn file(&self, current_dir: &Path) -> Option<PathBuf> {
+        let output = Path::new(&self.output);
+        if "-" == self.output || self.output.contains('{') || self.output.ends_with('/') {
+            return None;
+        }
+        let file = current_dir.join(output);
+        if file.is_dir() {
+            return None;
+        }
+        // lexically, as the file needn't exist yet
+        Some(file.components().collect())
+    }
+
+    /// The arguments to fetch it with: `defaults`, less any this entry sets itself, then its own.
+    ///
+    /// Options which can be repeated, like `--header`, are added to, rather than replaced.
+    fn args(&self, defaults: &[(&str, bool, Vec<String>)]) -> Vec<OsString> {
+        let own: Vec<&str> = self
+            .options
+            .iter()
+            .filter_map(|o| o.strip_prefix("--"))
+            .map(|o| o.split('=').next().unwrap_or_default())
+            .collect();
+
+        let mut args = vec![OsString::from("fetch-maybe")];
+        for (key, multiple, given) in defaults {
+            if *multiple || !own.contains(key) {
+                args.extend(given.iter().map(OsString::from));
+            }
+        }
+        args.extend(self.options.iter().map(OsString::from));
+        args.push(OsString::from(&self.url));
+        args.push(OsString::from(&self.output));
+        args
+    }
+}
+
+/// The options given for the whole run, each with whether it can be repeated and its
+/// arguments, as `--key=value` so no value can be taken for an option.
+fn defaults(matches: &clap::ArgMatches) -> Vec<(&'static str, bool, Vec<String>)> {
+    let mut defaults = Vec::new();
+    for (key, takes) in config::long_options(&app()) {
+        let times = matches.occurrences_of(key);
+        if 0 == times || BATCH_ONLY.contains(&key) {
+            continue;
+        }
+        let given = match (takes, matches.values_of_lossy(key)) {
+            (Some(_), Some(values)) if !values.is_empty() => {
+                values.iter().map(|v| format!("--{}={}", key, v)).collect()
+            }
+            // flags, many times for the likes of -vv, and options with optional values
+            _ => (0..times).map(|_| format!("--{}", key)).collect(),
+        };
+        defaults.push((key, takes.unwrap_or(false), given));
+    }
+    defaults
+}
+
+/// Where the entries come from.
+pub enum Source<'p> {
+    /// `--manifest`, all read and checked before anything's fetched.
+    File(&'p Path),
+    /// `--stdin`, each started as it arrives; with `-0`, NUL-terminated pairs.
+    Stdin { null: bool },
+}
+
+impl fmt::Display for Source<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::File(path) => write!(f, "manifest {:?}", path),
+            Source::Stdin { .. } => write!(f, "stdin"),
+        }
+    }
+}
+
+/// An entry ready to go: its arguments, and the host it's for.
+type Prepared = (Entry, clap::ArgMatches<'static>, String);
+
+/// What's needed to turn entries into fetches, and to keep them from writing the same file.
+struct Batch {
+    defaults: Vec<(&'static str, bool, Vec<String>)>,
+    current_dir: PathBuf,
+    outputs: HashMap<PathBuf, usize>,
+}
+
+impl Batch {
+    /// The entry, with the run's options, or why it can't be fetched, starting with its line.
+    fn prepare(&mut self, entry: Entry) -> Result<Prepared, String> {
+        let matches = app()
+            .get_matches_from_safe(entry.args(&self.defaults))
+            .map_err(|e| {
+                format!(
+                    "line {}: {}",
+                    entry.line,
+                    e.message.trim_start_matches("error: ")
+                )
+            })?;
+        let host = target::parse(&entry.url)
+            .map_err(|e| format!("line {}: {}", entry.line, e))?
+            .url
+            .host_str()
+            .unwrap_or_default()
+            .to_string();
+        // they'd fight over it, and the last to finish would win
+        if let Some(file) = entry.file(&self.current_dir) {
+            if let Some(first) = self.outputs.insert(file, entry.line) {
+                return Err(format!(
+                    "lines {} and {} both write {:?}",
+                    first, entry.line, entry.output
+                ));
+            }
+        }
+        Ok((entry, matches, host))
+    }
+}
+
+/// `--manifest` or `--stdin`: every entry, `--jobs` at a time, as the per-host limits allow,
+/// sharing an agent, and so its connections, and any `--limit-rate-total`.
+///
+/// A manifest's entries are all checked before anything is fetched. A failed entry, or a bad
+/// line on stdin, is reported and the rest carry on, unless `--fail-fast`; the run fails at
+/// the end if any did.
+pub fn run(
+    source: Source,
+    matches: &clap::ArgMatches,
+    report: &Report,
+    agent: &ureq::Agent,
+) -> Result<(), failure::Error> {
+    let jobs: usize = matches.value_of("jobs").unwrap_or("1").parse()?;
+    if 0 == jobs {
+        return Err(exit::classified(
+            exit::Kind::Usage,
+            "--jobs needs to be at least 1",
+        ));
+    }
+
+    let delay = match matches.value_of("per-host-delay") {
+        Some(v) => period::parse_duration(v)?
+            .to_std()
+            .with_context(|_| format_err!("negative per-host-delay: {:?}", v))?,
+        None => Duration::from_secs(0),
+    };
+    let max = match matches.value_of("per-host-max") {
+        Some(v) => v.parse()?,
+        None => 0,
+    };
+    let schedule = Arc::new(Schedule::new(delay, max));
+    let counts = Arc::new(Mutex::new(Counts::default()));
+    let fail_fast = matches.is_present("fail-fast");
+    // the count is the process's, and --watch runs the manifest again and again
+    let requests_before = fetch_maybe::requests_made();
+
+    let mut batch = Batch {
+        defaults: defaults(matches),
+        current_dir: env::current_dir()?,
+        outputs: HashMap::new(),
+    };
+    let workers = match source {
+        Source::File(path) => {
+            let usage = |message: String| {
+                exit::classified(exit::Kind::Usage, format!("{}: {}", source, message))
+            };
+            let text = fs::read_to_string(path)
+                .with_context(|_| format_err!("reading manifest {:?}", path))?;
+            let entries = parse(&text).map_err(|e| usage(e.to_string()))?;
+            let mut prepared = Vec::new();
+            for entry in entries {
+                prepared.push(batch.prepare(entry).map_err(usage)?);
+            }
+            counts.lock().expect("poisoned").entries = prepared.len();
+            let workers = jobs.min(prepared.len());
+            for entry in prepared {
+                schedule.push(entry.0.line, entry.2.clone(), entry);
+            }
+            schedule.close();
+            workers
+        }
+        Source::Stdin { null } => {
+            // on its own, so a --fail-fast run needn't wait for more input to finish
+            let schedule = Arc::clone(&schedule);
+            let counts = Arc::clone(&counts);
+            let quiet = matches!(report, Report::Nothing);
+            thread::spawn(move || {
+                read_stdin(null, batch, &schedule, &counts, quiet, fail_fast);
+                schedule.close();
+            });
+            jobs
+        }
+    };
+
+    let shared = Shared {
+        agent: Some(agent.clone()),
+        rate: match matches.value_of("limit-rate-total") {
+            Some(v) => Some(rate::Bucket::new(size::parse_size(v)?)),
+            None => None,
+        },
+        // one stream, so the sequence counts across entries
+        events: if matches.is_present("events") {
+            Events::stdout()
+        } else {
+            Events::default()
+        },
+        // and one DNS cache, so an entry's lookup is the next one's answer
+        ..Shared::default()
+    };
+    let cron = matches.is_present("cron");
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                while let Some((host, (entry, entry_matches, _))) = schedule.next() {
+                    let result = fetch(&entry, &entry_matches, &shared, report, cron);
+                    schedule.done(&host);
+                    let mut counts = counts.lock().expect("poisoned");
+                    match result {
+                        Some(true) => counts.changed += 1,
+                        Some(false) => counts.unchanged += 1,
+                        None => {
+                            counts.failed += 1;
+                            if fail_fast {
+                                schedule.stop();
+                            }
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    let counts = counts.lock().expect("poisoned");
+    if matches.is_present("stats") {
+        eprintln!(
+            "fetch-maybe: {} requests for {} entries, through one agent",
+            fetch_maybe::requests_made() - requests_before,
+            counts.entries
+        );
+    }
+    let not_started = counts.entries - counts.changed - counts.unchanged - counts.failed;
+    let summary = format!(
+        "{}: {} changed, {} unchanged, {} failed{}",
+        source,
+        counts.changed,
+        counts.unchanged,
+        counts.failed,
+        if not_started > 0 {
+            format!(", {} not started", not_started)
+        } else {
+            String::new()
+        }
+    );
+
+    // a failure is reported with the counts, and quiet, and --cron, only want to hear about those
+    if counts.failed > 0 {
+        bail!("{}", summary);
+    }
+    if !cron && 0 == matches.occurrences_of("quiet") {
+        eprintln!("fetch-maybe: {}", summary);
+    }
+    if matches.is_present("fail-if-unchanged") && 0 == counts.changed {
+        return Err(exit::Unchanged.into());
+    }
+    Ok(())
+}
+
+/// Queue each entry from stdin as it's read, until the end of it, or a `--fail-fast` stop.
+///
+/// A line that can't be fetched is reported, unless `quiet`, and counted as a failure; with
+/// `null`, the Nth pair of fields counts as line N.
+fn read_stdin(
+    null: bool,
+    mut batch: Batch,
+    schedule: &Schedule<Prepared>,
+    counts: &Mutex<Counts>,
+    quiet: bool,
+    fail_fast: bool,
+) {
+    let stdin = io::stdin();
+    let mut stdin = stdin.lock();
+    let mut read = |buf: &mut Vec<u8>| -> Result<Option<String>, failure::Error> {
+        buf.clear();
+        if 0 == stdin.read_until(if null { b'\0' } else { b'\n' }, buf)? {
+            return Ok(None);
+        }
+        if buf.last() == Some(&if null { b'\0' } else { b'\n' }) {
+            buf.pop();
+        }
+        Ok(Some(String::from_utf8(buf.clone()).map_err(|_| {
+            format_err!("not UTF-8: {:?}", String::from_utf8_lossy(buf))
+        })?))
+    };
+
+    let mut buf = Vec::new();
+    for number in 1.. {
+        let entry = match read(&mut buf) {
+            Ok(None) => return,
+            Ok(Some(url)) if null => match read(&mut buf) {
+                Ok(None) => Err(format!("line {}: {:?} has no output", number, url)),
+                Ok(Some(output)) if url.is_empty() || output.is_empty() => Err(format!(
+                    "line {}: expected a URL and an output, not an empty field",
+                    number
+                )),
+                Ok(Some(output)) => Ok(Some(Entry {
+                    line: number,
+                    url,
+                    output,
+                    options: Vec::new(),
+                })),
+                Err(e) => Err(format!("line {}: {}", number, e)),
+            },
+            Ok(Some(line)) => parse_line(number, &line).map_err(|e| {
+                e.iter_chain()
+                    .map(|f| f.to_string())
+                    .collect::<Vec<_>>()
+                    .join(": ")
+            }),
+            Err(e) => Err(format!("line {}: {}", number, e)),
+        };
+        let prepared = match entry {
+            Ok(None) => continue,
+            Ok(Some(entry)) => batch.prepare(entry),
+            Err(e) => Err(e),
+        };
+
+        let mut counts = counts.lock().expect("poisoned");
+        counts.entries += 1;
+        match prepared {
+            Ok(entry) => schedule.push(number, entry.2.clone(), entry),
+            Err(message) => {
+                if !quiet {
+                    eprintln!("fetch-maybe: stdin {}", message);
+                }
+                counts.failed += 1;
+                if fail_fast {
+                    schedule.stop();
+                    return;
+                }
+            }
+        }
+        if schedule.stopped() {
+            return;
+        }
+    }
+}
+
+#[derive(Default)]
+struct Counts {
+    /// Everything read which wasn't blank, whether or not it could be started.
+    entries: usize,
+    changed: usize,
+    unchanged: usize,
+    failed: usize,
+}
+
+/// Whether it changed the output, or `None` if it failed, which has been reported.
+fn fetch(
+    entry: &Entry,
+    matches: &clap::ArgMatches,
+    shared: &Shared,
+    report: &Report,
+    cron: bool,
+) -> Option<bool> {
+    logging::tag(Some(format!("line {}", entry.line)));
+    info!("      manifest: {}", target::redact(&entry.url));
+    let mut outcome = Outcome::default();
+    let shared = Shared {
+        events: shared.events.for_entry(entry.line),
+        ..shared.clone()
+    };
+    let result = fetch_maybe::run(matches, &shared, &mut outcome);
+    logging::tag(None);
+
+    match result {
+        Ok(()) => Some(outcome.changed()),
+        // it's been logged, and it's that run's to change
+        Err(ref e) if e.downcast_ref::<lock::Held>().is_some() => Some(false),
+        Err(e) => {
+            match report {
+                Report::Nothing => (),
+                // a paragraph each, saying which
+                _ if cron => Report::from(matches).print(&e, &outcome),
+                _ => eprintln!(
+                    "fetch-maybe: line {}: {}: {}",
+                    entry.line,
+                    target::redact(&entry.url),
+                    e.iter_chain()
+                        .map(|f| f.to_string())
+                        .collect::<Vec<_>>()
+                        .join(": ")
+                ),
+            }
+            None
+        }
+    }
+}
+
+#[test]
+fn test_parse() {
+    let text = concat!(
+        "# comment\n",
+        "\n",
+        "https://example.com/a a.txt\n",
+        "  https://example.com/b   'b c.txt' --header 'X-Token: \"t\"' --min-age=1h # why\n",
+    );
+    assert_eq!(
+        vec![
+            Entry {
+                line: 3,
+                url: "https://example.com/a".to_string(),
+                output: "a.txt".to_string(),
+                options: Vec::new(),
+            },
+            Entry {
+                line: 4,
+                url: "https://example.com/b".to_string(),
+                output: "b c.txt".to_string(),
+                options: vec![
+                    "--header".to_string(),
+                    "X-Token: \"t\"".to_string(),
+                    "--min-age=1h".to_string()
+                ],
+            },
+        ],
+        parse(text).unwrap()
+    );
+
+    let err = |text| parse(text).unwrap_err().to_string();
+    assert!(err("a b\nhttps://example.com/\n").starts_with("line 2:"));
+    assert!(err("a b -H 'X: y'").contains("only long options"));
+    assert!(err("a 'b").starts_with("line 1"));
+    assert!(err("--min-age 1h a b").contains("expected a URL"));
+}
+
+#[test]
+fn test_args() {
+    let entry = Entry {
+        line: 1,
+        url: "https://example.com/".to_string(),
+        output: "out".to_string(),
+        options: vec![
+            "--min-age=1h".to_string(),
+            "--header".to_string(),
+            "B: 2".to_string(),
+        ],
+    };
+    let defaults = [
+        ("min-age", false, vec!["--min-age=1d".to_string()]),
+        ("header", true, vec!["--header=A: 1".to_string()]),
+        (
+            "verbose",
+            false,
+            vec!["--verbose".to_string(), "--verbose".to_string()],
+        ),
+    ];
+    assert_eq!(
+        vec![
+            "fetch-maybe",
+            "--header=A: 1",
+            "--verbose",
+            "--verbose",
+            "--min-age=1h",
+            "--header",
+            "B: 2",
+            "https://example.com/",
+            "out"
+        ],
+        entry.args(&defaults)
+    );
+}
@@ -0,0 +1,69 @@
+use std::fs;
+
+use failure::bail;
+use failure::format_err;
+use failure::ResultExt;
+
+/// Those which can be sent again without doing any more than the first did, as RFC 9110 has it.
+const IDEMPOTENT: &[&str] = &["GET", "HEAD", "PUT", "DELETE", "OPTIONS", "TRACE"];
+
+/// `--method`, or POST when there's `--data` to send.
+pub fn from_matches<'m>(matches: &'m clap::ArgMatches) -> &'m str {
+    match matches.value_of("method") {
+        Some(method) => method,
+        None if matches.is_present("data") => "POST",
+        None => "GET",
+    }
+}
+
+/// Whether `method` can be sent again under `--retry`, without `--retry-non-idempotent`.
+pub fn is_idempotent(method: &str) -> bool {
+    IDEMPOTENT.contains(&method)
+}
+
+/// A method is a token, and case matters, so `post` isn't `POST`, but is still allowed.
+pub fn parse(v: &str) -> Result<(), failure::Error> {
+    let tchar = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+    if v.is_empty() || !v.chars().all(tchar) {
+        bail!("not a method name: {:?}", v);
+    }
+    Ok(())
+}
+
+/// `--data`: the value as it is, or the contents of FILE for `@FILE`, read again for each
+/// attempt, so it can be changed between them.
+pub fn data(arg: &str) -> Result<Vec<u8>, failure::Error> {
+    match arg.strip_prefix('@') {
+        Some(file) => {
+            Ok(fs::read(file).with_context(|_| format_err!("reading --data {:?}", file))?)
+        }
+        None => Ok(arg.as_bytes().to_vec()),
+    }
+}
+
+/// Whether a redirect with `status` is followed with the same method and body; otherwise it's a
+/// GET, with no body: always for a 303, and for a POST after a 301 or 302, as browsers do.
+pub fn kept_after(status: u16, method: &str) -> bool {
+    match status {
+        303 => "HEAD" == method,
+        301 | 302 => "POST" != method,
+        _ => true,
+    }
+}
+
+#[test]
+fn test_parse() {
+    assert!(parse("POST").is_ok());
+    assert!(parse("PROPFIND").is_ok());
+    assert!(parse("").is_err());
+    assert!(parse("GET /").is_err());
+}
+
+#[test]
+fn test_kept_after() {
+    assert!(!kept_after(303, "PUT"));
+    assert!(!kept_after(302, "POST"));
+    assert!(kept_after(302, "PUT"));
+    assert!(kept_after(307, "POST"));
+    assert!(kept_after(308, "POST"));
+}
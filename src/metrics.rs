@@ -0,0 +1,308 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+use failure::format_err;
+use failure::ResultExt;
+use log::debug;
+
+use crate::dir_of;
+use crate::outcome;
+use crate::outcome::Outcome;
+use crate::sink;
+
+const LAST_RUN: &str = "fetch_maybe_last_run_timestamp_seconds";
+const LAST_SUCCESS: &str = "fetch_maybe_last_success_timestamp_seconds";
+const LAST_OUTCOME: &str = "fetch_maybe_last_outcome";
+
+/// What a metrics file says of the runs that wrote it, as Unix times.
+pub struct Last {
+    pub run: Option<f64>,
+    pub success: Option<f64>,
+    /// As `Kind::name` has it, or `failed`.
+    pub outcome: Option<String>,
+}
+
+/// Replace `path`, atomically, with gauges for node_exporter's textfile collector.
+///
+/// A failed run still updates the last run and outcome, so staleness can be alerted on; the
+/// last success is carried over from the file it replaces.
+pub fn write(
+    path: &Path,
+    output: &str,
+    outcome: &Outcome,
+    succeeded: bool,
+    duration: Duration,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Result<(), failure::Error> {
+    let now = seconds(now);
+    let last_success = if succeeded {
+        Some(now)
+    } else {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|previous| last_success(&previous))
+    };
+    let text = text(output, outcome, succeeded, duration, now, last_success);
+
+    let dir = dir_of::dir_of(path, env::current_dir)?;
+    let mut temp = sink::temp_in(&dir)?;
+    temp.write_all(text.as_bytes())
+        .with_context(|_| format_err!("writing metrics for {:?}", path))?;
+    temp.persist_by_rename(path)
+        .map_err(|e| e.error)
+        .with_context(|_| format_err!("replacing {:?}", path))?;
+
+    debug!("       metrics: written to {:?}", path);
+    Ok(())
+}
+
+fn seconds(at: chrono::DateTime<chrono::Utc>) -> f64 {
+    at.timestamp_millis() as f64 / 1000.0
+}
+
+fn text(
+    output: &str,
+    outcome: &Outcome,
+    succeeded: bool,
+    duration: Duration,
+    now: f64,
+    last_success: Option<f64>,
+) -> String {
+    let label = format!("output={}", label_value(output));
+    let mut text = String::new();
+    let mut gauge = |name: &str, help: &str, samples: &[(String, f64)]| {
+        text.push_str(&format!(
+            "# HELP {} {}\n# TYPE {} gauge\n",
+            name, help, name
+        ));
+        for (labels, value) in samples {
+            text.push_str(&format!("{}{{{}}} {}\n", name, labels, value));
+        }
+    };
+
+    gauge(
+        LAST_RUN,
+        "When the last run finished.",
+        &[(label.clone(), now)],
+    );
+    if let Some(last_success) = last_success {
+        gauge(
+            LAST_SUCCESS,
+            "When the last successful run finished.",
+            &[(label.clone(), last_success)],
+        );
+    }
+
+    let current = if succeeded {
+        outcome.kind.name()
+    } else {
+        "failed"
+    };
+    let outcomes: Vec<(String, f64)> = outcome::Kind::ALL
+        .iter()
+        .map(|k| k.name())
+        .chain(Some("failed"))
+        .map(|name| {
+            (
+                format!("{},outcome={}", label, label_value(name)),
+                if name == current { 1.0 } else { 0.0 },
+            )
+        })
+        .collect();
+    gauge(
+        LAST_OUTCOME,
+        "1 for how the last run ended, 0 for the others.",
+        &outcomes,
+    );
+    gauge(
+        "fetch_maybe_bytes_downloaded",
+        "The body the last run received, in bytes.",
+        &[(label.clone(), outcome.bytes.unwrap_or(0) as f64)],
+    );
+    gauge(
+        "fetch_maybe_duration_seconds",
+        "How long the last run took, including retries.",
+        &[(label.clone(), duration.as_secs_f64())],
+    );
+    if let Some(expires) = outcome.cert_expires {
+        gauge(
+            "fetch_maybe_cert_expiry_timestamp_seconds",
+            "When the server's certificate expires, as the last run found.",
+            &[(label, seconds(expires.into()))],
+        );
+    }
+    text
+}
+
+/// Quoted, with the escapes the exposition format has: backslash, double quote and newline.
+fn label_value(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn last_success(previous: &str) -> Option<f64> {
+    value(previous, LAST_SUCCESS)
+}
+
+fn value(text: &str, name: &str) -> Option<f64> {
+    text.lines()
+        .find(|line| line.starts_with(name))
+        .and_then(|line| line.rsplit(' ').next())
+        .and_then(|value| value.parse().ok())
+}
+
+/// Read back what `write` wrote.
+pub fn last(text: &str) -> Last {
+    let outcome = text
+        .lines()
+        .filter(|line| line.starts_with(LAST_OUTCOME) && line.ends_with(" 1"))
+        .find_map(|line| {
+            let (_, rest) = line.split_once(",outcome=\"")?;
+            rest.split_once('"').map(|(name, _)| name.to_string())
+        });
+    Last {
+        run: value(text, LAST_RUN),
+        success: last_success(text),
+        outcome,
+    }
+}
+
+/// A sample's name, labels and value.
+#[cfg(test)]
+type Sample = (String, Vec<(String, String)>, f64);
+
+/// Just enough of the exposition format to check ours.
+#[cfg(test)]
+fn parse(text: &str) -> Vec<Sample> {
+    let valid_name = |name: &str| {
+        !name.is_empty()
+            && !name.starts_with(|c: char| c.is_ascii_digit())
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || '_' == c || ':' == c)
+    };
+    let mut typed = Vec::new();
+    let mut samples = Vec::new();
+    for line in text.lines() {
+        if let Some(comment) = line.strip_prefix("# ") {
+            let mut words = comment.splitn(3, ' ');
+            match (words.next(), words.next(), words.next()) {
+                (Some("HELP"), Some(name), Some(_)) => assert!(valid_name(name), "{}", line),
+                (Some("TYPE"), Some(name), Some("gauge")) => typed.push(name.to_string()),
+                _ => panic!("bad comment: {:?}", line),
+            }
+            continue;
+        }
+
+        let open = line.find('{').expect("labels");
+        let name = &line[..open];
+        assert!(valid_name(name), "{}", line);
+        assert!(typed.iter().any(|t| t == name), "untyped: {}", line);
+
+        let mut labels = Vec::new();
+        let mut chars = line[open + 1..].chars();
+        loop {
+            let key: String = chars.by_ref().take_while(|c| '=' != *c).collect();
+            assert!(valid_name(&key), "label {:?} in {}", key, line);
+            assert_eq!(Some('"'), chars.next(), "{}", line);
+            let mut value = String::new();
+            loop {
+                match chars.next().expect("closed") {
+                    '"' => break,
+                    '\\' => match chars.next() {
+                        Some('\\') => value.push('\\'),
+                        Some('"') => value.push('"'),
+                        Some('n') => value.push('\n'),
+                        other => panic!("bad escape {:?} in {}", other, line),
+                    },
+                    c => value.push(c),
+                }
+            }
+            labels.push((key, value));
+            match chars.next() {
+                Some(',') => continue,
+                Some('}') => break,
+                other => panic!("unexpected {:?} in {}", other, line),
+            }
+        }
+        let rest: String = chars.collect();
+        let value = rest
+            .strip_prefix(' ')
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_else(|| panic!("bad value in {}", line));
+        samples.push((name.to_string(), labels, value));
+    }
+    samples
+}
+
+#[test]
+fn test_text() {
+    let outcome = Outcome {
+        kind: outcome::Kind::Fetched,
+        bytes: Some(42),
+        cert_expires: Some(std::time::UNIX_EPOCH + Duration::from_secs(2_000_000_000)),
+        ..Outcome::default()
+    };
+    let odd = "dir/\"odd\"\\\n.txt";
+    let ok = text(
+        odd,
+        &outcome,
+        true,
+        Duration::from_millis(1500),
+        1e9,
+        Some(1e9),
+    );
+    let samples = parse(&ok);
+
+    let value = |name: &str, outcome: Option<&str>| {
+        samples
+            .iter()
+            .find(|(n, labels, _)| {
+                n == name
+                    && labels[0] == ("output".to_string(), odd.to_string())
+                    && labels.get(1).map(|(_, v)| v.as_str()) == outcome
+            })
+            .unwrap_or_else(|| panic!("no {} in {}", name, ok))
+            .2
+    };
+    assert_eq!(1e9, value("fetch_maybe_last_run_timestamp_seconds", None));
+    assert_eq!(1e9, value(LAST_SUCCESS, None));
+    assert_eq!(1.0, value("fetch_maybe_last_outcome", Some("fetched")));
+    assert_eq!(0.0, value("fetch_maybe_last_outcome", Some("failed")));
+    assert_eq!(42.0, value("fetch_maybe_bytes_downloaded", None));
+    assert_eq!(1.5, value("fetch_maybe_duration_seconds", None));
+    assert_eq!(
+        2e9,
+        value("fetch_maybe_cert_expiry_timestamp_seconds", None)
+    );
+
+    // a failure keeps the previous success, and is the only outcome set
+    let failed = text("out", &outcome, false, Duration::default(), 2e9, Some(1e9));
+    assert_eq!(Some(1e9), last_success(&failed));
+    let set: Vec<_> = parse(&failed)
+        .into_iter()
+        .filter(|(name, _, value)| "fetch_maybe_last_outcome" == name && 1.0 == *value)
+        .map(|(_, labels, _)| labels[1].1.clone())
+        .collect();
+    assert_eq!(vec!["failed"], set);
+    let last = last(&failed);
+    assert_eq!(
+        (Some(2e9), Some(1e9), Some("failed")),
+        (last.run, last.success, last.outcome.as_deref())
+    );
+
+    assert!(!text("out", &outcome, false, Duration::default(), 2e9, None).contains(LAST_SUCCESS));
+}
@@ -0,0 +1,56 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::Context;
+use std::task::Poll;
+use std::task::Waker;
+use std::thread;
+
+use crate::FetchOutcome;
+use crate::FetchRequest;
+
+type Finished = Result<FetchOutcome, failure::Error>;
+
+#[derive(Default)]
+struct Shared {
+    result: Option<Finished>,
+    waker: Option<Waker>,
+}
+
+/// `fetch`, for awaiting from any executor.
+///
+/// The transfer still runs on its own thread, through the same code as `fetch`, so the two
+/// can't decide differently; the future only waits for it, without blocking the executor.
+pub fn fetch_async(request: FetchRequest) -> impl Future<Output = Finished> {
+    let shared = Arc::new(Mutex::new(Shared::default()));
+    let done = Arc::clone(&shared);
+    thread::spawn(move || {
+        let result = crate::fetch(&request);
+        let mut done = done.lock().expect("poisoned");
+        done.result = Some(result);
+        if let Some(waker) = done.waker.take() {
+            waker.wake();
+        }
+    });
+    Pending { shared }
+}
+
+struct Pending {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Future for Pending {
+    type Output = Finished;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Finished> {
+        let mut shared = self.shared.lock().expect("poisoned");
+        match shared.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
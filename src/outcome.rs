@@ -0,0 +1,88 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use crate::dns;
+
+/// How a run ended, as far as the output is concerned.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Kind {
+    /// Done before the server had anything to say: `--min-age`, `--no-clobber` and the like.
+    #[default]
+    Skipped,
+    /// The server had nothing new: a `304`, a `204`, or nothing appended.
+    Unchanged,
+    /// Installed from `--store`, without downloading.
+    Stored,
+    /// A body was downloaded and written out.
+    Fetched,
+}
+
+impl Kind {
+    pub const ALL: &'static [Kind] = &[Kind::Skipped, Kind::Unchanged, Kind::Stored, Kind::Fetched];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Skipped => "skipped",
+            Kind::Unchanged => "unchanged",
+            Kind::Stored => "stored",
+            Kind::Fetched => "fetched",
+        }
+    }
+}
+
+/// What one attempt found out, for reporting once the run is over.
+#[derive(Default)]
+pub struct Outcome {
+    pub kind: Kind,
+    /// How far it got, for saying what failed: "requesting", "downloading" and so on.
+    pub phase: &'static str,
+    /// As requested, normalised and without credentials.
+    pub url: String,
+    /// Where the last request went, after redirects.
+    pub final_url: Option<String>,
+    /// The first address `final_url`'s host resolved to, the one ureq connects to.
+    pub remote: Option<SocketAddr>,
+    /// How many host names `--dns-cache-ttl` had the addresses of already.
+    pub lookups: dns::Lookups,
+    pub status: Option<u16>,
+    /// The server's `Last-Modified`, if it sent one that parsed.
+    pub last_modified: Option<SystemTime>,
+    /// The final response's, as it was sent.
+    pub etag: Option<String>,
+    /// When the final response stops being fresh, by its `Cache-Control` or `Expires`.
+    pub expires: Option<SystemTime>,
+    /// When the server's certificate does, if `--cert-expiry-warn` or `-fail` looked.
+    pub cert_expires: Option<SystemTime>,
+    /// Whether that's within either's window.
+    pub cert_expiring: Option<bool>,
+    /// Of the body, as received.
+    pub bytes: Option<u64>,
+    pub sha256: Option<[u8; 32]>,
+    /// What the body was copied through, once there was one to copy.
+    pub buffer: Option<usize>,
+    /// `None` for stdout and `--output-fd`, and before the output is known.
+    pub output: Option<PathBuf>,
+    /// Fetched, but the same bytes as the output it replaced.
+    pub identical: bool,
+    /// Why, if it was `Skipped`: `min-age`, `no-clobber` or `dry-run`.
+    pub skipped: Option<&'static str>,
+    /// The response heads for `--dump-headers`.
+    pub headers: String,
+    /// From the start of the attempt until the final response's headers arrived.
+    pub first_byte: Option<Duration>,
+    /// The whole attempt.
+    pub elapsed: Option<Duration>,
+}
+
+impl Outcome {
+    /// A redownload of the same bytes is a success, but not a change.
+    pub fn changed(&self) -> bool {
+        match self.kind {
+            Kind::Fetched => !self.identical,
+            Kind::Stored => true,
+            Kind::Skipped | Kind::Unchanged => false,
+        }
+    }
+}
@@ -0,0 +1,572 @@
+use std::ffi::OsStr;
+use std::io;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use failure::bail;
+use failure::format_err;
+use failure::ResultExt;
+use log::debug;
+use log::info;
+use log::warn;
+use percent_encoding::percent_decode_str;
+use url::Url;
+
+use crate::exit;
+
+const ENOTDIR: i32 = 20;
+
+/// Whether the output argument is a directory to put the download in, rather than the file.
+pub fn is_directory(output: &OsStr) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+    output.as_bytes().ends_with(b"/") || Path::new(output).is_dir()
+}
+
+/// Fail early, before any networking, if the output's directory can't possibly hold it.
+pub fn check_directory(dir: &Path) -> Result<(), failure::Error> {
+    // "" is the current directory, for bare file names
+    if dir.as_os_str().is_empty() {
+        return Ok(());
+    }
+
+    let fail = |message: String| Err(exit::classified(exit::Kind::Filesystem, message));
+    match dir.metadata() {
+        Ok(ref metadata) if metadata.is_dir() => Ok(()),
+        Ok(_) => fail(format!(
+            "{:?} is not a directory, so can't contain the output",
+            dir
+        )),
+        Err(ref e) if io::ErrorKind::NotFound == e.kind() => match dangling_symlink(dir) {
+            Some((link, target)) => fail(dangling_message(dir, link, &target)),
+            None => fail(format!(
+                "parent directory {:?} does not exist, see --create-dirs",
+                dir
+            )),
+        },
+        Err(ref e) if Some(ENOTDIR) == e.raw_os_error() => fail(format!(
+            "part of {:?} is not a directory, so it can't contain the output",
+            dir
+        )),
+        Err(e) => Err(e).with_context(|_| format_err!("reading output directory {:?}", dir))?,
+    }
+}
+
+/// Fail early if what we'd replace is a directory, which the rename would only refuse after the
+/// whole download; given an output argument that's a directory, the name is one made inside it.
+pub fn check_not_directory(
+    output: &Path,
+    existing: Option<&std::fs::Metadata>,
+) -> Result<(), failure::Error> {
+    match existing {
+        Some(metadata) if metadata.is_dir() => Err(exit::classified(
+            exit::Kind::Filesystem,
+            format!(
+                "output path {:?} is a directory, not a file to replace",
+                output
+            ),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// `mkdir -p`, with `mode` (less the umask) for the directories it creates.
+pub fn create_directory(dir: &Path, mode: u32) -> Result<(), failure::Error> {
+    use std::os::unix::fs::DirBuilderExt;
+
+    if dir.as_os_str().is_empty() || dir.is_dir() {
+        return Ok(());
+    }
+
+    // like `mkdir -p`, which won't make a link's target either
+    if let Some((link, target)) = dangling_symlink(dir) {
+        return Err(exit::classified(
+            exit::Kind::Filesystem,
+            dangling_message(dir, link, &target),
+        ));
+    }
+
+    // one at a time, so an error can say exactly which one failed
+    let missing: Vec<&Path> = dir.ancestors().take_while(|d| !d.exists()).collect();
+    for component in missing.into_iter().rev() {
+        match std::fs::DirBuilder::new().mode(mode).create(component) {
+            Ok(()) => info!("   create-dirs: created {:?}", component),
+            // raced with someone else making it
+            Err(ref e) if io::ErrorKind::AlreadyExists == e.kind() && component.is_dir() => (),
+            Err(e) => Err(e).with_context(|_| format_err!("creating directory {:?}", component))?,
+        }
+    }
+
+    Ok(())
+}
+
+/// `dir` with its symlinks resolved, so the temporary file is made where the output will really
+/// be, not somewhere that only looks like it; the output is still renamed to by the path given.
+///
+/// A directory that won't resolve as it's a dangling symlink fails; for anything else, `dir` is
+/// left as it is, for creating the temporary file in it to explain.
+pub fn real_directory(dir: &Path) -> Result<PathBuf, failure::Error> {
+    match std::fs::canonicalize(dir) {
+        Ok(real) => Ok(real),
+        Err(_) => match dangling_symlink(dir) {
+            Some((link, target)) => Err(exit::classified(
+                exit::Kind::Filesystem,
+                dangling_message(dir, link, &target),
+            )),
+            None => Ok(dir.to_path_buf()),
+        },
+    }
+}
+
+/// The first of `dir` and its parents that's a symlink to nothing, and what it says it's to.
+fn dangling_symlink(dir: &Path) -> Option<(&Path, PathBuf)> {
+    dir.ancestors().find_map(|d| {
+        let is_link = d.symlink_metadata().ok()?.file_type().is_symlink();
+        match std::fs::metadata(d) {
+            Err(ref e) if is_link && io::ErrorKind::NotFound == e.kind() => {
+                Some((d, std::fs::read_link(d).ok()?))
+            }
+            _ => None,
+        }
+    })
+}
+
+fn dangling_message(dir: &Path, link: &Path, target: &Path) -> String {
+    if dir == link {
+        format!(
+            "output directory {:?} is a symlink to {:?}, which doesn't exist",
+            link, target
+        )
+    } else {
+        format!(
+            "{:?}, part of output directory {:?}, is a symlink to {:?}, which doesn't exist",
+            link, dir, target
+        )
+    }
+}
+
+/// From `--rename-retries` and `--rename-retry-for`.
+pub struct RenameRetries {
+    pub retries: u32,
+    pub delay: Duration,
+}
+
+/// Whether a rename failed as something else has the output open or locked, for now; on Windows,
+/// a virus scanner or a reader, and on NFS, a file still open elsewhere. Anything else, like
+/// `EACCES`, would fail again.
+pub fn is_contended(e: &io::Error) -> bool {
+    if cfg!(windows) {
+        // ERROR_SHARING_VIOLATION and ERROR_LOCK_VIOLATION
+        matches!(e.raw_os_error(), Some(32) | Some(33))
+    } else {
+        Some(libc::EBUSY) == e.raw_os_error()
+    }
+}
+
+/// Make a rename in `dir` durable; a warning on failure, as some network filesystems refuse.
+pub fn sync_directory(dir: &Path) {
+    match std::fs::File::open(dir).and_then(|d| d.sync_all()) {
+        Ok(()) => debug!("         fsync: directory {:?} synced", dir),
+        Err(e) => warn!(
+            "couldn't fsync directory {:?}, the rename may not survive a crash: {}",
+            dir, e
+        ),
+    }
+}
+
+/// Chains longer than this are treated as loops, as the kernel does with `ELOOP`.
+const MAX_SYMLINK_HOPS: usize = 40;
+
+/// The file a symlinked output really is, so replacing it leaves the links in place.
+///
+/// A dangling link is only acceptable if `create` is set (`--create-dirs`), as
+/// there's then nothing surprising about the target appearing.
+pub fn follow_symlinks(output: &Path, create: bool) -> Result<PathBuf, failure::Error> {
+    let mut current = output.to_path_buf();
+    for _ in 0..MAX_SYMLINK_HOPS {
+        match current.symlink_metadata() {
+            Ok(ref metadata) if metadata.file_type().is_symlink() => {
+                let link = std::fs::read_link(&current)
+                    .with_context(|_| format_err!("reading symlink {:?}", current))?;
+                let parent = current.parent().unwrap_or_else(|| Path::new(""));
+                current = parent.join(link);
+            }
+            Ok(_) => return Ok(current),
+            Err(ref e) if io::ErrorKind::NotFound == e.kind() => {
+                if current.as_path() != output && !create {
+                    bail!(
+                        "output {:?} is a dangling symlink to {:?}, see --create-dirs",
+                        output,
+                        current
+                    );
+                }
+                return Ok(current);
+            }
+            Err(e) => {
+                Err(e).with_context(|_| format_err!("reading output's info: {:?}", current))?
+            }
+        }
+    }
+
+    bail!(
+        "output {:?} is more than {} symlinks deep, probably a loop",
+        output,
+        MAX_SYMLINK_HOPS
+    )
+}
+
+/// The file name a directory output gets: the last segment of the URL's path, without the query.
+pub fn name_from_url(url: &Url) -> Result<String, failure::Error> {
+    let segment = url
+        .path_segments()
+        .and_then(|mut s| s.next_back())
+        .unwrap_or_default();
+
+    let name = percent_decode_str(segment).decode_utf8_lossy();
+
+    if name.is_empty() {
+        bail!(
+            "can't name the output after {:?}, as its path has no last segment; name the output explicitly",
+            url.as_str()
+        );
+    }
+
+    Ok(match sanitise(&name) {
+        Some(name) => name,
+        None => {
+            let fallback = fallback_name(url);
+            warn!(
+                "URL's file name {:?} is unusable, naming the output {:?}",
+                name, fallback
+            );
+            fallback
+        }
+    })
+}
+
+/// The file name from a `Content-Disposition` header, preferring an RFC 5987 `filename*`.
+pub fn name_from_disposition(header: &str) -> Option<String> {
+    let params = disposition_params(header);
+    let find = |key: &str| {
+        params
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    };
+
+    let name = match find("filename*").and_then(decode_ext_value) {
+        Some(name) => name,
+        None => find("filename")?.to_string(),
+    };
+
+    let sanitised = sanitise(&name);
+    if sanitised.is_none() {
+        warn!("ignoring unusable Content-Disposition: {:?}", header);
+    }
+    sanitised
+}
+
+/// Longer names are refused by most filesystems.
+const NAME_LIMIT: usize = 255;
+
+/// Reduce an untrusted name to a single, plain, path component, or nothing if little is left.
+///
+/// Any directories are dropped, whichever separator they use, so `../../etc/cron.d/x` is just
+/// `x`. Control characters, including NULs, are removed.
+fn sanitise(name: &str) -> Option<String> {
+    let last = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let clean: String = last.chars().filter(|c| !c.is_control()).collect();
+    let clean = clean.trim();
+
+    if clean.is_empty() || clean == "." || clean == ".." || clean.len() > NAME_LIMIT {
+        return None;
+    }
+
+    Some(clean.to_string())
+}
+
+/// A name which depends only on the URL, for when it offers nothing usable itself.
+fn fallback_name(url: &Url) -> String {
+    use sha2::Digest as _;
+    let hash = sha2::Sha256::digest(url.as_str().as_bytes());
+    format!("download-{}", &crate::digest::hex(&hash)[..16])
+}
+
+/// `name` inside `dir`, guaranteeing the result can't be anywhere else.
+pub fn join(dir: &OsStr, name: &str) -> Result<PathBuf, failure::Error> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(Path::new(dir).join(name)),
+        _ => bail!("refusing to write outside the output directory: {:?}", name),
+    }
+}
+
+/// The `key=value` parameters after the disposition type, with quoted values unescaped.
+fn disposition_params(header: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut chars = header.chars().peekable();
+
+    // the disposition type, e.g. "attachment"
+    for c in &mut chars {
+        if ';' == c {
+            break;
+        }
+    }
+
+    loop {
+        let mut key = String::new();
+        for c in &mut chars {
+            if '=' == c {
+                break;
+            }
+            key.push(c);
+        }
+
+        while let Some(' ') | Some('\t') = chars.peek() {
+            chars.next();
+        }
+
+        let mut value = String::new();
+        if let Some('"') = chars.peek() {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => value.extend(chars.next()),
+                    c => value.push(c),
+                }
+            }
+        }
+        for c in &mut chars {
+            if ';' == c {
+                break;
+            }
+            value.push(c);
+        }
+
+        let key = key.trim();
+        if key.is_empty() {
+            break;
+        }
+        params.push((key.to_string(), value.trim().to_string()));
+    }
+
+    params
+}
+
+/// RFC 5987's `charset'language'percent-encoded`.
+fn decode_ext_value(value: &str) -> Option<String> {
+    let mut parts = value.splitn(3, '\'');
+    let charset = parts.next()?;
+    let _language = parts.next()?;
+    let encoded = percent_decode_str(parts.next()?);
+
+    if charset.eq_ignore_ascii_case("utf-8") {
+        encoded.decode_utf8().ok().map(|s| s.to_string())
+    } else if charset.eq_ignore_ascii_case("iso-8859-1") {
+        Some(encoded.map(char::from).collect())
+    } else {
+        None
+    }
+}
+
+#[test]
+fn test_name_from_url() {
+    let name = |url: &str| name_from_url(&Url::parse(url).unwrap());
+    assert_eq!(
+        "foo-1.2.3.tar.gz",
+        name("https://example.com/releases/foo-1.2.3.tar.gz?sig=abc").unwrap()
+    );
+    assert_eq!("a b.txt", name("https://example.com/a%20b.txt").unwrap());
+    assert!(name("https://example.com/releases/").is_err());
+    assert!(name("https://example.com").is_err());
+    assert!(name("https://example.com/x/%2E%2E").is_err());
+    assert_eq!("etc", name("https://example.com/x/..%2Fetc").unwrap());
+    assert_eq!(
+        "download-",
+        &name("https://example.com/x/%2F%00")
+            .unwrap()
+            .chars()
+            .take(9)
+            .collect::<String>()
+    );
+    assert_eq!(
+        name("https://example.com/x/%2F").unwrap(),
+        name("https://example.com/x/%2F").unwrap()
+    );
+}
+
+#[test]
+fn test_name_from_disposition() {
+    assert_eq!(
+        Some("report-2024-05.pdf".to_string()),
+        name_from_disposition("attachment; filename=\"report-2024-05.pdf\"")
+    );
+    assert_eq!(
+        Some("plain.txt".to_string()),
+        name_from_disposition("attachment;filename=plain.txt")
+    );
+    assert_eq!(
+        Some("a \"quoted\"; name".to_string()),
+        name_from_disposition(r#"attachment; filename="a \"quoted\"; name"; size=3"#)
+    );
+    assert_eq!(
+        Some("€ rates.pdf".to_string()),
+        name_from_disposition(
+            "attachment; filename=\"EURO rates.pdf\"; filename*=UTF-8''%e2%82%ac%20rates.pdf"
+        )
+    );
+    assert_eq!(
+        Some("£.txt".to_string()),
+        name_from_disposition("attachment; filename*=iso-8859-1'en'%A3.txt")
+    );
+    assert_eq!(None, name_from_disposition("inline"));
+}
+
+#[test]
+fn test_hostile_disposition() {
+    let name = |header: &str| name_from_disposition(header);
+    assert_eq!(
+        Some("x".to_string()),
+        name("attachment; filename=\"../../etc/cron.d/x\"")
+    );
+    assert_eq!(
+        Some("passwd".to_string()),
+        name("attachment; filename=\"/etc/passwd\"")
+    );
+    assert_eq!(
+        Some("x".to_string()),
+        name("attachment; filename=\"..\\\\..\\\\x\"")
+    );
+    assert_eq!(
+        Some("x".to_string()),
+        name("attachment; filename*=UTF-8''..%2F..%2Fetc%2Fcron.d%2Fx")
+    );
+    assert_eq!(
+        Some("evil.sh".to_string()),
+        name("attachment; filename*=UTF-8''evil%00%0a.sh")
+    );
+    assert_eq!(None, name("attachment; filename=\"..\""));
+    assert_eq!(None, name("attachment; filename*=UTF-8''%2E%2E%2F"));
+    assert_eq!(None, name("attachment; filename=\"a/\""));
+    assert_eq!(
+        None,
+        name(&format!("attachment; filename={}", "a".repeat(300)))
+    );
+}
+
+#[test]
+fn test_check_directory() {
+    assert!(check_directory(Path::new("")).is_ok());
+    assert!(check_directory(Path::new("/")).is_ok());
+    let file = tempfile::NamedTempFile::new().unwrap();
+    let err = check_directory(file.path()).unwrap_err().to_string();
+    assert!(err.contains("is not a directory"), "{}", err);
+    let err = check_directory(&file.path().join("sub"))
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("part of"), "{}", err);
+    let err = check_directory(Path::new("/does/not/exist"))
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("does not exist"), "{}", err);
+
+    let dir = tempfile::tempdir().unwrap();
+    let deep = dir.path().join("a/b/c");
+    create_directory(&deep, 0o755).unwrap();
+    assert!(check_directory(&deep).is_ok());
+    let err = create_directory(&file.path().join("a/b"), 0o755)
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("/a\""), "{}", err);
+}
+
+#[test]
+fn test_is_contended() {
+    let errno = io::Error::from_raw_os_error;
+    assert!(!is_contended(&errno(libc::EACCES)));
+    assert!(!is_contended(&errno(libc::EROFS)));
+    assert!(!is_contended(&io::Error::other(
+        "busy, but not the OS saying so"
+    )));
+    assert!(is_contended(&errno(libc::EBUSY)));
+}
+
+#[test]
+fn test_dangling_symlink() {
+    use std::os::unix::fs::symlink;
+
+    let dir = tempfile::tempdir().unwrap();
+    let real = dir.path().join("real");
+    std::fs::create_dir(&real).unwrap();
+    symlink(&real, dir.path().join("link")).unwrap();
+    assert_eq!(None, dangling_symlink(&dir.path().join("link")));
+    assert_eq!(
+        real.canonicalize().unwrap(),
+        real_directory(&dir.path().join("link")).unwrap()
+    );
+
+    let gone = dir.path().join("gone");
+    symlink("nowhere", &gone).unwrap();
+    let sub = gone.join("sub");
+    assert_eq!(
+        Some((gone.as_path(), PathBuf::from("nowhere"))),
+        dangling_symlink(&sub)
+    );
+    let err = real_directory(&sub).unwrap_err().to_string();
+    assert!(err.contains("part of output directory"), "{}", err);
+    let err = check_directory(&gone).unwrap_err().to_string();
+    assert!(err.contains("is a symlink to \"nowhere\""), "{}", err);
+    let err = create_directory(&sub, 0o755).unwrap_err().to_string();
+    assert!(err.contains("which doesn't exist"), "{}", err);
+
+    let missing = dir.path().join("missing");
+    assert_eq!(missing, real_directory(&missing).unwrap());
+}
+
+#[test]
+fn test_follow_symlinks() {
+    use std::os::unix::fs::symlink;
+
+    let dir = tempfile::tempdir().unwrap();
+    let real = dir.path().join("real");
+    std::fs::write(&real, "x").unwrap();
+    symlink("real", dir.path().join("one")).unwrap();
+    symlink(dir.path().join("one"), dir.path().join("two")).unwrap();
+    assert_eq!(
+        real,
+        follow_symlinks(&dir.path().join("two"), false).unwrap()
+    );
+    assert_eq!(real, follow_symlinks(&real, false).unwrap());
+
+    let fresh = dir.path().join("fresh");
+    assert_eq!(fresh, follow_symlinks(&fresh, false).unwrap());
+
+    symlink("sub/missing", dir.path().join("dangling")).unwrap();
+    let err = follow_symlinks(&dir.path().join("dangling"), false)
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("dangling"), "{}", err);
+    assert_eq!(
+        dir.path().join("sub/missing"),
+        follow_symlinks(&dir.path().join("dangling"), true).unwrap()
+    );
+
+    symlink("loop", dir.path().join("loop")).unwrap();
+    let err = follow_symlinks(&dir.path().join("loop"), false)
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("loop"), "{}", err);
+}
+
+#[test]
+fn test_join() {
+    assert_eq!(
+        PathBuf::from("/tmp/x"),
+        join(OsStr::new("/tmp"), "x").unwrap()
+    );
+    assert!(join(OsStr::new("/tmp"), "../x").is_err());
+    assert!(join(OsStr::new("/tmp"), "/x").is_err());
+    assert!(join(OsStr::new("/tmp"), "..").is_err());
+}
@@ -0,0 +1,182 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use failure::format_err;
+use failure::ResultExt;
+use log::debug;
+use log::info;
+
+use crate::backup;
+
+/// What's needed to carry on with a `--keep-partial` download: the start of the resource, and
+/// proof (for `If-Range`) that the server still has the same resource.
+#[derive(Debug, PartialEq)]
+pub struct Partial {
+    pub url: String,
+    pub validator: String,
+    pub offset: u64,
+}
+
+/// Where the start of a failed download is kept: `OUTPUT.part`.
+pub fn part_path(output: &Path) -> PathBuf {
+    backup::suffixed(output, ".part")
+}
+
+fn meta_path(output: &Path) -> PathBuf {
+    backup::suffixed(output, ".part.meta")
+}
+
+/// A strong ETag, or failing that a Last-Modified, as `If-Range` needs one or the other.
+pub fn validator(etag: Option<&str>, last_modified: Option<&str>) -> Option<String> {
+    match etag.map(str::trim) {
+        Some(etag) if !etag.starts_with("W/") && !etag.is_empty() => Some(etag.to_string()),
+        _ => last_modified.map(|l| l.trim().to_string()),
+    }
+}
+
+/// Keep the download so far, the temporary file readable at `source`, as `OUTPUT.part`.
+///
+/// Linked if possible, so gigabytes don't need copying. The description goes next to it,
+/// in `OUTPUT.part.meta`, written after, so a `.part` is never described as longer than it is.
+pub fn keep(
+    source: &Path,
+    output: &Path,
+    url: &str,
+    validator: &str,
+) -> Result<(), failure::Error> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let part = part_path(output);
+    let meta = meta_path(output);
+    let staging = backup::suffixed(&part, &format!(".fetch-maybe-{}", std::process::id()));
+    let _ = fs::remove_file(&meta);
+    let _ = fs::remove_file(&staging);
+
+    let c = |p: &Path| std::ffi::CString::new(p.as_os_str().as_bytes()).ok();
+    let linked = match (c(source), c(&staging)) {
+        (Some(from), Some(to)) => {
+            0 == unsafe {
+                libc::linkat(
+                    libc::AT_FDCWD,
+                    from.as_ptr(),
+                    libc::AT_FDCWD,
+                    to.as_ptr(),
+                    libc::AT_SYMLINK_FOLLOW,
+                )
+            }
+        }
+        _ => false,
+    };
+
+    if !linked {
+        debug!(
+            "       partial: can't link ({}), copying",
+            io::Error::last_os_error()
+        );
+        fs::copy(source, &staging)
+            .with_context(|_| format_err!("copying partial download to {:?}", staging))?;
+    }
+
+    if let Err(e) = fs::rename(&staging, &part) {
+        let _ = fs::remove_file(&staging);
+        Err(e).with_context(|_| format_err!("moving partial download to {:?}", part))?;
+    }
+
+    let offset = part
+        .metadata()
+        .with_context(|_| format_err!("reading {:?}'s info", part))?
+        .len();
+
+    fs::write(
+        &meta,
+        format!("url {}\nvalidator {}\noffset {}\n", url, validator, offset),
+    )
+    .with_context(|_| format_err!("describing partial download in {:?}", meta))?;
+
+    info!("       partial: kept {} bytes in {:?}", offset, part);
+    Ok(())
+}
+
+/// A kept partial download of `url` to carry on from, if there's a usable one.
+pub fn load(output: &Path, url: &str) -> Option<Partial> {
+    let meta = fs::read_to_string(meta_path(output)).ok()?;
+    let field = |name: &str| {
+        meta.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+            .map(str::to_string)
+    };
+
+    let partial = Partial {
+        url: field("url")?,
+        validator: field("validator")?,
+        offset: field("offset")?.parse().ok()?,
+    };
+
+    if partial.url != url {
+        info!(
+            "       partial: {:?} is of another URL, starting again",
+            part_path(output)
+        );
+        return None;
+    }
+
+    // a .part that's changed since we described it can't be trusted
+    match part_path(output).metadata() {
+        Ok(ref m) if m.len() == partial.offset && partial.offset > 0 => Some(partial),
+        _ => None,
+    }
+}
+
+/// After a success, the `.part` (and its description) are of no further use.
+///
+/// Only if there's a description, so we only ever remove `.part`s that we made.
+pub fn remove(output: &Path) {
+    let meta = meta_path(output);
+    if meta.exists() {
+        let _ = fs::remove_file(part_path(output));
+        let _ = fs::remove_file(&meta);
+        debug!("       partial: removed leftover {:?}", part_path(output));
+    }
+}
+
+#[test]
+fn test_validator() {
+    assert_eq!(
+        Some("\"abc\"".to_string()),
+        validator(Some("\"abc\""), Some("Wed, 21 Oct 2015 07:28:00 GMT"))
+    );
+    assert_eq!(
+        Some("Wed, 21 Oct 2015 07:28:00 GMT".to_string()),
+        validator(Some("W/\"abc\""), Some("Wed, 21 Oct 2015 07:28:00 GMT"))
+    );
+    assert_eq!(None, validator(Some("W/\"abc\""), None));
+}
+
+#[test]
+fn test_keep_and_load() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source");
+    let output = dir.path().join("out");
+    fs::write(&source, "abc").unwrap();
+
+    keep(&source, &output, "http://example.com/a", "\"e\"").unwrap();
+    assert_eq!("abc", fs::read_to_string(part_path(&output)).unwrap());
+    assert_eq!(
+        Some(Partial {
+            url: "http://example.com/a".to_string(),
+            validator: "\"e\"".to_string(),
+            offset: 3,
+        }),
+        load(&output, "http://example.com/a")
+    );
+    assert_eq!(None, load(&output, "http://example.com/b"));
+
+    fs::write(part_path(&output), "abcd").unwrap();
+    assert_eq!(None, load(&output, "http://example.com/a"));
+
+    remove(&output);
+    assert!(!part_path(&output).exists());
+    assert!(!meta_path(&output).exists());
+}
@@ -1,11 +1,12 @@
 use failure::bail;
+use failure::format_err;
 
 struct Parts<'s> {
     inner: &'s str,
 }
 
 impl<'s> Parts<'s> {
-    fn new(inner: &str) -> Parts {
+    fn new(inner: &str) -> Parts<'_> {
         Parts { inner }
     }
 }
@@ -25,9 +26,122 @@ impl<'s> Iterator for Parts<'s> {
 }
 
 pub fn parse_duration(s: &str) -> Result<chrono::Duration, failure::Error> {
+    // chrono's constructors panic out of range, and this runs in clap's validators
+    let too_large = || format_err!("duration too large: {:?}", s);
+
     if let Ok(secs) = s.parse() {
-        return Ok(chrono::Duration::seconds(secs));
+        return chrono::Duration::try_seconds(secs).ok_or_else(too_large);
+    }
+
+    let mut parts = Parts::new(s);
+    let mut total = chrono::Duration::zero();
+    let mut seen = false;
+
+    for (num, unit) in &mut parts {
+        let num: i64 = num
+            .trim()
+            .parse()
+            .map_err(|_| format_err!("can't parse {:?} as a number in {:?}", num, s))?;
+        let part = match unit {
+            'S' => chrono::Duration::try_seconds(num),
+            'M' => chrono::Duration::try_minutes(num),
+            'H' => chrono::Duration::try_hours(num),
+            'D' => chrono::Duration::try_days(num),
+            'W' => chrono::Duration::try_weeks(num),
+            _ => bail!(
+                "unrecognised unit {:?} in {:?}, expected one of smhdw",
+                unit,
+                s
+            ),
+        };
+        total = part
+            .and_then(|part| total.checked_add(&part))
+            .ok_or_else(too_large)?;
+        seen = true;
+    }
+
+    if !seen || !parts.inner.trim().is_empty() {
+        bail!("can't parse as a duration: {:?}", s)
+    }
+
+    Ok(total)
+}
+
+/// As `parse_duration` takes it, in its largest two units: `1d2h`, `5m3s`, `0s`.
+pub fn format_duration(d: chrono::Duration) -> String {
+    let units = [
+        ("w", 7 * 24 * 60 * 60),
+        ("d", 24 * 60 * 60),
+        ("h", 60 * 60),
+        ("m", 60),
+        ("s", 1),
+    ];
+    let mut left = d.num_seconds().abs();
+    let mut out = String::new();
+    let mut parts = 0;
+    for (unit, secs) in &units {
+        if left >= *secs || (0 == parts && "s" == *unit) {
+            out.push_str(&format!("{}{}", left / secs, unit));
+            left %= secs;
+            parts += 1;
+        } else if parts > 0 {
+            parts += 1;
+        }
+        if 2 == parts {
+            break;
+        }
+    }
+    if d < chrono::Duration::zero() {
+        out.insert(0, '-');
+    }
+    out
+}
+
+#[test]
+fn test_format_duration() {
+    let d = |s: &str| parse_duration(s).unwrap();
+    assert_eq!("1d2h", format_duration(d("1d2h3m")));
+    assert_eq!("5m3s", format_duration(d("5m3s")));
+    assert_eq!("1h", format_duration(d("1h30s")));
+    assert_eq!("0s", format_duration(d("0")));
+    assert_eq!("-2w", format_duration(-d("2w")));
+    for s in &["1d2h", "5m3s", "0s"] {
+        assert_eq!(d(s), d(&format_duration(d(s))));
+    }
+}
+
+#[test]
+fn test_parse_duration() {
+    assert_eq!(chrono::Duration::seconds(5), parse_duration("5").unwrap());
+    assert_eq!(
+        chrono::Duration::minutes(15),
+        parse_duration("15m").unwrap()
+    );
+    assert_eq!(
+        chrono::Duration::days(1) + chrono::Duration::hours(2),
+        parse_duration("1d2H").unwrap()
+    );
+    assert!(parse_duration("").is_err());
+    assert!(parse_duration("5x").is_err());
+    assert!(parse_duration("1h5").is_err());
+}
+
+#[test]
+fn test_parse_duration_too_large() {
+    // chrono's limit is i64::MAX milliseconds, a little under 15250284452 weeks
+    let most = chrono::Duration::MAX.num_seconds();
+    assert_eq!(
+        chrono::Duration::seconds(most),
+        parse_duration(&most.to_string()).unwrap()
+    );
+    assert!(parse_duration(&(most + 1).to_string()).is_err());
+    assert!(parse_duration("15250284452w").is_ok());
+    for s in &["15250284453w", "99999999999999w", "9223372036854775807s"] {
+        let e = parse_duration(s).unwrap_err().to_string();
+        assert!(e.contains("too large"), "{}: {}", s, e);
     }
 
-    bail!("can't parse as a duration: {:?}", s)
+    // each part fits, but not their sum
+    assert!(parse_duration("15250284452w15250284452w").is_err());
+    assert!(parse_duration(&format!("{}s{}s", most, most)).is_err());
 }
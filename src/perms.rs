@@ -0,0 +1,251 @@
+use std::ffi::CString;
+use std::fs;
+use std::io;
+
+use failure::bail;
+use failure::err_msg;
+use failure::format_err;
+use failure::ResultExt;
+use log::debug;
+use log::warn;
+
+/// An octal permission string, like `chmod` takes, e.g. `0644` or `755`.
+pub fn parse_mode(s: &str) -> Result<u32, failure::Error> {
+    let mode =
+        u32::from_str_radix(s, 8).with_context(|_| format_err!("parsing octal mode {:?}", s))?;
+    if mode > 0o7777 {
+        bail!("mode {:?} has bits beyond 07777", s);
+    }
+    Ok(mode)
+}
+
+/// Via the handle, so the permissions arrive with the content, atomically, on rename.
+pub fn set_mode(file: &fs::File, mode: u32) -> Result<(), failure::Error> {
+    use std::os::unix::fs::PermissionsExt;
+    file.set_permissions(fs::Permissions::from_mode(mode))
+        .with_context(|_| format_err!("setting mode {:04o} on temporary file", mode))?;
+    debug!("          mode: set to {:04o}", mode);
+    Ok(())
+}
+
+/// Who should own the output; either half may be left alone.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Owner {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl Owner {
+    /// `user[:group]`, as names or numeric ids, resolved now so typos fail before any download.
+    pub fn from_arg(arg: &str) -> Result<Owner, failure::Error> {
+        let mut parts = arg.splitn(2, ':');
+        let user = parts.next().unwrap_or_default();
+        let group = parts.next();
+
+        let uid = match user {
+            "" => None,
+            user => Some(match user.parse::<u32>() {
+                Ok(uid) => uid,
+                Err(_) => lookup_user(user)?,
+            }),
+        };
+
+        let gid = match group {
+            None | Some("") => None,
+            Some(group) => Some(match group.parse::<u32>() {
+                Ok(gid) => gid,
+                Err(_) => lookup_group(group)?,
+            }),
+        };
+
+        if uid.is_none() && gid.is_none() {
+            bail!("--chown needs a user, a group, or both: {:?}", arg);
+        }
+
+        Ok(Owner { uid, gid })
+    }
+
+    /// Whether we're allowed to give a file away like this, which we'd otherwise find out too late.
+    pub fn check_permitted(&self) -> Result<(), failure::Error> {
+        let euid = unsafe { libc::geteuid() };
+        if 0 == euid {
+            return Ok(());
+        }
+
+        if let Some(uid) = self.uid {
+            if uid != euid {
+                bail!(
+                    "not running as root (uid {}), so can't give the output to uid {}",
+                    euid,
+                    uid
+                );
+            }
+        }
+
+        if let Some(gid) = self.gid {
+            if !groups()?.contains(&gid) {
+                bail!(
+                    "not running as root, or in group {}, so can't give the output to it",
+                    gid
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Via the handle, so the ownership arrives with the content, atomically, on rename.
+    pub fn apply(&self, file: &fs::File) -> io::Result<()> {
+        std::os::unix::fs::fchown(file, self.uid, self.gid)?;
+        debug!("         owner: set to {:?}", self);
+        Ok(())
+    }
+}
+
+/// What a new file gets from `open(2)` with `0666`, as curl and wget's outputs do.
+pub fn default_mode() -> u32 {
+    // there's no way to read the umask without setting it; nothing else is running yet
+    let umask = unsafe {
+        let umask = libc::umask(0o022);
+        libc::umask(umask);
+        umask
+    };
+    0o666 & !(umask as u32)
+}
+
+pub fn mode_of(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+/// Give the new file the old one's owner, if we're allowed to; only root usually is.
+pub fn preserve_owner(file: &fs::File, previous: &fs::Metadata) {
+    use std::os::unix::fs::MetadataExt;
+
+    let owner = Owner {
+        uid: Some(previous.uid()),
+        gid: Some(previous.gid()),
+    };
+
+    let current = match file.metadata() {
+        Ok(metadata) => Owner {
+            uid: Some(metadata.uid()),
+            gid: Some(metadata.gid()),
+        },
+        Err(e) => {
+            warn!("couldn't preserve the output's owner: {}", e);
+            return;
+        }
+    };
+
+    if current == owner {
+        return;
+    }
+
+    if let Err(e) = owner.apply(file) {
+        warn!(
+            "couldn't preserve the output's owner ({:?}), see --no-preserve: {}",
+            owner, e
+        );
+    }
+}
+
+fn groups() -> Result<Vec<u32>, failure::Error> {
+    let count = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+    if count < 0 {
+        Err(io::Error::last_os_error()).with_context(|_| err_msg("listing our groups"))?;
+    }
+    let mut groups = vec![0; count as usize];
+    let count = unsafe { libc::getgroups(count, groups.as_mut_ptr()) };
+    if count < 0 {
+        Err(io::Error::last_os_error()).with_context(|_| err_msg("listing our groups"))?;
+    }
+    groups.truncate(count as usize);
+    groups.push(unsafe { libc::getegid() });
+    Ok(groups)
+}
+
+fn lookup_user(name: &str) -> Result<u32, failure::Error> {
+    let c_name = CString::new(name).with_context(|_| format_err!("user name {:?}", name))?;
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let err = unsafe {
+        libc::getpwnam_r(
+            c_name.as_ptr(),
+            &mut pwd,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    if 0 != err {
+        Err(io::Error::from_raw_os_error(err))
+            .with_context(|_| format_err!("looking up user {:?}", name))?;
+    }
+    if result.is_null() {
+        bail!("no such user: {:?}", name);
+    }
+    Ok(pwd.pw_uid)
+}
+
+fn lookup_group(name: &str) -> Result<u32, failure::Error> {
+    let c_name = CString::new(name).with_context(|_| format_err!("group name {:?}", name))?;
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    let mut grp: libc::group = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let err = unsafe {
+        libc::getgrnam_r(
+            c_name.as_ptr(),
+            &mut grp,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    if 0 != err {
+        Err(io::Error::from_raw_os_error(err))
+            .with_context(|_| format_err!("looking up group {:?}", name))?;
+    }
+    if result.is_null() {
+        bail!("no such group: {:?}", name);
+    }
+    Ok(grp.gr_gid)
+}
+
+#[test]
+fn test_owner() {
+    assert_eq!(
+        Owner {
+            uid: Some(0),
+            gid: None
+        },
+        Owner::from_arg("root").unwrap()
+    );
+    assert_eq!(
+        Owner {
+            uid: Some(1000),
+            gid: Some(0)
+        },
+        Owner::from_arg("1000:root").unwrap()
+    );
+    assert_eq!(
+        Owner {
+            uid: None,
+            gid: Some(5)
+        },
+        Owner::from_arg(":5").unwrap()
+    );
+    assert!(Owner::from_arg("no-such-user-hopefully").is_err());
+    assert!(Owner::from_arg("root:no-such-group-hopefully").is_err());
+    assert!(Owner::from_arg(":").is_err());
+}
+
+#[test]
+fn test_parse_mode() {
+    assert_eq!(0o644, parse_mode("0644").unwrap());
+    assert_eq!(0o755, parse_mode("755").unwrap());
+    assert!(parse_mode("0648").is_err());
+    assert!(parse_mode("10000").is_err());
+    assert!(parse_mode("rw-r--r--").is_err());
+}
@@ -0,0 +1,144 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use failure::bail;
+use failure::format_err;
+use failure::ResultExt;
+use log::debug;
+
+/// From linux/fs.h: the file can't be changed, renamed over or deleted, even by root.
+const FS_IMMUTABLE_FL: libc::c_int = 0x10;
+/// From linux/fs.h: the file can only be appended to.
+const FS_APPEND_FL: libc::c_int = 0x20;
+
+/// Fail before downloading anything if installing the output is sure to fail afterwards.
+///
+/// Best effort: `--no-preflight` skips it for filesystems where the probing misleads.
+pub fn check(
+    output: &Path,
+    dir: &Path,
+    existing: Option<&fs::Metadata>,
+    in_place: bool,
+) -> Result<(), failure::Error> {
+    if let Some(existing) = existing {
+        if existing.is_file() {
+            check_flags(output)?;
+        }
+        check_sticky(output, dir, existing)?;
+    }
+
+    if in_place && existing.is_some() {
+        if let Err(e) = fs::OpenOptions::new().write(true).open(output) {
+            Err(e).with_context(|_| {
+                format_err!(
+                    "{:?} can't be overwritten in place, see --no-preflight",
+                    output
+                )
+            })?;
+        }
+    } else {
+        probe_rename(dir)?;
+    }
+
+    debug!("     preflight: output looks replaceable");
+    Ok(())
+}
+
+fn check_flags(output: &Path) -> Result<(), failure::Error> {
+    use std::os::unix::io::AsRawFd;
+
+    // unreadable files just can't be checked
+    let file = match fs::File::open(output) {
+        Ok(file) => file,
+        Err(_) => return Ok(()),
+    };
+
+    let mut flags: libc::c_int = 0;
+    // unsupported on many filesystems, which can't have these flags anyway
+    if 0 != unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_GETFLAGS, &mut flags) } {
+        return Ok(());
+    }
+
+    if 0 != flags & FS_IMMUTABLE_FL {
+        bail!(
+            "{:?} is immutable (chattr +i), so can't be replaced, see --no-preflight",
+            output
+        );
+    }
+
+    if 0 != flags & FS_APPEND_FL {
+        bail!(
+            "{:?} is append-only (chattr +a), so can't be replaced, see --no-preflight",
+            output
+        );
+    }
+
+    Ok(())
+}
+
+/// In a sticky directory like /tmp, only a file's owner (or the directory's) may replace it.
+fn check_sticky(output: &Path, dir: &Path, existing: &fs::Metadata) -> Result<(), failure::Error> {
+    use std::os::unix::fs::MetadataExt;
+
+    let euid = unsafe { libc::geteuid() };
+    if 0 == euid || existing.uid() == euid {
+        return Ok(());
+    }
+
+    let dir_metadata = match dir.metadata() {
+        Ok(metadata) => metadata,
+        Err(_) => return Ok(()),
+    };
+
+    if 0 != dir_metadata.mode() & libc::S_ISVTX && dir_metadata.uid() != euid {
+        bail!(
+            "{:?} belongs to another user, in a sticky directory, so can't be replaced, see --no-preflight",
+            output
+        );
+    }
+
+    Ok(())
+}
+
+/// Create a file and rename it, as installing the output will.
+fn probe_rename(dir: &Path) -> Result<(), failure::Error> {
+    let probe = dir.join(format!(".fetch-maybe-probe-{}", std::process::id()));
+    let renamed = dir.join(format!(".fetch-maybe-probe-{}-renamed", std::process::id()));
+
+    fs::File::create(&probe)
+        .with_context(|_| format_err!("can't create files in {:?}, see --no-preflight", dir))?;
+
+    let result = fs::rename(&probe, &renamed);
+    let _ = fs::remove_file(&probe);
+    let _ = fs::remove_file(&renamed);
+
+    match result {
+        Ok(()) => Ok(()),
+        Err(ref e) if io::ErrorKind::PermissionDenied == e.kind() => bail!(
+            "can't rename files in {:?}, so can't install the output, see --no-preflight",
+            dir
+        ),
+        Err(e) => Err(e).with_context(|_| {
+            format_err!("renaming a probe file in {:?}, see --no-preflight", dir)
+        })?,
+    }
+}
+
+#[test]
+fn test_check() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    check(&output, dir.path(), None, false).unwrap();
+
+    fs::write(&output, "x").unwrap();
+    let metadata = output.metadata().unwrap();
+    check(&output, dir.path(), Some(&metadata), false).unwrap();
+    check(&output, dir.path(), Some(&metadata), true).unwrap();
+    assert_eq!(1, fs::read_dir(dir.path()).unwrap().count());
+
+    let err = check(&output, &dir.path().join("missing"), None, false)
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("can't create"), "{}", err);
+}
@@ -0,0 +1,132 @@
+use std::env;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+use log::warn;
+use url::Url;
+
+/// Set once a proxy variable has been warned about, so a manifest only says so once.
+static WARNED: AtomicBool = AtomicBool::new(false);
+
+/// Warn if the environment asks for `url` to go through a proxy. ureq 0.11 can't use one, so
+/// it's a direct connection regardless, which a network that requires the proxy won't allow.
+pub fn warn_if_wanted(url: &Url) {
+    if let Some(name) = wanted(url, |name| env::var(name).ok()) {
+        if !WARNED.swap(true, Ordering::Relaxed) {
+            warn!(
+                "         proxy: {} is set, but proxies aren't supported; connecting directly",
+                name
+            );
+        }
+    }
+}
+
+/// Which variable, if any, would have `url` fetched through a proxy, as curl reads them.
+fn wanted<F>(url: &Url, var: F) -> Option<&'static str>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let names: &[&'static str] = match url.scheme() {
+        "https" => &["https_proxy", "HTTPS_PROXY", "all_proxy", "ALL_PROXY"],
+        _ => &["http_proxy", "HTTP_PROXY", "all_proxy", "ALL_PROXY"],
+    };
+    let name = names
+        .iter()
+        .copied()
+        .find(|name| var(name).is_some_and(|v| !v.trim().is_empty()))?;
+
+    let host = url.host_str()?;
+    let excluded = ["no_proxy", "NO_PROXY"]
+        .iter()
+        .filter_map(|name| var(name))
+        .any(|list| excludes(&list, host));
+    if excluded {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+/// Whether a `NO_PROXY` list covers `host`: `*`, or the host or a domain it's in, each with
+/// or without a port, or a leading dot.
+fn excludes(list: &str, host: &str) -> bool {
+    let host = host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_ascii_lowercase();
+    list.split(',').any(|entry| {
+        let entry = entry.trim().to_ascii_lowercase();
+        if "*" == entry {
+            return true;
+        }
+        let entry = match entry.strip_prefix('[') {
+            Some(bracketed) => bracketed.split(']').next().unwrap_or(""),
+            // a bare IPv6 address has colons of its own, and no port
+            None if entry.matches(':').count() == 1 => entry.split(':').next().unwrap_or(""),
+            None => &entry,
+        };
+        let entry = entry.trim_start_matches("*.").trim_start_matches('.');
+        !entry.is_empty()
+            && (host == entry
+                || host
+                    .strip_suffix(entry)
+                    .is_some_and(|rest| rest.ends_with('.')))
+    })
+}
+
+#[test]
+fn test_wanted() {
+    fn env<'v>(vars: &'v [(&str, &str)]) -> impl Fn(&str) -> Option<String> + 'v {
+        move |name| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| value.to_string())
+        }
+    }
+    let url = |s: &str| Url::parse(s).unwrap();
+    let http = url("http://files.example.com/f");
+    let https = url("https://files.example.com/f");
+
+    assert_eq!(None, wanted(&http, env(&[])));
+    assert_eq!(
+        Some("http_proxy"),
+        wanted(&http, env(&[("http_proxy", "http://proxy:3128")]))
+    );
+    assert_eq!(
+        None,
+        wanted(&https, env(&[("http_proxy", "http://proxy:3128")]))
+    );
+    assert_eq!(
+        Some("HTTPS_PROXY"),
+        wanted(&https, env(&[("HTTPS_PROXY", "http://proxy:3128")]))
+    );
+    assert_eq!(
+        Some("ALL_PROXY"),
+        wanted(&https, env(&[("ALL_PROXY", "socks5://proxy")]))
+    );
+    assert_eq!(None, wanted(&http, env(&[("http_proxy", " ")])));
+
+    let excluded = |no_proxy: &str| {
+        let vars = [("http_proxy", "http://proxy:3128"), ("no_proxy", no_proxy)];
+        wanted(&http, env(&vars)).is_none()
+    };
+    for no_proxy in &[
+        "*",
+        "files.example.com",
+        "example.com",
+        ".example.com",
+        "*.example.com",
+        "localhost, example.com:80",
+        "FILES.EXAMPLE.COM",
+    ] {
+        assert!(excluded(no_proxy), "{:?}", no_proxy);
+    }
+    for no_proxy in &["", "ample.com", "other.example.com", "localhost,,"] {
+        assert!(!excluded(no_proxy), "{:?}", no_proxy);
+    }
+
+    assert!(excludes("::1", "[::1]"));
+    assert!(excludes("[::1]:8080", "[::1]"));
+    assert!(excludes("127.0.0.1", "127.0.0.1"));
+    assert!(!excludes("127.0.0.1", "127.0.0.10"));
+}
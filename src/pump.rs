@@ -0,0 +1,76 @@
+use std::io;
+use std::io::Read;
+use std::io::Write;
+
+/// Copy `from` to `to` through `buf`, counting what's been written in `copied`.
+///
+/// `buf` is the only buffer: reads go straight into it, and it's written on whole once it's
+/// full, or the reader's done. A read that fails still has what came before it written, so
+/// `copied` is how far a retry can carry on from.
+pub fn copy<R: Read + ?Sized, W: Write + ?Sized>(
+    from: &mut R,
+    to: &mut W,
+    buf: &mut [u8],
+    copied: &mut u64,
+) -> io::Result<()> {
+    loop {
+        let mut filled = 0;
+        let read = loop {
+            if filled == buf.len() {
+                break Ok(());
+            }
+            match from.read(&mut buf[filled..]) {
+                Ok(0) => break Ok(()),
+                Ok(n) => filled += n,
+                Err(ref e) if io::ErrorKind::Interrupted == e.kind() => (),
+                Err(e) => break Err(e),
+            }
+        };
+        to.write_all(&buf[..filled])?;
+        *copied += filled as u64;
+        read?;
+        if filled < buf.len() {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+struct Broken<'a>(&'a [u8]);
+
+#[cfg(test)]
+impl Read for Broken<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.0.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::ConnectionReset, "gone"));
+        }
+        let n = self.0.len().min(buf.len()).min(2);
+        buf[..n].copy_from_slice(&self.0[..n]);
+        self.0 = &self.0[n..];
+        Ok(n)
+    }
+}
+
+#[test]
+fn test_copy() {
+    let mut out = Vec::new();
+    let mut copied = 0;
+    copy(&mut &b"hello world"[..], &mut out, &mut [0; 4], &mut copied).unwrap();
+    assert_eq!(b"hello world", &out[..]);
+    assert_eq!(11, copied);
+
+    let mut out = Vec::new();
+    let mut copied = 0;
+    copy(&mut &b""[..], &mut out, &mut [0; 4], &mut copied).unwrap();
+    assert_eq!(0, copied);
+}
+
+#[test]
+fn test_copy_failing() {
+    let mut out = Vec::new();
+    let mut copied = 0;
+    let err = copy(&mut Broken(b"hello"), &mut out, &mut [0; 3], &mut copied).unwrap_err();
+    assert_eq!(io::ErrorKind::ConnectionReset, err.kind());
+    assert_eq!(b"hello", &out[..]);
+    assert_eq!(5, copied);
+}
@@ -0,0 +1,301 @@
+use std::cell::Cell;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use chrono::DateTime;
+use chrono::Utc;
+use failure::bail;
+use failure::format_err;
+use failure::ResultExt;
+use log::debug;
+
+use crate::dir_of;
+use crate::exit;
+use crate::lock;
+use crate::output;
+use crate::period;
+use crate::sink;
+use crate::size;
+
+/// How long to wait for another run to finish updating the file; it only holds it for a read,
+/// or a write and a rename.
+const LOCK_WAIT: Duration = Duration::from_secs(30);
+
+/// `--quota-file`: how much has been downloaded since the period began, by every run that
+/// shares the file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Record {
+    since: DateTime<Utc>,
+    bytes: u64,
+}
+
+impl Record {
+    /// Moved on by whole periods to the one `now` is in, and emptied, if that's not this one.
+    fn rolled(self, period: chrono::Duration, now: DateTime<Utc>) -> Record {
+        // a period too long for a date never ends
+        match self.since.checked_add_signed(period) {
+            Some(end) if now >= end => (),
+            _ => return self,
+        }
+        let periods = (now - self.since).num_seconds() / period.num_seconds().max(1);
+        Record {
+            since: self.since + period * periods as i32,
+            bytes: 0,
+        }
+    }
+}
+
+/// `--quota`, as this run found it, and what the run's downloaded since.
+pub struct Quota {
+    path: PathBuf,
+    limit: u64,
+    period: chrono::Duration,
+    /// By every run, as of when this one started.
+    used: u64,
+    /// By this run, across all its attempts, whether they worked or not.
+    this_run: Cell<u64>,
+}
+
+/// A read that took the run past `--quota` fails with this inside.
+#[derive(Debug)]
+pub struct Exceeded {
+    limit: u64,
+}
+
+impl fmt::Display for Exceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "the download went past what's left of --quota {}",
+            size::format_size(self.limit)
+        )
+    }
+}
+
+impl Error for Exceeded {}
+
+/// Whether `e` is an `Exceeded`, which is no reason to retry.
+pub fn is_exceeded(e: &io::Error) -> bool {
+    e.get_ref().is_some_and(|e| e.is::<Exceeded>())
+}
+
+impl Quota {
+    /// `--quota-file` with `--quota`, if it's there; the usage is read now, so runs going at
+    /// once can each think there's room for them, and only find out as they download.
+    pub fn from_matches(matches: &clap::ArgMatches) -> Result<Option<Quota>, failure::Error> {
+        let path = match matches.value_of_os("quota-file") {
+            Some(path) => PathBuf::from(path),
+            None => return Ok(None),
+        };
+        let limit = size::parse_size(matches.value_of("quota").expect("required"))?;
+        let v = matches.value_of("quota-period").expect("defaulted");
+        let period = period::parse_duration(v)?;
+        if period <= chrono::Duration::zero() {
+            bail!("--quota-period must be positive: {:?}", v);
+        }
+
+        let _lock = lock_for(&path)?;
+        let used = load(&path)?
+            .map(|record| record.rolled(period, Utc::now()).bytes)
+            .unwrap_or(0);
+        debug!(
+            "         quota: {} of {} used this period",
+            size::format_size(used),
+            size::format_size(limit)
+        );
+        Ok(Some(Quota {
+            path,
+            limit,
+            period,
+            used,
+            this_run: Cell::new(0),
+        }))
+    }
+
+    fn left(&self) -> u64 {
+        self.limit.saturating_sub(self.used + self.this_run.get())
+    }
+
+    /// Refuse a body of `len` that there isn't room for.
+    pub fn check(&self, len: u64) -> Result<(), failure::Error> {
+        if len > self.left() {
+            return Err(exit::classified(
+                exit::Kind::Quota,
+                format!(
+                    "the body is {} bytes, but only {} of --quota {} is left",
+                    len,
+                    size::format_size(self.left()),
+                    size::format_size(self.limit)
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Count `bytes` that came other than through `meter`.
+    pub fn count(&self, bytes: u64) {
+        self.this_run.set(self.this_run.get() + bytes);
+    }
+
+    /// Add what this run downloaded to the file, as it now is, under its lock.
+    pub fn record(&self) -> Result<(), failure::Error> {
+        let _lock = lock_for(&self.path)?;
+        let now = Utc::now();
+        let mut record = load(&self.path)?
+            .unwrap_or(Record {
+                since: now,
+                bytes: 0,
+            })
+            .rolled(self.period, now);
+        record.bytes += self.this_run.get();
+        store(&self.path, &record)?;
+        debug!(
+            "         quota: {} more, {} used this period",
+            size::format_size(self.this_run.get()),
+            size::format_size(record.bytes)
+        );
+        Ok(())
+    }
+}
+
+fn lock_for(path: &Path) -> Result<fs::File, failure::Error> {
+    let lock_path = lock::default_path(path);
+    match lock::acquire(&lock_path, LOCK_WAIT)? {
+        Some(file) => Ok(file),
+        None => bail!(
+            "{:?} was held for more than {:?}, updating --quota-file",
+            lock_path,
+            LOCK_WAIT
+        ),
+    }
+}
+
+/// `None` if there's no file yet; one that doesn't parse is an error, rather than a fresh
+/// start, which would let the quota be run through again.
+fn load(path: &Path) -> Result<Option<Record>, failure::Error> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(ref e) if io::ErrorKind::NotFound == e.kind() => return Ok(None),
+        Err(e) => return Err(e).with_context(|_| format_err!("reading {:?}", path))?,
+    };
+    match parse(&text) {
+        Some(record) => Ok(Some(record)),
+        None => bail!("--quota-file {:?} is corrupt", path),
+    }
+}
+
+fn parse(text: &str) -> Option<Record> {
+    let field = |name: &str| {
+        text.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+    };
+    Some(Record {
+        since: DateTime::parse_from_rfc3339(field("since")?)
+            .ok()?
+            .with_timezone(&Utc),
+        bytes: field("bytes")?.parse().ok()?,
+    })
+}
+
+/// Replaced by a rename, synced, so it's never half written, or lost to a crash.
+fn store(path: &Path, record: &Record) -> Result<(), failure::Error> {
+    let text = format!(
+        "since {}\nbytes {}\n",
+        record.since.to_rfc3339(),
+        record.bytes
+    );
+    let dir = dir_of::dir_of(path, env::current_dir)?;
+    let mut temp = sink::temp_in(&dir)?;
+    temp.write_all(text.as_bytes())
+        .and_then(|()| temp.sync_all())
+        .with_context(|_| format_err!("writing {:?}", path))?;
+    temp.persist_by_rename(path)
+        .map_err(|e| e.error)
+        .with_context(|_| format_err!("replacing {:?}", path))?;
+    output::sync_directory(&dir);
+    Ok(())
+}
+
+/// Counts what's read from `inner` against `quota`, if there is one, failing the read that
+/// goes past it.
+pub struct Metered<'q, R> {
+    inner: R,
+    quota: Option<&'q Quota>,
+}
+
+pub fn meter<R: Read>(inner: R, quota: Option<&Quota>) -> Metered<'_, R> {
+    Metered { inner, quota }
+}
+
+impl<R: Read> Read for Metered<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if let Some(quota) = self.quota {
+            let left = quota.left();
+            quota.this_run.set(quota.this_run.get() + read as u64);
+            if read as u64 > left {
+                return Err(io::Error::other(Exceeded { limit: quota.limit }));
+            }
+        }
+        Ok(read)
+    }
+}
+
+#[test]
+fn test_rolled() {
+    let since = DateTime::parse_from_rfc3339("2026-01-01T00:00:00Z")
+        .unwrap()
+        .with_timezone(&Utc);
+    let record = Record { since, bytes: 100 };
+    let period = chrono::Duration::days(30);
+    assert_eq!(
+        record,
+        record.rolled(period, since + chrono::Duration::days(29))
+    );
+    assert_eq!(
+        Record {
+            since: since + chrono::Duration::days(60),
+            bytes: 0
+        },
+        record.rolled(period, since + chrono::Duration::days(75))
+    );
+    assert_eq!(
+        record,
+        record.rolled(chrono::Duration::MAX, since + chrono::Duration::days(75))
+    );
+    assert_eq!(
+        Some(record),
+        parse("since 2026-01-01T00:00:00+00:00\nbytes 100\n")
+    );
+}
+
+#[test]
+fn test_meter() {
+    let dir = tempfile::tempdir().unwrap();
+    let quota = Quota {
+        path: dir.path().join("quota"),
+        limit: 10,
+        period: chrono::Duration::days(30),
+        used: 4,
+        this_run: Cell::new(0),
+    };
+    assert!(quota.check(6).is_ok());
+    assert!(quota.check(7).is_err());
+
+    let e = io::copy(&mut meter(&[7u8; 20][..], Some(&quota)), &mut io::sink()).unwrap_err();
+    assert!(is_exceeded(&e));
+    // what came is counted, even though it was too much
+    assert_eq!(20, quota.this_run.get());
+
+    quota.record().unwrap();
+    quota.record().unwrap();
+    assert_eq!(40, load(&quota.path).unwrap().unwrap().bytes);
+}
@@ -0,0 +1,55 @@
+use std::collections::hash_map::RandomState;
+use std::env;
+use std::hash::BuildHasher;
+use std::hash::Hasher;
+use std::time::Duration;
+
+use fetch_maybe::exit;
+
+/// Where `--splay` and `--watch-jitter` get their randomness: splitmix64, seeded by the
+/// `FETCH_MAYBE_SEED` variable, so a test can know what's coming, or else from std's hashers,
+/// which are keyed at random for each process.
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn from_env() -> Result<Random, failure::Error> {
+        let state = match env::var("FETCH_MAYBE_SEED") {
+            Ok(seed) => seed.parse().map_err(|_| {
+                exit::classified(
+                    exit::Kind::Usage,
+                    format!("FETCH_MAYBE_SEED isn't a number: {:?}", seed),
+                )
+            })?,
+            Err(_) => RandomState::new().build_hasher().finish(),
+        };
+        Ok(Random { state })
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniformly up to `max`.
+    pub fn below(&mut self, max: Duration) -> Duration {
+        max.mul_f64(self.next() as f64 / u64::MAX as f64)
+    }
+}
+
+#[test]
+fn test_below() {
+    let max = Duration::from_secs(10);
+    let mut random = Random { state: 7 };
+    let drawn: Vec<Duration> = (0..100).map(|_| random.below(max)).collect();
+    assert!(drawn.iter().all(|d| *d <= max));
+    assert!(drawn.windows(2).any(|pair| pair[0] != pair[1]));
+
+    let mut again = Random { state: 7 };
+    assert_eq!(drawn[0], again.below(max));
+    assert_eq!(Duration::from_secs(0), again.below(Duration::from_secs(0)));
+}
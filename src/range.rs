@@ -0,0 +1,153 @@
+use failure::bail;
+use failure::format_err;
+
+/// An inclusive range of bytes, as in a `Range: bytes=START-END` header; `END` may be open.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: Option<u64>,
+}
+
+impl ByteRange {
+    /// `START-END` or `START-`.
+    pub fn parse(s: &str) -> Result<ByteRange, failure::Error> {
+        let dash = s
+            .find('-')
+            .ok_or_else(|| format_err!("expected START-END or START-, got {:?}", s))?;
+        let (start, end) = (&s[..dash], &s[dash + 1..]);
+
+        let start = start
+            .trim()
+            .parse()
+            .map_err(|_| format_err!("can't parse range start in {:?}", s))?;
+
+        let end = if end.trim().is_empty() {
+            None
+        } else {
+            Some(
+                end.trim()
+                    .parse()
+                    .map_err(|_| format_err!("can't parse range end in {:?}", s))?,
+            )
+        };
+
+        if let Some(end) = end {
+            if end < start {
+                bail!("range ends before it starts: {:?}", s);
+            }
+        }
+
+        Ok(ByteRange { start, end })
+    }
+
+    pub fn header_value(&self) -> String {
+        match self.end {
+            Some(end) => format!("bytes={}-{}", self.start, end),
+            None => format!("bytes={}-", self.start),
+        }
+    }
+
+    /// The number of bytes requested, if the range is closed.
+    pub fn len(&self) -> Option<u64> {
+        self.end.map(|end| end - self.start + 1)
+    }
+}
+
+/// Check a `Content-Range: bytes START-END/TOTAL` response header is what we asked for.
+pub fn check_content_range(
+    requested: &ByteRange,
+    header: Option<&str>,
+) -> Result<(), failure::Error> {
+    let header = match header {
+        Some(header) => header,
+        None => bail!(
+            "partial response had no Content-Range, asked for {}",
+            requested.header_value()
+        ),
+    };
+
+    let (start, end, total) = parse_content_range(header)
+        .ok_or_else(|| format_err!("can't parse Content-Range: {:?}", header))?;
+
+    let ends_at_eof = total.map(|total| end + 1 == total);
+
+    let plausible = start == requested.start
+        && match requested.end {
+            // a server may stop short of what we asked for, but only at the end of the file
+            Some(requested_end) => {
+                end == requested_end || (end < requested_end && ends_at_eof != Some(false))
+            }
+            None => ends_at_eof != Some(false),
+        };
+
+    if !plausible {
+        bail!(
+            "server sent a different range: Content-Range: {:?}, asked for {}",
+            header,
+            requested.header_value()
+        );
+    }
+
+    Ok(())
+}
+
+/// The resource's length from a 416's `Content-Range: bytes */LENGTH`.
+pub fn unsatisfiable_length(header: Option<&str>) -> Option<u64> {
+    header?.trim().strip_prefix("bytes */")?.trim().parse().ok()
+}
+
+fn parse_content_range(header: &str) -> Option<(u64, u64, Option<u64>)> {
+    let spec = header.trim().strip_prefix("bytes ")?;
+    let slash = spec.find('/')?;
+    let (range, total) = (&spec[..slash], &spec[slash + 1..]);
+    let dash = range.find('-')?;
+    let start = range[..dash].trim().parse().ok()?;
+    let end = range[dash + 1..].trim().parse().ok()?;
+    let total = match total.trim() {
+        "*" => None,
+        total => Some(total.parse().ok()?),
+    };
+    Some((start, end, total))
+}
+
+#[test]
+fn test_parse() {
+    assert_eq!(
+        ByteRange {
+            start: 0,
+            end: Some(65535)
+        },
+        ByteRange::parse("0-65535").unwrap()
+    );
+    assert_eq!(
+        "bytes=100-",
+        ByteRange::parse("100-").unwrap().header_value()
+    );
+    assert_eq!(Some(10), ByteRange::parse("5-14").unwrap().len());
+    assert!(ByteRange::parse("5").is_err());
+    assert!(ByteRange::parse("10-5").is_err());
+    assert!(ByteRange::parse("-5").is_err());
+}
+
+#[test]
+fn test_unsatisfiable_length() {
+    assert_eq!(Some(1234), unsatisfiable_length(Some("bytes */1234")));
+    assert_eq!(None, unsatisfiable_length(Some("bytes 0-5/1234")));
+    assert_eq!(None, unsatisfiable_length(None));
+}
+
+#[test]
+fn test_check_content_range() {
+    let closed = ByteRange::parse("0-99").unwrap();
+    assert!(check_content_range(&closed, Some("bytes 0-99/1000")).is_ok());
+    assert!(check_content_range(&closed, Some("bytes 0-99/*")).is_ok());
+    assert!(check_content_range(&closed, Some("bytes 0-49/50")).is_ok());
+    assert!(check_content_range(&closed, Some("bytes 0-49/1000")).is_err());
+    assert!(check_content_range(&closed, Some("bytes 10-99/1000")).is_err());
+    assert!(check_content_range(&closed, None).is_err());
+
+    let open = ByteRange::parse("500-").unwrap();
+    assert!(check_content_range(&open, Some("bytes 500-999/1000")).is_ok());
+    assert!(check_content_range(&open, Some("bytes 500-599/1000")).is_err());
+    assert!(check_content_range(&open, Some("bytes 0-999/1000")).is_err());
+}
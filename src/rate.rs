@@ -0,0 +1,120 @@
+use std::io;
+use std::io::Read;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Reads are cut to this, at most, so transfers sharing a bucket take turns.
+const CHUNK: usize = 16 * 1024;
+
+/// A token bucket of bytes, for `--limit-rate` and `--limit-rate-total`; clones share it.
+///
+/// It holds a tenth of a second's worth. A read takes what it got, going into debt if there
+/// wasn't enough, and sleeps that off; so transfers sharing one are slowed in turn, and none
+/// can take it all.
+#[derive(Clone)]
+pub struct Bucket {
+    per_second: u64,
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    /// `per_second` bytes a second, which must be at least one.
+    pub fn new(per_second: u64) -> Bucket {
+        let per_second = per_second.max(1);
+        Bucket {
+            per_second,
+            state: Arc::new(Mutex::new(State {
+                tokens: Bucket::capacity(per_second),
+                refilled: Instant::now(),
+            })),
+        }
+    }
+
+    fn capacity(per_second: u64) -> f64 {
+        (per_second as f64 / 10.0).max(1.0)
+    }
+
+    /// Account for `bytes`, sleeping first if they're more than there was room for.
+    pub fn take(&self, bytes: usize) {
+        let wait = {
+            let mut state = self.state.lock().expect("poisoned");
+            let now = Instant::now();
+            let refill = now.duration_since(state.refilled).as_secs_f64() * self.per_second as f64;
+            state.tokens = (state.tokens + refill).min(Bucket::capacity(self.per_second));
+            state.refilled = now;
+            state.tokens -= bytes as f64;
+            if state.tokens >= 0.0 {
+                return;
+            }
+            Duration::from_secs_f64(-state.tokens / self.per_second as f64)
+        };
+        thread::sleep(wait);
+    }
+
+    /// The largest read worth making at once.
+    fn chunk(&self) -> usize {
+        (Bucket::capacity(self.per_second) as usize).clamp(1, CHUNK)
+    }
+}
+
+/// Reads from `inner`, no faster than every bucket allows.
+pub struct Limited<'b, R> {
+    inner: R,
+    buckets: &'b [Bucket],
+}
+
+pub fn limit<R: Read>(inner: R, buckets: &[Bucket]) -> Limited<'_, R> {
+    Limited { inner, buckets }
+}
+
+impl<R: Read> Read for Limited<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let chunk = self
+            .buckets
+            .iter()
+            .map(Bucket::chunk)
+            .min()
+            .unwrap_or(buf.len())
+            .min(buf.len());
+        let read = self.inner.read(&mut buf[..chunk])?;
+        for bucket in self.buckets {
+            bucket.take(read);
+        }
+        Ok(read)
+    }
+}
+
+#[test]
+fn test_bucket() {
+    let bucket = Bucket::new(100_000);
+    let start = Instant::now();
+    // the tenth of a second it starts with
+    bucket.take(10_000);
+    assert!(start.elapsed() < Duration::from_millis(50));
+    bucket.take(20_000);
+    assert!(start.elapsed() >= Duration::from_millis(150));
+}
+
+#[test]
+fn test_limited() {
+    let shared = Bucket::new(200_000);
+    let data = vec![7u8; 30_000];
+    let start = Instant::now();
+    let mut out = Vec::new();
+    io::copy(
+        &mut limit(&data[..], &[Bucket::new(100_000), shared]),
+        &mut out,
+    )
+    .unwrap();
+    assert_eq!(data, out);
+    // the slower of the two: 20k over its starting 10k, at 100k a second
+    assert!(start.elapsed() >= Duration::from_millis(150));
+}
@@ -0,0 +1,65 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use failure::bail;
+use failure::format_err;
+use failure::ResultExt;
+use log::warn;
+
+use crate::digest::Digest;
+use crate::digest::DigestWriter;
+
+/// Re-read the installed output, and check it's what was written, in case the filesystem lied.
+pub fn verify(
+    output: &Path,
+    written: &Digest,
+    mtime: Option<SystemTime>,
+) -> Result<(), failure::Error> {
+    let mut file = fs::File::open(output)
+        .with_context(|_| format_err!("re-opening {:?} to verify", output))?;
+    let mut hasher = DigestWriter::new(io::sink(), true);
+    io::copy(&mut file, &mut hasher).with_context(|_| format_err!("re-reading {:?}", output))?;
+    let (_, actual) = hasher.finish();
+    let actual = actual.expect("hashing");
+
+    if actual.bytes != written.bytes || actual.sha256 != written.sha256 {
+        bail!(
+            "read-back verification failed for {:?}: wrote {:?}, but it now reads as {:?}",
+            output,
+            written,
+            actual
+        );
+    }
+
+    if let Some(expected) = mtime {
+        let actual = file
+            .metadata()
+            .and_then(|m| m.modified())
+            .with_context(|_| format_err!("reading {:?}'s modification time", output))?;
+        let drift = match actual.duration_since(expected) {
+            Ok(later) => later,
+            Err(earlier) => earlier.duration(),
+        };
+
+        if drift >= Duration::from_secs(2) {
+            bail!(
+                "read-back verification failed for {:?}: modification time is {:?}, not {:?}",
+                output,
+                actual,
+                expected
+            );
+        }
+
+        if actual != expected {
+            warn!(
+                "filesystem truncated the modification time of {:?}: {:?}, not {:?}",
+                output, actual, expected
+            );
+        }
+    }
+
+    Ok(())
+}
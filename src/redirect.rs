@@ -0,0 +1,185 @@
+use std::fmt;
+
+use failure::bail;
+use failure::format_err;
+use failure::ResultExt;
+use log::warn;
+use url::Url;
+
+/// How many URLs, from each end, to show when describing a long chain.
+const SHOWN_AT_ENDS: usize = 3;
+
+/// Where to go next, if this response is a redirect we're willing to follow.
+pub fn location(response: &ureq::Response) -> Option<&str> {
+    match response.status() {
+        301 | 302 | 303 | 307 | 308 => response.header("Location"),
+        _ => None,
+    }
+}
+
+/// Whether what was given for `start`, its credentials, and headers like Authorization, may go
+/// to `url`: only at the same scheme, host and port, or there once upgraded to https, as ureq's
+/// own redirects would never send them on elsewhere.
+pub fn trusted(start: &Url, url: &Url) -> bool {
+    url.origin() == start.origin()
+        || ("http" == start.scheme()
+            && "https" == url.scheme()
+            && url.host() == start.host()
+            && url.port() == start.port())
+}
+
+/// Whether `url` is plain http in a run that started on https, which only
+/// `--allow-insecure-redirect` gets to.
+pub fn downgraded(start: &Url, url: &Url) -> bool {
+    "https" == start.scheme() && "http" == url.scheme()
+}
+
+/// The URLs visited while following redirects, starting with the one requested.
+pub struct Chain {
+    urls: Vec<Url>,
+    limit: usize,
+    /// `--allow-insecure-redirect`: follow a hop from https to http, rather than failing.
+    downgrade: bool,
+}
+
+impl Chain {
+    pub fn new(start: Url, limit: usize) -> Chain {
+        Chain {
+            urls: vec![start],
+            limit,
+            downgrade: false,
+        }
+    }
+
+    pub fn allow_downgrade(&mut self) {
+        self.downgrade = true;
+    }
+
+    pub fn current(&self) -> &Url {
+        self.urls.last().expect("never empty")
+    }
+
+    /// Follow a (possibly relative) `Location` header from the current URL.
+    pub fn follow_location(&mut self, location: &str) -> Result<(), failure::Error> {
+        let next = self
+            .current()
+            .join(location)
+            .with_context(|_| format_err!("parsing redirect location {:?}", location))?;
+        self.follow(next)
+    }
+
+    /// Record a hop to `next`, failing immediately if it's been seen before, or there are too many,
+    /// or if it's from https to http and that's not allowed.
+    pub fn follow(&mut self, next: Url) -> Result<(), failure::Error> {
+        if "https" == self.current().scheme() && "http" == next.scheme() {
+            if !self.downgrade {
+                bail!(
+                    "refusing to be redirected from https to http, {} -> {}; see --allow-insecure-redirect",
+                    self.current(),
+                    next
+                );
+            }
+            warn!(
+                "redirected from https to http, {} -> {}, so nothing secret is sent",
+                self.current(),
+                next
+            );
+        }
+
+        let seen = self.urls.contains(&next);
+        self.urls.push(next);
+
+        if seen {
+            bail!("redirect loop: {}", self);
+        }
+
+        if self.urls.len() > self.limit + 1 {
+            bail!("too many redirects (limit {}): {}", self.limit, self);
+        }
+
+        Ok(())
+    }
+
+    /// Ask for `instead` rather than the current URL, without it counting as a hop; failing if
+    /// it's been seen before.
+    pub fn replace(&mut self, instead: Url) -> Result<(), failure::Error> {
+        let seen = self.urls.contains(&instead);
+        *self.urls.last_mut().expect("never empty") = instead;
+        if seen {
+            bail!("redirect loop: {}", self);
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Chain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let show = |f: &mut fmt::Formatter, urls: &[Url]| -> fmt::Result {
+            for (i, url) in urls.iter().enumerate() {
+                if i > 0 {
+                    write!(f, " -> ")?;
+                }
+                write!(f, "{}", url)?;
+            }
+            Ok(())
+        };
+
+        if self.urls.len() <= SHOWN_AT_ENDS * 2 {
+            return show(f, &self.urls);
+        }
+
+        show(f, &self.urls[..SHOWN_AT_ENDS])?;
+        write!(f, " -> ({} more) -> ", self.urls.len() - SHOWN_AT_ENDS * 2)?;
+        show(f, &self.urls[self.urls.len() - SHOWN_AT_ENDS..])
+    }
+}
+
+#[test]
+fn test_chain() {
+    let url = |s: &str| Url::parse(s).unwrap();
+
+    let mut chain = Chain::new(url("http://a/"), 10);
+    chain.follow(url("http://b/")).unwrap();
+    assert_eq!("http://b/", chain.current().as_str());
+    let err = chain.follow(url("http://a/")).unwrap_err().to_string();
+    assert_eq!("redirect loop: http://a/ -> http://b/ -> http://a/", err);
+
+    let mut chain = Chain::new(url("http://a/0"), 10);
+    for i in 1..=10 {
+        chain.follow(url(&format!("http://a/{}", i))).unwrap();
+    }
+    let err = chain.follow(url("http://a/11")).unwrap_err().to_string();
+    assert_eq!(
+        "too many redirects (limit 10): http://a/0 -> http://a/1 -> http://a/2 \
+         -> (6 more) -> http://a/9 -> http://a/10 -> http://a/11",
+        err
+    );
+
+    let mut chain = Chain::new(url("https://a/"), 10);
+    chain.follow(url("https://b/")).unwrap();
+    let err = chain.follow(url("http://c/")).unwrap_err().to_string();
+    assert_eq!(
+        "refusing to be redirected from https to http, https://b/ -> http://c/; see --allow-insecure-redirect",
+        err
+    );
+    let mut chain = Chain::new(url("http://a/"), 10);
+    chain.follow(url("https://a/")).unwrap();
+    chain.allow_downgrade();
+    chain.follow(url("http://b/")).unwrap();
+    assert_eq!("http://b/", chain.current().as_str());
+}
+
+#[test]
+fn test_trusted() {
+    let url = |s: &str| Url::parse(s).unwrap();
+    let start = url("http://example.com/a");
+    assert!(trusted(&start, &url("http://example.com:80/b")));
+    assert!(trusted(&start, &url("https://example.com/b")));
+    assert!(!trusted(&start, &url("http://example.com:8080/b")));
+    assert!(!trusted(&start, &url("http://www.example.com/b")));
+
+    let secure = url("https://example.com/a");
+    assert!(!trusted(&secure, &url("http://example.com/b")));
+    assert!(downgraded(&secure, &url("http://example.com/b")));
+    assert!(!downgraded(&start, &url("http://example.com/b")));
+}
@@ -0,0 +1,149 @@
+//! The reports which have stdout to themselves: `--json` and `--porcelain`.
+//!
+//! `--json`'s is one object, on a line of its own.
+//! Version 1 has these fields, always all of them, with `null` for what the run didn't get as
+//! far as finding out:
+//!
+//! - `version`: `1`; a field's meaning only changes with this.
+//! - `url`: as requested, normalised and without credentials.
+//! - `final_url`: after redirects.
+//! - `outcome`: `fetched`, `stored`, `unchanged`, `skipped`, or `failed`.
+//! - `http_status`: of the final response, as a number.
+//! - `bytes`: of the body, as received.
+//! - `sha256`: of the body, in hex.
+//! - `last_modified`: the server's, as RFC 3339.
+//! - `etag`: the final response's, as sent.
+//! - `output`: the file written, or that would have been.
+//! - `error`: `null`, or `{"kind": ..., "message": ...}`; the kind is one of `exit::Kind`'s
+//!   names, as `exit::kind_name` gives them.
+//! - `time_starttransfer`, `time_total`: seconds, from the start of the last attempt to its
+//!   final response's headers, and to its end.
+//! - `remote_ip`: the first address that the final URL's host resolved to, which is the one
+//!   connected to.
+//! - `cert_expires`: when the server's certificate does, as RFC 3339, if `--cert-expiry-warn`
+//!   or `--cert-expiry-fail` looked.
+//! - `cert_expiring`: whether that's within either's window.
+
+use std::time::Duration;
+
+use chrono::DateTime;
+use chrono::Utc;
+
+use crate::digest;
+use crate::exit;
+use crate::json::string;
+use crate::outcome::Kind;
+use crate::outcome::Outcome;
+
+pub const VERSION: u32 = 1;
+
+/// The flags for them, and `--events`, which mean nothing else can be written to stdout.
+pub const FLAGS: &[&str] = &["json", "porcelain", "events"];
+
+/// `--porcelain`'s one line, which is a promise, so only ever added to: see its `--help`.
+pub fn porcelain(outcome: &Outcome, error: Option<&failure::Error>) -> String {
+    let unknown = || "-".to_string();
+    let line = match (error, outcome.kind) {
+        (Some(e), _) => format!("error {}", exit::kind_name(e, outcome.phase)),
+        (None, Kind::Fetched) => format!(
+            "downloaded {} {}",
+            outcome.bytes.map_or_else(unknown, |b| b.to_string()),
+            outcome
+                .sha256
+                .as_ref()
+                .map_or_else(unknown, |s| digest::hex(s))
+        ),
+        (None, Kind::Stored) => format!(
+            "stored {}",
+            outcome
+                .sha256
+                .as_ref()
+                .map_or_else(unknown, |s| digest::hex(s))
+        ),
+        (None, Kind::Unchanged) => "not-modified".to_string(),
+        (None, Kind::Skipped) => format!("skipped {}", outcome.skipped.unwrap_or("other")),
+    };
+    format!("{}\n", line)
+}
+
+/// The report for a run which ended with `error`, if it failed.
+pub fn json(outcome: &Outcome, error: Option<&failure::Error>) -> String {
+    let optional = |value: Option<String>| value.unwrap_or_else(|| "null".to_string());
+    let seconds = |d: Option<Duration>| optional(d.map(|d| format!("{:.6}", d.as_secs_f64())));
+    let error = error.map(|e| {
+        let kind = exit::kind_name(e, outcome.phase);
+        let message = e
+            .iter_chain()
+            .map(|f| f.to_string())
+            .collect::<Vec<_>>()
+            .join(": ");
+        format!(
+            "{{\"kind\":{},\"message\":{}}}",
+            string(kind),
+            string(&message)
+        )
+    });
+    format!(
+        concat!(
+            "{{\"version\":{},\"url\":{},\"final_url\":{},\"outcome\":{},",
+            "\"http_status\":{},\"bytes\":{},\"sha256\":{},\"last_modified\":{},",
+            "\"etag\":{},\"output\":{},\"error\":{},",
+            "\"time_starttransfer\":{},\"time_total\":{},\"remote_ip\":{},",
+            "\"cert_expires\":{},\"cert_expiring\":{}}}\n"
+        ),
+        VERSION,
+        string(&outcome.url),
+        optional(outcome.final_url.as_deref().map(string)),
+        string(if error.is_some() {
+            "failed"
+        } else {
+            outcome.kind.name()
+        }),
+        optional(outcome.status.map(|s| s.to_string())),
+        optional(outcome.bytes.map(|b| b.to_string())),
+        optional(outcome.sha256.as_ref().map(|s| string(&digest::hex(s)))),
+        optional(outcome.last_modified.map(|t| string(
+            &DateTime::<Utc>::from(t).to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+        ))),
+        optional(outcome.etag.as_deref().map(string)),
+        optional(
+            outcome
+                .output
+                .as_ref()
+                .map(|p| string(&p.to_string_lossy()))
+        ),
+        optional(error),
+        seconds(outcome.first_byte),
+        seconds(outcome.elapsed),
+        optional(outcome.remote.map(|r| string(&r.ip().to_string()))),
+        optional(outcome.cert_expires.map(|t| string(
+            &DateTime::<Utc>::from(t).to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+        ))),
+        optional(outcome.cert_expiring.map(|e| e.to_string())),
+    )
+}
+
+#[test]
+fn test_json() {
+    let outcome = Outcome {
+        url: "https://example.com/a".to_string(),
+        phase: "requesting",
+        etag: Some("\"v1\"".to_string()),
+        ..Outcome::default()
+    };
+    assert_eq!(
+        concat!(
+            "{\"version\":1,\"url\":\"https://example.com/a\",\"final_url\":null,",
+            "\"outcome\":\"skipped\",\"http_status\":null,\"bytes\":null,\"sha256\":null,",
+            "\"last_modified\":null,\"etag\":\"\\\"v1\\\"\",\"output\":null,\"error\":null,",
+            "\"time_starttransfer\":null,\"time_total\":null,\"remote_ip\":null,",
+            "\"cert_expires\":null,\"cert_expiring\":null}\n"
+        ),
+        json(&outcome, None)
+    );
+
+    let refused = exit::classified(exit::Kind::Status(404), "not found");
+    assert!(json(&outcome, Some(&refused)).contains("\"outcome\":\"failed\",".to_string().as_str()));
+    assert!(json(&outcome, Some(&refused))
+        .contains("\"error\":{\"kind\":\"client-error\",\"message\":\"not found\"}"));
+}
@@ -0,0 +1,131 @@
+use std::sync::atomic::Ordering;
+
+use log::debug;
+use url::Url;
+
+use crate::redirect;
+use crate::target;
+
+/// How every request a run makes is started: from the agent, with the credentials and the
+/// `-H` headers, each only where it's safe to send.
+pub struct Sender<'a> {
+    pub agent: Option<&'a ureq::Agent>,
+    /// The URL the run was asked for, which the credentials and sensitive headers were given for.
+    pub start: &'a Url,
+    pub credentials: Option<&'a target::Credentials>,
+    pub headers: &'a [(&'a str, &'a str)],
+}
+
+impl Sender<'_> {
+    /// Everything but the conditional parts, which only make sense for the main request; and
+    /// without the custom headers, which are set last.
+    ///
+    /// Redirects are followed by hand, so each hop is a new request. The credentials only go
+    /// to where they were given for; and a hop that's been downgraded to http doesn't get the
+    /// agent's cookies either.
+    pub fn request(&self, method: &str, url: &Url) -> ureq::Request {
+        crate::REQUESTS.fetch_add(1, Ordering::Relaxed);
+        let mut req = match self.agent {
+            Some(agent) if !redirect::downgraded(self.start, url) => {
+                agent.request(method, url.as_str())
+            }
+            // without the agent's cookies
+            _ => ureq::request(method, url.as_str()),
+        };
+        req.redirects(0);
+
+        if let Some(credentials) = self.credentials {
+            if redirect::trusted(self.start, url) {
+                req.auth(&credentials.user, &credentials.password);
+            } else {
+                debug!(
+                    "not sending credentials to {}",
+                    url.origin().ascii_serialization()
+                );
+            }
+        }
+
+        req
+    }
+
+    /// Set last, so the user can override anything; but what looks secret only goes where the
+    /// credentials would.
+    pub fn set_custom_headers(&self, req: &mut ureq::Request) {
+        let trusted =
+            Url::parse(req.get_url()).is_ok_and(|url| redirect::trusted(self.start, &url));
+        for (key, value) in self.headers {
+            if !trusted && target::is_sensitive_header(key) {
+                debug!("not sending header elsewhere: {:?}", key);
+                continue;
+            }
+            req.set(key, value);
+            if target::is_sensitive_header(key) {
+                debug!("sending header: {:?}: ***", key);
+            } else {
+                debug!("sending header: {:?}: {:?}", key, value);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_downgraded_hop() {
+    use std::io::BufRead;
+    use std::io::Write;
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = std::thread::spawn(move || {
+        let mut heads = Vec::new();
+        for _ in 0..2 {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut head = String::new();
+            let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+            while reader.read_line(&mut head).unwrap() > 2 {}
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            heads.push(head);
+        }
+        heads
+    });
+
+    let agent = ureq::agent();
+    agent.set_cookie(
+        ureq::Cookie::build("session", "s3cret")
+            .domain("127.0.0.1")
+            .path("/")
+            .finish(),
+    );
+    let credentials = target::Credentials {
+        user: "u".to_string(),
+        password: "secret".to_string(),
+    };
+    let plain = Url::parse(&format!("http://127.0.0.1:{}/f", port)).unwrap();
+    let send = |start: &str| {
+        let start = Url::parse(start).unwrap();
+        let sender = Sender {
+            agent: Some(&agent),
+            start: &start,
+            credentials: Some(&credentials),
+            headers: &[("X-Api-Key", "k"), ("X-Plain", "p")],
+        };
+        let mut req = sender.request("GET", &plain);
+        sender.set_custom_headers(&mut req);
+        assert!(req.call().ok());
+    };
+
+    // asked for over http, it's all sent; as the https hop to it, none of it
+    send(plain.as_str());
+    send(&format!("https://127.0.0.1:{}/f", port));
+    let heads = server.join().unwrap();
+    for header in &[
+        "Authorization: Basic",
+        "Cookie: session=s3cret",
+        "X-Api-Key: k",
+    ] {
+        assert!(heads[0].contains(header), "{}", heads[0]);
+        assert!(!heads[1].contains(header), "{}", heads[1]);
+    }
+    assert!(heads[1].contains("X-Plain: p"), "{}", heads[1]);
+}
@@ -0,0 +1,63 @@
+use std::fs;
+use std::io;
+use std::io::Seek;
+use std::path::Path;
+
+use failure::format_err;
+use failure::ResultExt;
+use log::warn;
+use tempfile_fast::PersistableTempFile;
+
+use crate::sink;
+use crate::xattrs;
+
+pub const EXDEV: i32 = 18;
+
+/// Copy a finished temporary file into a new one in `dir`, so it can be renamed into place there.
+///
+/// For when the first was staged on another filesystem (`--temp-dir`). The owner, mode and
+/// times come along; extended attributes are copied again from `xattrs_from`, the old output,
+/// as the staged file's SELinux label belongs to wherever it was staged.
+pub fn restage(
+    staged: &fs::File,
+    dir: &Path,
+    xattrs_from: Option<(&Path, bool)>,
+) -> Result<PersistableTempFile, failure::Error> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = staged
+        .metadata()
+        .with_context(|_| format_err!("reading staged download's info"))?;
+
+    let mut copy = sink::temp_in(dir)?;
+
+    let mut source = staged;
+    source
+        .seek(io::SeekFrom::Start(0))
+        .with_context(|_| format_err!("rewinding staged download"))?;
+    io::copy(&mut source, &mut copy)
+        .with_context(|_| format_err!("copying staged download into {:?}", dir))?;
+
+    if let Err(e) =
+        std::os::unix::fs::fchown(copy.as_ref(), Some(metadata.uid()), Some(metadata.gid()))
+    {
+        warn!("couldn't keep the staged download's owner: {}", e);
+    }
+
+    if let Some((from, all_user)) = xattrs_from {
+        xattrs::copy(from, copy.as_ref(), all_user);
+    }
+
+    copy.as_ref()
+        .set_permissions(metadata.permissions())
+        .with_context(|_| format_err!("setting the copy's mode"))?;
+
+    // last, as writing the copy moved its mtime
+    let atime = filetime::FileTime::from_last_access_time(&metadata);
+    let mtime = filetime::FileTime::from_last_modification_time(&metadata);
+    if let Err(e) = filetime::set_file_handle_times(copy.as_ref(), Some(atime), Some(mtime)) {
+        warn!("failed to set the copy's times: {:?}", e);
+    }
+
+    Ok(copy)
+}
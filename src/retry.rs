@@ -0,0 +1,19 @@
+use std::fmt;
+
+/// The attempt failed in a way that's worth starting over from the top, and `--retry` allowed it.
+pub struct Restart;
+
+impl fmt::Display for Restart {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "starting the download again")
+    }
+}
+
+// main prints errors with Debug
+impl fmt::Debug for Restart {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl failure::Fail for Restart {}
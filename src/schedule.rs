@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use log::debug;
+
+/// Which batch entry to start next, keeping to `--per-host-max` and `--per-host-delay`.
+///
+/// Entries go in order, except that one whose host has to wait lets later ones for other hosts
+/// go first. More can be added until it's closed.
+pub struct Schedule<T> {
+    state: Mutex<State<T>>,
+    changed: Condvar,
+    delay: Duration,
+    max: usize,
+}
+
+struct State<T> {
+    /// The entries not yet started: their lines, hosts, and whatever's needed to start them.
+    pending: Vec<(usize, String, T)>,
+    hosts: HashMap<String, Host>,
+    /// No more entries are coming.
+    closed: bool,
+    stopped: bool,
+}
+
+#[derive(Default)]
+struct Host {
+    in_flight: usize,
+    last_start: Option<Instant>,
+}
+
+impl<T> Schedule<T> {
+    /// `max` of zero is no limit.
+    pub fn new(delay: Duration, max: usize) -> Schedule<T> {
+        Schedule {
+            state: Mutex::new(State {
+                pending: Vec::new(),
+                hosts: HashMap::new(),
+                closed: false,
+                stopped: false,
+            }),
+            changed: Condvar::new(),
+            delay,
+            max,
+        }
+    }
+
+    /// Queue an entry, from `line`, for `host`.
+    pub fn push(&self, line: usize, host: String, entry: T) {
+        self.state
+            .lock()
+            .expect("poisoned")
+            .pending
+            .push((line, host, entry));
+        self.changed.notify_all();
+    }
+
+    /// There are no more entries to come.
+    pub fn close(&self) {
+        self.state.lock().expect("poisoned").closed = true;
+        self.changed.notify_all();
+    }
+
+    /// The next entry to start, and its host, waiting until one can; `None` once there are
+    /// none left.
+    pub fn next(&self) -> Option<(String, T)> {
+        let mut state = self.state.lock().expect("poisoned");
+        loop {
+            if state.stopped || (state.closed && state.pending.is_empty()) {
+                return None;
+            }
+
+            let now = Instant::now();
+            // how long until the soonest entry only waiting on the delay can go
+            let mut wait: Option<(Duration, usize, String)> = None;
+            let mut ready = None;
+            for (position, (line, name, _)) in state.pending.iter().enumerate() {
+                let host = state.hosts.get(name);
+                if self.max > 0 && host.map_or(0, |h| h.in_flight) >= self.max {
+                    continue;
+                }
+                let since = match host.and_then(|h| h.last_start) {
+                    Some(last) => now.duration_since(last),
+                    None => self.delay,
+                };
+                if since >= self.delay {
+                    ready = Some(position);
+                    break;
+                }
+                let left = self.delay - since;
+                if wait.as_ref().is_none_or(|(soonest, _, _)| left < *soonest) {
+                    wait = Some((left, *line, name.clone()));
+                }
+            }
+
+            if let Some(position) = ready {
+                let (_, name, entry) = state.pending.remove(position);
+                let host = state.hosts.entry(name.clone()).or_default();
+                host.in_flight += 1;
+                host.last_start = Some(now);
+                return Some((name, entry));
+            }
+
+            // otherwise everything's waiting for a host to finish something, or for more
+            state = match wait {
+                Some((wait, line, name)) => {
+                    debug!(
+                        "      per-host: line {} waits {:?} for {}",
+                        line, wait, name
+                    );
+                    self.changed.wait_timeout(state, wait).expect("poisoned").0
+                }
+                None if state.pending.is_empty() => self.changed.wait(state).expect("poisoned"),
+                None => {
+                    debug!("      per-host: waiting for a request to finish");
+                    self.changed.wait(state).expect("poisoned")
+                }
+            };
+        }
+    }
+
+    /// An entry for `host`, from `next`, has finished.
+    pub fn done(&self, host: &str) {
+        let mut state = self.state.lock().expect("poisoned");
+        if let Some(host) = state.hosts.get_mut(host) {
+            host.in_flight -= 1;
+        }
+        self.changed.notify_all();
+    }
+
+    /// Start nothing more.
+    pub fn stop(&self) {
+        self.state.lock().expect("poisoned").stopped = true;
+        self.changed.notify_all();
+    }
+
+    /// Whether `stop` has been called, so there's no point adding more.
+    pub fn stopped(&self) -> bool {
+        self.state.lock().expect("poisoned").stopped
+    }
+}
+
+#[cfg(test)]
+fn queued(hosts: &[&str], delay: Duration, max: usize) -> Schedule<usize> {
+    let schedule = Schedule::new(delay, max);
+    for (i, host) in hosts.iter().enumerate() {
+        schedule.push(i + 1, host.to_string(), i + 1);
+    }
+    schedule
+}
+
+#[test]
+fn test_max() {
+    let schedule = queued(&["a", "a", "b"], Duration::from_secs(0), 1);
+    schedule.close();
+    let next = || schedule.next().map(|(_, line)| line);
+    assert_eq!(Some(1), next());
+    // the second "a" has to wait, so "b" goes first
+    assert_eq!(Some(3), next());
+    schedule.done("a");
+    assert_eq!(Some(2), next());
+    schedule.stop();
+    assert_eq!(None, next());
+}
+
+#[test]
+fn test_delay() {
+    let schedule = queued(&["a", "a"], Duration::from_millis(50), 0);
+    let start = Instant::now();
+    assert_eq!(Some(("a".to_string(), 1)), schedule.next());
+    assert_eq!(Some(("a".to_string(), 2)), schedule.next());
+    assert!(start.elapsed() >= Duration::from_millis(50));
+
+    // nothing pending, but more could come
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            std::thread::sleep(Duration::from_millis(20));
+            schedule.push(3, "b".to_string(), 3);
+            schedule.close();
+        });
+        assert_eq!(Some(("b".to_string(), 3)), schedule.next());
+        assert_eq!(None, schedule.next());
+    });
+}
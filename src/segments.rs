@@ -0,0 +1,377 @@
+use std::cell::Cell;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::os::unix::fs::FileExt;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+
+use failure::bail;
+use failure::format_err;
+use log::warn;
+
+use crate::events;
+use crate::interim;
+use crate::range::ByteRange;
+use crate::rate;
+
+/// What a segment reads at once, before writing it in its place.
+const BUFFER: usize = 64 * 1024;
+
+/// How often the segments' progress is added up.
+const POLL: Duration = Duration::from_millis(100);
+
+/// `--parallel-segments`, until a server turns out not to send ranges after all.
+pub struct Segments {
+    count: u64,
+    given_up: Cell<bool>,
+}
+
+impl Segments {
+    pub fn from_matches(matches: &clap::ArgMatches) -> Result<Option<Segments>, failure::Error> {
+        let v = match matches.value_of("parallel-segments") {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        let count = v
+            .parse::<u64>()
+            .map_err(|_| format_err!("--parallel-segments isn't a number: {:?}", v))?;
+        if 0 == count {
+            bail!("--parallel-segments must be at least one");
+        }
+        Ok(Some(Segments {
+            count,
+            given_up: Cell::new(false),
+        }))
+    }
+
+    pub fn active(&self) -> bool {
+        !self.given_up.get()
+    }
+
+    pub fn give_up(&self) {
+        self.given_up.set(true);
+    }
+
+    /// A body of `len`, cut into as many ranges as there are to be segments, as even as they go;
+    /// fewer if it's too short for them all to have something.
+    pub fn split(&self, len: u64) -> Vec<ByteRange> {
+        let count = self.count.min(len).max(1);
+        let each = len.div_ceil(count);
+        (0..count)
+            .map(|i| i * each)
+            .take_while(|&start| start < len)
+            .map(|start| ByteRange {
+                start,
+                end: Some(start.saturating_add(each).min(len) - 1),
+            })
+            .collect()
+    }
+}
+
+/// Asks for a range of the body, as it was first sent; `None` if it all came instead.
+pub type Fetch<'f> =
+    &'f (dyn Fn(ByteRange) -> Result<Option<interim::Body>, failure::Error> + Sync);
+
+/// A segment came back as the whole body, so the server doesn't do ranges, whatever it says.
+pub struct Unranged {
+    number: usize,
+}
+
+impl fmt::Display for Unranged {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "segment {} came back whole, rather than as the range asked for",
+            self.number
+        )
+    }
+}
+
+impl fmt::Debug for Unranged {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl failure::Fail for Unranged {}
+
+pub fn is_unranged(e: &failure::Error) -> bool {
+    e.downcast_ref::<Unranged>().is_some()
+}
+
+/// What every segment writes with.
+struct Shared<'s> {
+    file: &'s fs::File,
+    fetch: Fetch<'s>,
+    buckets: &'s [rate::Bucket],
+    retries: usize,
+    received: &'s AtomicU64,
+    /// Set by the first to fail, so the rest stop.
+    failed: &'s AtomicBool,
+}
+
+/// Download `ranges` into `file` at once, each written in its place; the first from `first`,
+/// the response that's already here, the rest asked for with `fetch`.
+///
+/// A segment that fails resumes from where it got to, with `retries` of its own. Every read
+/// comes out of `buckets`, and `progress` counts them all together. The first failure stops the
+/// rest, and is what's returned.
+pub fn download(
+    file: &fs::File,
+    ranges: &[ByteRange],
+    first: &mut dyn Read,
+    fetch: Fetch,
+    buckets: &[rate::Bucket],
+    retries: usize,
+    progress: &mut events::Progress,
+) -> Result<(), failure::Error> {
+    let received = AtomicU64::new(0);
+    let failed = AtomicBool::new(false);
+    let finished = AtomicBool::new(false);
+    let shared = Shared {
+        file,
+        fetch,
+        buckets,
+        retries,
+        received: &received,
+        failed: &failed,
+    };
+
+    let results = thread::scope(|scope| {
+        let reporter = scope.spawn(|| {
+            while !finished.load(Ordering::Relaxed) {
+                progress.reached(received.load(Ordering::Relaxed));
+                thread::sleep(POLL);
+            }
+            progress.reached(received.load(Ordering::Relaxed));
+        });
+        let shared = &shared;
+        let rest: Vec<_> = ranges
+            .iter()
+            .enumerate()
+            .skip(1)
+            .map(|(number, &range)| scope.spawn(move || shared.fill(number, range, None)))
+            .collect();
+        let mut results = vec![shared.fill(0, ranges[0], Some(first))];
+        results.extend(
+            rest.into_iter()
+                .map(|segment| segment.join().expect("segment panicked")),
+        );
+        finished.store(true, Ordering::Relaxed);
+        reporter.join().expect("reporter panicked");
+        results
+    });
+    results.into_iter().collect()
+}
+
+impl Shared<'_> {
+    /// Fill `range` of the file, from `first` if there is one, then asking for what's left.
+    ///
+    /// Stops, without complaint, once another segment has failed, as that's the failure to
+    /// report.
+    fn fill(
+        &self,
+        number: usize,
+        range: ByteRange,
+        mut first: Option<&mut dyn Read>,
+    ) -> Result<(), failure::Error> {
+        let end = range.end.expect("bounded") + 1;
+        let mut offset = range.start;
+        let mut retries = self.retries;
+        let mut buf = vec![0; BUFFER];
+        loop {
+            let body = match first.take() {
+                Some(first) => Ok(Some(Box::new(first) as Box<dyn Read + '_>)),
+                None => (self.fetch)(ByteRange {
+                    start: offset,
+                    end: range.end,
+                })
+                .map(|body| body.map(|body| body as Box<dyn Read + '_>)),
+            };
+            let failure = match body {
+                Ok(Some(mut body)) => loop {
+                    if self.failed.load(Ordering::Relaxed) {
+                        return Ok(());
+                    }
+                    let want = (end - offset).min(buf.len() as u64) as usize;
+                    let read = match rate::limit(&mut body, self.buckets).read(&mut buf[..want]) {
+                        Ok(0) => break format_err!("ended at offset {}, short of {}", offset, end),
+                        Ok(read) => read,
+                        Err(ref e) if io::ErrorKind::Interrupted == e.kind() => continue,
+                        Err(e) => break failure::Error::from(e),
+                    };
+                    if let Err(e) = self.file.write_all_at(&buf[..read], offset) {
+                        self.failed.store(true, Ordering::Relaxed);
+                        return Err(failure::Error::from(e)
+                            .context(format!("writing segment {}", number))
+                            .into());
+                    }
+                    offset += read as u64;
+                    self.received.fetch_add(read as u64, Ordering::Relaxed);
+                    if offset == end {
+                        return Ok(());
+                    }
+                },
+                Ok(None) => {
+                    self.failed.store(true, Ordering::Relaxed);
+                    return Err(Unranged { number }.into());
+                }
+                Err(e) => e,
+            };
+
+            if self.failed.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+            if 0 == retries {
+                self.failed.store(true, Ordering::Relaxed);
+                return Err(failure
+                    .context(format!("downloading segment {}", number))
+                    .into());
+            }
+            retries -= 1;
+            warn!(
+                "segment {}: {}; resuming from offset {}",
+                number, failure, offset
+            );
+        }
+    }
+}
+
+/// Reads `len` bytes of `file` from the start, without moving its position, so what the
+/// segments put together can be checked on its way through the writers.
+pub struct ReadBack<'f> {
+    file: &'f fs::File,
+    offset: u64,
+    len: u64,
+}
+
+pub fn read_back(file: &fs::File, len: u64) -> ReadBack<'_> {
+    ReadBack {
+        file,
+        offset: 0,
+        len,
+    }
+}
+
+impl Read for ReadBack<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let want = (self.len - self.offset).min(buf.len() as u64) as usize;
+        let read = self.file.read_at(&mut buf[..want], self.offset)?;
+        self.offset += read as u64;
+        Ok(read)
+    }
+}
+
+/// Writes on to `inner`, unless the segments already put everything in place, when what's
+/// written is only counted, and dropped.
+pub struct Placed<W> {
+    inner: W,
+    placed: bool,
+}
+
+impl<W> Placed<W> {
+    pub fn new(inner: W, placed: bool) -> Placed<W> {
+        Placed { inner, placed }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for Placed<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.placed {
+            return Ok(buf.len());
+        }
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[test]
+fn test_split() {
+    let segments = Segments {
+        count: 4,
+        given_up: Cell::new(false),
+    };
+    let ends = |len| -> Vec<(u64, u64)> {
+        segments
+            .split(len)
+            .iter()
+            .map(|range| (range.start, range.end.unwrap()))
+            .collect()
+    };
+    assert_eq!(vec![(0, 2), (3, 5), (6, 8), (9, 9)], ends(10));
+    assert_eq!(vec![(0, 0), (1, 1)], ends(2));
+    assert_eq!(vec![(0, 99), (100, 199), (200, 299), (300, 399)], ends(400));
+    assert_eq!(Some(&(3 << 62, u64::MAX - 1)), ends(u64::MAX).last());
+}
+
+#[test]
+fn test_download() {
+    use std::io::Cursor;
+
+    let body: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+    let file = tempfile::tempfile().unwrap();
+    let ranges = Segments {
+        count: 3,
+        given_up: Cell::new(false),
+    }
+    .split(body.len() as u64);
+    let failed = std::sync::Mutex::new(false);
+    let fetch = |range: ByteRange| -> Result<Option<interim::Body>, failure::Error> {
+        let end = range.end.unwrap() as usize + 1;
+        // each range is cut short once, to be resumed
+        let mut failed = failed.lock().unwrap();
+        let end = if *failed {
+            end
+        } else {
+            *failed = true;
+            range.start as usize + 10
+        };
+        Ok(Some(Box::new(Cursor::new(
+            body[range.start as usize..end].to_vec(),
+        ))))
+    };
+    let events = events::Events::default();
+    let mut progress = events.progress(Some(body.len() as u64));
+    download(
+        &file,
+        &ranges,
+        &mut Cursor::new(body.clone()),
+        &fetch,
+        &[],
+        1,
+        &mut progress,
+    )
+    .unwrap();
+    assert_eq!(body.len() as u64, progress.bytes());
+
+    let mut back = Vec::new();
+    read_back(&file, body.len() as u64)
+        .read_to_end(&mut back)
+        .unwrap();
+    assert_eq!(body, back);
+
+    let err = download(
+        &file,
+        &ranges,
+        &mut Cursor::new(body.clone()),
+        &|_| Ok(None),
+        &[],
+        1,
+        &mut progress,
+    )
+    .unwrap_err();
+    assert!(is_unranged(&err), "{}", err);
+}
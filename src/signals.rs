@@ -0,0 +1,260 @@
+use std::ffi::CString;
+use std::fs;
+use std::path::Path;
+use std::ptr;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicPtr;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use failure::format_err;
+use failure::ResultExt;
+use log::debug;
+use log::info;
+use log::warn;
+
+use crate::sink;
+
+const SIGNALS: &[libc::c_int] = &[libc::SIGINT, libc::SIGTERM, libc::SIGHUP];
+
+/// How many named temporary files can be remembered at once: one for each of `--jobs`, and
+/// plenty to spare.
+const SLOTS: usize = 64;
+
+/// The named temporary files to remove if we're killed; each null while it's free.
+static TEMP_PATHS: [AtomicPtr<libc::c_char>; SLOTS] =
+    [const { AtomicPtr::new(ptr::null_mut()) }; SLOTS];
+
+/// Set once the handler has started, so a name it may be reading isn't freed under it.
+static HANDLING: AtomicBool = AtomicBool::new(false);
+
+/// Set by a SIGHUP after `reopen_on_hangup`, until `hung_up` notices.
+static HUNG_UP: AtomicBool = AtomicBool::new(false);
+
+/// Set by the first SIGINT or SIGTERM after `stay_resident`, for the run to finish and stop.
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+/// Set by a SIGHUP after `stay_resident`, until `woken` notices.
+static WOKEN: AtomicBool = AtomicBool::new(false);
+
+/// Temporary files untouched for this long aren't being written by anyone.
+pub const STALE_AFTER: Duration = Duration::from_secs(60 * 60);
+
+/// Exit with `128 + signal` on SIGINT, SIGTERM or SIGHUP, removing any temporary file first.
+/// `reopen_on_hangup` takes SIGHUP back.
+///
+/// Drop doesn't run when a signal kills us, and the fallback temporary files have names.
+pub fn install() {
+    for &signal in SIGNALS {
+        // only fails for invalid signal numbers
+        unsafe { libc::signal(signal, on_signal as *const () as libc::sighandler_t) };
+    }
+}
+
+extern "C" fn on_signal(signal: libc::c_int) {
+    // only async-signal-safe calls in here: unlink and _exit
+    HANDLING.store(true, Ordering::SeqCst);
+    for slot in &TEMP_PATHS {
+        let path = slot.load(Ordering::SeqCst);
+        if !path.is_null() {
+            unsafe { libc::unlink(path) };
+        }
+    }
+    unsafe { libc::_exit(128 + signal) };
+}
+
+/// Have SIGHUP ask for the log file to be reopened, as after a rotation, instead of exiting.
+pub fn reopen_on_hangup() {
+    unsafe { libc::signal(libc::SIGHUP, on_hangup as *const () as libc::sighandler_t) };
+}
+
+extern "C" fn on_hangup(_signal: libc::c_int) {
+    HUNG_UP.store(true, Ordering::SeqCst);
+}
+
+/// Whether there's been a SIGHUP since the last time this was asked.
+pub fn hung_up() -> bool {
+    HUNG_UP.swap(false, Ordering::SeqCst)
+}
+
+/// For `--watch`: the first SIGINT or SIGTERM lets what's under way finish, and a second
+/// exits as `install` would, and SIGHUP asks for the next check now, as well as for the log
+/// file to be reopened.
+pub fn stay_resident() {
+    for &signal in &[libc::SIGINT, libc::SIGTERM] {
+        unsafe { libc::signal(signal, on_stop as *const () as libc::sighandler_t) };
+    }
+    unsafe { libc::signal(libc::SIGHUP, on_wake as *const () as libc::sighandler_t) };
+}
+
+extern "C" fn on_stop(signal: libc::c_int) {
+    if STOPPING.swap(true, Ordering::SeqCst) {
+        on_signal(signal);
+    }
+}
+
+extern "C" fn on_wake(_signal: libc::c_int) {
+    HUNG_UP.store(true, Ordering::SeqCst);
+    WOKEN.store(true, Ordering::SeqCst);
+}
+
+/// Whether we've been asked to stop, after `stay_resident`.
+pub fn stopping() -> bool {
+    STOPPING.load(Ordering::SeqCst)
+}
+
+/// Whether there's been a SIGHUP since the last time this was asked, after `stay_resident`.
+pub fn woken() -> bool {
+    WOKEN.swap(false, Ordering::SeqCst)
+}
+
+/// A temporary file the signal handler will remove, until this is dropped: which must be
+/// before it's renamed into place, when it might be the output.
+pub struct Watched {
+    slot: usize,
+}
+
+impl Drop for Watched {
+    fn drop(&mut self) {
+        let path = TEMP_PATHS[self.slot].swap(ptr::null_mut(), Ordering::SeqCst);
+        // leaked if the handler is running, as it may be reading it right now
+        if !path.is_null() && !HANDLING.load(Ordering::SeqCst) {
+            drop(unsafe { CString::from_raw(path) });
+        }
+    }
+}
+
+/// Remember the temporary file's name, if it has one, for the signal handler.
+#[must_use]
+pub fn watch(temp: &tempfile_fast::PersistableTempFile) -> Option<Watched> {
+    use std::os::unix::ffi::OsStrExt;
+
+    // O_TMPFILE files have no name to leave behind
+    let path = match temp {
+        tempfile_fast::PersistableTempFile::Fallback(named) => named.path(),
+        tempfile_fast::PersistableTempFile::Linux(_) => return None,
+    };
+
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?.into_raw();
+    for (slot, free) in TEMP_PATHS.iter().enumerate() {
+        if free
+            .compare_exchange(ptr::null_mut(), path, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            return Some(Watched { slot });
+        }
+    }
+
+    // a kill now leaves this one for --clean-stale-temps
+    debug!("too many temporary files to remove them all if killed");
+    drop(unsafe { CString::from_raw(path) });
+    None
+}
+
+/// Whether a name is one that we give temporary files; and only ours, as the directory is
+/// shared with whatever else writes there.
+fn is_our_temp(name: &str) -> bool {
+    // `sink::temp_in`'s, with tempfile's six random characters
+    let named = match name.strip_prefix(sink::TEMP_PREFIX) {
+        Some(random) => random.len() == 6 && random.chars().all(|c| c.is_ascii_alphanumeric()),
+        None => false,
+    };
+
+    // backup's staging name, `OUTPUT.fetch-maybe-PID`
+    let backup = match name.rfind(".fetch-maybe-") {
+        Some(pos) => {
+            let pid = &name[pos + ".fetch-maybe-".len()..];
+            !pid.is_empty() && pid.chars().all(|c| c.is_ascii_digit())
+        }
+        None => false,
+    };
+
+    named || backup
+}
+
+/// Remove temporary files left in `dir` by runs that were killed outright, e.g. by SIGKILL.
+pub fn clean_stale_temps(dir: &Path) -> Result<(), failure::Error> {
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+
+    let entries =
+        fs::read_dir(dir).with_context(|_| format_err!("listing {:?} for stale temps", dir))?;
+
+    let now = SystemTime::now();
+    for entry in entries {
+        let entry = entry.with_context(|_| format_err!("listing {:?} for stale temps", dir))?;
+        let name = entry.file_name();
+        if !name.to_str().map(is_our_temp).unwrap_or(false) {
+            continue;
+        }
+
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        if !metadata.is_file() {
+            continue;
+        }
+
+        let age = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok());
+        if age.map(|age| age < STALE_AFTER).unwrap_or(true) {
+            continue;
+        }
+
+        match fs::remove_file(entry.path()) {
+            Ok(()) => info!("   clean-temps: removed stale {:?}", entry.path()),
+            Err(e) => warn!("couldn't remove stale {:?}: {}", entry.path(), e),
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_watch() {
+    let dir = tempfile::tempdir().unwrap();
+    let named = || {
+        tempfile_fast::PersistableTempFile::Fallback(tempfile::NamedTempFile::new_in(&dir).unwrap())
+    };
+    let watching = || {
+        TEMP_PATHS
+            .iter()
+            .filter(|slot| !slot.load(Ordering::SeqCst).is_null())
+    };
+
+    let (first, second) = (named(), named());
+    let watched = (watch(&first), watch(&second));
+    assert!(watched.0.is_some() && watched.1.is_some());
+    assert!(watching().count() >= 2);
+
+    // each is forgotten on its own, and the slot's free for the next
+    let slot = watched.0.as_ref().unwrap().slot;
+    drop(watched.0);
+    assert!(TEMP_PATHS[slot].load(Ordering::SeqCst).is_null());
+    assert!(!TEMP_PATHS[watched.1.as_ref().unwrap().slot]
+        .load(Ordering::SeqCst)
+        .is_null());
+}
+
+#[test]
+fn test_is_our_temp() {
+    assert!(is_our_temp(".fetch-maybe-tmpAb12Cd"));
+    assert!(is_our_temp("out.tar.gz.fetch-maybe-1234"));
+
+    // others' temporary files, even tempfile's
+    assert!(!is_our_temp(".tmpAb12Cd"));
+    assert!(!is_our_temp(".1f3a9c0d2e4b5a6f.tmp"));
+    assert!(!is_our_temp(".fetch-maybe-tmp"));
+    assert!(!is_our_temp(".fetch-maybe-tmpAb12Cd.part"));
+    assert!(!is_our_temp("notes.tmp"));
+    assert!(!is_our_temp(".config.tmp"));
+    assert!(!is_our_temp("out.fetch-maybe-"));
+    assert!(!is_our_temp("out.tar.gz"));
+}
@@ -0,0 +1,276 @@
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+
+use failure::bail;
+use failure::format_err;
+use failure::ResultExt;
+use log::debug;
+use log::info;
+use tempfile_fast::PersistableTempFile;
+
+use crate::exit;
+
+/// Where the body goes: usually a temporary file, to be renamed over the output.
+pub enum Sink {
+    Temp(tempfile_fast::PersistableTempFile),
+    Stdout(io::Stdout),
+    /// From `--output-fd`; whoever passed it to us decides what becomes of it.
+    Fd(fs::File),
+    /// `--discard`: only counted, and checked against any digests and sizes.
+    Discard(io::Sink),
+    /// An output that's a FIFO, a device, or anything else that isn't a file: written into as
+    /// it is, as there's no replacing it.
+    Special(fs::File),
+}
+
+/// Whether `output` is there, and is neither a file nor a directory.
+pub fn is_special(output: &Path) -> bool {
+    fs::metadata(output).is_ok_and(|m| !m.is_file() && !m.is_dir())
+}
+
+/// Opened for writing, but not truncated, which a device might not take to; a FIFO waits here
+/// for something to read it.
+pub fn open_special(output: &Path) -> Result<fs::File, failure::Error> {
+    let fifo = fs::metadata(output).is_ok_and(|m| m.file_type().is_fifo());
+    if fifo {
+        info!("        output: waiting for {:?} to have a reader", output);
+    }
+    Ok(fs::OpenOptions::new()
+        .write(true)
+        .open(output)
+        .with_context(|_| format_err!("opening {:?}", output))?)
+}
+
+/// What reading a `Special` output stopped.
+#[derive(Debug)]
+pub struct ReaderGone;
+
+impl fmt::Display for ReaderGone {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "whatever was reading the output went away (broken pipe)")
+    }
+}
+
+impl Error for ReaderGone {}
+
+/// Whether `e` is a `ReaderGone`; there's no resuming into a pipe that's been closed.
+pub fn is_reader_gone(e: &io::Error) -> bool {
+    e.get_ref().is_some_and(|e| e.is::<ReaderGone>())
+}
+
+/// The output that `--discard` stands for, and that's discarded in the same way when given.
+pub const DISCARD: &str = "/dev/null";
+
+/// Flags which only mean anything when there's an output file, which stdout isn't.
+pub const FILE_ONLY: &[&str] = &[
+    "also-copy",
+    "also-link",
+    "append",
+    "backup",
+    "chown",
+    "clean-stale-temps",
+    "compare-url",
+    "content-disposition",
+    "create-dirs",
+    "diff",
+    "extract",
+    "fsync",
+    "in-place",
+    "keep-partial",
+    "keep-versions",
+    "max-shrink",
+    "min-age",
+    "min-free",
+    "min-size",
+    "mode",
+    "mtime-from",
+    "no-clobber",
+    "no-mtime",
+    "no-preflight",
+    "no-preserve",
+    "no-preserve-xattr",
+    "on-change",
+    "on-change-failure",
+    "on-conflict",
+    "on-unchanged",
+    "output-symlink",
+    "paranoid",
+    "preallocate",
+    "preserve-xattrs",
+    "reference-time",
+    "rename-retries",
+    "rename-retry-for",
+    "store",
+    "temp-dir",
+    "validate-cmd",
+];
+
+/// Take ownership of a descriptor we were started with, checking it's open for writing.
+pub fn inherited_fd(arg: &str) -> Result<fs::File, failure::Error> {
+    use std::os::unix::io::FromRawFd;
+
+    let fd = arg
+        .parse::<i32>()
+        .with_context(|_| format_err!("parsing --output-fd {:?}", arg))?;
+
+    if fd < 0 {
+        bail!("--output-fd must not be negative: {}", fd);
+    }
+
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 {
+        Err(io::Error::last_os_error())
+            .with_context(|_| format_err!("--output-fd {} isn't usable", fd))?;
+    }
+
+    if libc::O_RDONLY == flags & libc::O_ACCMODE {
+        bail!("--output-fd {} is open read-only", fd);
+    }
+
+    // it's ours now: nothing else in this process knows about it
+    Ok(unsafe { fs::File::from_raw_fd(fd) })
+}
+
+/// What our named temporary files are called, before six random characters; for
+/// `--clean-stale-temps` to tell them from anyone else's.
+pub const TEMP_PREFIX: &str = ".fetch-maybe-tmp";
+
+/// A temporary file in `dir`, to be renamed over something there; failing, saying why in terms
+/// of the directory, as "No such file or directory" alone doesn't say which.
+pub fn temp_in(dir: &Path) -> Result<PersistableTempFile, failure::Error> {
+    new_temp_in(dir).map_err(|e| {
+        let shown = match env::current_dir() {
+            Ok(cwd) if dir.is_relative() => cwd.join(dir),
+            _ => dir.to_path_buf(),
+        };
+        match why_no_temp(&e) {
+            Some(why) => exit::classified(
+                exit::Kind::Filesystem,
+                format!(
+                    "can't create a temporary file in {:?}: {} ({})",
+                    shown, why, e
+                ),
+            ),
+            None => failure::Error::from(e)
+                .context(format!("creating temporary file in {:?}", shown))
+                .into(),
+        }
+    })
+}
+
+fn new_temp_in(dir: &Path) -> io::Result<PersistableTempFile> {
+    Ok(match PersistableTempFile::new_in(dir)? {
+        // tempfile's own names are too like everyone else's
+        PersistableTempFile::Fallback(_) => PersistableTempFile::Fallback(
+            tempfile::Builder::new()
+                .prefix(TEMP_PREFIX)
+                .tempfile_in(dir)?,
+        ),
+        linux => linux,
+    })
+}
+
+fn why_no_temp(e: &io::Error) -> Option<&'static str> {
+    // not the errno: tempfile's fallback hides it behind the path it tried, but keeps the kind
+    Some(match e.kind() {
+        io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded => {
+            "its filesystem is full, or the quota is"
+        }
+        io::ErrorKind::PermissionDenied => "we aren't allowed to write there",
+        io::ErrorKind::ReadOnlyFilesystem => "its filesystem is read-only",
+        io::ErrorKind::NotFound => "it doesn't exist",
+        io::ErrorKind::NotADirectory => "part of it isn't a directory",
+        _ => return None,
+    })
+}
+
+impl Sink {
+    /// The temporary file, if there is one.
+    pub fn file(&self) -> Option<&fs::File> {
+        match self {
+            Sink::Temp(temp) => Some(temp.as_ref()),
+            Sink::Stdout(_) | Sink::Fd(_) | Sink::Discard(_) | Sink::Special(_) => None,
+        }
+    }
+}
+
+/// Reserve `len` bytes for the download up front, so a full disk fails now, not hours in.
+///
+/// `how` is `fallocate`, or `truncate` which only sets the size, for filesystems where that's
+/// all there is. Filesystems which support neither just get written to normally.
+pub fn preallocate(file: &fs::File, len: u64, how: &str) -> Result<(), failure::Error> {
+    use std::os::unix::io::AsRawFd;
+
+    let result = match how {
+        "fallocate" => {
+            let len = len as libc::off_t;
+            if 0 == unsafe { libc::fallocate(file.as_raw_fd(), 0, 0, len) } {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        }
+        "truncate" => file.set_len(len),
+        other => unreachable!("clap validated: {:?}", other),
+    };
+
+    match result {
+        Ok(()) => {
+            debug!("   preallocate: reserved {} bytes with {}", len, how);
+            Ok(())
+        }
+        Err(ref e) if Some(libc::ENOSPC) == e.raw_os_error() => bail!(
+            "not enough disk space for the {} byte download, see --preallocate",
+            len
+        ),
+        Err(e) => {
+            debug!("   preallocate: unsupported, writing normally: {}", e);
+            Ok(())
+        }
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Sink::Temp(temp) => temp.write(buf),
+            Sink::Stdout(stdout) => stdout.write(buf),
+            Sink::Fd(file) => file.write(buf),
+            Sink::Discard(sink) => sink.write(buf),
+            Sink::Special(file) => file.write(buf).map_err(|e| match e.kind() {
+                io::ErrorKind::BrokenPipe => io::Error::new(e.kind(), ReaderGone),
+                _ => e,
+            }),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Sink::Temp(temp) => temp.flush(),
+            Sink::Stdout(stdout) => stdout.flush(),
+            Sink::Fd(file) => file.flush(),
+            Sink::Discard(sink) => sink.flush(),
+            Sink::Special(file) => file.flush(),
+        }
+    }
+}
+
+#[test]
+fn test_why_no_temp() {
+    let why = |errno| why_no_temp(&io::Error::from_raw_os_error(errno));
+    assert_eq!(Some("its filesystem is read-only"), why(libc::EROFS));
+    assert_eq!(
+        Some("its filesystem is full, or the quota is"),
+        why(libc::ENOSPC)
+    );
+    assert_eq!(Some("we aren't allowed to write there"), why(libc::EACCES));
+    assert_eq!(Some("it doesn't exist"), why(libc::ENOENT));
+    assert_eq!(None, why(libc::EIO));
+    assert_eq!(None, why_no_temp(&io::Error::other("odd")));
+}
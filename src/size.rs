@@ -0,0 +1,56 @@
+use failure::bail;
+use failure::format_err;
+
+/// Parse a byte count, with an optional binary suffix: `512`, `64k`, `4M`, `1g`.
+pub fn parse_size(s: &str) -> Result<u64, failure::Error> {
+    let s = s.trim();
+    let (num, multiplier) = match s.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&s[..s.len() - 1], 1 << 10),
+        Some('M') => (&s[..s.len() - 1], 1 << 20),
+        Some('G') => (&s[..s.len() - 1], 1 << 30),
+        Some('T') => (&s[..s.len() - 1], 1 << 40),
+        Some(c) if c.is_ascii_digit() => (s, 1),
+        _ => bail!("can't parse as a size: {:?}", s),
+    };
+
+    let num: u64 = num
+        .parse()
+        .map_err(|_| format_err!("can't parse as a size: {:?}", s))?;
+
+    num.checked_mul(multiplier)
+        .ok_or_else(|| format_err!("size is too large: {:?}", s))
+}
+
+/// The other way, to a tenth, with the suffixes `parse_size` takes: `512`, `1.5k`, `4.0M`.
+pub fn format_size(bytes: u64) -> String {
+    let mut value = bytes as f64;
+    for suffix in &["", "k", "M", "G"] {
+        if value < 1024.0 {
+            return match *suffix {
+                "" => bytes.to_string(),
+                suffix => format!("{:.1}{}", value, suffix),
+            };
+        }
+        value /= 1024.0;
+    }
+    format!("{:.1}T", value)
+}
+
+#[test]
+fn test_format_size() {
+    assert_eq!("512", format_size(512));
+    assert_eq!("1.5k", format_size(1536));
+    assert_eq!("4.0M", format_size(4 << 20));
+    assert_eq!("2048.0T", format_size(2 << 50));
+}
+
+#[test]
+fn test_parse_size() {
+    assert_eq!(512, parse_size("512").unwrap());
+    assert_eq!(64 * 1024, parse_size("64k").unwrap());
+    assert_eq!(4 * 1024 * 1024, parse_size("4M").unwrap());
+    assert!(parse_size("").is_err());
+    assert!(parse_size("k").is_err());
+    assert!(parse_size("1.5m").is_err());
+    assert!(parse_size("99999999999999t").is_err());
+}
@@ -0,0 +1,109 @@
+use std::fs;
+use std::io;
+use std::io::Write;
+
+use failure::bail;
+use failure::err_msg;
+use failure::ResultExt;
+use log::debug;
+
+use crate::sink::Sink;
+
+/// How much is written between checks of the free space, when the length wasn't known up front.
+const CHECK_EVERY: u64 = 16 << 20;
+
+/// Bytes free to us on the filesystem holding `file`; root may also use the reserved blocks.
+pub fn available(file: &fs::File) -> io::Result<u64> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if 0 != unsafe { libc::fstatvfs(file.as_raw_fd(), &mut stat) } {
+        return Err(io::Error::last_os_error());
+    }
+
+    let blocks = if 0 == unsafe { libc::geteuid() } {
+        stat.f_bfree
+    } else {
+        stat.f_bavail
+    };
+
+    Ok((blocks as u64).saturating_mul(stat.f_frsize as u64))
+}
+
+/// Fail before writing anything if `need` bytes, plus the `--min-free` reserve, won't fit.
+pub fn check(file: &fs::File, need: u64, min_free: u64) -> Result<(), failure::Error> {
+    let have = available(file).with_context(|_| err_msg("checking free disk space"))?;
+    debug!("    disk space: need {} bytes, have {}", need, have);
+    if have < need.saturating_add(min_free) {
+        if 0 == min_free {
+            bail!("not enough disk space: need {} bytes, have {}", need, have);
+        }
+        bail!(
+            "not enough disk space: need {} bytes, have {}, and --min-free keeps {}",
+            need,
+            have,
+            min_free
+        );
+    }
+    Ok(())
+}
+
+/// Stops the download if writing it would leave less than `min_free` on the disk.
+///
+/// For bodies whose length we couldn't check up front; it only looks every few megabytes.
+pub struct Reserve {
+    inner: Sink,
+    min_free: Option<u64>,
+    unchecked: u64,
+}
+
+impl Reserve {
+    pub fn new(inner: Sink, min_free: Option<u64>) -> Reserve {
+        Reserve {
+            inner,
+            min_free,
+            unchecked: 0,
+        }
+    }
+
+    pub fn into_inner(self) -> Sink {
+        self.inner
+    }
+}
+
+impl Write for Reserve {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let (Some(min_free), Some(file)) = (self.min_free, self.inner.file()) {
+            if self.unchecked >= CHECK_EVERY {
+                self.unchecked = 0;
+                let have = available(file)?;
+                if have < min_free {
+                    return Err(io::Error::other(format!(
+                        "only {} bytes of disk space left, less than --min-free {}",
+                        have, min_free
+                    )));
+                }
+            }
+        }
+
+        let written = self.inner.write(buf)?;
+        self.unchecked += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[test]
+fn test_check() {
+    let file = tempfile::tempfile().unwrap();
+    let have = available(&file).unwrap();
+    assert!(have > 0);
+    check(&file, 1, 0).unwrap();
+    let err = check(&file, u64::MAX, 0).unwrap_err().to_string();
+    assert!(err.contains("need"), "{}", err);
+    let err = check(&file, 1, u64::MAX - 1).unwrap_err().to_string();
+    assert!(err.contains("--min-free"), "{}", err);
+}
@@ -0,0 +1,123 @@
+use std::time::Duration;
+
+use crate::outcome;
+use crate::outcome::Outcome;
+use crate::size;
+
+/// One line saying how a run went: what happened, the status, how much at what rate, and
+/// where the time went, then whether the output changed. Only what it got as far as is said.
+///
+/// The connection is made inside ureq's request, so its time is part of the first byte's.
+pub fn line(outcome: &Outcome, succeeded: bool) -> String {
+    let mut parts = vec![if succeeded {
+        outcome.kind.name().to_string()
+    } else {
+        format!("failed while {}", outcome.phase)
+    }];
+    if let Some(status) = outcome.status {
+        parts.push(status.to_string());
+    }
+
+    let transfer = match (outcome.elapsed, outcome.first_byte) {
+        (Some(elapsed), Some(first_byte)) => Some(elapsed.saturating_sub(first_byte)),
+        _ => None,
+    };
+    if let Some(bytes) = outcome.bytes {
+        match transfer {
+            Some(transfer) if transfer > Duration::from_millis(0) => parts.push(format!(
+                "{} bytes at {}/s",
+                size::format_size(bytes),
+                size::format_size((bytes as f64 / transfer.as_secs_f64()) as u64)
+            )),
+            _ => parts.push(format!("{} bytes", size::format_size(bytes))),
+        }
+    }
+    if let Some(buffer) = outcome.buffer {
+        parts.push(format!("{} buffer", size::format_size(buffer as u64)));
+    }
+    let lookups = outcome.lookups;
+    if lookups.hits + lookups.misses > 0 {
+        parts.push(format!(
+            "{} of {} lookups cached",
+            lookups.hits,
+            lookups.hits + lookups.misses
+        ));
+    }
+
+    if let Some(elapsed) = outcome.elapsed {
+        parts.push(match (outcome.first_byte, transfer) {
+            (Some(first_byte), Some(transfer)) if outcome::Kind::Fetched == outcome.kind => {
+                format!(
+                    "{:.2}s ({:.2}s to the first byte, {:.2}s transferring)",
+                    elapsed.as_secs_f64(),
+                    first_byte.as_secs_f64(),
+                    transfer.as_secs_f64()
+                )
+            }
+            (Some(first_byte), _) => format!(
+                "{:.2}s ({:.2}s to the first byte)",
+                elapsed.as_secs_f64(),
+                first_byte.as_secs_f64()
+            ),
+            (None, _) => format!("{:.2}s", elapsed.as_secs_f64()),
+        });
+    }
+
+    parts.push(
+        if outcome.changed() {
+            "changed"
+        } else {
+            "unchanged"
+        }
+        .to_string(),
+    );
+    parts.join(", ")
+}
+
+#[test]
+fn test_line() {
+    let mut outcome = Outcome {
+        kind: outcome::Kind::Fetched,
+        status: Some(200),
+        bytes: Some(3 << 20),
+        first_byte: Some(Duration::from_millis(500)),
+        elapsed: Some(Duration::from_millis(2500)),
+        buffer: Some(128 << 10),
+        ..Outcome::default()
+    };
+    assert_eq!(
+        "fetched, 200, 3.0M bytes at 1.5M/s, 128.0k buffer, 2.50s (0.50s to the first byte, 2.00s transferring), changed",
+        line(&outcome, true)
+    );
+
+    outcome.kind = outcome::Kind::Unchanged;
+    outcome.status = Some(304);
+    outcome.bytes = None;
+    outcome.buffer = None;
+    outcome.elapsed = Some(Duration::from_millis(500));
+    assert_eq!(
+        "unchanged, 304, 0.50s (0.50s to the first byte), unchanged",
+        line(&outcome, true)
+    );
+
+    let skipped = Outcome {
+        elapsed: Some(Duration::from_millis(1)),
+        ..Outcome::default()
+    };
+    assert_eq!("skipped, 0.00s, unchanged", line(&skipped, true));
+
+    let cached = Outcome {
+        lookups: crate::dns::Lookups { hits: 1, misses: 1 },
+        ..Outcome::default()
+    };
+    assert_eq!(
+        "skipped, 1 of 2 lookups cached, unchanged",
+        line(&cached, true)
+    );
+
+    let failed = Outcome {
+        phase: "requesting",
+        ..Outcome::default()
+    };
+    assert_eq!("failed while requesting, unchanged", line(&failed, false));
+}
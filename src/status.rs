@@ -0,0 +1,285 @@
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::time::SystemTime;
+
+use chrono::DateTime;
+use chrono::Utc;
+use failure::format_err;
+use failure::ResultExt;
+use log::warn;
+
+use crate::cache;
+use crate::exit;
+use crate::json::string;
+use crate::metrics;
+use crate::period;
+use crate::timestamp;
+
+/// How long `--remote`'s HEAD may take to connect, and then to answer.
+const REMOTE_TIMEOUT_MS: u64 = 30_000;
+
+/// What's known of one thing about the output: said one way for people, and as JSON.
+struct Row {
+    key: &'static str,
+    text: Option<String>,
+    json: String,
+}
+
+#[derive(Default)]
+struct Rows {
+    rows: Vec<Row>,
+}
+
+impl Rows {
+    fn add(&mut self, key: &'static str, value: Option<(String, String)>) {
+        let (text, json) = match value {
+            Some((text, json)) => (Some(text), json),
+            None => (None, "null".to_string()),
+        };
+        self.rows.push(Row { key, text, json });
+    }
+
+    fn string(&mut self, key: &'static str, value: Option<&str>) {
+        self.add(key, value.map(|v| (v.to_string(), string(v))));
+    }
+
+    fn time(&mut self, key: &'static str, time: Option<DateTime<Utc>>, now: DateTime<Utc>) {
+        self.add(
+            key,
+            time.map(|time| {
+                let shown = time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+                let relative = if time <= now {
+                    format!("{} ago", period::format_duration(now - time))
+                } else {
+                    format!("in {}", period::format_duration(time - now))
+                };
+                (format!("{} ({})", shown, relative), string(&shown))
+            }),
+        );
+    }
+}
+
+/// `status [URL] OUTPUT`: everything that's known of the output, without the network: its age,
+/// and for a `--cache-dir` entry, what its `.meta` recorded of the response, and whether that's
+/// still fresh; with `--metrics-file`, when it was last run, and last worked; and what a run
+/// would decide, with `--min-age` if it's given one.
+///
+/// With `--remote`, the URL, given or recorded, is also asked for its headers, to say whether
+/// it looks newer. Lines of `KEY: VALUE`, or with `--json`, an object of them, `null` for what
+/// isn't known. Freshness or not, it's only a report, so it's a success.
+pub fn run(matches: &clap::ArgMatches) -> Result<(), failure::Error> {
+    let (url, output) = match matches.value_of_os("output") {
+        Some(output) => (matches.value_of_os("url"), Path::new(output)),
+        None => (
+            None,
+            Path::new(matches.value_of_os("url").expect("required")),
+        ),
+    };
+    let url = match url {
+        Some(url) => Some(
+            url.to_str()
+                .ok_or_else(|| {
+                    exit::classified(exit::Kind::Usage, format!("URL isn't UTF-8: {:?}", url))
+                })?
+                .to_string(),
+        ),
+        None => None,
+    };
+    let min_age = match matches.value_of("min-age") {
+        Some(v) => Some(period::parse_duration(v)?),
+        None => None,
+    };
+    let now = Utc::now();
+
+    let metadata = match fs::metadata(output) {
+        Ok(metadata) => Some(metadata),
+        Err(ref e) if io::ErrorKind::NotFound == e.kind() => None,
+        Err(e) => Err(e).with_context(|_| format_err!("reading {:?}'s info", output))?,
+    };
+    let mtime = metadata
+        .as_ref()
+        .and_then(|m| m.modified().ok())
+        .map(DateTime::<Utc>::from);
+    let meta = cache::meta(output);
+    let url = url.or_else(|| meta.as_ref().map(|meta| meta.url.clone()));
+    let recorded = |field: fn(&cache::Meta) -> &Option<String>| {
+        meta.as_ref().and_then(|meta| field(meta).as_deref())
+    };
+    let parsed = |field: fn(&cache::Meta) -> &Option<String>| {
+        recorded(field)
+            .and_then(|v| DateTime::parse_from_rfc3339(v).ok())
+            .map(|t| t.with_timezone(&Utc))
+    };
+
+    let mut rows = Rows::default();
+    rows.string("output", Some(&output.to_string_lossy()));
+    rows.add(
+        "size",
+        metadata
+            .as_ref()
+            .map(|m| (format!("{} bytes", m.len()), m.len().to_string())),
+    );
+    rows.time("modified", mtime, now);
+    rows.string("url", url.as_deref());
+    rows.string("final_url", recorded(|m| &m.final_url));
+    rows.string("etag", recorded(|m| &m.etag));
+    rows.time("last_modified", parsed(|m| &m.last_modified), now);
+    let expires = parsed(|m| &m.expires);
+    rows.time("expires", expires, now);
+    rows.add(
+        "expired",
+        expires.map(|expires| {
+            let expired = expires <= now;
+            let text = if expired { "yes" } else { "no" };
+            (text.to_string(), expired.to_string())
+        }),
+    );
+    rows.time("fetched", parsed(|m| &m.fetched), now);
+
+    let last = match matches.value_of_os("metrics-file") {
+        Some(path) => match fs::read_to_string(path) {
+            Ok(text) => Some(metrics::last(&text)),
+            Err(ref e) if io::ErrorKind::NotFound == e.kind() => None,
+            Err(e) => Err(e).with_context(|_| format_err!("reading {:?}", path))?,
+        },
+        None => None,
+    };
+    let seconds = |secs: Option<f64>| {
+        secs.and_then(|secs| DateTime::<Utc>::from_timestamp_millis((secs * 1000.0) as i64))
+    };
+    rows.time("last_run", seconds(last.as_ref().and_then(|l| l.run)), now);
+    rows.time(
+        "last_success",
+        seconds(last.as_ref().and_then(|l| l.success)),
+        now,
+    );
+    rows.string(
+        "last_outcome",
+        last.as_ref().and_then(|l| l.outcome.as_deref()),
+    );
+
+    let (decision, reason) = decide(mtime, now, min_age, recorded(|m| &m.etag).is_some());
+    rows.string("decision", Some(decision));
+    rows.string("reason", Some(&reason));
+
+    if matches.is_present("remote") {
+        let url = url.as_deref().ok_or_else(|| {
+            exit::classified(
+                exit::Kind::Usage,
+                format!(
+                    "--remote needs a URL, and {:?} doesn't record one; give it",
+                    output
+                ),
+            )
+        })?;
+        let (status, newer) = remote(url, recorded(|m| &m.etag), mtime);
+        rows.string("remote_status", Some(&status));
+        rows.add(
+            "remote_newer",
+            newer.map(|(newer, why)| {
+                let text = if newer { "yes" } else { "no" };
+                (format!("{}, {}", text, why), newer.to_string())
+            }),
+        );
+    }
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    if matches.is_present("json") {
+        let fields: Vec<String> = rows
+            .rows
+            .iter()
+            .map(|row| format!("{}:{}", string(row.key), row.json))
+            .collect();
+        writeln!(stdout, "{{{}}}", fields.join(","))?;
+    } else {
+        for row in &rows.rows {
+            if let Some(text) = &row.text {
+                writeln!(stdout, "{:>14}: {}", row.key.replace('_', " "), text)?;
+            }
+        }
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+/// What a run would do, as its `decision` event would say, and why; taking the output's
+/// times, and any ETag recorded for it, as a run does by default.
+fn decide(
+    mtime: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+    min_age: Option<chrono::Duration>,
+    etag: bool,
+) -> (&'static str, String) {
+    let mtime = match mtime {
+        Some(mtime) => mtime,
+        None => return ("unconditional", "there's no output".to_string()),
+    };
+    if let Some(min_age) = min_age {
+        if now - mtime < min_age {
+            return (
+                "skip",
+                format!(
+                    "min-age: it's been {}, of {}",
+                    period::format_duration(now - mtime),
+                    period::format_duration(min_age)
+                ),
+            );
+        }
+    }
+    let headers = if etag {
+        "If-None-Match and If-Modified-Since"
+    } else {
+        "If-Modified-Since"
+    };
+    ("conditional", format!("with {}", headers))
+}
+
+/// The status line of a HEAD of `url`, and whether what's there looks newer than the output,
+/// and why, if that can be told.
+fn remote(
+    url: &str,
+    etag: Option<&str>,
+    mtime: Option<DateTime<Utc>>,
+) -> (String, Option<(bool, &'static str)>) {
+    let response = ureq::head(url)
+        .timeout_connect(REMOTE_TIMEOUT_MS)
+        .timeout_read(REMOTE_TIMEOUT_MS)
+        .call();
+    if let Some(e) = response.synthetic_error() {
+        warn!("--remote: HEAD {}: {}", url, e);
+        return (format!("failed: {}", e), None);
+    }
+    let status = response.status_line().to_string();
+    if !response.ok() {
+        return (status, None);
+    }
+    let newer = match (etag, response.header("ETag"), mtime) {
+        (_, _, None) => Some((true, "as there's no output")),
+        (Some(ours), Some(theirs), _) => Some((ours.trim() != theirs.trim(), "by its ETag")),
+        (_, _, Some(mtime)) => response
+            .header("Last-Modified")
+            .and_then(|v| DateTime::parse_from_rfc2822(v).ok())
+            .map(|server| {
+                let newer = timestamp::server_is_newer(SystemTime::from(server), mtime);
+                (newer, "by its Last-Modified")
+            }),
+    };
+    (status, newer)
+}
+
+#[test]
+fn test_decide() {
+    let now = Utc::now();
+    let hour = chrono::Duration::hours(1);
+    assert_eq!("unconditional", decide(None, now, None, false).0);
+    assert_eq!(
+        "skip",
+        decide(Some(now - hour), now, Some(hour * 2), false).0
+    );
+    let (decision, reason) = decide(Some(now - hour * 3), now, Some(hour * 2), true);
+    assert_eq!("conditional", decision);
+    assert!(reason.contains("If-None-Match"), "{}", reason);
+}
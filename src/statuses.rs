@@ -0,0 +1,64 @@
+use failure::bail;
+use failure::format_err;
+
+/// A set of status codes, for `--ok-status`: `200,203`, `200-299`, or a mix.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Statuses(Vec<(u16, u16)>);
+
+impl Default for Statuses {
+    /// Any 2xx.
+    fn default() -> Statuses {
+        Statuses(vec![(200, 299)])
+    }
+}
+
+impl Statuses {
+    pub fn parse(s: &str) -> Result<Statuses, failure::Error> {
+        let code = |v: &str| -> Result<u16, failure::Error> {
+            let code = v
+                .trim()
+                .parse::<u16>()
+                .map_err(|_| format_err!("expected a status code, not {:?}", v))?;
+            if !(100..=599).contains(&code) {
+                bail!("{} isn't a status code: they're from 100 to 599", code);
+            }
+            Ok(code)
+        };
+        let mut ranges = Vec::new();
+        for item in s.split(',') {
+            let range = match item.split_once('-') {
+                Some((from, to)) => (code(from)?, code(to)?),
+                None => (code(item)?, code(item)?),
+            };
+            if range.0 > range.1 {
+                bail!("{:?} is backwards", item.trim());
+            }
+            ranges.push(range);
+        }
+        Ok(Statuses(ranges))
+    }
+
+    pub fn contains(&self, status: u16) -> bool {
+        self.0
+            .iter()
+            .any(|(from, to)| (*from..=*to).contains(&status))
+    }
+}
+
+#[test]
+fn test_parse() {
+    let statuses = Statuses::parse("200, 203,207-209").unwrap();
+    assert!(statuses.contains(200));
+    assert!(!statuses.contains(201));
+    assert!(statuses.contains(203));
+    assert!(statuses.contains(208));
+    assert!(!statuses.contains(210));
+
+    assert!(Statuses::default().contains(204));
+    assert!(!Statuses::default().contains(304));
+
+    assert!(Statuses::parse("200,").is_err());
+    assert!(Statuses::parse("299-200").is_err());
+    assert!(Statuses::parse("99").is_err());
+    assert!(Statuses::parse("ok").is_err());
+}
@@ -0,0 +1,208 @@
+use base64::Engine as _;
+use failure::bail;
+use log::debug;
+use log::warn;
+
+use crate::digest;
+use crate::digest::Digest;
+
+/// What an object store says about the object's content, in its response headers.
+#[derive(Debug, Default, PartialEq)]
+pub struct Claims {
+    pub sha256: Option<[u8; 32]>,
+    pub md5: Option<[u8; 16]>,
+    pub crc32c: Option<u32>,
+}
+
+impl Claims {
+    pub fn is_empty(&self) -> bool {
+        self.sha256.is_none() && self.md5.is_none() && self.crc32c.is_none()
+    }
+
+    /// Recognise S3's `x-amz-checksum-sha256`, GCS's `x-goog-hash`, and md5 ETags.
+    ///
+    /// An ETag is only trusted to be an md5 when the response otherwise looks like it's
+    /// from one of these stores; multipart uploads and KMS encryption make it something else.
+    pub fn from_headers<'r, F>(lookup: F) -> Claims
+    where
+        F: Fn(&str) -> Vec<&'r str>,
+    {
+        let mut claims = Claims::default();
+
+        for value in lookup("x-amz-checksum-sha256") {
+            claims.sha256 = decode("x-amz-checksum-sha256", value);
+        }
+
+        for value in lookup("x-goog-hash") {
+            for part in value.split(',') {
+                let mut kv = part.trim().splitn(2, '=');
+                let key = kv.next().unwrap_or_default();
+                let value = kv.next().unwrap_or_default();
+                match key {
+                    "crc32c" => {
+                        claims.crc32c = decode("x-goog-hash crc32c", value).map(u32::from_be_bytes)
+                    }
+                    "md5" => claims.md5 = decode("x-goog-hash md5", value),
+                    _ => debug!("       storage: ignoring x-goog-hash {:?}", key),
+                }
+            }
+        }
+
+        let from_store = !lookup("x-amz-request-id").is_empty()
+            || !lookup("x-amz-id-2").is_empty()
+            || !lookup("x-goog-generation").is_empty()
+            || !lookup("x-goog-hash").is_empty();
+
+        if claims.md5.is_none() && from_store {
+            if let Some(etag) = lookup("ETag").first() {
+                claims.md5 = etag_md5(etag, &lookup("x-amz-server-side-encryption"));
+            }
+        }
+
+        claims
+    }
+}
+
+fn etag_md5(etag: &str, encryption: &[&str]) -> Option<[u8; 16]> {
+    if etag.starts_with("W/") {
+        debug!("       storage: weak ETag, not an md5");
+        return None;
+    }
+
+    let etag = etag.trim_matches('"');
+
+    if let Some((hash, parts)) = etag.split_once('-') {
+        if is_hex(hash, 32) && !parts.is_empty() && parts.bytes().all(|b| b.is_ascii_digit()) {
+            debug!("       storage: multipart ETag, not an md5 of the content");
+            return None;
+        }
+    }
+
+    if encryption.iter().any(|e| e.starts_with("aws:kms")) {
+        debug!("       storage: KMS encrypted object; its ETag isn't an md5 of the content");
+        return None;
+    }
+
+    if !is_hex(etag, 32) {
+        return None;
+    }
+
+    let mut md5 = [0u8; 16];
+    for (i, byte) in md5.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&etag[i * 2..i * 2 + 2], 16).expect("checked hex");
+    }
+    Some(md5)
+}
+
+fn is_hex(s: &str, len: usize) -> bool {
+    s.len() == len && s.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+fn decode<const N: usize>(name: &str, value: &str) -> Option<[u8; N]> {
+    match base64::engine::general_purpose::STANDARD.decode(value.trim()) {
+        Ok(bytes) if bytes.len() == N => {
+            let mut out = [0u8; N];
+            out.copy_from_slice(&bytes);
+            Some(out)
+        }
+        _ => {
+            warn!("ignoring unparseable {}: {:?}", name, value);
+            None
+        }
+    }
+}
+
+/// Compare a download to the store's claims, listing every disagreement.
+pub fn verify(claims: &Claims, digest: &Digest) -> Result<(), failure::Error> {
+    let mut failures = Vec::new();
+
+    if let Some(claimed) = &claims.sha256 {
+        if claimed != &digest.sha256 {
+            failures.push(format!(
+                "sha256 claimed {}, downloaded {}",
+                digest::hex(claimed),
+                digest.sha256_hex()
+            ));
+        }
+    }
+
+    if let (Some(claimed), Some(actual)) = (&claims.md5, &digest.md5) {
+        if claimed != actual {
+            failures.push(format!(
+                "md5 claimed {}, downloaded {}",
+                digest::hex(claimed),
+                digest::hex(actual)
+            ));
+        }
+    }
+
+    if let (Some(claimed), Some(actual)) = (claims.crc32c, digest.crc32c) {
+        if claimed != actual {
+            failures.push(format!(
+                "crc32c claimed {:08x}, downloaded {:08x}",
+                claimed, actual
+            ));
+        }
+    }
+
+    if !failures.is_empty() {
+        bail!(
+            "storage checksum mismatch (see --no-verify-storage-checksums): {}",
+            failures.join("; ")
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_claims() {
+    let headers = |pairs: &'static [(&'static str, &'static str)]| {
+        move |name: &str| -> Vec<&'static str> {
+            pairs
+                .iter()
+                .filter(|(k, _)| k.eq_ignore_ascii_case(name))
+                .map(|(_, v)| *v)
+                .collect()
+        }
+    };
+
+    let gcs = Claims::from_headers(headers(&[
+        ("x-goog-hash", "crc32c=Nks/tw=="),
+        ("x-goog-hash", "md5=kAFQmDzST7DWlj99KOF/cg=="),
+        ("ETag", "\"ignored\""),
+    ]));
+    assert_eq!(Some(0x364b3fb7), gcs.crc32c);
+    assert_eq!(
+        "900150983cd24fb0d6963f7d28e17f72",
+        digest::hex(&gcs.md5.unwrap())
+    );
+
+    let s3 = Claims::from_headers(headers(&[
+        ("x-amz-request-id", "1"),
+        (
+            "x-amz-checksum-sha256",
+            "ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0=",
+        ),
+        ("ETag", "\"900150983cd24fb0d6963f7d28e17f72\""),
+    ]));
+    assert!(s3.sha256.is_some());
+    assert!(s3.md5.is_some());
+
+    let multipart = Claims::from_headers(headers(&[
+        ("x-amz-request-id", "1"),
+        ("ETag", "\"900150983cd24fb0d6963f7d28e17f72-12\""),
+    ]));
+    assert!(multipart.is_empty());
+
+    let kms = Claims::from_headers(headers(&[
+        ("x-amz-request-id", "1"),
+        ("x-amz-server-side-encryption", "aws:kms"),
+        ("ETag", "\"900150983cd24fb0d6963f7d28e17f72\""),
+    ]));
+    assert!(kms.is_empty());
+
+    let not_a_store =
+        Claims::from_headers(headers(&[("ETag", "\"900150983cd24fb0d6963f7d28e17f72\"")]));
+    assert!(not_a_store.is_empty());
+}
@@ -0,0 +1,128 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use failure::format_err;
+use failure::ResultExt;
+use log::debug;
+use log::info;
+
+use crate::backup;
+use crate::digest;
+use crate::restage::EXDEV;
+
+/// Where `--store` keeps the content with this sha256.
+pub fn entry(store: &Path, sha256: &[u8]) -> PathBuf {
+    store.join(digest::hex(sha256))
+}
+
+/// Whether `path` is already the same file as `entry`, so there's nothing to link.
+fn same_file(path: &Path, entry: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (fs::metadata(path), fs::metadata(entry)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+/// Atomically make `dest` a hard link to `source`, or, across filesystems, a copy of it if
+/// `copy` allows; `false` if it was left alone.
+fn replace_with(source: &Path, dest: &Path, copy: bool) -> Result<bool, failure::Error> {
+    let staging = backup::suffixed(dest, &format!(".fetch-maybe-{}", std::process::id()));
+    let _ = fs::remove_file(&staging);
+
+    match fs::hard_link(source, &staging) {
+        Ok(()) => (),
+        Err(ref e) if Some(EXDEV) == e.raw_os_error() && !copy => {
+            debug!(
+                "         store: {:?} is on another filesystem, leaving it be",
+                dest
+            );
+            return Ok(false);
+        }
+        Err(ref e) if Some(EXDEV) == e.raw_os_error() => {
+            debug!(
+                "         store: {:?} is on another filesystem, copying",
+                dest
+            );
+            fs::copy(source, &staging)
+                .with_context(|_| format_err!("copying {:?} to {:?}", source, staging))?;
+        }
+        Err(e) => Err(e).with_context(|_| format_err!("linking {:?} to {:?}", source, staging))?,
+    }
+
+    if let Err(e) = fs::rename(&staging, dest) {
+        let _ = fs::remove_file(&staging);
+        Err(e).with_context(|_| format_err!("moving {:?} into place", dest))?;
+    }
+    Ok(true)
+}
+
+/// Install the stored copy of `sha256` as `output`, without downloading it, if there is one.
+pub fn install(store: &Path, sha256: &[u8], output: &Path) -> Result<bool, failure::Error> {
+    let entry = entry(store, sha256);
+    if !entry.is_file() {
+        return Ok(false);
+    }
+
+    if !same_file(output, &entry) {
+        replace_with(&entry, output, true)?;
+    }
+    info!("         store: {:?} installed from {:?}", output, entry);
+    Ok(true)
+}
+
+/// After `output` is installed: share its content with any identical output, through the store.
+///
+/// New content is linked into the store; content that's already there replaces the output
+/// with a link to the stored copy, so all the outputs are one file, with one mode, owner and
+/// times, those of the first to be stored.
+pub fn add(store: &Path, sha256: &[u8], output: &Path) -> Result<(), failure::Error> {
+    let entry = entry(store, sha256);
+    if same_file(output, &entry) {
+        return Ok(());
+    }
+
+    match fs::symlink_metadata(&entry) {
+        // a copy of a copy would save nothing, so a download on another filesystem stays as it is
+        Ok(_) => {
+            if replace_with(&entry, output, false)? {
+                info!("         store: {:?} shares {:?}", output, entry);
+            }
+        }
+        Err(ref e) if io::ErrorKind::NotFound == e.kind() => {
+            replace_with(output, &entry, true)?;
+            info!("         store: added {:?}", entry);
+        }
+        Err(e) => Err(e).with_context(|_| format_err!("reading {:?}'s info", entry))?,
+    }
+    Ok(())
+}
+
+#[test]
+fn test_add() {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let store = dir.path().join("store");
+    fs::create_dir(&store).unwrap();
+    let sha256 = [7u8; 32];
+    let first = dir.path().join("first");
+    let second = dir.path().join("second");
+    fs::write(&first, "abc").unwrap();
+    fs::write(&second, "abc").unwrap();
+
+    assert!(!install(&store, &sha256, &dir.path().join("third")).unwrap());
+
+    add(&store, &sha256, &first).unwrap();
+    add(&store, &sha256, &second).unwrap();
+    let ino = |p: &Path| fs::metadata(p).unwrap().ino();
+    assert_eq!(ino(&first), ino(&entry(&store, &sha256)));
+    assert_eq!(ino(&first), ino(&second));
+
+    let third = dir.path().join("third");
+    assert!(install(&store, &sha256, &third).unwrap());
+    assert_eq!(ino(&first), ino(&third));
+}
@@ -0,0 +1,191 @@
+use std::io;
+use std::os::unix::net::UnixDatagram;
+
+use env_logger::filter::Filter;
+use log::kv;
+use log::Level;
+
+const IDENTIFIER: &str = "fetch-maybe";
+const SYSLOG_SOCKET: &str = "/dev/log";
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Where `--log-target` sends records.
+#[derive(Clone, Copy)]
+pub enum Target {
+    /// RFC 3164 datagrams to the local syslog socket.
+    Syslog,
+    /// The journal's native protocol, with the record's fields as `URL=`, `STATUS=` and so on.
+    Journald,
+}
+
+pub struct Logger {
+    target: Target,
+    socket: UnixDatagram,
+    filter: Filter,
+}
+
+impl Logger {
+    pub fn connect(target: Target, filter: Filter) -> io::Result<Logger> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(match target {
+            Target::Syslog => SYSLOG_SOCKET,
+            Target::Journald => JOURNAL_SOCKET,
+        })?;
+        Ok(Logger {
+            target,
+            socket,
+            filter,
+        })
+    }
+}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+        // the padding that lines up the labels on a terminal is just noise here
+        let message = record.args().to_string();
+        let message = message.trim_start();
+        let datagram = match self.target {
+            Target::Syslog => syslog_datagram(record.level(), message, std::process::id()),
+            Target::Journald => journal_datagram(record, message),
+        };
+        // nowhere to report a failure to log
+        let _ = self.socket.send(&datagram);
+    }
+
+    fn flush(&self) {}
+}
+
+/// The syslog severity for a level.
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// The local socket fills in the time and host; the facility is `user`.
+fn syslog_datagram(level: Level, message: &str, pid: u32) -> Vec<u8> {
+    const USER: u8 = 1;
+    format!(
+        "<{}>{}[{}]: {}",
+        USER * 8 + severity(level),
+        IDENTIFIER,
+        pid,
+        message
+    )
+    .into_bytes()
+}
+
+fn journal_datagram(record: &log::Record, message: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+    journal_field(&mut out, "PRIORITY", &severity(record.level()).to_string());
+    journal_field(&mut out, "SYSLOG_IDENTIFIER", IDENTIFIER);
+    journal_field(&mut out, "MESSAGE", message);
+    let mut fields = Vec::new();
+    // only fails if a visitor does, and ours doesn't
+    let _ = record.key_values().visit(&mut Fields(&mut fields));
+    for (key, value) in fields {
+        journal_field(&mut out, &key, &value);
+    }
+    out
+}
+
+/// `KEY=value`, or, if the value has a newline in it, the name, its length and then the value.
+fn journal_field(out: &mut Vec<u8>, key: &str, value: &str) {
+    out.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        out.push(b'\n');
+        out.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        out.push(b'=');
+    }
+    out.extend_from_slice(value.as_bytes());
+    out.push(b'\n');
+}
+
+/// The record's key-values, with the names as the journal wants them: `duration_ms` is
+/// `DURATION_MS`. Missing values are left out.
+struct Fields<'f>(&'f mut Vec<(String, String)>);
+
+impl<'kvs> kv::VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let key = key
+            .as_str()
+            .chars()
+            .map(|c| match c {
+                c if c.is_ascii_alphanumeric() => c.to_ascii_uppercase(),
+                _ => '_',
+            })
+            .collect();
+        let mut present = Present(None);
+        value.visit(&mut present)?;
+        if let Some(value) = present.0 {
+            self.0.push((key, value));
+        }
+        Ok(())
+    }
+}
+
+struct Present(Option<String>);
+
+impl<'v> kv::VisitValue<'v> for Present {
+    fn visit_any(&mut self, value: kv::Value) -> Result<(), kv::Error> {
+        self.0 = Some(value.to_string());
+        Ok(())
+    }
+
+    fn visit_null(&mut self) -> Result<(), kv::Error> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_syslog_datagram() {
+    assert_eq!(
+        b"<11>fetch-maybe[42]: request failed".to_vec(),
+        syslog_datagram(Level::Error, "request failed", 42)
+    );
+    assert_eq!(
+        b"<15>fetch-maybe[42]: x".to_vec(),
+        syslog_datagram(Level::Trace, "x", 42)
+    );
+}
+
+#[test]
+fn test_journal_datagram() {
+    let status: Option<u16> = Some(200);
+    let bytes: Option<u64> = None;
+    let kvs: &[(&str, kv::Value)] = &[
+        ("url", kv::ToValue::to_value("https://example.com/")),
+        ("status", kv::ToValue::to_value(&status)),
+        ("bytes", kv::ToValue::to_value(&bytes)),
+        ("duration_ms", kv::ToValue::to_value(&7u64)),
+    ];
+    let record = log::Record::builder()
+        .args(format_args!("ignored"))
+        .level(Level::Info)
+        .key_values(&kvs)
+        .build();
+    assert_eq!(
+        concat!(
+            "PRIORITY=6\nSYSLOG_IDENTIFIER=fetch-maybe\nMESSAGE=output: ready\n",
+            "URL=https://example.com/\nSTATUS=200\nDURATION_MS=7\n"
+        )
+        .as_bytes()
+        .to_vec(),
+        journal_datagram(&record, "output: ready")
+    );
+
+    let mut out = Vec::new();
+    journal_field(&mut out, "MESSAGE", "a\nb");
+    assert_eq!(b"MESSAGE\n\x03\0\0\0\0\0\0\0a\nb\n".to_vec(), out);
+}
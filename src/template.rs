@@ -0,0 +1,271 @@
+use std::ffi::OsStr;
+
+use chrono::DateTime;
+use chrono::Utc;
+use failure::bail;
+use failure::format_err;
+use url::Url;
+
+use crate::digest;
+use crate::output;
+
+/// The placeholders an output's file name can use, as `{name}` or `{name:argument}`.
+pub const SUPPORTED: &[&str] = &[
+    "url_basename",
+    "final_url_basename",
+    "etag",
+    "last_modified:FORMAT",
+    "sha256:LENGTH",
+];
+
+const DEFAULT_TIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// An output file name with placeholders, filled in from the request and response.
+#[derive(Debug, PartialEq)]
+pub struct Template {
+    pieces: Vec<Piece>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Piece {
+    Literal(String),
+    UrlBasename,
+    FinalUrlBasename,
+    Etag,
+    LastModified(String),
+    Sha256(usize),
+}
+
+/// What the placeholders can be filled in with, so far.
+#[derive(Default)]
+pub struct Values {
+    pub url_basename: Option<String>,
+    pub final_url_basename: Option<String>,
+    pub etag: Option<String>,
+    pub last_modified: Option<DateTime<Utc>>,
+    pub sha256: Option<[u8; 32]>,
+}
+
+impl Values {
+    /// From the request, and, once there is one, the final response's URL and headers.
+    pub fn new(
+        url: &Url,
+        final_url: Option<&Url>,
+        etag: Option<&str>,
+        last_modified: Option<&str>,
+    ) -> Values {
+        Values {
+            url_basename: output::name_from_url(url).ok(),
+            final_url_basename: final_url.and_then(|u| output::name_from_url(u).ok()),
+            etag: etag.map(|e| {
+                e.trim()
+                    .trim_start_matches("W/")
+                    .trim_matches('"')
+                    .to_string()
+            }),
+            last_modified: last_modified
+                .and_then(|l| DateTime::parse_from_rfc2822(l.trim()).ok())
+                .map(|l| l.with_timezone(&Utc)),
+            sha256: None,
+        }
+    }
+}
+
+/// Whether an output argument is a template, rather than a plain path.
+pub fn is_template(arg: &OsStr) -> bool {
+    arg.to_string_lossy().contains('{')
+}
+
+impl Template {
+    /// `{{` and `}}` are literal braces.
+    pub fn parse(name: &str) -> Result<Template, failure::Error> {
+        let mut pieces = Vec::new();
+        let mut literal = String::new();
+        let mut chars = name.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if Some(&'{') == chars.peek() => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if Some(&'}') == chars.peek() => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut spec = String::new();
+                    let mut closed = false;
+                    for c in chars.by_ref() {
+                        if '}' == c {
+                            closed = true;
+                            break;
+                        }
+                        spec.push(c);
+                    }
+                    if !closed {
+                        bail!("unclosed '{{' in output name {:?}", name);
+                    }
+                    if !literal.is_empty() {
+                        pieces.push(Piece::Literal(literal.split_off(0)));
+                    }
+                    pieces.push(Piece::parse(&spec)?);
+                }
+                '}' => bail!(
+                    "unmatched '}}' in output name {:?}, write '}}}}' for a brace",
+                    name
+                ),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(literal));
+        }
+        Ok(Template { pieces })
+    }
+
+    /// Whether it can't be filled in until the response headers arrive.
+    pub fn needs_response(&self) -> bool {
+        self.pieces
+            .iter()
+            .any(|p| !matches!(p, Piece::Literal(_) | Piece::UrlBasename))
+    }
+
+    /// Whether it can't be filled in until the whole body is hashed.
+    pub fn needs_body(&self) -> bool {
+        self.pieces.iter().any(|p| matches!(p, Piece::Sha256(_)))
+    }
+
+    pub fn render(&self, values: &Values) -> Result<String, failure::Error> {
+        let missing =
+            |what: &str| format_err!("the output name needs {}, but there isn't one", what);
+        let mut name = String::new();
+        for piece in &self.pieces {
+            let value = match piece {
+                Piece::Literal(literal) => {
+                    name.push_str(literal);
+                    continue;
+                }
+                Piece::UrlBasename => values
+                    .url_basename
+                    .clone()
+                    .ok_or_else(|| missing("the URL's file name"))?,
+                Piece::FinalUrlBasename => values
+                    .final_url_basename
+                    .clone()
+                    .ok_or_else(|| missing("the final URL's file name"))?,
+                Piece::Etag => values.etag.clone().ok_or_else(|| missing("an ETag"))?,
+                Piece::LastModified(format) => values
+                    .last_modified
+                    .ok_or_else(|| missing("a Last-Modified"))?
+                    .format(format)
+                    .to_string(),
+                Piece::Sha256(len) => {
+                    let hex = digest::hex(&values.sha256.ok_or_else(|| missing("a sha256"))?);
+                    hex[..*len].to_string()
+                }
+            };
+            // values come from the server, so mustn't be able to add directories
+            name.extend(value.chars().map(|c| match c {
+                '/' | '\\' => '_',
+                c if c.is_control() => '_',
+                c => c,
+            }));
+        }
+        Ok(name)
+    }
+}
+
+impl Piece {
+    fn parse(spec: &str) -> Result<Piece, failure::Error> {
+        let mut parts = spec.splitn(2, ':');
+        let name = parts.next().unwrap_or_default();
+        let arg = parts.next();
+        Ok(match (name, arg) {
+            ("url_basename", None) => Piece::UrlBasename,
+            ("final_url_basename", None) => Piece::FinalUrlBasename,
+            ("etag", None) => Piece::Etag,
+            ("last_modified", format) => {
+                let format = format.unwrap_or(DEFAULT_TIME_FORMAT);
+                let valid = chrono::format::StrftimeItems::new(format)
+                    .all(|item| chrono::format::Item::Error != item);
+                if !valid {
+                    bail!("invalid time format in {{{}}}", spec);
+                }
+                Piece::LastModified(format.to_string())
+            }
+            ("sha256", len) => {
+                let len = match len {
+                    Some(len) => len
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|&len| len > 0 && len <= 64)
+                        .ok_or_else(|| format_err!("{{{}}} needs a length from 1 to 64", spec))?,
+                    None => 64,
+                };
+                Piece::Sha256(len)
+            }
+            _ => bail!(
+                "unknown placeholder {{{}}} in the output name; supported: {}",
+                spec,
+                SUPPORTED
+                    .iter()
+                    .map(|s| format!("{{{}}}", s))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        })
+    }
+}
+
+#[test]
+fn test_parse() {
+    let t = Template::parse("{url_basename}.{sha256:8}").unwrap();
+    assert!(t.needs_response());
+    assert!(t.needs_body());
+    assert_eq!(
+        vec![
+            Piece::UrlBasename,
+            Piece::Literal(".".to_string()),
+            Piece::Sha256(8)
+        ],
+        t.pieces
+    );
+
+    let t = Template::parse("{{{url_basename}}}").unwrap();
+    assert!(!t.needs_response());
+
+    let err = Template::parse("{nope}").unwrap_err().to_string();
+    assert!(err.contains("{nope}") && err.contains("{etag}"), "{}", err);
+    assert!(Template::parse("{sha256:65}").is_err());
+    assert!(Template::parse("{last_modified:%Q}").is_err());
+    assert!(Template::parse("a}b").is_err());
+    assert!(Template::parse("{etag").is_err());
+}
+
+#[test]
+fn test_render() {
+    let url = Url::parse("https://example.com/dist/foo.tar.gz").unwrap();
+    let last = Url::parse("https://cdn.example.com/x/y/foo-1.2.tar.gz").unwrap();
+    let mut values = Values::new(
+        &url,
+        Some(&last),
+        Some("W/\"a/b\""),
+        Some("Thu, 03 Oct 2019 12:00:00 GMT"),
+    );
+    values.sha256 = Some([0xab; 32]);
+
+    let t = Template::parse(
+        "{url_basename}-{final_url_basename}-{etag}-{last_modified:%Y%m%d}-{sha256:8}-{last_modified}",
+    )
+    .unwrap();
+    assert_eq!(
+        "foo.tar.gz-foo-1.2.tar.gz-a_b-20191003-abababab-20191003T120000Z",
+        t.render(&values).unwrap()
+    );
+
+    let err = Template::parse("{etag}")
+        .unwrap()
+        .render(&Values::new(&url, None, None, None))
+        .unwrap_err();
+    assert!(err.to_string().contains("ETag"), "{}", err);
+}
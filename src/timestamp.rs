@@ -0,0 +1,203 @@
+use std::fs;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use chrono::DateTime;
+use chrono::SubsecRound as _;
+use chrono::Utc;
+use log::warn;
+
+/// The local time that a previous download is judged by: `mtime`, `ctime` or `btime`.
+pub fn reference(metadata: &fs::Metadata, which: &str) -> Option<SystemTime> {
+    use std::os::unix::fs::MetadataExt;
+
+    match which {
+        // errors only for unsupported platforms, apparently
+        "mtime" => metadata.modified().ok(),
+        "ctime" => Some(
+            UNIX_EPOCH
+                + Duration::from_secs(metadata.ctime().max(0) as u64)
+                + Duration::from_nanos(metadata.ctime_nsec().max(0) as u64),
+        ),
+        "btime" => match metadata.created() {
+            Ok(created) => Some(created),
+            Err(e) => {
+                warn!("birth time unavailable here, using mtime instead: {}", e);
+                metadata.modified().ok()
+            }
+        },
+        other => unreachable!("clap validated: {:?}", other),
+    }
+}
+
+/// HTTP dates only have whole seconds, so local times must be cut down to match before comparing.
+///
+/// Otherwise a file stamped 123.456 is forever "newer" than a server's 123, or the other way round.
+pub fn whole_seconds(time: SystemTime) -> DateTime<Utc> {
+    DateTime::<Utc>::from(time).trunc_subsecs(0)
+}
+
+/// An IMF-fixdate, as HTTP wants, rather than RFC 2822's optional zero padding and offsets.
+pub fn http_date(time: DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Whether the server's time is really after ours, ignoring what HTTP can't express.
+pub fn server_is_newer(server: SystemTime, local: DateTime<Utc>) -> bool {
+    whole_seconds(server) > local.trunc_subsecs(0)
+}
+
+/// When a response received at `now` stops being fresh: after `Cache-Control`'s `max-age`,
+/// less any `Age` it's already had, or else at `Expires`. `no-cache` and `no-store` make it
+/// stale at once, as does an `Expires` that doesn't parse.
+pub fn expires(
+    cache_control: Option<&str>,
+    expires: Option<&str>,
+    age: Option<&str>,
+    now: SystemTime,
+) -> Option<SystemTime> {
+    let directives: Vec<String> = cache_control
+        .unwrap_or("")
+        .split(',')
+        .map(|d| d.trim().to_ascii_lowercase())
+        .collect();
+    if directives
+        .iter()
+        .any(|d| "no-cache" == d || "no-store" == d)
+    {
+        return Some(now);
+    }
+    let max_age = directives.iter().find_map(|d| {
+        d.strip_prefix("max-age=")?
+            .trim_matches('"')
+            .parse::<u64>()
+            .ok()
+    });
+    if let Some(max_age) = max_age {
+        let age = age
+            .and_then(|age| age.trim().parse::<u64>().ok())
+            .unwrap_or(0);
+        return Some(now + Duration::from_secs(max_age.saturating_sub(age)));
+    }
+    expires.map(|expires| {
+        DateTime::parse_from_rfc2822(expires.trim())
+            .map(SystemTime::from)
+            .unwrap_or(now)
+    })
+}
+
+pub const FUTURE_POLICIES: &[&str] = &["clamp", "keep", "reject"];
+
+/// `time`, unless it's a second or more after `now`; then as `--future-mtime` has it: taken as
+/// `now`, kept anyway, or not used at all. `what` is whose time it is, for the warning.
+pub fn unless_future(
+    time: SystemTime,
+    now: SystemTime,
+    policy: &str,
+    what: &str,
+) -> Option<SystemTime> {
+    let ahead = match time.duration_since(now) {
+        Ok(ahead) if ahead >= Duration::from_secs(1) => ahead,
+        _ => return Some(time),
+    };
+    match policy {
+        "keep" => Some(time),
+        "clamp" => {
+            warn!(
+                "{} is {}s in the future, so taking it as now (see --future-mtime)",
+                what,
+                ahead.as_secs()
+            );
+            Some(now)
+        }
+        "reject" => {
+            warn!(
+                "{} is {}s in the future, so ignoring it (see --future-mtime)",
+                what,
+                ahead.as_secs()
+            );
+            None
+        }
+        other => unreachable!("clap validated: {:?}", other),
+    }
+}
+
+#[test]
+fn test_whole_seconds() {
+    let local = UNIX_EPOCH + Duration::from_millis(123_456);
+    let server = UNIX_EPOCH + Duration::from_secs(123);
+
+    assert_eq!(DateTime::<Utc>::from(server), whole_seconds(local));
+    assert_eq!(
+        "Thu, 01 Jan 1970 00:02:03 GMT",
+        http_date(whole_seconds(local))
+    );
+
+    // equal after truncation, in both directions
+    assert!(!server_is_newer(server, DateTime::from(local)));
+    assert!(!server_is_newer(local, DateTime::from(server)));
+
+    assert!(server_is_newer(
+        UNIX_EPOCH + Duration::from_secs(124),
+        DateTime::from(local)
+    ));
+}
+
+#[test]
+fn test_reference() {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    let old = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    filetime::set_file_mtime(file.path(), filetime::FileTime::from(old)).unwrap();
+    let metadata = file.path().metadata().unwrap();
+
+    assert_eq!(Some(old), reference(&metadata, "mtime"));
+    // setting the mtime is itself a metadata change
+    assert!(reference(&metadata, "ctime").unwrap() > old);
+    assert!(reference(&metadata, "btime").is_some());
+}
+
+#[test]
+fn test_unless_future() {
+    let now = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    let past = now - Duration::from_secs(60);
+    let future = now + Duration::from_secs(3600);
+    for policy in FUTURE_POLICIES {
+        assert_eq!(Some(past), unless_future(past, now, policy, "it"));
+        let soon = now + Duration::from_millis(500);
+        assert_eq!(Some(soon), unless_future(soon, now, policy, "it"));
+    }
+    assert_eq!(Some(now), unless_future(future, now, "clamp", "it"));
+    assert_eq!(Some(future), unless_future(future, now, "keep", "it"));
+    assert_eq!(None, unless_future(future, now, "reject", "it"));
+}
+
+#[test]
+fn test_expires() {
+    let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+    let at = |secs| Some(UNIX_EPOCH + Duration::from_secs(secs));
+    assert_eq!(None, expires(None, None, None, now));
+    assert_eq!(
+        at(1_000_060),
+        expires(Some("public, max-age=100"), None, Some("40"), now)
+    );
+    assert_eq!(
+        Some(now),
+        expires(Some("max-age=100, No-Store"), None, None, now)
+    );
+    // max-age wins
+    assert_eq!(
+        at(1_000_100),
+        expires(
+            Some("max-age=100"),
+            Some("Thu, 01 Jan 1970 00:00:00 GMT"),
+            None,
+            now
+        )
+    );
+    assert_eq!(
+        at(0),
+        expires(None, Some("Thu, 01 Jan 1970 00:00:00 GMT"), None, now)
+    );
+    assert_eq!(Some(now), expires(None, Some("0"), None, now));
+}
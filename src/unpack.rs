@@ -0,0 +1,373 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::io::Write;
+
+use failure::bail;
+use log::debug;
+
+use crate::size::format_size;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Gzip,
+    Xz,
+    Bzip2,
+}
+
+pub const FORMATS: &[&str] = &["auto", "gzip", "xz", "bzip2"];
+
+/// What `--unpack` asked for: a known format, or `None` to work it out from the body.
+pub fn from_arg(arg: &str) -> Result<Option<Format>, failure::Error> {
+    let format = match arg {
+        "auto" => return Ok(None),
+        "gzip" => Format::Gzip,
+        "xz" => Format::Xz,
+        "bzip2" => Format::Bzip2,
+        other => bail!("unrecognised --unpack format: {:?}", other),
+    };
+    supported(format)?;
+    Ok(Some(format))
+}
+
+fn supported(format: Format) -> Result<(), failure::Error> {
+    match format {
+        Format::Xz if !cfg!(feature = "xz") => bail!("built without xz support (feature \"xz\")"),
+        Format::Bzip2 if !cfg!(feature = "bzip2") => {
+            bail!("built without bzip2 support (feature \"bzip2\")")
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Guess from the end of a URL path, as `--unpack auto` does before looking at the body.
+pub fn from_extension(path: &str) -> Option<Format> {
+    let name = path.rsplit('/').next().unwrap_or_default();
+    if name.ends_with(".gz") || name.ends_with(".tgz") {
+        Some(Format::Gzip)
+    } else if name.ends_with(".xz") {
+        Some(Format::Xz)
+    } else if name.ends_with(".bz2") {
+        Some(Format::Bzip2)
+    } else {
+        None
+    }
+}
+
+const LONGEST_MAGIC: usize = 6;
+
+/// Below this, the ratio isn't judged, as a small enough body can be very compressible indeed.
+const RATIO_FROM: u64 = 1 << 20;
+
+/// How big the body may get as it's unpacked, which a few kilobytes of gzip can make terabytes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Limits {
+    /// `--max-size`: the most it may unpack to; without `--unpack`, its own size.
+    pub max_size: Option<u64>,
+    /// `--max-unpack-ratio`: how many times its packed size it may unpack to, past `RATIO_FROM`.
+    pub max_ratio: Option<u64>,
+}
+
+/// The write that took unpacking past the `Limits` fails with this inside.
+#[derive(Debug)]
+pub struct TooBig {
+    packed: u64,
+    unpacked: u64,
+    limit: String,
+}
+
+impl fmt::Display for TooBig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.packed == self.unpacked {
+            return write!(
+                f,
+                "the body was more than {}, past {}",
+                self.unpacked, self.limit
+            );
+        }
+        write!(
+            f,
+            "{} bytes of body unpacked to more than {} ({}), past {}",
+            self.packed,
+            self.unpacked,
+            format_size(self.unpacked),
+            self.limit
+        )
+    }
+}
+
+impl Error for TooBig {}
+
+/// Whether `e` is a `TooBig`, which is no reason to retry.
+pub fn is_too_big(e: &io::Error) -> bool {
+    e.get_ref().is_some_and(|e| e.is::<TooBig>())
+}
+
+/// Counts what's unpacked, on its way to `inner`, against what went in.
+pub struct Meter<W: Write> {
+    inner: W,
+    limits: Limits,
+    packed: u64,
+    unpacked: u64,
+}
+
+impl<W: Write> Meter<W> {
+    fn new(inner: W, limits: Limits) -> Meter<W> {
+        Meter {
+            inner,
+            limits,
+            packed: 0,
+            unpacked: 0,
+        }
+    }
+
+    fn check(&self, more: usize) -> io::Result<()> {
+        let unpacked = self.unpacked + more as u64;
+        let limit = match (self.limits.max_size, self.limits.max_ratio) {
+            (Some(max), _) if unpacked > max => format!("--max-size {}", max),
+            (_, Some(ratio))
+                if unpacked > RATIO_FROM && unpacked > self.packed.saturating_mul(ratio) =>
+            {
+                format!("--max-unpack-ratio {}", ratio)
+            }
+            _ => return Ok(()),
+        };
+        Err(io::Error::other(TooBig {
+            packed: self.packed,
+            unpacked,
+            limit,
+        }))
+    }
+}
+
+impl<W: Write> Write for Meter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.check(buf.len())?;
+        let written = self.inner.write(buf)?;
+        self.unpacked += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn from_magic(prefix: &[u8]) -> Option<Format> {
+    if prefix.starts_with(&[0x1f, 0x8b]) {
+        Some(Format::Gzip)
+    } else if prefix.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0]) {
+        Some(Format::Xz)
+    } else if prefix.starts_with(b"BZh") {
+        Some(Format::Bzip2)
+    } else {
+        None
+    }
+}
+
+/// Decompresses everything written to it into `inner`, within its `Limits`.
+///
+/// Without a format, the first few bytes are held back to sniff one; a body
+/// which isn't recognisably compressed is passed through untouched.
+pub enum Unpacker<W: Write> {
+    Sniffing(Meter<W>, Vec<u8>),
+    Plain(Meter<W>),
+    /// Only seen part way through switching state.
+    Switching,
+    Gzip(flate2::write::MultiGzDecoder<Meter<W>>),
+    #[cfg(feature = "xz")]
+    Xz(xz2::write::XzDecoder<Meter<W>>),
+    #[cfg(feature = "bzip2")]
+    Bzip2(bzip2::write::BzDecoder<Meter<W>>),
+}
+
+impl<W: Write> Unpacker<W> {
+    pub fn new(inner: W, format: Option<Format>, limits: Limits) -> Unpacker<W> {
+        let inner = Meter::new(inner, limits);
+        match format {
+            None => Unpacker::Sniffing(inner, Vec::with_capacity(LONGEST_MAGIC)),
+            Some(format) => Unpacker::with(inner, format),
+        }
+    }
+
+    /// Doesn't decompress anything, for when `--unpack` wasn't given.
+    pub fn plain(inner: W, limits: Limits) -> Unpacker<W> {
+        Unpacker::Plain(Meter::new(inner, limits))
+    }
+
+    fn with(inner: Meter<W>, format: Format) -> Unpacker<W> {
+        match format {
+            Format::Gzip => Unpacker::Gzip(flate2::write::MultiGzDecoder::new(inner)),
+            #[cfg(feature = "xz")]
+            Format::Xz => Unpacker::Xz(xz2::write::XzDecoder::new_multi_decoder(inner)),
+            #[cfg(feature = "bzip2")]
+            Format::Bzip2 => Unpacker::Bzip2(bzip2::write::BzDecoder::new(inner)),
+            #[allow(unreachable_patterns)]
+            _ => unreachable!("format support is checked when it's chosen"),
+        }
+    }
+
+    /// Stop sniffing, and decide with what's been seen so far.
+    fn settle(&mut self) -> io::Result<()> {
+        let (inner, held) = match std::mem::replace(self, Unpacker::Switching) {
+            Unpacker::Sniffing(inner, held) => (inner, held),
+            other => {
+                *self = other;
+                return Ok(());
+            }
+        };
+
+        let format = from_magic(&held).filter(|&format| supported(format).is_ok());
+        debug!("        unpack: detected {:?}", format);
+        *self = match format {
+            Some(format) => Unpacker::with(inner, format),
+            None => Unpacker::Plain(inner),
+        };
+        self.write_all(&held)
+    }
+
+    /// The inner writer, once the compressed stream is known to be complete.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.settle()?;
+        match self {
+            Unpacker::Sniffing(..) | Unpacker::Switching => unreachable!("settled"),
+            Unpacker::Plain(inner) => Ok(inner.inner),
+            Unpacker::Gzip(decoder) => Ok(decoder.finish()?.inner),
+            #[cfg(feature = "xz")]
+            Unpacker::Xz(mut decoder) => Ok(decoder.finish()?.inner),
+            #[cfg(feature = "bzip2")]
+            Unpacker::Bzip2(mut decoder) => Ok(decoder.finish()?.inner),
+        }
+    }
+
+    /// The `Meter`'s count of what's gone in, once there's a `Meter` past the decoder.
+    fn packed(&mut self) -> Option<&mut u64> {
+        let packed = match self {
+            Unpacker::Sniffing(..) | Unpacker::Switching => return None,
+            Unpacker::Plain(inner) => &mut inner.packed,
+            Unpacker::Gzip(decoder) => &mut decoder.get_mut().packed,
+            #[cfg(feature = "xz")]
+            Unpacker::Xz(decoder) => &mut decoder.get_mut().packed,
+            #[cfg(feature = "bzip2")]
+            Unpacker::Bzip2(decoder) => &mut decoder.get_mut().packed,
+        };
+        Some(packed)
+    }
+}
+
+impl<W: Write> Write for Unpacker<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Unpacker::Sniffing(_, held) = self {
+            let wanted = (LONGEST_MAGIC - held.len()).min(buf.len());
+            held.extend_from_slice(&buf[..wanted]);
+            if held.len() == LONGEST_MAGIC {
+                self.settle()?;
+            }
+            return Ok(wanted);
+        }
+
+        // counted before it's unpacked, so what comes out is judged against it
+        *self.packed().expect("settled") += buf.len() as u64;
+        let written = match self {
+            Unpacker::Sniffing(..) => unreachable!("handled above"),
+            Unpacker::Plain(inner) => inner.write(buf),
+            Unpacker::Switching => unreachable!("only while switching"),
+            Unpacker::Gzip(decoder) => decoder.write(buf),
+            #[cfg(feature = "xz")]
+            Unpacker::Xz(decoder) => decoder.write(buf),
+            #[cfg(feature = "bzip2")]
+            Unpacker::Bzip2(decoder) => decoder.write(buf),
+        }?;
+        *self.packed().expect("settled") -= (buf.len() - written) as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            // nothing has been passed on yet
+            Unpacker::Sniffing(..) => Ok(()),
+            Unpacker::Plain(inner) => inner.flush(),
+            Unpacker::Switching => unreachable!("only while switching"),
+            Unpacker::Gzip(decoder) => decoder.flush(),
+            #[cfg(feature = "xz")]
+            Unpacker::Xz(decoder) => decoder.flush(),
+            #[cfg(feature = "bzip2")]
+            Unpacker::Bzip2(decoder) => decoder.flush(),
+        }
+    }
+}
+
+#[test]
+fn test_unpack() {
+    use flate2::write::GzEncoder;
+
+    let mut gz = GzEncoder::new(Vec::new(), flate2::Compression::default());
+    gz.write_all(b"hello ").unwrap();
+    let mut gz = gz.finish().unwrap();
+    let mut second = GzEncoder::new(Vec::new(), flate2::Compression::default());
+    second.write_all(b"world").unwrap();
+    gz.extend(second.finish().unwrap());
+
+    for format in &[None, Some(Format::Gzip)] {
+        let mut unpacker = Unpacker::new(Vec::new(), *format, Limits::default());
+        for byte in &gz {
+            unpacker.write_all(&[*byte]).unwrap();
+        }
+        assert_eq!(b"hello world".to_vec(), unpacker.finish().unwrap());
+    }
+
+    let mut truncated = Unpacker::new(Vec::new(), None, Limits::default());
+    truncated.write_all(&gz[..gz.len() / 2]).unwrap();
+    assert!(truncated.finish().is_err());
+
+    let mut plain = Unpacker::new(Vec::new(), None, Limits::default());
+    plain.write_all(b"hi").unwrap();
+    assert_eq!(b"hi".to_vec(), plain.finish().unwrap());
+
+    assert_eq!(Some(Format::Gzip), from_extension("/data.csv.gz"));
+    assert_eq!(None, from_extension("/gz/data.csv"));
+}
+
+#[test]
+fn test_limits() {
+    use flate2::write::GzEncoder;
+
+    let mut gz = GzEncoder::new(Vec::new(), flate2::Compression::default());
+    gz.write_all(&vec![0; 4 << 20]).unwrap();
+    let gz = gz.finish().unwrap();
+
+    let ratio = Limits {
+        max_size: None,
+        max_ratio: Some(100),
+    };
+    let mut unpacker = Unpacker::new(Vec::new(), None, ratio);
+    let err = unpacker.write_all(&gz).unwrap_err();
+    assert!(is_too_big(&err), "{}", err);
+    assert!(
+        err.to_string().ends_with("past --max-unpack-ratio 100"),
+        "{}",
+        err
+    );
+
+    let sized = Limits {
+        max_size: Some(4 << 20),
+        max_ratio: None,
+    };
+    let mut unpacker = Unpacker::new(Vec::new(), Some(Format::Gzip), sized);
+    unpacker.write_all(&gz).unwrap();
+    assert_eq!(4 << 20, unpacker.finish().unwrap().len());
+
+    let mut plain = Unpacker::plain(
+        Vec::new(),
+        Limits {
+            max_size: Some(3),
+            max_ratio: Some(1),
+        },
+    );
+    plain.write_all(b"abc").unwrap();
+    let err = plain.write_all(b"d").unwrap_err();
+    assert_eq!(
+        "the body was more than 4, past --max-size 3",
+        err.to_string()
+    );
+}
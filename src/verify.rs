@@ -0,0 +1,235 @@
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+use failure::format_err;
+use failure::ResultExt;
+
+use crate::cache;
+use crate::checksum;
+use crate::compare;
+use crate::delta;
+use crate::digest;
+use crate::exit;
+use crate::json::string;
+
+/// What was recorded of an output when it was written.
+#[derive(Debug, PartialEq)]
+struct Recorded {
+    /// Missing from a `--cache-dir` entry's `.meta` from before they had one.
+    sha256: Option<[u8; 32]>,
+    /// Its size and mtime, as `compare::stamp` has them.
+    stamp: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Status {
+    Ok,
+    /// With what differs: `size`, `mtime` and `sha256`.
+    Modified(Vec<&'static str>),
+    Missing,
+    NoMetadata,
+    /// The record's too old to have a digest, so there's nothing to check against.
+    NoDigest,
+}
+
+impl Status {
+    fn name(&self) -> &'static str {
+        match self {
+            Status::Ok => "OK",
+            Status::Modified(_) => "MODIFIED",
+            Status::Missing => "MISSING",
+            Status::NoMetadata => "NO-METADATA",
+            Status::NoDigest => "NO-DIGEST",
+        }
+    }
+
+    /// An old record isn't a failure; only one that doesn't match, or isn't there at all.
+    fn failed(&self) -> bool {
+        match self {
+            Status::Ok | Status::NoDigest => false,
+            Status::Modified(_) | Status::Missing | Status::NoMetadata => true,
+        }
+    }
+}
+
+/// `verify OUTPUT...`: re-hash each output, without asking the network anything, and compare it
+/// with what was recorded when it was written; `OUTPUT.meta`, for a `--cache-dir` entry, or
+/// the `OUTPUT.blocks` that `--delta-index` keeps.
+///
+/// Each gets a line, `OUTPUT: STATUS`, or with `--json`, an object with `output`, `status`, in
+/// lower case, and `differs`, what didn't match. Any that don't match, or are missing, or have
+/// no record at all, fail the run, once they've all been looked at.
+pub fn run(matches: &clap::ArgMatches) -> Result<(), failure::Error> {
+    let json = matches.is_present("json");
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    let mut failed = 0;
+    let mut total = 0;
+    for output in matches.values_of_os("outputs").expect("required") {
+        let output = Path::new(output);
+        let status = check(output)?;
+        let differs = match &status {
+            Status::Modified(differs) => differs.as_slice(),
+            _ => &[],
+        };
+        if json {
+            writeln!(
+                stdout,
+                "{{\"output\":{},\"status\":{},\"differs\":[{}]}}",
+                string(&output.to_string_lossy()),
+                string(&status.name().to_ascii_lowercase()),
+                differs
+                    .iter()
+                    .map(|d| string(d))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            )?;
+        } else if differs.is_empty() {
+            writeln!(stdout, "{}: {}", output.display(), status.name())?;
+        } else {
+            writeln!(
+                stdout,
+                "{}: {} ({})",
+                output.display(),
+                status.name(),
+                differs.join(", ")
+            )?;
+        }
+        total += 1;
+        if status.failed() {
+            failed += 1;
+        }
+    }
+    stdout.flush()?;
+
+    if failed > 0 {
+        return Err(exit::classified(
+            exit::Kind::Verification,
+            format!("{} of {} outputs failed verification", failed, total),
+        ));
+    }
+    Ok(())
+}
+
+fn check(output: &Path) -> Result<Status, failure::Error> {
+    let metadata = match fs::metadata(output) {
+        Ok(metadata) => metadata,
+        Err(ref e) if io::ErrorKind::NotFound == e.kind() => return Ok(Status::Missing),
+        Err(e) => return Err(e).with_context(|_| format_err!("reading {:?}'s info", output))?,
+    };
+    let recorded = match recorded(output)? {
+        Some(recorded) => recorded,
+        None => return Ok(Status::NoMetadata),
+    };
+    let expected = match recorded.sha256 {
+        Some(sha256) => sha256,
+        None => return Ok(Status::NoDigest),
+    };
+
+    let mut differs = Vec::new();
+    if let Some(stamp) = &recorded.stamp {
+        let now = compare::stamp(&metadata);
+        let (recorded_size, recorded_mtime) = stamp.split_once(' ').unwrap_or((stamp, ""));
+        let (size, mtime) = now.split_once(' ').expect("stamped");
+        if recorded_size != size {
+            differs.push("size");
+        }
+        if recorded_mtime != mtime {
+            differs.push("mtime");
+        }
+    }
+
+    let mut file = fs::File::open(output).with_context(|_| format_err!("opening {:?}", output))?;
+    let mut hasher = digest::DigestWriter::new(io::sink(), true);
+    io::copy(&mut file, &mut hasher).with_context(|_| format_err!("reading {:?}", output))?;
+    let actual = hasher.finish().1.expect("hashing");
+    if actual.sha256 != expected {
+        differs.push("sha256");
+    }
+
+    Ok(if differs.is_empty() {
+        Status::Ok
+    } else {
+        Status::Modified(differs)
+    })
+}
+
+/// The `--cache-dir` record for `output`, if it's an entry, or else its `--delta-index` blocks.
+fn recorded(output: &Path) -> Result<Option<Recorded>, failure::Error> {
+    if let Some(meta) = cache::meta(output) {
+        let sha256 = match &meta.sha256 {
+            Some(hex) => Some(
+                checksum::parse_hex(hex)
+                    .with_context(|_| format_err!("{:?}'s .meta has a bad sha256", output))?,
+            ),
+            None => None,
+        };
+        return Ok(Some(Recorded {
+            sha256,
+            stamp: meta.output,
+        }));
+    }
+
+    let blocks = delta::path(output);
+    match read(&blocks)? {
+        Some(text) => match delta::parse(&text) {
+            Some((map, stamp)) => Ok(Some(Recorded {
+                sha256: Some(map.sha256),
+                stamp,
+            })),
+            None => Err(format_err!("{:?} is corrupt", blocks)),
+        },
+        None => Ok(None),
+    }
+}
+
+fn read(path: &Path) -> Result<Option<String>, failure::Error> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(Some(text)),
+        Err(ref e) if io::ErrorKind::NotFound == e.kind() => Ok(None),
+        Err(e) => Err(e).with_context(|_| format_err!("reading {:?}", path))?,
+    }
+}
+
+#[test]
+fn test_check() {
+    use sha2::Digest as _;
+
+    let dir = tempfile::tempdir().unwrap();
+    let url = url::Url::parse("https://example.com/a.tar.gz").unwrap();
+    let entry = cache::path(dir.path(), &url);
+    fs::write(&entry, b"abc").unwrap();
+    let written = digest::Digest {
+        sha256: sha2::Sha256::digest(b"abc").into(),
+        bytes: 3,
+        md5: None,
+        crc32c: None,
+    };
+    let outcome = crate::outcome::Outcome::default();
+    cache::record(&entry, url.as_str(), &outcome, Some(&written)).unwrap();
+    assert_eq!(Status::Ok, check(&entry).unwrap());
+
+    // the same size, and the mtime put back, but not the same
+    let mtime = filetime::FileTime::from_last_modification_time(&fs::metadata(&entry).unwrap());
+    fs::write(&entry, b"abd").unwrap();
+    filetime::set_file_mtime(&entry, mtime).unwrap();
+    assert_eq!(Status::Modified(vec!["sha256"]), check(&entry).unwrap());
+    fs::write(&entry, b"abcd").unwrap();
+    filetime::set_file_mtime(&entry, filetime::FileTime::from_unix_time(0, 0)).unwrap();
+    assert_eq!(
+        Status::Modified(vec!["size", "mtime", "sha256"]),
+        check(&entry).unwrap()
+    );
+
+    // from before there was a digest to record
+    cache::record(&entry, url.as_str(), &outcome, None).unwrap();
+    assert_eq!(Status::NoDigest, check(&entry).unwrap());
+
+    let plain = dir.path().join("plain");
+    fs::write(&plain, b"abc").unwrap();
+    assert_eq!(Status::NoMetadata, check(&plain).unwrap());
+    fs::remove_file(&entry).unwrap();
+    assert_eq!(Status::Missing, check(&entry).unwrap());
+}
@@ -0,0 +1,60 @@
+use std::ffi::OsStr;
+
+/// The optional features, and whether this build has each.
+const FEATURES: &[(&str, bool)] = &[
+    ("async", cfg!(feature = "async")),
+    ("bzip2", cfg!(feature = "bzip2")),
+    ("tls-rustls", cfg!(feature = "tls-rustls")),
+    ("xz", cfg!(feature = "xz")),
+];
+
+/// Which TLS the build has; the library refuses to build without one.
+const TLS: &str = if cfg!(feature = "tls-rustls") {
+    "rustls, through ureq, trusting webpki-roots' bundled CAs"
+} else {
+    "none"
+};
+
+/// `--version` with `-v` or `--verbose`, anywhere before a `--`; clap would print its one line
+/// as soon as it saw `--version`, which stays as it is for scripts.
+pub fn wants_verbose<S: AsRef<OsStr>>(args: &[S]) -> bool {
+    let args: Vec<&OsStr> = args
+        .iter()
+        .skip(1)
+        .map(AsRef::as_ref)
+        .take_while(|arg| *arg != "--")
+        .collect();
+    let any = |names: &[&str]| args.iter().any(|arg| names.iter().any(|n| arg == n));
+    any(&["--version", "-V"]) && any(&["--verbose", "-v"])
+}
+
+/// What's worth knowing for a bug report: which source, for which platform, with what in it.
+pub fn verbose() -> String {
+    let features: Vec<String> = FEATURES
+        .iter()
+        .map(|(name, on)| format!("{}{}", if *on { '+' } else { '-' }, name))
+        .collect();
+    format!(
+        concat!(
+            "fetch-maybe {}\n",
+            "commit:   {}\n",
+            "target:   {}\n",
+            "tls:      {}\n",
+            "features: {}\n"
+        ),
+        clap::crate_version!(),
+        env!("BUILD_GIT_COMMIT"),
+        env!("BUILD_TARGET"),
+        TLS,
+        features.join(" ")
+    )
+}
+
+#[test]
+fn test_wants_verbose() {
+    assert!(wants_verbose(&["fetch-maybe", "--version", "-v"]));
+    assert!(wants_verbose(&["fetch-maybe", "--verbose", "-V"]));
+    assert!(!wants_verbose(&["fetch-maybe", "--version"]));
+    assert!(!wants_verbose(&["fetch-maybe", "-v", "url", "out"]));
+    assert!(!wants_verbose(&["fetch-maybe", "--version", "--", "-v"]));
+}
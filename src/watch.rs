@@ -0,0 +1,100 @@
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use log::debug;
+use log::error;
+use log::info;
+
+use fetch_maybe::exit;
+use fetch_maybe::period;
+use fetch_maybe::signals;
+
+use crate::random::Random;
+
+/// How often a wait looks for a signal; the handlers can only set flags.
+const POLL: Duration = Duration::from_millis(100);
+
+/// `--watch`: run `cycle` every interval, from the start of one to the start of the next, plus
+/// up to `--watch-jitter`, until a SIGINT or SIGTERM, and at once on a SIGHUP.
+///
+/// A failed cycle is logged, and the next goes ahead as planned, unless `--watch-fail-fast`.
+/// When we're stopped, it's with the last cycle's result, so the exit status says whether it
+/// worked. Every cycle gets the same agent, so connections are kept between them.
+pub fn run<F>(
+    matches: &clap::ArgMatches,
+    random: &mut Random,
+    mut cycle: F,
+) -> Result<(), failure::Error>
+where
+    F: FnMut(&ureq::Agent) -> Result<&'static str, failure::Error>,
+{
+    let interval = positive(matches, "watch")?;
+    let jitter = match matches.value_of("watch-jitter") {
+        Some(_) => Some(positive(matches, "watch-jitter")?),
+        None => None,
+    };
+    let fail_fast = matches.is_present("watch-fail-fast");
+
+    signals::stay_resident();
+    let agent = ureq::agent();
+    for number in 1u64.. {
+        let started = Instant::now();
+        let last = cycle(&agent).map(|what| info!("         watch: cycle {}: {}", number, what));
+        if let Err(e) = &last {
+            let chain = e.iter_chain().map(|f| f.to_string()).collect::<Vec<_>>();
+            error!(
+                "         watch: cycle {} failed: {}",
+                number,
+                chain.join(": ")
+            );
+            if fail_fast {
+                return last;
+            }
+        }
+
+        let wait = interval
+            + jitter
+                .map(|jitter| random.below(jitter))
+                .unwrap_or_default();
+        let next = started + wait;
+        debug!(
+            "         watch: next cycle in {:?}",
+            next.saturating_duration_since(Instant::now())
+        );
+        if !sleep_until(next) {
+            info!("         watch: stopping, after {} cycles", number);
+            return last;
+        }
+    }
+    unreachable!("counted past u64::MAX cycles")
+}
+
+fn positive(matches: &clap::ArgMatches, name: &str) -> Result<Duration, failure::Error> {
+    let v = matches.value_of(name).expect("present");
+    match period::parse_duration(v)?.to_std() {
+        Ok(duration) if duration > Duration::from_secs(0) => Ok(duration),
+        _ => Err(exit::classified(
+            exit::Kind::Usage,
+            format!("--{} must be positive: {:?}", name, v),
+        )),
+    }
+}
+
+/// Wait for `next`, or a SIGHUP, or for us to be asked to stop, which is `false`.
+fn sleep_until(next: Instant) -> bool {
+    loop {
+        if signals::stopping() {
+            return false;
+        }
+        if signals::woken() {
+            info!("         watch: SIGHUP, checking now");
+            return true;
+        }
+        let left = next.saturating_duration_since(Instant::now());
+        if left == Duration::from_secs(0) {
+            return true;
+        }
+        thread::sleep(left.min(POLL));
+    }
+}
@@ -0,0 +1,173 @@
+use std::time::Duration;
+
+use failure::bail;
+
+use crate::outcome::Outcome;
+
+/// The variables a `--write-out` template can use, as `%{name}`.
+pub const SUPPORTED: &[&str] = &[
+    "status",
+    "size_download",
+    "time_namelookup",
+    "time_connect",
+    "time_starttransfer",
+    "time_total",
+    "final_url",
+    "remote_ip",
+    "outcome",
+    "output",
+];
+
+/// A `--write-out` template: text with `%{variable}`s, and `\n`, `\t`, `\r` and `\\` escapes,
+/// so it can be given in single quotes.
+#[derive(Debug, PartialEq)]
+pub struct Template {
+    pieces: Vec<Piece>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Piece {
+    Literal(String),
+    Variable(&'static str),
+}
+
+impl Template {
+    /// `%%` is a literal `%`.
+    pub fn parse(text: &str) -> Result<Template, failure::Error> {
+        let mut pieces = Vec::new();
+        let mut literal = String::new();
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '%' if Some(&'%') == chars.peek() => {
+                    chars.next();
+                    literal.push('%');
+                }
+                '%' if Some(&'{') == chars.peek() => {
+                    chars.next();
+                    let mut name = String::new();
+                    let mut closed = false;
+                    for c in chars.by_ref() {
+                        if '}' == c {
+                            closed = true;
+                            break;
+                        }
+                        name.push(c);
+                    }
+                    if !closed {
+                        bail!("unclosed '%{{' in --write-out {:?}", text);
+                    }
+                    let variable = match SUPPORTED.iter().find(|v| **v == name) {
+                        Some(variable) => variable,
+                        None => bail!(
+                            "unknown --write-out variable %{{{}}}, expected one of: {}",
+                            name,
+                            SUPPORTED.join(", ")
+                        ),
+                    };
+                    if !literal.is_empty() {
+                        pieces.push(Piece::Literal(literal.split_off(0)));
+                    }
+                    pieces.push(Piece::Variable(variable));
+                }
+                '\\' => match chars.next() {
+                    Some('n') => literal.push('\n'),
+                    Some('t') => literal.push('\t'),
+                    Some('r') => literal.push('\r'),
+                    Some('\\') => literal.push('\\'),
+                    Some(other) => {
+                        literal.push('\\');
+                        literal.push(other);
+                    }
+                    None => literal.push('\\'),
+                },
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(literal));
+        }
+        Ok(Template { pieces })
+    }
+
+    /// With what the run found out; anything it didn't get as far as is left empty.
+    pub fn render(&self, outcome: &Outcome) -> String {
+        let seconds = |d: Option<Duration>| match d {
+            Some(d) => format!("{:.6}", d.as_secs_f64()),
+            None => String::new(),
+        };
+        let mut text = String::new();
+        for piece in &self.pieces {
+            let value = match piece {
+                Piece::Literal(literal) => {
+                    text.push_str(literal);
+                    continue;
+                }
+                Piece::Variable("status") => outcome.status.map(|s| s.to_string()),
+                Piece::Variable("size_download") => outcome.bytes.map(|b| b.to_string()),
+                // ureq looks up and connects inside the request, so these can't be told apart
+                Piece::Variable("time_namelookup") | Piece::Variable("time_connect") => None,
+                Piece::Variable("time_starttransfer") => Some(seconds(outcome.first_byte)),
+                Piece::Variable("time_total") => Some(seconds(outcome.elapsed)),
+                Piece::Variable("final_url") => Some(
+                    outcome
+                        .final_url
+                        .clone()
+                        .unwrap_or_else(|| outcome.url.clone()),
+                ),
+                Piece::Variable("remote_ip") => outcome.remote.map(|r| r.ip().to_string()),
+                Piece::Variable("outcome") => Some(outcome.kind.name().to_string()),
+                Piece::Variable("output") => outcome
+                    .output
+                    .as_ref()
+                    .map(|o| o.to_string_lossy().into_owned()),
+                Piece::Variable(other) => unreachable!("unsupported variable {}", other),
+            };
+            text.push_str(&value.unwrap_or_default());
+        }
+        text
+    }
+}
+
+#[test]
+fn test_parse() {
+    assert_eq!(
+        Template {
+            pieces: vec![
+                Piece::Variable("status"),
+                Piece::Literal(" 100% ".to_string()),
+                Piece::Variable("output"),
+                Piece::Literal("\n\\x".to_string()),
+            ]
+        },
+        Template::parse("%{status} 100%% %{output}\\n\\x").unwrap()
+    );
+    assert!(Template::parse("%{nope}")
+        .unwrap_err()
+        .to_string()
+        .contains("unknown --write-out variable %{nope}"));
+    assert!(Template::parse("%{status").is_err());
+}
+
+#[test]
+fn test_render() {
+    let template = Template::parse(
+        "%{status}|%{size_download}|%{time_total}|%{final_url}|%{outcome}|%{time_connect}",
+    )
+    .unwrap();
+    let mut outcome = Outcome {
+        url: "http://example.com/".to_string(),
+        ..Outcome::default()
+    };
+    assert_eq!("|||http://example.com/|skipped|", template.render(&outcome));
+
+    outcome.status = Some(200);
+    outcome.bytes = Some(5);
+    outcome.elapsed = Some(Duration::from_millis(1500));
+    outcome.final_url = Some("http://example.com/b".to_string());
+    outcome.kind = crate::outcome::Kind::Fetched;
+    assert_eq!(
+        "200|5|1.500000|http://example.com/b|fetched|",
+        template.render(&outcome)
+    );
+}
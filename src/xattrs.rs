@@ -0,0 +1,61 @@
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use log::debug;
+use log::warn;
+use xattr::FileExt as _;
+
+/// Copied whenever possible: they decide who can read the file, like its mode does.
+const ALWAYS: &[&str] = &["security.selinux", "system.posix_acl_access"];
+
+/// Copy the old output's extended attributes onto the temporary file, as best we can.
+///
+/// Filesystems and platforms without them are skipped silently; anything else only warns.
+pub fn copy(from: &Path, to: &fs::File, all_user: bool) {
+    if !xattr::SUPPORTED_PLATFORM {
+        return;
+    }
+
+    let mut names: Vec<OsString> = ALWAYS.iter().map(OsString::from).collect();
+
+    if all_user {
+        match xattr::list(from) {
+            Ok(list) => names.extend(list.filter(|name| is_user(name))),
+            Err(ref e) if unsupported(e) => return,
+            Err(e) => warn!("couldn't list {:?}'s extended attributes: {}", from, e),
+        }
+    }
+
+    for name in names {
+        let value = match xattr::get(from, &name) {
+            Ok(Some(value)) => value,
+            Ok(None) => continue,
+            Err(ref e) if unsupported(e) => return,
+            Err(e) => {
+                warn!("couldn't read {:?} from {:?}: {}", name, from, e);
+                continue;
+            }
+        };
+
+        match to.set_xattr(&name, &value) {
+            Ok(()) => debug!("        xattrs: copied {:?}", name),
+            Err(ref e) if unsupported(e) => return,
+            Err(e) => warn!(
+                "couldn't preserve {:?}, see --no-preserve-xattr: {}",
+                name, e
+            ),
+        }
+    }
+}
+
+fn is_user(name: &OsStr) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+    name.as_bytes().starts_with(b"user.")
+}
+
+fn unsupported(e: &io::Error) -> bool {
+    Some(libc::ENOTSUP) == e.raw_os_error() || Some(libc::EOPNOTSUPP) == e.raw_os_error()
+}
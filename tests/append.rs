@@ -0,0 +1,122 @@
+use std::fs;
+
+mod common;
+
+use common::path_arg;
+use common::response;
+use common::run;
+use common::serve;
+
+fn append_to(existing: &str, responses: Vec<Vec<u8>>) -> (bool, String, Vec<String>) {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("log");
+    fs::write(&output, existing).unwrap();
+
+    let server = serve(responses);
+    let result = run(&[
+        "--append",
+        &format!("{}/log", server.url),
+        path_arg(&output),
+    ]);
+
+    (
+        result.status.success(),
+        fs::read_to_string(&output).unwrap(),
+        server.requests(),
+    )
+}
+
+#[test]
+fn appends_new_bytes() {
+    let (ok, content, requests) = append_to(
+        "0123",
+        vec![response(
+            "206 Partial Content",
+            &["Content-Range: bytes 4-7/8"],
+            b"4567",
+        )],
+    );
+    assert!(ok);
+    assert_eq!("01234567", content);
+    assert!(requests[0].contains("Range: bytes=4-"), "{:?}", requests);
+}
+
+#[test]
+fn nothing_new() {
+    let (ok, content, _) = append_to(
+        "0123",
+        vec![response(
+            "416 Range Not Satisfiable",
+            &["Content-Range: bytes */4"],
+            b"",
+        )],
+    );
+    assert!(ok);
+    assert_eq!("0123", content);
+}
+
+#[test]
+fn rewritten_remote_replaces() {
+    let (ok, content, _) = append_to("0123", vec![response("200 OK", &[], b"abcdefgh")]);
+    assert!(ok);
+    assert_eq!("abcdefgh", content);
+}
+
+#[test]
+fn shrunken_remote_refetched() {
+    let (ok, content, requests) = append_to(
+        "0123456789",
+        vec![
+            response(
+                "416 Range Not Satisfiable",
+                &["Content-Range: bytes */3"],
+                b"",
+            ),
+            response("200 OK", &[], b"abc"),
+        ],
+    );
+    assert!(ok);
+    assert_eq!("abc", content);
+    assert_eq!(2, requests.len());
+    assert!(!requests[1].contains("Range:"), "{:?}", requests);
+}
+
+#[test]
+fn misplaced_append_fails() {
+    let (ok, content, _) = append_to(
+        "0123",
+        vec![response(
+            "206 Partial Content",
+            &["Content-Range: bytes 2-7/8"],
+            b"234567",
+        )],
+    );
+    assert!(!ok);
+    assert_eq!("0123", content);
+}
+
+#[test]
+fn huge_length_is_not_enough_space() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("log");
+    fs::write(&output, "0123").unwrap();
+
+    // with what's there already, more than a u64 holds
+    let server = serve(vec![response(
+        "206 Partial Content",
+        &[
+            "Content-Range: bytes 4-7/8",
+            "Content-Length: 18446744073709551615",
+        ],
+        b"4567",
+    )]);
+    let result = run(&[
+        "--append",
+        &format!("{}/log", server.url),
+        path_arg(&output),
+    ]);
+    assert_eq!(Some(1), result.status.code(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("not enough disk space"), "{}", stderr);
+    assert_eq!("0123", fs::read_to_string(&output).unwrap());
+}
@@ -0,0 +1,135 @@
+use std::fs;
+
+mod common;
+
+use common::path_arg;
+use common::response;
+use common::run;
+use common::serve;
+
+const ABC: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+#[test]
+fn matching_sha256_persisted() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+
+    let server = serve(vec![response("200 OK", &[], b"abc")]);
+    let result = run(&[
+        "--sha256",
+        ABC,
+        &format!("{}/file", server.url),
+        path_arg(&output),
+    ]);
+
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("abc", fs::read_to_string(&output).unwrap());
+}
+
+#[test]
+fn mismatched_sha256_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out.tar.gz");
+    let sums = dir.path().join("SHA256SUMS");
+    fs::write(&output, "previous").unwrap();
+    fs::write(&sums, format!("{}  out.tar.gz\n", ABC)).unwrap();
+
+    let server = serve(vec![response("200 OK", &[], b"abd")]);
+    let result = run(&[
+        "--sha256",
+        &format!("@{}", path_arg(&sums)),
+        &format!("{}/file", server.url),
+        path_arg(&output),
+    ]);
+
+    assert!(!result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains(ABC), "{}", stderr);
+    assert_eq!("previous", fs::read_to_string(&output).unwrap());
+}
+
+#[test]
+fn checksum_url_enforced() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+
+    let sums = format!("{}  foo.tar.gz\n{}  bar.tar.gz\n", ABC, "0".repeat(64));
+    let server = serve(vec![
+        response("200 OK", &[], sums.as_bytes()),
+        response("200 OK", &[], b"abc"),
+    ]);
+    let result = run(&[
+        "--checksum-url",
+        &format!("{}/SHA256SUMS", server.url),
+        &format!("{}/dist/foo.tar.gz", server.url),
+        path_arg(&output),
+    ]);
+
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("abc", fs::read_to_string(&output).unwrap());
+}
+
+#[test]
+fn checksum_url_failure_stops_early() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+
+    let server = serve(vec![
+        response("404 Not Found", &[], b""),
+        response("200 OK", &[], b"abc"),
+    ]);
+    let result = run(&[
+        "--checksum-url",
+        &format!("{}/foo.tar.gz.sha256", server.url),
+        &format!("{}/foo.tar.gz", server.url),
+        path_arg(&output),
+    ]);
+
+    assert!(!result.status.success(), "{:?}", result);
+    assert_eq!(1, server.requests().len());
+    assert!(!output.exists());
+}
+
+#[test]
+fn storage_checksum_mismatch() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    fs::write(&output, b"old").unwrap();
+    let gcs_md5_of_abc = "x-goog-hash: crc32c=Nks/tw==,md5=kAFQmDzST7DWlj99KOF/cg==";
+    let server = serve(vec![
+        response("200 OK", &[gcs_md5_of_abc], b"abd"),
+        response("200 OK", &[gcs_md5_of_abc], b"abd"),
+    ]);
+
+    let result = run(&[&server.url, path_arg(&output)]);
+    assert!(!result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("md5 claimed 90015098"), "{}", stderr);
+    assert!(stderr.contains("crc32c claimed 364b3fb7"), "{}", stderr);
+    assert_eq!(b"old", fs::read(&output).unwrap().as_slice());
+
+    let result = run(&[
+        "--no-verify-storage-checksums",
+        &server.url,
+        path_arg(&output),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(b"abd", fs::read(&output).unwrap().as_slice());
+}
+
+#[test]
+fn storage_multipart_etag_skipped() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let server = serve(vec![response(
+        "200 OK",
+        &[
+            "x-amz-request-id: 1",
+            "ETag: \"00000000000000000000000000000000-3\"",
+        ],
+        b"abc",
+    )]);
+
+    let result = run(&[&server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+}
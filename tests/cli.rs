@@ -0,0 +1,709 @@
+use std::fs;
+use std::net::TcpListener;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+mod common;
+
+use common::path_arg;
+use common::response;
+use common::run;
+use common::run_with_env;
+use common::run_with_stdin;
+use common::serve;
+use common::serve_on;
+
+#[test]
+fn completions_only_on_stdout() {
+    let result = run(&["--generate-completions", "bash"]);
+    assert!(result.status.success(), "{:?}", result);
+    assert!(result.stderr.is_empty(), "{:?}", result);
+    let script = String::from_utf8_lossy(&result.stdout);
+    assert!(script.starts_with("_fetch-maybe() {"), "{}", script);
+    assert!(script.contains("--min-age"), "{}", script);
+}
+
+#[test]
+fn dry_run_prints_the_request() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    fs::write(&output, "old").unwrap();
+    let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    filetime::set_file_mtime(&output, filetime::FileTime::from(old)).unwrap();
+
+    // nothing listens there, so any request would fail
+    let result = run(&[
+        "--dry-run",
+        "--user",
+        "me:secret",
+        "-H",
+        "X-Api-Key: hidden",
+        "http://127.0.0.1:9/file",
+        path_arg(&output),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(
+        concat!(
+            "GET http://127.0.0.1:9/file\n",
+            "Host: 127.0.0.1\n",
+            "User-Agent: ureq\n",
+            "Accept: */*\n",
+            "Authorization: ***\n",
+            "If-Modified-Since: Sun, 09 Sep 2001 01:46:40 GMT\n",
+            "X-Api-Key: ***\n",
+            "decision: would send conditional GET\n",
+        ),
+        String::from_utf8_lossy(&result.stdout)
+    );
+    // no lock, or temporary file, left behind
+    let names: Vec<_> = fs::read_dir(dir.path()).unwrap().collect();
+    assert_eq!(1, names.len(), "{:?}", names);
+}
+
+#[test]
+fn head_prints_the_headers() {
+    let server = serve(vec![
+        response("200 OK", &["ETag: \"v1\""], b"body"),
+        response("405 Method Not Allowed", &[], b""),
+        response("200 OK", &["X-Via: get"], b"ignored"),
+        response("404 Not Found", &[], b""),
+    ]);
+
+    let result = run(&["--head", &server.url]);
+    assert!(result.status.success(), "{:?}", result);
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    assert!(stdout.starts_with("HTTP/1.1 200 OK\r\n"), "{}", stdout);
+    assert!(stdout.contains("\r\netag: \"v1\"\r\n"), "{}", stdout);
+    assert!(stdout.ends_with("\r\n\r\n"), "{}", stdout);
+
+    let result = run(&["-I", &server.url]);
+    assert!(result.status.success(), "{:?}", result);
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    assert!(stdout.contains("x-via: get"), "{}", stdout);
+    assert!(!stdout.contains("ignored"), "{}", stdout);
+
+    let result = run(&["--head", &server.url]);
+    assert_eq!(Some(7), result.status.code(), "{:?}", result);
+    assert!(String::from_utf8_lossy(&result.stdout).starts_with("HTTP/1.1 404"));
+
+    let methods: Vec<_> = server
+        .requests()
+        .iter()
+        .map(|r| r.split(' ').next().unwrap_or_default().to_string())
+        .collect();
+    assert_eq!(vec!["HEAD", "HEAD", "GET", "HEAD"], methods);
+}
+
+#[test]
+fn head_is_conditional_on_the_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    fs::write(&output, "old").unwrap();
+    let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    filetime::set_file_mtime(&output, filetime::FileTime::from(old)).unwrap();
+    let server = serve(vec![response("304 Not Modified", &[], b"")]);
+
+    let result = run(&["--head", &server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert!(String::from_utf8_lossy(&result.stdout).starts_with("HTTP/1.1 304 Not Modified\r\n"));
+    let requests = server.requests();
+    assert!(
+        requests[0].contains("If-Modified-Since: Sun, 09 Sep 2001 01:46:40 GMT"),
+        "{:?}",
+        requests
+    );
+    assert_eq!("old", fs::read_to_string(&output).unwrap());
+    // no lock, or temporary file, left behind
+    let names: Vec<_> = fs::read_dir(dir.path()).unwrap().collect();
+    assert_eq!(1, names.len(), "{:?}", names);
+}
+
+#[test]
+fn data_is_posted() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let query = dir.path().join("query.json");
+    fs::write(&query, "{\"export\": \"all\"}").unwrap();
+    let server = serve(vec![
+        response("303 See Other", &["Location: /export/1"], b""),
+        response("200 OK", &[], b"exported"),
+        response("304 Not Modified", &[], b""),
+        response("200 OK", &["Content-Length: 100"], b"cut short"),
+    ]);
+    let data = format!("@{}", query.display());
+    let post = |extra: &[&str]| {
+        let mut args = vec![
+            "-vvv",
+            "--data",
+            &data,
+            "--data-content-type",
+            "application/json",
+        ];
+        args.extend_from_slice(extra);
+        args.extend_from_slice(&[&server.url, path_arg(&output)]);
+        run(&args)
+    };
+
+    let result = post(&[]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("exported", fs::read_to_string(&output).unwrap());
+    let requests = server.requests();
+    assert!(requests[0].starts_with("POST / "), "{:?}", requests);
+    assert!(
+        requests[0].contains("Content-Type: application/json\r\n"),
+        "{:?}",
+        requests
+    );
+    assert!(
+        requests[0].ends_with("\r\n\r\n{\"export\": \"all\"}"),
+        "{:?}",
+        requests
+    );
+    assert!(requests[1].starts_with("GET /export/1 "), "{:?}", requests);
+    assert!(!requests[1].contains("Content-Type"), "{:?}", requests);
+
+    // still conditional on the output
+    let result = post(&[]);
+    assert!(result.status.success(), "{:?}", result);
+    assert!(server.requests()[0].contains("If-Modified-Since: "));
+    assert_eq!("exported", fs::read_to_string(&output).unwrap());
+
+    // not sent twice, unless that's said to be safe
+    let result = post(&["--retry", "2"]);
+    assert!(!result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains("not sending a POST again without --retry-non-idempotent"),
+        "{}",
+        stderr
+    );
+    assert_eq!(1, server.requests().len());
+}
+
+#[test]
+fn compare_url_gates_the_download() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let server = serve(vec![
+        response("200 OK", &["ETag: \"s1\""], b"v1"),
+        response("200 OK", &[], b"big one"),
+        response("304 Not Modified", &[], b""),
+        response("200 OK", &["ETag: \"s2\""], b"v1"),
+        response("200 OK", &[], b"v2"),
+        response("200 OK", &[], b"big two"),
+        response("500 Internal Server Error", &[], b""),
+        response("304 Not Modified", &[], b""),
+    ]);
+    let sentinel = format!("{}/MANIFEST.sha256", server.url);
+    let fetch = || {
+        run(&[
+            "-v",
+            "--compare-url",
+            &sentinel,
+            &server.url,
+            path_arg(&output),
+        ])
+    };
+
+    let result = fetch();
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("big one", fs::read_to_string(&output).unwrap());
+    assert!(dir.path().join("out.compare").exists());
+    assert_eq!(2, server.requests().len());
+
+    // not modified, then the same body again: either way, the big one isn't asked for
+    for _ in 0..2 {
+        let result = fetch();
+        assert!(result.status.success(), "{:?}", result);
+    }
+    let requests = server.requests();
+    assert_eq!(2, requests.len(), "{:?}", requests);
+    assert!(
+        requests[0].contains("If-None-Match: \"s1\""),
+        "{:?}",
+        requests
+    );
+    assert!(
+        requests[1].starts_with("GET /MANIFEST.sha256 "),
+        "{:?}",
+        requests
+    );
+
+    let result = fetch();
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("big two", fs::read_to_string(&output).unwrap());
+    assert_eq!(2, server.requests().len());
+
+    let result = fetch();
+    assert!(result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains("couldn't fetch --compare-url") && stderr.contains("500"),
+        "{}",
+        stderr
+    );
+    let requests = server.requests();
+    assert!(requests[1].contains("If-Modified-Since"), "{:?}", requests);
+}
+
+#[test]
+fn dry_run_within_min_age() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    fs::write(&output, "fresh").unwrap();
+
+    let result = run(&[
+        "--dry-run",
+        "--min-age",
+        "1h",
+        "http://127.0.0.1:9/file",
+        path_arg(&output),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(
+        "GET http://127.0.0.1:9/file\ndecision: would skip: within min-age\n",
+        String::from_utf8_lossy(&result.stdout)
+    );
+}
+
+#[test]
+fn write_out_after_success_and_failure() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let server = serve(vec![
+        response("200 OK", &[], b"hello"),
+        response("404 Not Found", &[], b""),
+    ]);
+    let template = r"%{status} %{size_download} %{outcome} %{final_url} %{remote_ip}\n";
+
+    let result = run(&[
+        "--write-out",
+        template,
+        &format!("{}/a", server.url),
+        path_arg(&output),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(
+        format!("200 5 fetched {}/a 127.0.0.1\n", server.url),
+        String::from_utf8_lossy(&result.stdout)
+    );
+
+    let result = run(&[
+        "-w",
+        "%{status} %{time_total}",
+        &format!("{}/b", server.url),
+        path_arg(&output),
+    ]);
+    assert!(!result.status.success(), "{:?}", result);
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    assert!(stdout.starts_with("404 0."), "{}", stdout);
+}
+
+#[test]
+fn write_out_remote_ip_is_the_final_hops() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let elsewhere = serve_on("127.0.0.2", 1, |_, _| response("200 OK", &[], b"moved"));
+    let next = format!("Location: {}/f", elsewhere.url);
+    let server = serve(vec![response("302 Found", &[&next], b"")]);
+
+    let result = run(&[
+        "-w",
+        "%{remote_ip}",
+        &format!("{}/f", server.url),
+        path_arg(&output),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("127.0.0.2", String::from_utf8_lossy(&result.stdout));
+}
+
+#[test]
+fn write_out_connect_times_are_empty() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let server = serve(vec![response("200 OK", &[], b"hello")]);
+
+    // ureq connects, and shakes hands, before it returns anything: there's no time to show,
+    // and a zero would claim a handshake saved
+    let result = run(&[
+        "-w",
+        "%{time_namelookup}|%{time_connect}|%{time_total}",
+        &server.url,
+        path_arg(&output),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    let times: Vec<&str> = stdout.split('|').collect();
+    assert_eq!(&["", ""], &times[..2], "{}", stdout);
+    assert!(times[2].parse::<f64>().is_ok(), "{}", stdout);
+}
+
+#[test]
+fn write_out_unknown_variable() {
+    let result = run(&["-w", "%{nope}", "http://127.0.0.1:9/", "out"]);
+    assert_eq!(Some(2), result.status.code(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains("unknown --write-out variable"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn metrics_file_survives_failure() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let metrics = dir.path().join("fetch.prom");
+    let server = serve(vec![
+        response("200 OK", &[], b"hello"),
+        response("500 Internal Server Error", &[], b""),
+    ]);
+    let fetch = || {
+        run(&[
+            "--metrics-file",
+            path_arg(&metrics),
+            &server.url,
+            path_arg(&output),
+        ])
+    };
+
+    assert!(fetch().status.success());
+    let first = fs::read_to_string(&metrics).unwrap();
+    assert!(
+        first.contains("fetch_maybe_bytes_downloaded{output=") && first.contains("} 5\n"),
+        "{}",
+        first
+    );
+
+    assert!(!fetch().status.success());
+    let second = fs::read_to_string(&metrics).unwrap();
+    let success = |text: &str| {
+        text.lines()
+            .find(|l| l.starts_with("fetch_maybe_last_success_timestamp_seconds{"))
+            .map(str::to_string)
+    };
+    assert_eq!(success(&first), success(&second), "{}", second);
+    assert!(second.contains(",outcome=\"failed\"} 1\n"), "{}", second);
+}
+
+#[test]
+fn stats_at_default_verbosity() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let server = serve(vec![response("200 OK", &[], b"hello")]);
+
+    let result = run(&["--stats", &server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.starts_with("fetch-maybe: fetched, 200, 5 bytes") && stderr.ends_with(", changed\n"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn buffer_size() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let server = serve(vec![response("200 OK", &[], b"hello")]);
+
+    let result = run(&[
+        "--stats",
+        "--buffer-size",
+        "1M",
+        &server.url,
+        path_arg(&output),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("hello", fs::read_to_string(&output).unwrap());
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains(", 1.0M buffer, "), "{}", stderr);
+
+    for refused in &["0", "65M"] {
+        let result = run(&["--buffer-size", refused, &server.url, path_arg(&output)]);
+        assert_eq!(Some(2), result.status.code(), "{:?}", result);
+    }
+}
+
+#[test]
+fn interactive_needs_a_terminal() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    fs::write(&output, "old").unwrap();
+    let server = serve(vec![response("200 OK", &[], b"new")]);
+
+    let result = run_with_stdin(&["-i", &server.url, path_arg(&output)], b"y\n");
+    assert_eq!(Some(2), result.status.code(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains("needs stdin to be a terminal"),
+        "{}",
+        stderr
+    );
+    assert!(server.requests().is_empty());
+    assert_eq!("old", fs::read_to_string(&output).unwrap());
+}
+
+#[test]
+fn version_verbose() {
+    let plain = run(&["--version"]);
+    assert!(plain.status.success(), "{:?}", plain);
+    let first = format!("fetch-maybe {}", env!("CARGO_PKG_VERSION"));
+    assert_eq!(
+        format!("{}\n", first),
+        String::from_utf8_lossy(&plain.stdout)
+    );
+
+    let verbose = run(&["--version", "-v"]);
+    assert!(verbose.status.success(), "{:?}", verbose);
+    let stdout = String::from_utf8_lossy(&verbose.stdout);
+    let mut lines = stdout.lines();
+    assert_eq!(Some(first.as_str()), lines.next());
+    for label in &["commit: ", "target: ", "tls: ", "features: "] {
+        assert!(
+            lines.next().is_some_and(|l| l.starts_with(label)),
+            "{}",
+            stdout
+        );
+    }
+}
+
+#[test]
+fn version_verbose_names_the_tls_backend() {
+    let result = run(&["--version", "--verbose"]);
+    assert!(result.status.success(), "{:?}", result);
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    let line = |label: &str| {
+        stdout
+            .lines()
+            .find_map(|l| l.strip_prefix(label))
+            .map(str::trim)
+            .unwrap_or_else(|| panic!("no {:?} in {}", label, stdout))
+            .to_string()
+    };
+
+    // the default build, and the only TLS there is
+    assert!(line("tls:").starts_with("rustls"), "{}", stdout);
+    assert!(
+        line("features:").split(' ').any(|f| f == "+tls-rustls"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn dns_failure_is_its_own_status() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+
+    // reserved never to resolve; offline, it's a failure to ask, which is still dns
+    let result = run(&[
+        "--dns-timeout",
+        "30s",
+        "http://fetch-maybe.invalid/",
+        path_arg(&output),
+    ]);
+    assert_eq!(Some(3), result.status.code(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("\"fetch-maybe.invalid\""), "{}", stderr);
+    assert!(!output.exists());
+
+    let result = run(&[
+        "--dns-timeout",
+        "soon",
+        "http://localhost/",
+        path_arg(&output),
+    ]);
+    assert_eq!(Some(2), result.status.code(), "{:?}", result);
+}
+
+#[test]
+fn hsts_upgrades_remembered_hosts() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let state = dir.path().join("state");
+    fs::create_dir_all(state.join("fetch-maybe")).unwrap();
+    fs::write(
+        state.join("fetch-maybe").join("hsts"),
+        "localhost 2999-01-01T00:00:00+00:00 includeSubDomains\n",
+    )
+    .unwrap();
+    let env = [("XDG_STATE_HOME", path_arg(&state))];
+
+    // it's asked over https, which a plain server can't answer
+    let server = serve(vec![response("200 OK", &[], b"plain")]);
+    let url = server.url.replace("127.0.0.1", "localhost");
+    let result = run_with_env(&["-vv", &url, path_arg(&output)], &env);
+    assert!(!result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    let upgraded = url.replace("http:", "https:");
+    assert!(stderr.contains(&upgraded), "{}", stderr);
+    assert!(!output.exists());
+
+    let server = serve(vec![response("200 OK", &[], b"plain")]);
+    let url = server.url.replace("127.0.0.1", "localhost");
+    let result = run_with_env(&["--no-hsts", &url, path_arg(&output)], &env);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(b"plain", fs::read(&output).unwrap().as_slice());
+}
+
+#[test]
+fn connect_failure_is_its_own_status() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let closed = {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap()
+    };
+
+    // the one address is the only one tried, and nothing's reported as having answered
+    let result = run(&[
+        "-w",
+        "%{remote_ip}|",
+        &format!("http://{}/", closed),
+        path_arg(&output),
+    ]);
+    assert_eq!(Some(4), result.status.code(), "{:?}", result);
+    assert_eq!("|", String::from_utf8_lossy(&result.stdout));
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("Connection refused"), "{}", stderr);
+    assert!(!output.exists());
+}
+
+#[test]
+fn credentials_stay_with_their_host() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let elsewhere = serve_on("127.0.0.2", 1, |_, _| response("200 OK", &[], b"moved"));
+    let next = format!("Location: {}/f", elsewhere.url);
+    let server = serve(vec![
+        response("302 Found", &["Location: /again"], b""),
+        response("302 Found", &[&next], b""),
+    ]);
+    let url = server.url.replace("http://", "http://u:secret@");
+
+    let result = run(&[
+        "-H",
+        "X-Api-Key: k",
+        "-H",
+        "X-Plain: p",
+        &format!("{}/f", url),
+        path_arg(&output),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(b"moved", fs::read(&output).unwrap().as_slice());
+
+    // still its own host after the first hop
+    for request in server.requests() {
+        assert!(request.contains("Authorization: Basic"), "{}", request);
+        assert!(request.contains("X-Api-Key: k"), "{}", request);
+    }
+    let moved = &elsewhere.requests()[0];
+    assert!(!moved.contains("Authorization"), "{}", moved);
+    assert!(!moved.contains("X-Api-Key"), "{}", moved);
+    assert!(moved.contains("X-Plain: p"), "{}", moved);
+}
+
+#[test]
+fn proxy_authorization_is_masked_and_kept_home() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let elsewhere = serve_on("127.0.0.2", 1, |_, _| response("200 OK", &[], b"moved"));
+    let next = format!("Location: {}/f", elsewhere.url);
+    let server = serve(vec![response("302 Found", &[&next], b"")]);
+
+    let result = run(&[
+        "-vvv",
+        "-H",
+        "Proxy-Authorization: Basic c2VjcmV0",
+        &format!("{}/f", server.url),
+        path_arg(&output),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(!stderr.contains("c2VjcmV0"), "{}", stderr);
+    assert!(
+        stderr.contains("sending header: \"Proxy-Authorization\": ***"),
+        "{}",
+        stderr
+    );
+
+    let first = &server.requests()[0];
+    assert!(
+        first.contains("Proxy-Authorization: Basic c2VjcmV0"),
+        "{}",
+        first
+    );
+    let moved = &elsewhere.requests()[0];
+    assert!(!moved.contains("Proxy-Authorization"), "{}", moved);
+}
+
+#[test]
+fn proxy_variables_are_warned_about() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let server = serve(vec![response("200 OK", &[], b"direct"); 4]);
+
+    let result = run(&[
+        "--proxy",
+        "https://127.0.0.1:9",
+        &server.url,
+        path_arg(&output),
+    ]);
+    assert_eq!(Some(2), result.status.code(), "{:?}", result);
+    assert!(!output.exists());
+
+    // there's no proxy support, so it's straight to the origin, but said so, at -v
+    let dead = "http://127.0.0.1:9";
+    for name in &["http_proxy", "HTTP_PROXY", "ALL_PROXY"] {
+        let result = run_with_env(&["-v", &server.url, path_arg(&output)], &[(name, dead)]);
+        assert!(result.status.success(), "{:?}", result);
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        assert!(
+            stderr.contains(&format!(
+                "proxy: {} is set, but proxies aren't supported; connecting directly",
+                name
+            )),
+            "{}",
+            stderr
+        );
+    }
+    assert_eq!(b"direct", fs::read(&output).unwrap().as_slice());
+
+    // nor does one that's not for this host, or this scheme
+    let env = [
+        ("http_proxy", dead),
+        ("NO_PROXY", "localhost,127.0.0.1"),
+        ("HTTPS_PROXY", dead),
+    ];
+    let result = run_with_env(&["-v", &server.url, path_arg(&output)], &env);
+    assert!(result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(!stderr.contains("proxy:"), "{}", stderr);
+}
+
+#[test]
+fn ttfb_timeout() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+
+    // connections queue up to be accepted, so this takes the request and never answers it
+    let silent = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", silent.local_addr().unwrap());
+
+    let started = Instant::now();
+    let result = run(&["--ttfb-timeout", "1s", &url, path_arg(&output)]);
+    assert_eq!(Some(6), result.status.code(), "{:?}", result);
+    assert!(started.elapsed() < Duration::from_secs(10));
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("--ttfb-timeout"), "{}", stderr);
+    assert!(!output.exists());
+
+    let server = serve(vec![response("200 OK", &[], b"quick")]);
+    let result = run(&["--ttfb-timeout", "10s", &server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("quick", fs::read_to_string(&output).unwrap());
+}
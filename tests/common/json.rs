@@ -0,0 +1,152 @@
+//! Just enough JSON to read back what fetch-maybe writes.
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// A field of an object, panicking if it isn't there: the fields are the schema.
+    pub fn get(&self, key: &str) -> &Json {
+        match self {
+            Json::Object(fields) => fields
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v)
+                .unwrap_or_else(|| panic!("no {:?} in {:?}", key, self)),
+            other => panic!("not an object: {:?}", other),
+        }
+    }
+
+    pub fn keys(&self) -> Vec<&str> {
+        match self {
+            Json::Object(fields) => fields.iter().map(|(k, _)| k.as_str()).collect(),
+            other => panic!("not an object: {:?}", other),
+        }
+    }
+
+    pub fn str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn number(&self) -> Option<f64> {
+        match self {
+            Json::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+}
+
+pub fn parse(text: &str) -> Json {
+    let mut chars = text.trim_end().chars().peekable();
+    let value = value(&mut chars);
+    assert_eq!(None, chars.next(), "trailing text in {:?}", text);
+    value
+}
+
+fn value(chars: &mut Peekable<Chars>) -> Json {
+    skip_space(chars);
+    match chars.peek().copied() {
+        Some('{') => {
+            chars.next();
+            let mut fields = Vec::new();
+            loop {
+                skip_space(chars);
+                if Some(&'}') == chars.peek() && fields.is_empty() {
+                    chars.next();
+                    break;
+                }
+                let key = string(chars);
+                skip_space(chars);
+                assert_eq!(Some(':'), chars.next());
+                fields.push((key, value(chars)));
+                skip_space(chars);
+                match chars.next() {
+                    Some(',') => continue,
+                    Some('}') => break,
+                    other => panic!("expected , or }} in an object, not {:?}", other),
+                }
+            }
+            Json::Object(fields)
+        }
+        Some('[') => {
+            chars.next();
+            let mut items = Vec::new();
+            loop {
+                skip_space(chars);
+                if Some(&']') == chars.peek() && items.is_empty() {
+                    chars.next();
+                    break;
+                }
+                items.push(value(chars));
+                skip_space(chars);
+                match chars.next() {
+                    Some(',') => continue,
+                    Some(']') => break,
+                    other => panic!("expected , or ] in an array, not {:?}", other),
+                }
+            }
+            Json::Array(items)
+        }
+        Some('"') => Json::String(string(chars)),
+        Some(c) if c.is_ascii_alphabetic() => {
+            let word: String =
+                std::iter::from_fn(|| chars.next_if(char::is_ascii_alphabetic)).collect();
+            match word.as_str() {
+                "null" => Json::Null,
+                "true" => Json::Bool(true),
+                "false" => Json::Bool(false),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        Some(_) => {
+            let number: String = std::iter::from_fn(|| {
+                chars.next_if(|c| c.is_ascii_digit() || "+-.eE".contains(*c))
+            })
+            .collect();
+            Json::Number(
+                number
+                    .parse()
+                    .unwrap_or_else(|_| panic!("bad number {:?}", number)),
+            )
+        }
+        None => panic!("unexpected end"),
+    }
+}
+
+fn string(chars: &mut Peekable<Chars>) -> String {
+    assert_eq!(Some('"'), chars.next());
+    let mut out = String::new();
+    loop {
+        match chars.next().expect("unterminated string") {
+            '"' => return out,
+            '\\' => match chars.next().expect("unterminated escape") {
+                'n' => out.push('\n'),
+                'r' => out.push('\r'),
+                't' => out.push('\t'),
+                'u' => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    let code = u32::from_str_radix(&hex, 16).expect("hex escape");
+                    out.push(std::char::from_u32(code).expect("a char"));
+                }
+                c => out.push(c),
+            },
+            c => out.push(c),
+        }
+    }
+}
+
+fn skip_space(chars: &mut Peekable<Chars>) {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+}
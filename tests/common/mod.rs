@@ -0,0 +1,159 @@
+#![allow(dead_code)]
+
+pub mod json;
+
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Write;
+use std::net::Shutdown;
+use std::net::TcpListener;
+use std::path::Path;
+use std::process::Command;
+use std::process::Output;
+use std::process::Stdio;
+use std::sync::mpsc;
+use std::thread;
+
+/// A stub HTTP server, answering each connection with the next canned response.
+pub struct Server {
+    pub url: String,
+    requests: mpsc::Receiver<String>,
+}
+
+impl Server {
+    /// The requests received so far, in order: their heads, then any body they had.
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.try_iter().collect()
+    }
+}
+
+pub fn serve(responses: Vec<Vec<u8>>) -> Server {
+    let count = responses.len();
+    serve_with(count, move |number, _| responses[number].clone())
+}
+
+/// A stub HTTP server answering `count` connections, in turn, with what `answer` makes of
+/// their number, from 0, and the request's head.
+pub fn serve_with<F>(count: usize, answer: F) -> Server
+where
+    F: Fn(usize, &str) -> Vec<u8> + Send + 'static,
+{
+    serve_on("127.0.0.1", count, answer)
+}
+
+/// As `serve_with`, but listening on another loopback address, like 127.0.0.2, to be a
+/// different host.
+pub fn serve_on<F>(host: &str, count: usize, answer: F) -> Server
+where
+    F: Fn(usize, &str) -> Vec<u8> + Send + 'static,
+{
+    let listener = TcpListener::bind((host, 0)).expect("binding stub server");
+    let url = format!("http://{}", listener.local_addr().expect("bound"));
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        for number in 0..count {
+            let (mut stream, _) = match listener.accept() {
+                Ok(conn) => conn,
+                Err(_) => return,
+            };
+
+            let mut head = String::new();
+            let mut reader = BufReader::new(stream.try_clone().expect("clone"));
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" {
+                    break;
+                }
+                head.push_str(&line);
+            }
+            let length = head.lines().find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                if !name.eq_ignore_ascii_case("content-length") {
+                    return None;
+                }
+                value.trim().parse::<usize>().ok()
+            });
+            if let Some(length) = length {
+                let mut body = vec![0; length];
+                if reader.read_exact(&mut body).is_ok() {
+                    head.push_str("\r\n");
+                    head.push_str(&String::from_utf8_lossy(&body));
+                }
+            }
+            let response = answer(number, &head);
+            let _ = tx.send(head);
+
+            let _ = stream.write_all(&response);
+            let _ = stream.flush();
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    });
+
+    Server { url, requests: rx }
+}
+
+/// A raw response; a Content-Length is added unless the headers describe the body already.
+pub fn response(status: &str, headers: &[&str], body: &[u8]) -> Vec<u8> {
+    let mut out = format!("HTTP/1.1 {}\r\n", status);
+    for header in headers {
+        out.push_str(header);
+        out.push_str("\r\n");
+    }
+
+    let describes_body = headers.iter().any(|h| {
+        let h = h.to_ascii_lowercase();
+        h.starts_with("content-length:") || h.starts_with("transfer-encoding:")
+    });
+
+    if !describes_body && !status.starts_with("204") && !status.starts_with("304") {
+        out.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+
+    out.push_str("\r\n");
+    let mut out = out.into_bytes();
+    out.extend_from_slice(body);
+    out
+}
+
+pub fn run(args: &[&str]) -> Output {
+    run_with_env(args, &[])
+}
+
+pub fn run_with_env(args: &[&str], env: &[(&str, &str)]) -> Output {
+    command(args, env).output().expect("running fetch-maybe")
+}
+
+/// With `input` written to its stdin, then closed.
+pub fn run_with_stdin(args: &[&str], input: &[u8]) -> Output {
+    let mut child = command(args, &[])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("running fetch-maybe");
+    child
+        .stdin
+        .take()
+        .expect("piped")
+        .write_all(input)
+        .expect("writing stdin");
+    child.wait_with_output().expect("running fetch-maybe")
+}
+
+fn command(args: &[&str], env: &[(&str, &str)]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_fetch-maybe"));
+    command
+        .args(args)
+        .env_remove("RUST_LOG")
+        .envs(env.iter().copied())
+        .env_remove("RUST_BACKTRACE")
+        // so whoever's running the tests doesn't get their own defaults
+        .env("XDG_CONFIG_HOME", "/nonexistent");
+    command
+}
+
+pub fn path_arg(path: &Path) -> &str {
+    path.to_str().expect("test paths are utf-8")
+}
@@ -0,0 +1,219 @@
+use std::fs;
+
+mod common;
+
+use common::path_arg;
+use common::response;
+use common::run;
+use common::run_with_env;
+use common::serve;
+
+#[test]
+fn config_defaults_and_profiles() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let config = dir.path().join("config.toml");
+    fs::write(
+        &config,
+        concat!(
+            "header = [\"X-From: top\"]\n",
+            "no-mtime = true\n",
+            "\n",
+            "[profile.special]\n",
+            "header = [\"X-From: profile\"]\n",
+        ),
+    )
+    .unwrap();
+
+    let server = serve(vec![
+        response("200 OK", &[], b"abc"),
+        response("200 OK", &[], b"abc"),
+        response("200 OK", &[], b"abc"),
+    ]);
+    let fetch = |extra: &[&str]| {
+        let mut args = vec!["--config", path_arg(&config)];
+        args.extend(extra);
+        args.extend(&[server.url.as_str(), path_arg(&output)]);
+        let result = run(&args);
+        assert!(result.status.success(), "{:?}", result);
+    };
+
+    fetch(&[]);
+    fetch(&["--profile", "special"]);
+    fetch(&["--profile", "special", "-H", "X-From: command line"]);
+
+    let requests = server.requests();
+    assert!(requests[0].contains("X-From: top"), "{}", requests[0]);
+    assert!(requests[1].contains("X-From: profile"), "{}", requests[1]);
+    assert!(!requests[1].contains("X-From: top"), "{}", requests[1]);
+    assert!(
+        requests[2].contains("X-From: command line"),
+        "{}",
+        requests[2]
+    );
+    assert!(!requests[2].contains("X-From: profile"), "{}", requests[2]);
+}
+
+#[test]
+fn config_unknown_setting_warns() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let config = dir.path().join("config.toml");
+    fs::write(&config, "min-aeg = \"1h\"\n").unwrap();
+
+    let server = serve(vec![response("200 OK", &[], b"abc")]);
+    let result = run(&[
+        "-v",
+        "--config",
+        path_arg(&config),
+        &server.url,
+        path_arg(&output),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("unknown setting \"min-aeg\""), "{}", stderr);
+}
+
+#[test]
+fn missing_explicit_config_fails() {
+    let dir = tempfile::tempdir().unwrap();
+    let result = run(&[
+        "--config",
+        path_arg(&dir.path().join("missing.toml")),
+        "http://127.0.0.1:9/",
+        path_arg(&dir.path().join("out")),
+    ]);
+    assert!(!result.status.success(), "{:?}", result);
+}
+
+#[test]
+fn environment_between_command_line_and_config() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let config = dir.path().join("config.toml");
+    fs::write(&config, "header = [\"X-From: config\"]\n").unwrap();
+
+    let server = serve(vec![
+        response("200 OK", &[], b"abc"),
+        response("200 OK", &[], b"abc"),
+    ]);
+    let env = [(
+        "FETCH_MAYBE_HEADER",
+        "X-From: environment\nAuthorization: secret",
+    )];
+
+    let result = run_with_env(
+        &[
+            "-vvv",
+            "--config",
+            path_arg(&config),
+            &server.url,
+            path_arg(&output),
+        ],
+        &env,
+    );
+    assert!(result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains("Authorization: ***") && !stderr.contains("secret"),
+        "{}",
+        stderr
+    );
+
+    let result = run_with_env(
+        &[
+            "--config",
+            path_arg(&config),
+            "-H",
+            "X-From: command line",
+            &server.url,
+            path_arg(&output),
+        ],
+        &env,
+    );
+    assert!(result.status.success(), "{:?}", result);
+
+    let requests = server.requests();
+    assert!(
+        requests[0].contains("X-From: environment"),
+        "{}",
+        requests[0]
+    );
+    assert!(
+        requests[0].contains("Authorization: secret"),
+        "{}",
+        requests[0]
+    );
+    assert!(!requests[0].contains("X-From: config"), "{}", requests[0]);
+    assert!(
+        requests[1].contains("X-From: command line"),
+        "{}",
+        requests[1]
+    );
+    assert!(
+        !requests[1].contains("X-From: environment"),
+        "{}",
+        requests[1]
+    );
+}
+
+#[test]
+fn args_file_wins() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let config = dir.path().join("config.toml");
+    fs::write(&config, "header = [\"X-From: config\"]\n").unwrap();
+    let args = dir.path().join("args");
+    fs::write(
+        &args,
+        "# one a line, unsplit\n--header\n  X-From: the args file  \n\n--no-mtime\n",
+    )
+    .unwrap();
+
+    let server = serve(vec![response("200 OK", &[], b"abc")]);
+    let result = run_with_env(
+        &[
+            "--config",
+            path_arg(&config),
+            &format!("@{}", path_arg(&args)),
+            &server.url,
+            path_arg(&output),
+        ],
+        &[("FETCH_MAYBE_HEADER", "X-From: env")],
+    );
+    assert!(result.status.success(), "{:?}", result);
+    let request = &server.requests()[0];
+    assert!(request.contains("X-From: the args file\r\n"), "{}", request);
+    assert!(!request.contains("X-From: env"), "{}", request);
+    assert!(!request.contains("X-From: config"), "{}", request);
+}
+
+#[test]
+fn args_file_errors_say_where() {
+    let dir = tempfile::tempdir().unwrap();
+    let args = dir.path().join("args");
+
+    fs::write(&args, "-v\n@other\n").unwrap();
+    let result = run(&["--args-file", path_arg(&args), "http://localhost/", "out"]);
+    assert_eq!(Some(2), result.status.code(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains(&format!("args file {:?} line 2: ", args)),
+        "{}",
+        stderr
+    );
+
+    fs::write(&args, "-v\n--no-such-option\n").unwrap();
+    let result = run(&[
+        &format!("--args-file={}", path_arg(&args)),
+        "http://localhost/",
+        "out",
+    ]);
+    assert_eq!(Some(2), result.status.code(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains(&format!("from args file {:?} line 2", args)),
+        "{}",
+        stderr
+    );
+}
@@ -0,0 +1,21 @@
+use std::fs;
+
+mod common;
+use common::path_arg;
+use common::response;
+use common::run;
+use common::serve;
+
+#[test]
+fn diff_printed() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("app.conf");
+    fs::write(&output, "a = 1\nb = 2\n").unwrap();
+    let server = serve(vec![response("200 OK", &[], b"a = 1\nb = 3\n")]);
+
+    let result = run(&["--diff", &server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("-b = 2\n+b = 3\n"), "{}", stderr);
+    assert_eq!("a = 1\nb = 3\n", fs::read_to_string(&output).unwrap());
+}
@@ -0,0 +1,128 @@
+use std::fs;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use fetch_maybe::FetchOutcome;
+use fetch_maybe::FetchRequest;
+
+mod common;
+
+use common::response;
+use common::serve;
+
+#[test]
+fn fetch_outcomes() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+
+    let server = serve(vec![
+        response(
+            "200 OK",
+            &["Last-Modified: Thu, 03 Oct 2019 12:00:00 GMT"],
+            b"hello",
+        ),
+        response("304 Not Modified", &[], b""),
+    ]);
+
+    let mut request = FetchRequest::new(format!("{}/file", server.url), &output);
+    request.agent = Some(ureq::agent());
+
+    let lastmod = SystemTime::UNIX_EPOCH + Duration::from_secs(1_570_104_000);
+    assert_eq!(
+        FetchOutcome::Downloaded {
+            bytes: Some(5),
+            last_modified: Some(lastmod),
+        },
+        fetch_maybe::fetch(&request).unwrap()
+    );
+    assert_eq!("hello", fs::read_to_string(&output).unwrap());
+
+    assert_eq!(
+        FetchOutcome::NotModified,
+        fetch_maybe::fetch(&request).unwrap()
+    );
+    let requests = server.requests();
+    assert!(
+        requests[1].contains("If-Modified-Since: Thu, 03 Oct 2019 12:00:00 GMT"),
+        "{:?}",
+        requests
+    );
+
+    // the mtime is the server's, years ago, but the ctime is when it was written
+    request.min_age = Some(Duration::from_secs(3600));
+    request.args = vec!["--reference-time".into(), "ctime".into()];
+    assert_eq!(FetchOutcome::Skipped, fetch_maybe::fetch(&request).unwrap());
+    assert!(server.requests().is_empty());
+}
+
+#[test]
+fn fetch_bad_arguments() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut request = FetchRequest::new("http://localhost/", dir.path().join("out"));
+    request.headers = vec!["no colon".to_string()];
+
+    let err = fetch_maybe::fetch(&request).unwrap_err();
+    assert_eq!(2, fetch_maybe::exit::code(&err, ""));
+}
+
+/// As small an executor as will do: park until woken.
+#[cfg(feature = "async")]
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    use std::sync::Arc;
+    use std::task::Context;
+    use std::task::Poll;
+    use std::task::Wake;
+    use std::thread::Thread;
+
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Arc::new(Unpark(std::thread::current())).into();
+    let mut cx = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn fetch_async_agrees() {
+    let responses = || {
+        vec![
+            response(
+                "200 OK",
+                &["Last-Modified: Thu, 03 Oct 2019 12:00:00 GMT"],
+                b"hello",
+            ),
+            response("304 Not Modified", &[], b""),
+        ]
+    };
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut outcomes = Vec::new();
+    for name in &["sync", "async"] {
+        let server = serve(responses());
+        let request = FetchRequest::new(format!("{}/file", server.url), dir.path().join(name));
+        for _ in 0..2 {
+            outcomes.push(if "sync" == *name {
+                fetch_maybe::fetch(&request).unwrap()
+            } else {
+                block_on(fetch_maybe::fetch_async(request.clone())).unwrap()
+            });
+        }
+        assert_eq!("hello", fs::read_to_string(dir.path().join(name)).unwrap());
+        assert_eq!(2, server.requests().len());
+    }
+
+    assert_eq!(outcomes[..2], outcomes[2..]);
+    assert_eq!(FetchOutcome::NotModified, outcomes[3]);
+}
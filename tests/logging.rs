@@ -0,0 +1,128 @@
+use std::fs;
+
+mod common;
+
+use common::path_arg;
+use common::response;
+use common::run;
+use common::run_with_env;
+use common::serve;
+
+#[test]
+fn json_logs() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+
+    let server = serve(vec![response("200 OK", &[], b"abc")]);
+    let result = run(&[
+        "--log-format",
+        "json",
+        "-vv",
+        &server.url,
+        path_arg(&output),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr
+            .lines()
+            .all(|l| l.starts_with("{\"timestamp\":") && l.ends_with('}')),
+        "{}",
+        stderr
+    );
+    let ready = stderr
+        .lines()
+        .find(|l| l.contains("output: ready"))
+        .unwrap_or_else(|| panic!("{}", stderr));
+    assert!(
+        ready.contains(",\"status\":200,\"bytes\":3,") && ready.contains(",\"output\":\""),
+        "{}",
+        ready
+    );
+}
+
+#[test]
+fn log_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let log = dir.path().join("log");
+    fs::write(&log, "earlier\n").unwrap();
+
+    let server = serve(vec![response("200 OK", &[], b"abc")]);
+    let result = run(&[
+        "-vv",
+        "--log-file",
+        path_arg(&log),
+        "--log-file-only",
+        &server.url,
+        path_arg(&output),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    assert!(result.stderr.is_empty(), "{:?}", result);
+
+    let log = fs::read_to_string(&log).unwrap();
+    assert!(log.starts_with("earlier\n"), "{}", log);
+    let ready = log
+        .lines()
+        .find(|l| l.ends_with("output: ready"))
+        .unwrap_or_else(|| panic!("{}", log));
+    // with the date: 2019-10-03T12:00:00.000Z INFO  fetch_maybe > ...
+    assert_eq!(Some('T'), ready.chars().nth(10), "{}", ready);
+    assert!(ready.contains("Z INFO  fetch_maybe > "), "{}", ready);
+}
+
+#[test]
+fn log_file_unopenable() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let log = dir.path().join("missing").join("log");
+
+    let server = serve(vec![response("200 OK", &[], b"abc")]);
+    let result = run(&[
+        "-v",
+        "--log-file",
+        path_arg(&log),
+        "--log-file-only",
+        &server.url,
+        path_arg(&output),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("couldn't open log file"), "{}", stderr);
+    assert_eq!(b"abc", fs::read(&output).unwrap().as_slice());
+}
+
+#[test]
+fn rust_log_refines_verbosity() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+
+    let server = serve(vec![response("200 OK", &[], b"abc")]);
+    let result = run_with_env(
+        &[&server.url, path_arg(&output)],
+        &[("RUST_LOG", "fetch_maybe::lock=debug")],
+    );
+    assert!(result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("lock: holding"), "{}", stderr);
+    assert!(!stderr.contains("request: sending"), "{}", stderr);
+}
+
+#[test]
+fn color_only_when_asked() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+
+    let server = serve(vec![
+        response("200 OK", &[], b"abc"),
+        response("200 OK", &[], b"abc"),
+    ]);
+    let result = run(&["-vv", &server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert!(!result.stderr.contains(&0x1b), "{:?}", result);
+
+    let result = run(&["-vv", "--color", "always", &server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert!(result.stderr.contains(&0x1b), "{:?}", result);
+}
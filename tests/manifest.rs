@@ -0,0 +1,409 @@
+use std::fs;
+use std::time::Duration;
+use std::time::Instant;
+
+mod common;
+
+use common::json;
+use common::path_arg;
+use common::response;
+use common::run;
+use common::run_with_stdin;
+use common::serve;
+
+#[test]
+fn manifest_fetches_each_entry() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = serve(vec![
+        response("200 OK", &[], b"one"),
+        response("304 Not Modified", &[], b""),
+        response("404 Not Found", &[], b""),
+    ]);
+    fs::write(dir.path().join("b"), "old").unwrap();
+
+    let manifest = dir.path().join("manifest");
+    fs::write(
+        &manifest,
+        format!(
+            concat!(
+                "# url, output, options\n",
+                "{url}/a {dir}/a --header 'X-Entry: a'\n",
+                "\n",
+                "{url}/b '{dir}/b'\n",
+                "{url}/c {dir}/c\n",
+            ),
+            url = server.url,
+            dir = path_arg(dir.path())
+        ),
+    )
+    .unwrap();
+
+    let result = run(&["--header", "X-All: yes", "--manifest", path_arg(&manifest)]);
+    assert_eq!(Some(1), result.status.code(), "{:?}", result);
+    assert_eq!("one", fs::read_to_string(dir.path().join("a")).unwrap());
+    assert_eq!("old", fs::read_to_string(dir.path().join("b")).unwrap());
+    assert!(!dir.path().join("c").exists());
+
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains("line 5: ") && stderr.contains("404"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("1 changed, 1 unchanged, 1 failed"),
+        "{}",
+        stderr
+    );
+
+    let requests = server.requests();
+    assert!(
+        requests[0].contains("X-All: yes") && requests[0].contains("X-Entry: a"),
+        "{:?}",
+        requests
+    );
+    assert!(
+        requests[1].contains("X-All: yes") && !requests[1].contains("X-Entry"),
+        "{:?}",
+        requests
+    );
+}
+
+#[test]
+fn manifest_checked_first() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = serve(vec![response("200 OK", &[], b"one")]);
+
+    let manifest = dir.path().join("manifest");
+    fs::write(
+        &manifest,
+        format!(
+            "{url}/a {dir}/a\n{url}/b {dir}/b --min-age soon\n",
+            url = server.url,
+            dir = path_arg(dir.path())
+        ),
+    )
+    .unwrap();
+
+    let result = run(&["--manifest", path_arg(&manifest)]);
+    assert_eq!(Some(2), result.status.code(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("line 2: "), "{}", stderr);
+    assert!(!dir.path().join("a").exists());
+    assert!(server.requests().is_empty());
+}
+
+#[test]
+fn manifest_jobs() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = serve(vec![response("200 OK", &[], b"same"); 3]);
+
+    let manifest = dir.path().join("manifest");
+    let lines: String = (1..=3)
+        .map(|n| format!("{}/{} {}/{}\n", server.url, n, path_arg(dir.path()), n))
+        .collect();
+    fs::write(&manifest, lines).unwrap();
+
+    let result = run(&["-vv", "--jobs", "3", "--manifest", path_arg(&manifest)]);
+    assert!(result.status.success(), "{:?}", result);
+    for n in 1..=3 {
+        let output = dir.path().join(n.to_string());
+        assert_eq!("same", fs::read_to_string(output).unwrap());
+    }
+
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    for n in 1..=3 {
+        assert!(
+            stderr.contains(&format!("line {}:       manifest: {}/{}", n, server.url, n)),
+            "{}",
+            stderr
+        );
+    }
+    assert!(
+        stderr.contains("3 changed, 0 unchanged, 0 failed"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn manifest_counts_every_request() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = serve(vec![
+        response("302 Found", &["Location: /moved"], b""),
+        response("200 OK", &[], b"moved"),
+        response("200 OK", &[], b"plain"),
+        response("200 OK", &[], b"again"),
+        response("200 OK", &[], b"again"),
+    ]);
+
+    // in order, so the redirect's hop is the next request
+    let manifest = dir.path().join("manifest");
+    fs::write(
+        &manifest,
+        format!(
+            "{url}/a {dir}/a\n{url}/b {dir}/b\n",
+            url = server.url,
+            dir = path_arg(dir.path())
+        ),
+    )
+    .unwrap();
+
+    let result = run(&["--stats", "--manifest", path_arg(&manifest)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("moved", fs::read_to_string(dir.path().join("a")).unwrap());
+    assert_eq!("plain", fs::read_to_string(dir.path().join("b")).unwrap());
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains("fetch-maybe: 3 requests for 2 entries, through one agent"),
+        "{}",
+        stderr
+    );
+
+    // only asked for with --stats, however verbose
+    let result = run(&["-vv", "--manifest", path_arg(&manifest)]);
+    assert!(result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(!stderr.contains("requests for"), "{}", stderr);
+}
+
+#[test]
+fn manifest_same_output_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    let manifest = dir.path().join("manifest");
+    fs::write(
+        &manifest,
+        format!(
+            "http://localhost/a {dir}/out\nhttp://localhost/b {dir}/./out\n",
+            dir = path_arg(dir.path())
+        ),
+    )
+    .unwrap();
+
+    let result = run(&["--manifest", path_arg(&manifest)]);
+    assert_eq!(Some(2), result.status.code(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("lines 1 and 2 both write"), "{}", stderr);
+}
+
+#[test]
+fn manifest_fail_fast() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = serve(vec![
+        response("404 Not Found", &[], b""),
+        response("200 OK", &[], b"two"),
+    ]);
+
+    let manifest = dir.path().join("manifest");
+    fs::write(
+        &manifest,
+        format!(
+            "{url}/a {dir}/a\n{url}/b {dir}/b\n",
+            url = server.url,
+            dir = path_arg(dir.path())
+        ),
+    )
+    .unwrap();
+
+    let result = run(&["--fail-fast", "--manifest", path_arg(&manifest)]);
+    assert_eq!(Some(1), result.status.code(), "{:?}", result);
+    assert!(!dir.path().join("b").exists());
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("1 failed, 1 not started"), "{}", stderr);
+}
+
+#[test]
+fn manifest_per_host_delay() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = serve(vec![response("200 OK", &[], b"same"); 2]);
+
+    let manifest = dir.path().join("manifest");
+    let lines: String = (1..=2)
+        .map(|n| format!("{}/{} {}/{}\n", server.url, n, path_arg(dir.path()), n))
+        .collect();
+    fs::write(&manifest, lines).unwrap();
+
+    let started = Instant::now();
+    let result = run(&[
+        "-vvv",
+        "--jobs",
+        "2",
+        "--per-host-delay",
+        "1s",
+        "--manifest",
+        path_arg(&manifest),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    assert!(started.elapsed() >= Duration::from_secs(1));
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains("per-host: line 2 waits") && stderr.contains("for 127.0.0.1"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn manifest_total_rate() {
+    let dir = tempfile::tempdir().unwrap();
+    let body = vec![b'x'; 20_000];
+    let server = serve(vec![response("200 OK", &[], &body); 2]);
+
+    let manifest = dir.path().join("manifest");
+    let lines: String = (1..=2)
+        .map(|n| format!("{}/{} {}/{}\n", server.url, n, path_arg(dir.path()), n))
+        .collect();
+    fs::write(&manifest, lines).unwrap();
+
+    // each alone could be done in a second, but not both together
+    let started = Instant::now();
+    let result = run(&[
+        "--jobs",
+        "2",
+        "--limit-rate",
+        "20k",
+        "--limit-rate-total",
+        "20k",
+        "--manifest",
+        path_arg(&manifest),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    assert!(
+        started.elapsed() >= Duration::from_millis(1500),
+        "{:?}",
+        started.elapsed()
+    );
+    for n in 1..=2 {
+        let output = dir.path().join(n.to_string());
+        assert_eq!(body, fs::read(output).unwrap());
+    }
+}
+
+#[test]
+fn stdin_streams_and_carries_on() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = serve(vec![
+        response("200 OK", &[], b"one"),
+        response("200 OK", &[], b"three"),
+    ]);
+
+    let input = format!(
+        "{url}/a {dir}/a\nnot-enough\n\n{url}/c\t{dir}/c --header 'X-Entry: c'\n",
+        url = server.url,
+        dir = path_arg(dir.path())
+    );
+    let result = run_with_stdin(&["--stdin"], input.as_bytes());
+    assert_eq!(Some(1), result.status.code(), "{:?}", result);
+    assert_eq!("one", fs::read_to_string(dir.path().join("a")).unwrap());
+    assert_eq!("three", fs::read_to_string(dir.path().join("c")).unwrap());
+    assert!(server.requests()[1].contains("X-Entry: c"));
+
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains("stdin line 2: expected a URL"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("stdin: 2 changed, 0 unchanged, 1 failed"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn stdin_null_pairs() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = serve(vec![response("200 OK", &[], b"spaced")]);
+
+    let input = format!(
+        "{url}/a\0{dir}/a b\0",
+        url = server.url,
+        dir = path_arg(dir.path())
+    );
+    let result = run_with_stdin(&["--stdin", "-0"], input.as_bytes());
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(
+        "spaced",
+        fs::read_to_string(dir.path().join("a b")).unwrap()
+    );
+}
+
+#[test]
+fn stdin_fail_fast() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = serve(vec![response("200 OK", &[], b"two")]);
+
+    let input = format!(
+        "broken\n{url}/b {dir}/b\n",
+        url = server.url,
+        dir = path_arg(dir.path())
+    );
+    let result = run_with_stdin(&["--stdin", "--fail-fast"], input.as_bytes());
+    assert_eq!(Some(1), result.status.code(), "{:?}", result);
+    assert!(!dir.path().join("b").exists());
+    assert!(server.requests().is_empty());
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains("stdin: 0 changed, 0 unchanged, 1 failed"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn manifest_events() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = serve(vec![response("200 OK", &[], b"same"); 2]);
+
+    let manifest = dir.path().join("manifest");
+    let lines: String = (1..=2)
+        .map(|n| format!("{}/{} {}/{}\n", server.url, n, path_arg(dir.path()), n))
+        .collect();
+    fs::write(&manifest, lines).unwrap();
+
+    let result = run(&["--events", "--jobs", "2", "--manifest", path_arg(&manifest)]);
+    assert!(result.status.success(), "{:?}", result);
+    let stdout = String::from_utf8(result.stdout.clone()).unwrap();
+    let events: Vec<json::Json> = stdout.lines().map(json::parse).collect();
+    for (i, event) in events.iter().enumerate() {
+        assert_eq!(
+            Some((i + 1) as f64),
+            event.get("sequence").number(),
+            "{}",
+            stdout
+        );
+    }
+    for entry in 1..=2 {
+        let done = events
+            .iter()
+            .filter(|e| Some(entry as f64) == e.get("entry").number())
+            .filter(|e| Some("done") == e.get("event").str())
+            .count();
+        assert_eq!(1, done, "{}", stdout);
+    }
+}
+
+#[test]
+fn manifest_fail_if_unchanged() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = serve(vec![
+        response("304 Not Modified", &[], b""),
+        response("304 Not Modified", &[], b""),
+        response("304 Not Modified", &[], b""),
+        response("200 OK", &[], b"new"),
+    ]);
+
+    let manifest = dir.path().join("manifest");
+    let lines: String = (1..=2)
+        .map(|n| format!("{}/{} {}/{}\n", server.url, n, path_arg(dir.path()), n))
+        .collect();
+    fs::write(&manifest, lines).unwrap();
+
+    let result = run(&["--fail-if-unchanged", "--manifest", path_arg(&manifest)]);
+    assert_eq!(Some(100), result.status.code(), "{:?}", result);
+
+    // one change is enough
+    let result = run(&["--fail-if-unchanged", "--manifest", path_arg(&manifest)]);
+    assert_eq!(Some(0), result.status.code(), "{:?}", result);
+}
@@ -0,0 +1,1451 @@
+use std::fs;
+
+mod common;
+use common::path_arg;
+use common::response;
+use common::run;
+use common::serve;
+
+const ABC: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+#[test]
+fn directory_output_named_from_url() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = serve(vec![response("200 OK", &[], b"abc")]);
+
+    let result = run(&[
+        &format!("{}/releases/foo-1.2.3.tar.gz?token=x", server.url),
+        &format!("{}/", path_arg(dir.path())),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(
+        "abc",
+        fs::read_to_string(dir.path().join("foo-1.2.3.tar.gz")).unwrap()
+    );
+}
+
+#[test]
+fn directory_output_needs_a_name() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = serve(vec![]);
+
+    let result = run(&[&format!("{}/releases/", server.url), path_arg(dir.path())]);
+    assert!(!result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("name the output explicitly"), "{}", stderr);
+    assert!(server.requests().is_empty());
+}
+
+#[test]
+fn content_disposition_names_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = serve(vec![
+        response(
+            "200 OK",
+            &["Content-Disposition: attachment; filename=\"report-2024-05.pdf\""],
+            b"pdf",
+        ),
+        response("200 OK", &[], b"nameless"),
+    ]);
+
+    let url = format!("{}/download/", server.url);
+    let args = ["--content-disposition", &url, path_arg(dir.path())];
+
+    let result = run(&args);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(
+        "pdf",
+        fs::read_to_string(dir.path().join("report-2024-05.pdf")).unwrap()
+    );
+
+    let result = run(&args);
+    assert!(!result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("name the output explicitly"), "{}", stderr);
+    assert_eq!(1, fs::read_dir(dir.path()).unwrap().count());
+}
+
+#[test]
+fn hostile_content_disposition_stays_in_directory() {
+    let root = tempfile::tempdir().unwrap();
+    let dir = root.path().join("downloads");
+    fs::create_dir(&dir).unwrap();
+    let server = serve(vec![response(
+        "200 OK",
+        &["Content-Disposition: attachment; filename*=UTF-8''..%2F..%2Fescaped"],
+        b"abc",
+    )]);
+
+    let result = run(&[
+        "--content-disposition",
+        &format!("{}/file", server.url),
+        path_arg(&dir),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("abc", fs::read_to_string(dir.join("escaped")).unwrap());
+    assert!(!root.path().join("escaped").exists());
+}
+
+#[test]
+fn parent_is_a_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("file");
+    fs::write(&file, b"").unwrap();
+    let server = serve(vec![]);
+
+    let result = run(&[&server.url, path_arg(&file.join("out"))]);
+    assert!(!result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("is not a directory"), "{}", stderr);
+    assert!(server.requests().is_empty());
+}
+
+#[test]
+fn output_is_a_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("data")).unwrap();
+    let server = serve(vec![]);
+
+    // the directory argument is fine, it's the name made inside it that's taken
+    let url = format!("{}/data", server.url);
+    let result = run(&[&url, &format!("{}/", path_arg(dir.path()))]);
+    assert_eq!(Some(9), result.status.code(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains("/data\" is a directory, not a file to replace"),
+        "{}",
+        stderr
+    );
+    assert!(server.requests().is_empty());
+    assert!(dir.path().join("data").is_dir());
+}
+
+#[test]
+fn create_dirs() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("2024/05/01/out");
+    let server = serve(vec![response("200 OK", &[], b"abc")]);
+
+    let result = run(&[&server.url, path_arg(&output)]);
+    assert!(!result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("parent directory"), "{}", stderr);
+
+    let result = run(&["--create-dirs", &server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("abc", fs::read_to_string(&output).unwrap());
+}
+
+#[test]
+fn mode_applied() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let server = serve(vec![response("200 OK", &[], b"abc")]);
+
+    let result = run(&["--mode", "0640", &server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(
+        0o640,
+        fs::metadata(&output).unwrap().permissions().mode() & 0o7777
+    );
+
+    let result = run(&["--mode", "0999", &server.url, path_arg(&output)]);
+    assert!(!result.status.success(), "{:?}", result);
+}
+
+#[test]
+fn chown_unknown_user_fails_early() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = serve(vec![]);
+
+    let result = run(&[
+        "--chown",
+        "no-such-user-hopefully:root",
+        &server.url,
+        path_arg(&dir.path().join("out")),
+    ]);
+    assert!(!result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("no such user"), "{}", stderr);
+    assert!(server.requests().is_empty());
+}
+
+#[test]
+fn mode_preserved() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    fs::write(&output, b"old").unwrap();
+    fs::set_permissions(&output, fs::Permissions::from_mode(0o604)).unwrap();
+    let mode = || fs::metadata(&output).unwrap().permissions().mode() & 0o7777;
+    let server = serve(vec![
+        response("200 OK", &[], b"abc"),
+        response("200 OK", &[], b"abc"),
+        response("200 OK", &[], b"abc"),
+    ]);
+
+    let result = run(&[&server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(0o604, mode());
+
+    let result = run(&["--mode", "0640", &server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(0o640, mode());
+
+    let result = run(&["--no-preserve", &server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(0o666 & !umask(), mode());
+}
+
+/// Read without setting it, unlike umask(2), as the tests run in parallel.
+fn umask() -> u32 {
+    let status = fs::read_to_string("/proc/self/status").unwrap();
+    let line = status
+        .lines()
+        .find(|l| l.starts_with("Umask:"))
+        .expect("Linux 4.7+");
+    u32::from_str_radix(line["Umask:".len()..].trim(), 8).unwrap()
+}
+
+#[test]
+fn new_output_respects_umask() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let server = serve(vec![response("200 OK", &[], b"abc")]);
+
+    let result = run(&[&server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(
+        0o666 & !umask(),
+        fs::metadata(&output).unwrap().permissions().mode() & 0o7777
+    );
+}
+
+#[test]
+fn user_xattrs_preserved() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    fs::write(&output, b"old").unwrap();
+    if xattr::set(&output, "user.origin", b"mirror").is_err() {
+        // the filesystem under the temporary directory doesn't do user xattrs
+        return;
+    }
+    let server = serve(vec![
+        response("200 OK", &[], b"abc"),
+        response("200 OK", &[], b"abc"),
+    ]);
+
+    let result = run(&["--preserve-xattrs", &server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(
+        Some(b"mirror".to_vec()),
+        xattr::get(&output, "user.origin").unwrap()
+    );
+
+    let result = run(&[&server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(None, xattr::get(&output, "user.origin").unwrap());
+}
+
+#[test]
+fn times_from_last_modified() {
+    use std::time::Duration;
+    use std::time::UNIX_EPOCH;
+
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let server = serve(vec![response(
+        "200 OK",
+        &["Last-Modified: Wed, 21 Oct 2015 07:28:00 GMT"],
+        b"abc",
+    )]);
+
+    let result = run(&[&server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+
+    let expected = UNIX_EPOCH + Duration::from_secs(1_445_412_480);
+    let metadata = fs::metadata(&output).unwrap();
+    assert_eq!(expected, metadata.modified().unwrap());
+    assert_eq!(expected, metadata.accessed().unwrap());
+}
+
+#[test]
+fn no_mtime_keeps_download_time() {
+    use std::time::Duration;
+    use std::time::SystemTime;
+
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let server = serve(vec![response(
+        "200 OK",
+        &["Last-Modified: Wed, 21 Oct 2015 07:28:00 GMT"],
+        b"abc",
+    )]);
+
+    let before = SystemTime::now() - Duration::from_secs(1);
+    let result = run(&["--no-mtime", &server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert!(fs::metadata(&output).unwrap().modified().unwrap() > before);
+}
+
+#[test]
+fn mtime_from_keep() {
+    use std::time::Duration;
+    use std::time::UNIX_EPOCH;
+
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    fs::write(&output, b"abc").unwrap();
+    let old = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    filetime::set_file_mtime(&output, filetime::FileTime::from(old)).unwrap();
+
+    let last_modified = "Last-Modified: Wed, 21 Oct 2015 07:28:00 GMT";
+    let server = serve(vec![
+        response("200 OK", &[last_modified], b"abc"),
+        response("200 OK", &[last_modified], b"abd"),
+    ]);
+    let args = ["--mtime-from", "keep", &server.url, path_arg(&output)];
+
+    // identical: the old time carries forward, even though the server claims a newer one
+    let result = run(&args);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(old, fs::metadata(&output).unwrap().modified().unwrap());
+
+    // changed: the server's time, as usual
+    let result = run(&args);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(
+        UNIX_EPOCH + Duration::from_secs(1_445_412_480),
+        fs::metadata(&output).unwrap().modified().unwrap()
+    );
+}
+
+#[test]
+fn if_modified_since_whole_seconds() {
+    use std::time::Duration;
+    use std::time::UNIX_EPOCH;
+
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    fs::write(&output, b"abc").unwrap();
+    let mtime = UNIX_EPOCH + Duration::from_millis(1_445_412_480_456);
+    filetime::set_file_mtime(&output, filetime::FileTime::from(mtime)).unwrap();
+    let server = serve(vec![response("304 Not Modified", &[], b"")]);
+
+    let result = run(&[&server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    let requests = server.requests();
+    assert!(
+        requests[0].contains("If-Modified-Since: Wed, 21 Oct 2015 07:28:00 GMT\r\n"),
+        "{:?}",
+        requests
+    );
+}
+
+#[test]
+fn backup_kept() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    fs::write(&output, b"one").unwrap();
+    let server = serve(vec![
+        response("200 OK", &[], b"two"),
+        response("200 OK", &[], b"two"),
+        response("200 OK", &[], b"three"),
+    ]);
+
+    let result = run(&["--backup", &server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("one", fs::read_to_string(dir.path().join("out~")).unwrap());
+
+    // the same content again isn't a new version, so the backup stays
+    let result = run(&["--backup", &server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("one", fs::read_to_string(dir.path().join("out~")).unwrap());
+
+    let result = run(&["--backup=.old", &server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(
+        "two",
+        fs::read_to_string(dir.path().join("out.old")).unwrap()
+    );
+    assert_eq!("three", fs::read_to_string(&output).unwrap());
+}
+
+#[test]
+fn stdout_output() {
+    let server = serve(vec![response("200 OK", &[], b"abc")]);
+
+    let result = run(&["--sha256", ABC, &server.url, "-"]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(b"abc", result.stdout.as_slice());
+
+    let result = run(&["--backup", &server.url, "-"]);
+    assert!(!result.status.success(), "{:?}", result);
+    assert!(result.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains("--backup needs an output file"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn discarded_output() {
+    use std::os::unix::fs::FileTypeExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let etag = dir.path().join("etag");
+    fs::write(&etag, "\"v1\"\n").unwrap();
+    let server = serve(vec![
+        response("200 OK", &[], b"abc"),
+        response("200 OK", &[], b"abc"),
+        response("200 OK", &[], b"abd"),
+        response("304 Not Modified", &[], b""),
+    ]);
+
+    for output in &["--discard", "/dev/null"] {
+        let result = run(&["--sha256", ABC, &server.url, output]);
+        assert!(result.status.success(), "{:?}", result);
+        assert!(result.stdout.is_empty(), "{:?}", result);
+    }
+    // still the device, not replaced by a download
+    assert!(fs::metadata("/dev/null")
+        .unwrap()
+        .file_type()
+        .is_char_device());
+
+    let result = run(&["--sha256", ABC, "--discard", &server.url]);
+    assert!(!result.status.success(), "{:?}", result);
+
+    let result = run(&[
+        "--discard",
+        "--etag-compare",
+        path_arg(&etag),
+        "-z",
+        path_arg(&etag),
+        &server.url,
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    let requests = server.requests();
+    assert_eq!(4, requests.len());
+    assert!(
+        requests[3].contains("If-None-Match: \"v1\""),
+        "{:?}",
+        requests
+    );
+    assert!(
+        requests[3].contains("If-Modified-Since: "),
+        "{:?}",
+        requests
+    );
+
+    let result = run(&["--discard", "--backup", &server.url]);
+    assert!(!result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains("--backup needs an output file, so can't be used when discarding"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn output_fd() {
+    use std::process::Command;
+    use std::process::Stdio;
+
+    let dir = tempfile::tempdir().unwrap();
+    let target = dir.path().join("supervised");
+    let server = serve(vec![response("200 OK", &[], b"abc")]);
+
+    // the descriptor the supervisor hands over; stdout is just the easiest to arrange
+    let result = Command::new(env!("CARGO_BIN_EXE_fetch-maybe"))
+        .args(["--output-fd", "1", &server.url])
+        .stdout(Stdio::from(fs::File::create(&target).unwrap()))
+        .env_remove("RUST_BACKTRACE")
+        .output()
+        .unwrap();
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("abc", fs::read_to_string(&target).unwrap());
+
+    let result = Command::new(env!("CARGO_BIN_EXE_fetch-maybe"))
+        .args(["--output-fd", "0", &server.url])
+        .stdin(Stdio::from(fs::File::open(&target).unwrap()))
+        .env_remove("RUST_BACKTRACE")
+        .output()
+        .unwrap();
+    assert!(!result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("read-only"), "{}", stderr);
+}
+
+#[test]
+fn output_symlink_followed() {
+    use std::os::unix::fs::symlink;
+
+    let dir = tempfile::tempdir().unwrap();
+    let real = dir.path().join("real");
+    let link = dir.path().join("link");
+    fs::write(&real, b"old").unwrap();
+    symlink("real", &link).unwrap();
+    let server = serve(vec![
+        response("200 OK", &[], b"new"),
+        response("200 OK", &[], b"newer"),
+        response("200 OK", &[], b"made"),
+    ]);
+
+    let result = run(&["--output-symlink", "follow", &server.url, path_arg(&link)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert!(fs::symlink_metadata(&link)
+        .unwrap()
+        .file_type()
+        .is_symlink());
+    assert_eq!("new", fs::read_to_string(&real).unwrap());
+
+    // the default replaces the link itself
+    let result = run(&[&server.url, path_arg(&link)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert!(fs::symlink_metadata(&link).unwrap().file_type().is_file());
+    assert_eq!("new", fs::read_to_string(&real).unwrap());
+
+    let dangling = dir.path().join("dangling");
+    symlink("sub/made", &dangling).unwrap();
+    let result = run(&[
+        "--output-symlink",
+        "follow",
+        &server.url,
+        path_arg(&dangling),
+    ]);
+    assert!(!result.status.success(), "{:?}", result);
+
+    let result = run(&[
+        "--output-symlink",
+        "follow",
+        "--create-dirs",
+        &server.url,
+        path_arg(&dangling),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("made", fs::read_to_string(&dangling).unwrap());
+}
+
+#[test]
+fn symlinked_parent_directory() {
+    use std::os::unix::fs::symlink;
+
+    let dir = tempfile::tempdir().unwrap();
+    let real = dir.path().join("real");
+    fs::create_dir(&real).unwrap();
+    let link = dir.path().join("link");
+    symlink("real", &link).unwrap();
+    // the output itself, as given, is still what's replaced
+    fs::write(dir.path().join("elsewhere"), b"old").unwrap();
+    symlink("../elsewhere", real.join("out")).unwrap();
+    let server = serve(vec![response("200 OK", &[], b"new")]);
+
+    let output = link.join("out");
+    let result = run(&["-vvv", &server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert!(fs::symlink_metadata(&link)
+        .unwrap()
+        .file_type()
+        .is_symlink());
+    assert!(fs::symlink_metadata(real.join("out"))
+        .unwrap()
+        .file_type()
+        .is_file());
+    assert_eq!("new", fs::read_to_string(real.join("out")).unwrap());
+    assert_eq!(
+        "old",
+        fs::read_to_string(dir.path().join("elsewhere")).unwrap()
+    );
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains(&format!(
+            "output tmp dir: {:?}, resolved to {:?}",
+            link,
+            real.canonicalize().unwrap()
+        )),
+        "{}",
+        stderr
+    );
+
+    let gone = dir.path().join("gone");
+    symlink("nowhere", &gone).unwrap();
+    let result = run(&[&server.url, path_arg(&gone.join("out"))]);
+    assert_eq!(Some(9), result.status.code(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains(&format!(
+            "output directory {:?} is a symlink to \"nowhere\", which doesn't exist",
+            gone
+        )),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn fsync_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let server = serve(vec![response("200 OK", &[], b"abc")]);
+
+    let result = run(&["--fsync", &server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("abc", fs::read_to_string(&output).unwrap());
+}
+
+#[test]
+fn temp_file_failures_say_why() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let server = serve(vec![]);
+
+    // relative, so the message has to say which directory it means
+    let missing = "no-such-temp-dir";
+    let result = run(&["--temp-dir", missing, &server.url, path_arg(&output)]);
+    assert_eq!(Some(9), result.status.code(), "{:?}", result);
+    let absolute = std::env::current_dir().unwrap().join(missing);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains(&format!(
+            "can't create a temporary file in {:?}: it doesn't exist",
+            absolute
+        )),
+        "{}",
+        stderr
+    );
+
+    // root can write anywhere, read-only permissions or not
+    if 0 == unsafe { libc::geteuid() } {
+        return;
+    }
+    let read_only = tempfile::tempdir().unwrap();
+    fs::set_permissions(read_only.path(), fs::Permissions::from_mode(0o555)).unwrap();
+    let result = run(&[
+        "--temp-dir",
+        path_arg(read_only.path()),
+        &server.url,
+        path_arg(&output),
+    ]);
+    assert_eq!(Some(9), result.status.code(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains("we aren't allowed to write there"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn temp_dir_on_another_filesystem() {
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::Duration;
+    use std::time::UNIX_EPOCH;
+
+    // only a different filesystem from the usual temp dir on some machines, but harmless elsewhere
+    let staging = match tempfile::tempdir_in("/dev/shm") {
+        Ok(dir) => dir,
+        Err(_) => return,
+    };
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let server = serve(vec![response(
+        "200 OK",
+        &["Last-Modified: Wed, 21 Oct 2015 07:28:00 GMT"],
+        b"abc",
+    )]);
+
+    let result = run(&[
+        "-vvv",
+        "--mode",
+        "0640",
+        "--temp-dir",
+        path_arg(staging.path()),
+        &server.url,
+        path_arg(&output),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("abc", fs::read_to_string(&output).unwrap());
+    let metadata = fs::metadata(&output).unwrap();
+    assert_eq!(0o640, metadata.permissions().mode() & 0o7777);
+    assert_eq!(
+        UNIX_EPOCH + Duration::from_secs(1_445_412_480),
+        metadata.modified().unwrap()
+    );
+    assert_eq!(0, fs::read_dir(staging.path()).unwrap().count());
+
+    use std::os::unix::fs::MetadataExt;
+    if fs::metadata(staging.path()).unwrap().dev() != fs::metadata(dir.path()).unwrap().dev() {
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        assert!(stderr.contains("another filesystem"), "{}", stderr);
+    }
+}
+
+#[test]
+fn locked_output_skipped() {
+    use std::os::unix::io::AsRawFd;
+
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let held = fs::File::create(dir.path().join("out.lock")).unwrap();
+    assert_eq!(0, unsafe {
+        libc::flock(held.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB)
+    });
+    let server = serve(vec![response("200 OK", &[], b"abc")]);
+
+    let result = run(&[&server.url, path_arg(&output)]);
+    assert_eq!(Some(75), result.status.code(), "{:?}", result);
+    assert!(server.requests().is_empty());
+    assert!(!output.exists());
+
+    let result = run(&["--no-lock", &server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("abc", fs::read_to_string(&output).unwrap());
+}
+
+#[test]
+fn output_changed_while_downloading() {
+    use std::io::Read;
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    fs::write(&output, b"old").unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let edited = output.clone();
+    let server = std::thread::spawn(move || {
+        // the first of each pair of requests sees someone else edit the output mid-download
+        for edit in &[true, true, false] {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut head = [0u8; 1024];
+            let _ = stream.read(&mut head).unwrap();
+            if *edit {
+                fs::write(&edited, b"hand edited").unwrap();
+            }
+            stream.write_all(&response("200 OK", &[], b"new")).unwrap();
+        }
+    });
+
+    let result = run(&["--on-conflict", "fail", &url, path_arg(&output)]);
+    assert!(!result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("changed while downloading"), "{}", stderr);
+    assert_eq!("hand edited", fs::read_to_string(&output).unwrap());
+
+    let result = run(&["--on-conflict", "retry", &url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("new", fs::read_to_string(&output).unwrap());
+    server.join().unwrap();
+}
+
+#[test]
+fn min_free_checked_up_front() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    fs::write(&output, b"old").unwrap();
+    let server = serve(vec![
+        response("200 OK", &[], b"abc"),
+        response("200 OK", &[], b"abc"),
+    ]);
+
+    let result = run(&["--min-free", "1000000T", &server.url, path_arg(&output)]);
+    assert!(!result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("need 3 bytes"), "{}", stderr);
+    assert_eq!("old", fs::read_to_string(&output).unwrap());
+
+    let result = run(&["--min-free", "1k", &server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("abc", fs::read_to_string(&output).unwrap());
+}
+
+#[test]
+fn in_place_keeps_inode() {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let other = dir.path().join("other");
+    fs::write(&output, b"old and longer").unwrap();
+    fs::hard_link(&output, &other).unwrap();
+    let inode = fs::metadata(&output).unwrap().ino();
+    let server = serve(vec![response("200 OK", &[], b"new")]);
+
+    let result = run(&["--in-place", "--backup", &server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("new", fs::read_to_string(&output).unwrap());
+    assert_eq!("new", fs::read_to_string(&other).unwrap());
+    assert_eq!(inode, fs::metadata(&output).unwrap().ino());
+    assert_eq!(
+        "old and longer",
+        fs::read_to_string(dir.path().join("out~")).unwrap()
+    );
+}
+
+#[test]
+fn also_link() {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out-1.0");
+    let latest = dir.path().join("latest");
+    fs::write(&latest, b"previous").unwrap();
+    let server = serve(vec![response("200 OK", &[], b"new")]);
+
+    let result = run(&[
+        "--also-link",
+        path_arg(&latest),
+        "--also-link",
+        path_arg(&dir.path().join("missing/latest")),
+        &server.url,
+        path_arg(&output),
+    ]);
+    assert!(!result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("missing/latest"), "{}", stderr);
+
+    assert_eq!("new", fs::read_to_string(&output).unwrap());
+    assert_eq!(
+        fs::metadata(&output).unwrap().ino(),
+        fs::metadata(&latest).unwrap().ino()
+    );
+}
+
+#[test]
+fn no_clobber() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let server = serve(vec![response("200 OK", &[], b"new")]);
+
+    let result = run(&["--no-clobber", &server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("new", fs::read_to_string(&output).unwrap());
+
+    fs::write(&output, b"seeded").unwrap();
+    let result = run(&["--no-clobber", &server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("seeded", fs::read_to_string(&output).unwrap());
+    assert_eq!(1, server.requests().len());
+}
+
+#[test]
+fn immutable_output_fails_early() {
+    use std::process::Command;
+
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    fs::write(&output, b"old").unwrap();
+
+    // needs root, and a filesystem with the flag
+    let chattr = |flag: &str| {
+        Command::new("chattr")
+            .args([flag, path_arg(&output)])
+            .output()
+            .map(|o| o.status.success())
+            .unwrap_or(false)
+    };
+    if !chattr("+i") {
+        return;
+    }
+
+    let server = serve(vec![response("200 OK", &[], b"new")]);
+    let result = run(&[&server.url, path_arg(&output)]);
+    chattr("-i");
+
+    assert!(!result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("immutable"), "{}", stderr);
+    assert!(server.requests().is_empty());
+    assert_eq!("old", fs::read_to_string(&output).unwrap());
+}
+
+#[test]
+fn cache_dir() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = dir.path().join("cache");
+    let server = serve(vec![
+        response("200 OK", &["ETag: \"v1\""], b"abc"),
+        response("304 Not Modified", &[], b""),
+    ]);
+    let url = format!("{}/dist/foo.tar.gz", server.url);
+
+    let result = run(&["--cache-dir", path_arg(&cache), &url]);
+    assert!(result.status.success(), "{:?}", result);
+    let printed = String::from_utf8(result.stdout).unwrap();
+    let entry = std::path::PathBuf::from(printed.trim_end());
+    assert!(entry.is_absolute(), "{:?}", entry);
+    assert!(entry.starts_with(fs::canonicalize(&cache).unwrap()));
+    assert!(
+        entry.to_str().unwrap().ends_with("-foo.tar.gz"),
+        "{:?}",
+        entry
+    );
+    assert_eq!("abc", fs::read_to_string(&entry).unwrap());
+
+    let result = run(&["--cache-dir", path_arg(&cache), &url]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(printed, String::from_utf8(result.stdout).unwrap());
+    assert_eq!("abc", fs::read_to_string(&entry).unwrap());
+
+    let requests = server.requests();
+    assert!(
+        requests[1].contains("If-None-Match: \"v1\""),
+        "{:?}",
+        requests
+    );
+}
+
+#[test]
+fn cache_gc() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = dir.path().join("cache");
+    let server = serve(vec![response("200 OK", &[], b"abc")]);
+    let result = run(&[
+        "--cache-dir",
+        path_arg(&cache),
+        &format!("{}/foo.tar.gz", server.url),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    let entry = String::from_utf8(result.stdout)
+        .unwrap()
+        .trim_end()
+        .to_string();
+    fs::write(cache.join("notes.txt"), "mine").unwrap();
+
+    let gc = |extra: &[&str]| {
+        let mut args = vec![
+            "--gc",
+            "--cache-dir",
+            path_arg(&cache),
+            "--cache-max-size",
+            "1",
+        ];
+        args.extend_from_slice(extra);
+        run(&args)
+    };
+
+    let result = gc(&["--dry-run"]);
+    assert!(result.status.success(), "{:?}", result);
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    assert!(stdout.contains("would remove"), "{}", stdout);
+    assert!(stdout.contains(".meta"), "{}", stdout);
+    assert!(!stdout.contains("notes.txt"), "{}", stdout);
+    assert!(fs::metadata(&entry).is_ok());
+
+    let result = gc(&[]);
+    assert!(result.status.success(), "{:?}", result);
+    let names: Vec<_> = fs::read_dir(&cache)
+        .unwrap()
+        .map(|e| e.unwrap().file_name())
+        .collect();
+    assert_eq!(vec!["notes.txt"], names);
+}
+
+#[test]
+fn store_shares_identical_outputs() {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let store = dir.path().join("store");
+    let first = dir.path().join("first");
+    let second = dir.path().join("second");
+    let server = serve(vec![response("200 OK", &[], b"abc")]);
+
+    let result = run(&["--store", path_arg(&store), &server.url, path_arg(&first)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(
+        fs::metadata(&first).unwrap().ino(),
+        fs::metadata(store.join(ABC)).unwrap().ino()
+    );
+
+    // the server has nothing more to give, so this must come from the store
+    let result = run(&[
+        "--store",
+        path_arg(&store),
+        "--sha256",
+        ABC,
+        &server.url,
+        path_arg(&second),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("abc", fs::read_to_string(&second).unwrap());
+    assert_eq!(
+        fs::metadata(&first).unwrap().ino(),
+        fs::metadata(&second).unwrap().ino()
+    );
+    assert_eq!(1, server.requests().len());
+}
+
+#[test]
+fn history_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let history = dir.path().join("history.jsonl");
+    let server = serve(vec![
+        response("200 OK", &[], b"abc"),
+        response("304 Not Modified", &[], b""),
+        response("304 Not Modified", &[], b""),
+    ]);
+
+    let fetch = |all: bool| {
+        let mut args = vec!["--history-file", path_arg(&history)];
+        if all {
+            args.push("--history-all");
+        }
+        args.extend(&[server.url.as_str(), path_arg(&output)]);
+        let result = run(&args);
+        assert!(result.status.success(), "{:?}", result);
+    };
+
+    fetch(false);
+    fetch(false);
+    fetch(true);
+
+    let history = fs::read_to_string(&history).unwrap();
+    let lines: Vec<&str> = history.lines().collect();
+    assert_eq!(2, lines.len(), "{}", history);
+    assert!(lines[0].contains("\"result\":\"fetched\""), "{}", lines[0]);
+    assert!(
+        lines[0].contains("\"status\":200,\"bytes\":3"),
+        "{}",
+        lines[0]
+    );
+    assert!(lines[0].contains(ABC), "{}", lines[0]);
+    assert!(
+        lines[1].contains("\"result\":\"unchanged\""),
+        "{}",
+        lines[1]
+    );
+    assert!(lines[1].contains("\"status\":304"), "{}", lines[1]);
+}
+
+#[test]
+fn output_name_template() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = serve(vec![
+        response(
+            "200 OK",
+            &[
+                "ETag: \"v1\"",
+                "Last-Modified: Thu, 03 Oct 2019 12:00:00 GMT",
+            ],
+            b"abc",
+        ),
+        response("200 OK", &[], b"abc"),
+    ]);
+    let url = format!("{}/dist/foo.tar.gz", server.url);
+
+    let template = dir
+        .path()
+        .join("{url_basename}-{etag}-{last_modified:%Y%m%d}");
+    let result = run(&[&url, path_arg(&template)]);
+    assert!(result.status.success(), "{:?}", result);
+    let named = dir.path().join("foo.tar.gz-v1-20191003");
+    assert_eq!("abc", fs::read_to_string(&named).unwrap());
+
+    let template = dir.path().join("{sha256:8}.tar.gz");
+    let result = run(&[&url, path_arg(&template)]);
+    assert!(result.status.success(), "{:?}", result);
+    let hashed = dir.path().join(format!("{}.tar.gz", &ABC[..8]));
+    assert_eq!("abc", fs::read_to_string(&hashed).unwrap());
+
+    // just the two outputs; the temporary files went with the renames
+    assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+}
+
+#[test]
+fn output_name_template_unknown_placeholder() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = serve(vec![response("200 OK", &[], b"abc")]);
+
+    let template = dir.path().join("{nope}");
+    let result = run(&[&server.url, path_arg(&template)]);
+    assert!(!result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("{sha256:LENGTH}"), "{}", stderr);
+    assert!(server.requests().is_empty());
+}
+
+#[test]
+fn on_change_hooks() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let log = dir.path().join("log");
+    let server = serve(vec![
+        response("200 OK", &[], b"abc"),
+        response("200 OK", &[], b"abc"),
+        response("304 Not Modified", &[], b""),
+        response("200 OK", &[], b"abd"),
+    ]);
+
+    let on_change = format!(
+        "echo \"changed $FETCH_MAYBE_SHA256 $(cat {{}})\" >> {}",
+        path_arg(&log)
+    );
+    let on_unchanged = format!("echo unchanged >> {}", path_arg(&log));
+    let fetch = || {
+        let result = run(&[
+            "--no-mtime",
+            "--on-change",
+            &on_change,
+            "--on-unchanged",
+            &on_unchanged,
+            &server.url,
+            path_arg(&output),
+        ]);
+        assert!(result.status.success(), "{:?}", result);
+    };
+
+    // new, the same bytes again, not modified, then different
+    for _ in 0..4 {
+        fetch();
+    }
+
+    let unchanged = format!("unchanged {}\n", path_arg(&output));
+    assert_eq!(
+        format!(
+            "changed {} abc\n{}{}changed {} abd\n",
+            ABC,
+            unchanged,
+            unchanged,
+            "a52d159f262b2c6ddb724a61840befc36eb30c88877a4030b65cbe86298449c9"
+        ),
+        fs::read_to_string(&log).unwrap()
+    );
+}
+
+#[test]
+fn on_change_failure() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let server = serve(vec![
+        response("200 OK", &[], b"abc"),
+        response("200 OK", &[], b"abd"),
+    ]);
+
+    let result = run(&["--on-change", "false", &server.url, path_arg(&output)]);
+    assert!(!result.status.success(), "{:?}", result);
+    assert_eq!("abc", fs::read_to_string(&output).unwrap());
+
+    let result = run(&[
+        "--on-change",
+        "false",
+        "--on-change-failure",
+        "warn",
+        &server.url,
+        path_arg(&output),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+}
+
+#[test]
+fn on_change_output_is_shown() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let server = serve(vec![response("200 OK", &[], b"abc")]);
+
+    let result = run(&[
+        "--on-change",
+        "echo reloaded; echo and said so >&2",
+        &server.url,
+        path_arg(&output),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("reloaded\n", String::from_utf8_lossy(&result.stdout));
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("and said so"), "{}", stderr);
+}
+
+#[test]
+fn future_mtime_from_a_fast_server() {
+    use std::time::Duration;
+    use std::time::SystemTime;
+
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let hour_ahead = SystemTime::now() + Duration::from_secs(3600);
+    let last_modified = format!(
+        "Last-Modified: {}",
+        chrono::DateTime::<chrono::Utc>::from(hour_ahead).format("%a, %d %b %Y %H:%M:%S GMT")
+    );
+    let server = serve(vec![response("200 OK", &[&last_modified], b"abc"); 3]);
+    let mtime = || fs::metadata(&output).unwrap().modified().unwrap();
+
+    let result = run(&["-v", &server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert!(mtime() < SystemTime::now() + Duration::from_secs(1));
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains("the server's Last-Modified is 3") && stderr.contains("taking it as now"),
+        "{}",
+        stderr
+    );
+
+    let result = run(&["--future-mtime", "keep", &server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert!(mtime() > SystemTime::now() + Duration::from_secs(3000));
+
+    fs::remove_file(&output).unwrap();
+    let result = run(&[
+        "-v",
+        "--future-mtime",
+        "reject",
+        &server.url,
+        path_arg(&output),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    assert!(mtime() < SystemTime::now() + Duration::from_secs(1));
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("so ignoring it"), "{}", stderr);
+}
+
+#[test]
+fn future_mtime_of_the_output() {
+    use std::time::Duration;
+    use std::time::SystemTime;
+
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    fs::write(&output, b"abc").unwrap();
+    let hour_ahead = SystemTime::now() + Duration::from_secs(3600);
+    let server = serve(vec![response("304 Not Modified", &[], b""); 3]);
+
+    let conditional = |policy: &str| -> Option<String> {
+        filetime::set_file_mtime(&output, filetime::FileTime::from(hour_ahead)).unwrap();
+        let result = run(&["--future-mtime", policy, &server.url, path_arg(&output)]);
+        assert!(result.status.success(), "{:?}", result);
+        let request = server.requests().pop().unwrap();
+        let since = request
+            .lines()
+            .find(|l| l.starts_with("If-Modified-Since: "))?
+            .trim_start_matches("If-Modified-Since: ")
+            .to_string();
+        Some(since)
+    };
+    let date =
+        |since: String| SystemTime::from(chrono::DateTime::parse_from_rfc2822(&since).unwrap());
+
+    assert!(date(conditional("clamp").unwrap()) < SystemTime::now() + Duration::from_secs(1));
+    assert!(date(conditional("keep").unwrap()) > SystemTime::now() + Duration::from_secs(3000));
+    assert_eq!(None, conditional("reject"));
+}
+
+#[test]
+fn quota() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let quota = dir.path().join("quota");
+    let server = serve(vec![
+        response("200 OK", &[], b"abcdef"),
+        response("200 OK", &[], b"ghijkl"),
+        response(
+            "200 OK",
+            &["Transfer-Encoding: chunked"],
+            b"6\r\nghijkl\r\n0\r\n\r\n",
+        ),
+    ]);
+    let fetch = || {
+        run(&[
+            "--quota-file",
+            path_arg(&quota),
+            "--quota",
+            "10",
+            &server.url,
+            path_arg(&output),
+        ])
+    };
+    let used = || {
+        let text = fs::read_to_string(&quota).unwrap();
+        let bytes = text.lines().find_map(|l| l.strip_prefix("bytes ")).unwrap();
+        bytes.parse::<u64>().unwrap()
+    };
+
+    let result = fetch();
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(6, used());
+
+    // the Content-Length says it won't fit
+    let result = fetch();
+    assert_eq!(Some(11), result.status.code(), "{:?}", result);
+    assert_eq!(6, used());
+
+    // with no Content-Length, it's only found out part way, but that still counts
+    let result = fetch();
+    assert_eq!(Some(11), result.status.code(), "{:?}", result);
+    assert!(used() > 10, "{}", used());
+    assert_eq!("abcdef", fs::read_to_string(&output).unwrap());
+}
+
+#[test]
+fn verify_cache_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = dir.path().join("cache");
+    let server = serve(vec![response("200 OK", &[], b"abc")]);
+    let result = run(&[
+        "--cache-dir",
+        path_arg(&cache),
+        &format!("{}/foo.tar.gz", server.url),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    let entry = String::from_utf8(result.stdout)
+        .unwrap()
+        .trim_end()
+        .to_string();
+    let missing = dir.path().join("missing");
+
+    let result = run(&["verify", &entry]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(
+        format!("{}: OK\n", entry),
+        String::from_utf8_lossy(&result.stdout)
+    );
+
+    fs::write(&entry, b"abd").unwrap();
+    let result = run(&["verify", "--json", &entry, path_arg(&missing)]);
+    assert_eq!(Some(10), result.status.code(), "{:?}", result);
+    let stdout = String::from_utf8(result.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(2, lines.len(), "{}", stdout);
+    assert!(lines[0].contains("\"status\":\"modified\""), "{}", stdout);
+    assert!(lines[0].contains("\"sha256\"]"), "{}", stdout);
+    assert!(lines[1].contains("\"status\":\"missing\""), "{}", stdout);
+
+    // a record from before there were digests is only noted
+    let meta = format!("{}.meta", entry);
+    let old: String = fs::read_to_string(&meta)
+        .unwrap()
+        .lines()
+        .filter(|line| line.starts_with("url "))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    fs::write(&meta, old).unwrap();
+    let result = run(&["verify", &entry]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(
+        format!("{}: NO-DIGEST\n", entry),
+        String::from_utf8_lossy(&result.stdout)
+    );
+}
+
+#[test]
+fn status_of_cache_entry() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = dir.path().join("cache");
+    let metrics = dir.path().join("metrics.prom");
+    let headers = &[
+        "ETag: \"v1\"",
+        "Last-Modified: Tue, 01 Sep 2026 00:00:00 GMT",
+        "Cache-Control: public, max-age=3600",
+    ];
+    let server = serve(vec![
+        response("200 OK", headers, b"abc"),
+        response("200 OK", headers, b""),
+        response("200 OK", &["ETag: \"v2\""], b""),
+    ]);
+    let url = format!("{}/foo.tar.gz", server.url);
+    let result = run(&[
+        "--cache-dir",
+        path_arg(&cache),
+        "--metrics-file",
+        path_arg(&metrics),
+        &url,
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    let entry = String::from_utf8(result.stdout)
+        .unwrap()
+        .trim_end()
+        .to_string();
+
+    let result = run(&["status", "--metrics-file", path_arg(&metrics), &entry]);
+    assert!(result.status.success(), "{:?}", result);
+    let stdout = String::from_utf8(result.stdout).unwrap();
+    for expected in &[
+        format!("           url: {}\n", url),
+        "          etag: \"v1\"\n".to_string(),
+        " last modified: 2026-09-01T00:00:00Z (".to_string(),
+        "       expired: no\n".to_string(),
+        "  last outcome: fetched\n".to_string(),
+        "      decision: conditional\n".to_string(),
+    ] {
+        assert!(stdout.contains(expected), "{:?} in {}", expected, stdout);
+    }
+
+    let json = |args: &[&str]| {
+        let result = run(&[&["status", "--json"], args].concat());
+        assert!(result.status.success(), "{:?}", result);
+        String::from_utf8(result.stdout).unwrap()
+    };
+    // the mtime's the Last-Modified
+    let stdout = json(&["--min-age", "100w", "--remote", &entry]);
+    assert!(stdout.contains("\"decision\":\"skip\""), "{}", stdout);
+    assert!(stdout.contains("\"remote_newer\":false"), "{}", stdout);
+    assert!(stdout.contains("\"last_run\":null"), "{}", stdout);
+    let stdout = json(&["--remote", &url, &entry]);
+    assert!(stdout.contains("\"remote_newer\":true"), "{}", stdout);
+
+    let requests = server.requests();
+    assert!(
+        requests[1].starts_with("HEAD /foo.tar.gz "),
+        "{:?}",
+        requests
+    );
+}
+
+#[test]
+fn fifo_output_is_written_into() {
+    use std::io::Read;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::FileTypeExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let fifo = dir.path().join("feed.pipe");
+    let name = std::ffi::CString::new(fifo.as_os_str().as_bytes()).unwrap();
+    assert_eq!(0, unsafe { libc::mkfifo(name.as_ptr(), 0o600) });
+
+    let server = serve(vec![response("200 OK", &[], b"abc")]);
+    let reader = {
+        let fifo = fifo.clone();
+        std::thread::spawn(move || fs::read(&fifo).unwrap())
+    };
+    let result = run(&[&server.url, path_arg(&fifo)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(b"abc", reader.join().unwrap().as_slice());
+    assert!(fs::metadata(&fifo).unwrap().file_type().is_fifo());
+
+    // the reader goes after its first byte, well inside the body
+    let body = vec![b'x'; 4 << 20];
+    let server = serve(vec![response("200 OK", &[], &body)]);
+    let reader = {
+        let fifo = fifo.clone();
+        std::thread::spawn(move || {
+            let mut first = [0];
+            fs::File::open(&fifo)
+                .unwrap()
+                .read_exact(&mut first)
+                .unwrap();
+        })
+    };
+    let result = run(&["--retry", "2", &server.url, path_arg(&fifo)]);
+    reader.join().unwrap();
+    assert_eq!(Some(9), result.status.code(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("broken pipe"), "{}", stderr);
+
+    let result = run(&["--backup", &server.url, path_arg(&fifo)]);
+    assert_eq!(Some(1), result.status.code(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("FIFO"), "{}", stderr);
+}
@@ -0,0 +1,358 @@
+use std::fs;
+
+mod common;
+
+use common::path_arg;
+use common::response;
+use common::run;
+use common::serve;
+use common::serve_with;
+
+#[test]
+fn partial_content_written() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+
+    let server = serve(vec![response(
+        "206 Partial Content",
+        &["Content-Range: bytes 0-3/10"],
+        b"0123",
+    )]);
+    let result = run(&[
+        "--range",
+        "0-3",
+        &format!("{}/file", server.url),
+        path_arg(&output),
+    ]);
+
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("0123", fs::read_to_string(&output).unwrap());
+    assert!(server.requests()[0].contains("Range: bytes=0-3"));
+}
+
+#[test]
+fn partial_content_despite_ok_status() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+
+    let server = serve(vec![response(
+        "206 Partial Content",
+        &["Content-Range: bytes 0-3/10"],
+        b"0123",
+    )]);
+    let result = run(&[
+        "--ok-status",
+        "200",
+        "--range",
+        "0-3",
+        &format!("{}/file", server.url),
+        path_arg(&output),
+    ]);
+
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("0123", fs::read_to_string(&output).unwrap());
+}
+
+#[test]
+fn wrong_content_range_fails() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+
+    let server = serve(vec![response(
+        "206 Partial Content",
+        &["Content-Range: bytes 4-7/10"],
+        b"4567",
+    )]);
+    let result = run(&[
+        "--range",
+        "0-3",
+        &format!("{}/file", server.url),
+        path_arg(&output),
+    ]);
+
+    assert!(!result.status.success(), "{:?}", result);
+    assert!(!output.exists());
+}
+
+#[test]
+fn ignored_range_fails_by_default() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+
+    let server = serve(vec![response("200 OK", &[], b"0123456789")]);
+    let result = run(&[
+        "--range",
+        "2-5",
+        &format!("{}/file", server.url),
+        path_arg(&output),
+    ]);
+
+    assert!(!result.status.success(), "{:?}", result);
+    assert!(!output.exists());
+}
+
+#[test]
+fn ignored_range_truncated() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+
+    let server = serve(vec![response("200 OK", &[], b"0123456789")]);
+    let result = run(&[
+        "--range",
+        "2-5",
+        "--range-fallback",
+        "truncate",
+        &format!("{}/file", server.url),
+        path_arg(&output),
+    ]);
+
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("2345", fs::read_to_string(&output).unwrap());
+}
+
+#[test]
+fn partial_kept_and_resumed() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let part = dir.path().join("out.part");
+
+    let server = serve(vec![
+        response("200 OK", &["ETag: \"v1\"", "Content-Length: 6"], b"hel"),
+        response(
+            "206 Partial Content",
+            &["ETag: \"v1\"", "Content-Range: bytes 3-5/6"],
+            b"lo!",
+        ),
+    ]);
+
+    let result = run(&["--keep-partial", &server.url, path_arg(&output)]);
+    assert!(!result.status.success(), "{:?}", result);
+    assert!(!output.exists());
+    assert_eq!("hel", fs::read_to_string(&part).unwrap());
+
+    let result = run(&["--keep-partial", &server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("hello!", fs::read_to_string(&output).unwrap());
+    assert!(!part.exists());
+    assert!(!dir.path().join("out.part.meta").exists());
+
+    let requests = server.requests();
+    assert!(!requests[0].contains("Range"), "{}", requests[0]);
+    assert!(requests[1].contains("Range: bytes=3-"), "{}", requests[1]);
+    assert!(requests[1].contains("If-Range: \"v1\""), "{}", requests[1]);
+}
+
+#[test]
+fn retry_resumes_from_offset() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+
+    let server = serve(vec![
+        response(
+            "200 OK",
+            &["Accept-Ranges: bytes", "ETag: \"v1\"", "Content-Length: 6"],
+            b"hel",
+        ),
+        response(
+            "206 Partial Content",
+            &["ETag: \"v1\"", "Content-Range: bytes 3-5/6"],
+            b"lo!",
+        ),
+    ]);
+
+    let result = run(&[
+        "--retry",
+        "1",
+        "--sha256",
+        "ce06092fb948d9ffac7d1a376e404b26b7575bcc11ee05a4615fef4fec3a308b",
+        &server.url,
+        path_arg(&output),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("hello!", fs::read_to_string(&output).unwrap());
+
+    let requests = server.requests();
+    assert!(requests[1].contains("Range: bytes=3-"), "{}", requests[1]);
+    assert!(requests[1].contains("If-Range: \"v1\""), "{}", requests[1]);
+}
+
+#[test]
+fn retry_without_ranges_starts_again() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+
+    let server = serve(vec![
+        response("200 OK", &["Content-Length: 6"], b"hel"),
+        response("200 OK", &[], b"hello!"),
+    ]);
+
+    let result = run(&["--retry", "1", &server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("hello!", fs::read_to_string(&output).unwrap());
+    assert!(!server.requests()[1].contains("Range"));
+}
+
+fn block_index(body: &[u8], block_size: usize, whole: &[u8]) -> Vec<u8> {
+    let hex = |data: &[u8]| {
+        let digest = <sha2::Sha256 as sha2::Digest>::digest(data);
+        digest
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    };
+    let mut index = format!(
+        "size {}\nblock-size {}\nsha256 {}\n",
+        body.len(),
+        block_size,
+        hex(whole)
+    );
+    for block in body.chunks(block_size) {
+        index.push_str(&format!("block {}\n", hex(block)));
+    }
+    index.into_bytes()
+}
+
+#[test]
+fn delta_fetches_changed_blocks() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    fs::write(&output, b"aaaabbbbcccc").unwrap();
+    let new = b"aaaaXXXXcccc";
+    let ranges = &["Accept-Ranges: bytes", "ETag: \"v2\""];
+    let server = serve(vec![
+        response("200 OK", ranges, new),
+        response("200 OK", &[], &block_index(new, 4, new)),
+        response(
+            "206 Partial Content",
+            &["Content-Range: bytes 4-7/12"],
+            b"XXXX",
+        ),
+        // an index that's wrong about the whole, so it's all downloaded after all
+        response("200 OK", ranges, b"aaaaXXXXYYYY"),
+        response("200 OK", &[], &block_index(b"aaaaXXXXYYYY", 4, b"wrong")),
+        response(
+            "206 Partial Content",
+            &["Content-Range: bytes 8-11/12"],
+            b"YYYY",
+        ),
+        response("200 OK", ranges, b"aaaaXXXXYYYY"),
+    ]);
+    let index = format!("{}/out.blocks", server.url);
+    let fetch = || {
+        run(&[
+            "-v",
+            "--delta-index",
+            &index,
+            &server.url,
+            path_arg(&output),
+        ])
+    };
+
+    let result = fetch();
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("aaaaXXXXcccc", fs::read_to_string(&output).unwrap());
+    let requests = server.requests();
+    assert_eq!(3, requests.len(), "{:?}", requests);
+    assert!(
+        requests[1].starts_with("GET /out.blocks "),
+        "{:?}",
+        requests
+    );
+    assert!(
+        requests[2].contains("Range: bytes=4-7\r\n"),
+        "{:?}",
+        requests
+    );
+    assert!(
+        requests[2].contains("If-Range: \"v2\"\r\n"),
+        "{:?}",
+        requests
+    );
+    // kept, so next time the output needn't be read, in the index's blocks
+    let blocks = fs::read_to_string(dir.path().join("out.blocks")).unwrap();
+    assert!(blocks.contains("block-size 4\n"), "{}", blocks);
+
+    let result = fetch();
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("aaaaXXXXYYYY", fs::read_to_string(&output).unwrap());
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("downloading it all instead"), "{}", stderr);
+    assert_eq!(4, server.requests().len());
+}
+
+#[test]
+fn parallel_segments() {
+    use sha2::Digest as _;
+
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let body: Vec<u8> = (0..40_000u32).map(|i| (i % 251) as u8).collect();
+    let sha256 = format!("{:x}", sha2::Sha256::digest(&body));
+
+    let served = body.clone();
+    let server = serve_with(5, move |number, head| {
+        let ranges = &["Accept-Ranges: bytes", "ETag: \"v1\""];
+        let range = head
+            .lines()
+            .find_map(|line| line.strip_prefix("Range: bytes="))
+            .map(|range| {
+                let (start, end) = range.split_once('-').unwrap();
+                (
+                    start.parse::<usize>().unwrap(),
+                    end.parse::<usize>().unwrap(),
+                )
+            });
+        match range {
+            None => response("200 OK", ranges, &served),
+            Some((start, end)) => {
+                let content_range =
+                    format!("Content-Range: bytes {}-{}/{}", start, end, served.len());
+                let mut answer = response(
+                    "206 Partial Content",
+                    &[&content_range],
+                    &served[start..=end],
+                );
+                // the first range asked for is cut short, so it has to be resumed
+                if 1 == number {
+                    answer.truncate(answer.len() - 100);
+                }
+                answer
+            }
+        }
+    });
+    let result = run(&[
+        "-v",
+        "--parallel-segments",
+        "4",
+        "--retry",
+        "1",
+        "--sha256",
+        &sha256,
+        &server.url,
+        path_arg(&output),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(body, fs::read(&output).unwrap());
+
+    let requests = server.requests();
+    assert_eq!(5, requests.len(), "{:?}", requests);
+    for request in &requests[1..] {
+        assert!(request.contains("If-Range: \"v1\"\r\n"), "{:?}", request);
+    }
+    let mut asked: Vec<&str> = requests[1..]
+        .iter()
+        .filter_map(|request| request.lines().find(|line| line.starts_with("Range: ")))
+        .collect();
+    asked.sort();
+    assert_eq!(4, asked.len(), "{:?}", asked);
+    assert!(asked.contains(&"Range: bytes=30000-39999"), "{:?}", asked);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("resuming from offset"), "{}", stderr);
+
+    // without Accept-Ranges, it's one stream
+    let server = serve(vec![response("200 OK", &["ETag: \"v1\""], &body)]);
+    let result = run(&["--parallel-segments", "4", &server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(body, fs::read(&output).unwrap());
+    assert_eq!(1, server.requests().len());
+}
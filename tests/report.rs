@@ -0,0 +1,310 @@
+use std::fs;
+use std::time::Duration;
+use std::time::SystemTime;
+
+mod common;
+
+use common::json;
+use common::json::Json;
+use common::path_arg;
+use common::response;
+use common::run;
+use common::serve;
+use common::serve_on;
+
+const FIELDS: &[&str] = &[
+    "version",
+    "url",
+    "final_url",
+    "outcome",
+    "http_status",
+    "bytes",
+    "sha256",
+    "last_modified",
+    "etag",
+    "output",
+    "error",
+    "time_starttransfer",
+    "time_total",
+    "remote_ip",
+    "cert_expires",
+    "cert_expiring",
+];
+
+/// The run's stdout, as its one report, with every field there.
+fn report(args: &[&str]) -> (std::process::Output, Json) {
+    let result = run(&[&["--json"], args].concat());
+    let stdout = String::from_utf8(result.stdout.clone()).unwrap();
+    assert_eq!(1, stdout.lines().count(), "{:?}", result);
+    let report = json::parse(&stdout);
+    assert_eq!(FIELDS, &report.keys()[..], "{}", stdout);
+    assert_eq!(Some(1.0), report.get("version").number());
+    (result, report)
+}
+
+#[test]
+fn json_fetched() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let server = serve(vec![response(
+        "200 OK",
+        &[
+            "ETag: \"abc\"",
+            "Last-Modified: Sun, 09 Sep 2001 01:46:40 GMT",
+        ],
+        b"hello",
+    )]);
+
+    let (result, report) = report(&[&server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(Some("fetched"), report.get("outcome").str());
+    assert_eq!(Some(200.0), report.get("http_status").number());
+    assert_eq!(Some("127.0.0.1"), report.get("remote_ip").str());
+    assert_eq!(Some(5.0), report.get("bytes").number());
+    assert_eq!(
+        Some("2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"),
+        report.get("sha256").str()
+    );
+    assert_eq!(
+        Some("2001-09-09T01:46:40Z"),
+        report.get("last_modified").str()
+    );
+    assert_eq!(Some("\"abc\""), report.get("etag").str());
+    assert_eq!(Some(path_arg(&output)), report.get("output").str());
+    assert_eq!(&Json::Null, report.get("error"));
+    assert!(report.get("time_total").number().is_some());
+}
+
+#[test]
+fn json_remote_ip_after_redirect() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let elsewhere = serve_on("127.0.0.2", 1, |_, _| response("200 OK", &[], b"moved"));
+    let next = format!("Location: {}/f", elsewhere.url);
+    let server = serve(vec![response("302 Found", &[&next], b"")]);
+
+    let (result, report) = report(&[&format!("{}/f", server.url), path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(Some("127.0.0.2"), report.get("remote_ip").str());
+    assert_eq!(
+        Some(format!("{}/f", elsewhere.url).as_str()),
+        report.get("final_url").str()
+    );
+}
+
+#[test]
+fn json_unchanged() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    fs::write(&output, "old").unwrap();
+    let old = SystemTime::now() - Duration::from_secs(60 * 60);
+    filetime::set_file_mtime(&output, filetime::FileTime::from(old)).unwrap();
+    let server = serve(vec![response("304 Not Modified", &[], b"")]);
+
+    let (result, report) = report(&[&server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(Some("unchanged"), report.get("outcome").str());
+    assert_eq!(Some(304.0), report.get("http_status").number());
+    assert_eq!(&Json::Null, report.get("bytes"));
+}
+
+#[test]
+fn json_skipped() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    fs::write(&output, "fresh").unwrap();
+
+    let (result, report) = report(&["--min-age", "1h", "http://127.0.0.1:9/", path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(Some("skipped"), report.get("outcome").str());
+    assert_eq!(&Json::Null, report.get("http_status"));
+    assert_eq!(&Json::Null, report.get("time_starttransfer"));
+}
+
+#[test]
+fn json_failed() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let server = serve(vec![response("404 Not Found", &[], b"")]);
+
+    let (result, report) = report(&[&server.url, path_arg(&output)]);
+    assert_eq!(Some(7), result.status.code(), "{:?}", result);
+    assert_eq!(Some("failed"), report.get("outcome").str());
+    assert_eq!(Some(404.0), report.get("http_status").number());
+    let error = report.get("error");
+    assert_eq!(Some("client-error"), error.get("kind").str());
+    assert!(
+        error.get("message").str().unwrap().contains("404"),
+        "{:?}",
+        error
+    );
+}
+
+#[test]
+fn json_refuses_stdout() {
+    let (result, report) = report(&["http://127.0.0.1:9/", "-"]);
+    assert_eq!(Some(2), result.status.code(), "{:?}", result);
+    assert_eq!(Some("usage"), report.get("error").get("kind").str());
+}
+
+/// The run's stdout, which has to be just the one line.
+fn porcelain(args: &[&str]) -> (std::process::Output, String) {
+    let result = run(&[&["--porcelain"], args].concat());
+    let stdout = String::from_utf8(result.stdout.clone()).unwrap();
+    assert_eq!(1, stdout.lines().count(), "{:?}", result);
+    assert!(stdout.ends_with('\n'), "{:?}", stdout);
+    (result, stdout.trim_end().to_string())
+}
+
+#[test]
+fn porcelain_each_line() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let store = dir.path().join("store");
+    let sha = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+    let server = serve(vec![
+        response("200 OK", &[], b"hello"),
+        response("304 Not Modified", &[], b""),
+        response("404 Not Found", &[], b""),
+    ]);
+
+    let (result, line) = porcelain(&["--store", path_arg(&store), &server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(format!("downloaded 5 {}", sha), line);
+
+    let old = SystemTime::now() - Duration::from_secs(60 * 60);
+    filetime::set_file_mtime(&output, filetime::FileTime::from(old)).unwrap();
+    let (_, line) = porcelain(&[&server.url, path_arg(&output)]);
+    assert_eq!("not-modified", line);
+
+    let (_, line) = porcelain(&["--min-age", "1d", &server.url, path_arg(&output)]);
+    assert_eq!("skipped min-age", line);
+
+    let (_, line) = porcelain(&["--no-clobber", &server.url, path_arg(&output)]);
+    assert_eq!("skipped no-clobber", line);
+
+    let copy = dir.path().join("copy");
+    let (_, line) = porcelain(&[
+        "--store",
+        path_arg(&store),
+        "--sha256",
+        sha,
+        &server.url,
+        path_arg(&copy),
+    ]);
+    assert_eq!(format!("stored {}", sha), line);
+
+    let (result, line) = porcelain(&[&format!("{}/missing", server.url), path_arg(&output)]);
+    assert_eq!(Some(7), result.status.code());
+    assert_eq!("error client-error", line);
+
+    let (_, line) = porcelain(&[&server.url, "-"]);
+    assert_eq!("error usage", line);
+}
+
+/// The run's stdout, as events, checking they're in sequence.
+fn stream(result: &std::process::Output) -> Vec<Json> {
+    let stdout = String::from_utf8(result.stdout.clone()).unwrap();
+    let events: Vec<Json> = stdout.lines().map(json::parse).collect();
+    for (i, event) in events.iter().enumerate() {
+        assert_eq!(
+            Some((i + 1) as f64),
+            event.get("sequence").number(),
+            "{}",
+            stdout
+        );
+        assert!(event.get("timestamp").str().is_some(), "{}", stdout);
+    }
+    events
+}
+
+fn names(events: &[Json]) -> Vec<&str> {
+    events
+        .iter()
+        .map(|e| e.get("event").str().unwrap())
+        .collect()
+}
+
+#[test]
+fn events_fetched() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let server = serve(vec![response("200 OK", &["ETag: \"abc\""], b"hello")]);
+
+    let result = run(&[
+        "--events",
+        "--sha256",
+        "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+        &server.url,
+        path_arg(&output),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    let events = stream(&result);
+    assert_eq!(
+        vec![
+            "start",
+            "decision",
+            "response",
+            "progress",
+            "verified",
+            "persisted",
+            "done"
+        ],
+        names(&events)
+    );
+    assert_eq!(Some(path_arg(&output)), events[0].get("output").str());
+    assert_eq!(Some("unconditional"), events[1].get("decision").str());
+    assert_eq!(Some(200.0), events[2].get("status").number());
+    assert_eq!(Some("\"abc\""), events[2].get("headers").get("etag").str());
+    assert_eq!(Some(5.0), events[3].get("total").number());
+    assert_eq!(
+        Json::Array(vec![Json::String("sha256".to_string())]),
+        *events[4].get("checks")
+    );
+    assert_eq!(Some("fetched"), events[6].get("outcome").str());
+    assert_eq!(Json::Null, *events[6].get("error"));
+    assert!(!events[0].keys().contains(&"entry"));
+}
+
+#[test]
+fn events_conditional_and_skipped() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    fs::write(&output, "old").unwrap();
+    let old = SystemTime::now() - Duration::from_secs(60 * 60);
+    filetime::set_file_mtime(&output, filetime::FileTime::from(old)).unwrap();
+    let server = serve(vec![response("304 Not Modified", &[], b"")]);
+
+    let result = run(&["--events", &server.url, path_arg(&output)]);
+    let events = stream(&result);
+    assert_eq!(
+        vec!["start", "decision", "response", "done"],
+        names(&events)
+    );
+    assert_eq!(Some("conditional"), events[1].get("decision").str());
+    assert_eq!(Some("unchanged"), events[3].get("outcome").str());
+
+    let result = run(&[
+        "--events",
+        "--min-age",
+        "1d",
+        &server.url,
+        path_arg(&output),
+    ]);
+    let events = stream(&result);
+    assert_eq!(vec!["start", "decision", "done"], names(&events));
+    assert_eq!(Some("skip"), events[1].get("decision").str());
+    assert_eq!(Some("min-age"), events[1].get("reason").str());
+}
+
+#[test]
+fn events_failed() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = serve(vec![response("404 Not Found", &[], b"")]);
+    let result = run(&["--events", &server.url, path_arg(&dir.path().join("out"))]);
+    assert_eq!(Some(7), result.status.code(), "{:?}", result);
+    let events = stream(&result);
+    let done = events.last().unwrap();
+    assert_eq!(Some("failed"), done.get("outcome").str());
+    assert_eq!(Some("client-error"), done.get("error").str());
+}
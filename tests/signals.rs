@@ -0,0 +1,233 @@
+use std::fs;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpListener;
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+use std::time::SystemTime;
+
+mod common;
+
+use common::path_arg;
+use common::response;
+use common::run;
+use common::run_with_env;
+use common::serve;
+
+#[test]
+fn killed_mid_download() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    fs::write(&output, b"old").unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_fetch-maybe"))
+        .args([&url, path_arg(&output)])
+        .spawn()
+        .unwrap();
+
+    // half a body, then nothing, so it's still downloading when it's killed
+    let (mut stream, _) = listener.accept().unwrap();
+    let mut head = [0u8; 1024];
+    let _ = stream.read(&mut head).unwrap();
+    stream
+        .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\nabc")
+        .unwrap();
+    thread::sleep(Duration::from_millis(200));
+
+    assert_eq!(0, unsafe {
+        libc::kill(child.id() as libc::pid_t, libc::SIGTERM)
+    });
+    let status = child.wait().unwrap();
+    assert_eq!(Some(128 + libc::SIGTERM), status.code());
+
+    assert_eq!("old", fs::read_to_string(&output).unwrap());
+    let mut left: Vec<_> = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    left.sort();
+    assert_eq!(vec!["out", "out.lock"], left);
+}
+
+#[test]
+fn watch_until_terminated() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let server = serve(vec![
+        response("200 OK", &[], b"v1"),
+        response("200 OK", &[], b"v2"),
+        response("500 Internal Server Error", &[], b""),
+    ]);
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_fetch-maybe"))
+        .args(["--watch", "1h", &server.url, path_arg(&output)])
+        .env("XDG_CONFIG_HOME", "/nonexistent")
+        .spawn()
+        .unwrap();
+    let pid = child.id() as libc::pid_t;
+    let signal = |signal| assert_eq!(0, unsafe { libc::kill(pid, signal) });
+    let mut requests = 0;
+    let mut wait_for_request = || {
+        for _ in 0..100 {
+            requests += server.requests().len();
+            if requests > 0 {
+                requests -= 1;
+                // for the response to be dealt with
+                thread::sleep(Duration::from_millis(200));
+                return;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        panic!("no request arrived");
+    };
+
+    wait_for_request();
+    assert_eq!("v1", fs::read_to_string(&output).unwrap());
+
+    // nothing's due for an hour, but a SIGHUP checks now
+    signal(libc::SIGHUP);
+    wait_for_request();
+    assert_eq!("v2", fs::read_to_string(&output).unwrap());
+
+    // a failure is no reason to stop
+    signal(libc::SIGHUP);
+    wait_for_request();
+    assert_eq!(None, child.try_wait().unwrap());
+
+    // but it's what the exit status says, once we're told to stop
+    signal(libc::SIGTERM);
+    assert_eq!(Some(8), child.wait().unwrap().code());
+    assert_eq!("v2", fs::read_to_string(&output).unwrap());
+}
+
+#[test]
+fn watch_counts_each_manifest_run() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = serve(vec![
+        response("200 OK", &[], b"v1"),
+        response("200 OK", &[], b"v2"),
+    ]);
+    let manifest = dir.path().join("manifest");
+    fs::write(
+        &manifest,
+        format!("{}/a {}\n", server.url, path_arg(&dir.path().join("a"))),
+    )
+    .unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_fetch-maybe"))
+        .args([
+            "--watch",
+            "1h",
+            "--stats",
+            "--manifest",
+            path_arg(&manifest),
+        ])
+        .env("XDG_CONFIG_HOME", "/nonexistent")
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let pid = child.id() as libc::pid_t;
+    let wait_for_request = || {
+        for _ in 0..100 {
+            if !server.requests().is_empty() {
+                // for the run to finish
+                thread::sleep(Duration::from_millis(200));
+                return;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        panic!("no request arrived");
+    };
+
+    wait_for_request();
+    assert_eq!(0, unsafe { libc::kill(pid, libc::SIGHUP) });
+    wait_for_request();
+    assert_eq!(0, unsafe { libc::kill(pid, libc::SIGTERM) });
+    child.wait().unwrap();
+
+    // each run's own, not the process's running total
+    let mut stderr = String::new();
+    child
+        .stderr
+        .take()
+        .unwrap()
+        .read_to_string(&mut stderr)
+        .unwrap();
+    let counted: Vec<&str> = stderr
+        .lines()
+        .filter(|line| line.contains("requests for"))
+        .collect();
+    assert_eq!(
+        vec!["fetch-maybe: 1 requests for 1 entries, through one agent"; 2],
+        counted,
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn splay() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let server = serve(vec![
+        response("200 OK", &[], b"abc"),
+        response("200 OK", &[], b"abc"),
+    ]);
+    let splayed = || {
+        let result = run_with_env(
+            &["-vv", "--splay", "1s", &server.url, path_arg(&output)],
+            &[("FETCH_MAYBE_SEED", "7")],
+        );
+        assert!(result.status.success(), "{:?}", result);
+        let stderr = String::from_utf8(result.stderr).unwrap();
+        let line = stderr.lines().find(|line| line.contains("splay: waiting"));
+        line.expect("logged")
+            .split("splay: ")
+            .nth(1)
+            .unwrap()
+            .to_string()
+    };
+    // the same seed, the same wait
+    assert_eq!(splayed(), splayed());
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_fetch-maybe"))
+        .args(["--splay", "1h", &server.url, path_arg(&output)])
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(0, unsafe {
+        libc::kill(child.id() as libc::pid_t, libc::SIGTERM)
+    });
+    assert_eq!(Some(128 + libc::SIGTERM), child.wait().unwrap().code());
+}
+
+#[test]
+fn clean_stale_temps() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let stale = dir.path().join(".fetch-maybe-tmpAb12Cd");
+    let fresh = dir.path().join(".fetch-maybe-tmpXy34Zw");
+    let unrelated = dir.path().join("notes.tmp");
+    let others = dir.path().join(".tmpAb12Cd");
+    for path in &[&stale, &fresh, &unrelated, &others] {
+        fs::write(path, b"").unwrap();
+    }
+    let old = filetime::FileTime::from(SystemTime::now() - Duration::from_secs(2 * 60 * 60));
+    filetime::set_file_mtime(&stale, old).unwrap();
+    filetime::set_file_mtime(&unrelated, old).unwrap();
+    filetime::set_file_mtime(&others, old).unwrap();
+
+    let server = serve(vec![response("200 OK", &[], b"abc")]);
+    let result = run(&["--clean-stale-temps", &server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+
+    assert!(!stale.exists());
+    assert!(fresh.exists());
+    assert!(unrelated.exists());
+    assert!(others.exists());
+    assert_eq!("abc", fs::read_to_string(&output).unwrap());
+}
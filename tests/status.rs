@@ -0,0 +1,430 @@
+use std::fs;
+
+mod common;
+
+use common::path_arg;
+use common::response;
+use common::run;
+use common::serve;
+
+#[test]
+fn no_content_leaves_output_alone() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    fs::write(&output, "previous").unwrap();
+
+    let server = serve(vec![response("204 No Content", &[], b"")]);
+    let result = run(&[&format!("{}/report", server.url), path_arg(&output)]);
+
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("previous", fs::read_to_string(&output).unwrap());
+}
+
+#[test]
+fn no_content_can_empty_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    fs::write(&output, "previous").unwrap();
+
+    let server = serve(vec![response("204 No Content", &[], b"")]);
+    let result = run(&[
+        "--empty-on-204",
+        &format!("{}/report", server.url),
+        path_arg(&output),
+    ]);
+
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("", fs::read_to_string(&output).unwrap());
+}
+
+#[test]
+fn unrequested_partial_content_fails() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    fs::write(&output, "previous").unwrap();
+
+    let server = serve(vec![response(
+        "206 Partial Content",
+        &["Content-Range: bytes 0-3/100"],
+        b"part",
+    )]);
+    let result = run(&[
+        "-H",
+        "Range: bytes=0-3",
+        &format!("{}/file", server.url),
+        path_arg(&output),
+    ]);
+
+    assert!(!result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("bytes 0-3/100"), "{}", stderr);
+    assert_eq!("previous", fs::read_to_string(&output).unwrap());
+}
+
+#[test]
+fn redirect_loop_fails_with_chain() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+
+    let server = serve(vec![
+        response("302 Found", &["Location: /b"], b""),
+        response("302 Found", &["Location: /a"], b""),
+    ]);
+    let result = run(&[&format!("{}/a", server.url), path_arg(&output)]);
+
+    assert!(!result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("redirect loop"), "{}", stderr);
+    assert!(stderr.contains("/a -> "), "{}", stderr);
+    assert_eq!(2, server.requests().len());
+    assert!(!output.exists());
+}
+
+#[test]
+fn redirect_followed() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+
+    let server = serve(vec![
+        response("307 Temporary Redirect", &["Location: /b"], b""),
+        response("200 OK", &[], b"moved"),
+    ]);
+    let result = run(&[&format!("{}/a", server.url), path_arg(&output)]);
+
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("moved", fs::read_to_string(&output).unwrap());
+}
+
+#[test]
+fn too_many_headers_fails() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+
+    let server = serve(vec![response(
+        "200 OK",
+        &["Set-Cookie: a=1", "Set-Cookie: b=2", "Set-Cookie: c=3"],
+        b"body",
+    )]);
+    let result = run(&[
+        "--max-headers",
+        "3",
+        &format!("{}/file", server.url),
+        path_arg(&output),
+    ]);
+
+    assert!(!result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains("response headers exceed limit"),
+        "{}",
+        stderr
+    );
+    assert!(!output.exists());
+}
+
+#[test]
+fn error_body_saved() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let body = dir.path().join("body");
+
+    let server = serve(vec![response(
+        "403 Forbidden",
+        &["Content-Type: application/json"],
+        b"{\"error\": \"token expired\"}",
+    )]);
+    let result = run(&[
+        "--fail-with-body",
+        path_arg(&body),
+        &format!("{}/file", server.url),
+        path_arg(&output),
+    ]);
+
+    assert!(!result.status.success(), "{:?}", result);
+    assert_eq!(
+        "{\"error\": \"token expired\"}",
+        fs::read_to_string(&body).unwrap()
+    );
+    assert!(!output.exists());
+}
+
+#[test]
+fn headers_dumped() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let headers = dir.path().join("headers");
+
+    let server = serve(vec![
+        response("307 Temporary Redirect", &["Location: /b"], b""),
+        response(
+            "200 OK",
+            &["Set-Cookie: a=1; Path=/", "Set-Cookie: b=2", "X-Thing: yes"],
+            b"moved",
+        ),
+    ]);
+    let result = run(&[
+        "--dump-headers",
+        path_arg(&headers),
+        "--dump-headers-all",
+        "--mask-cookies",
+        &format!("{}/a", server.url),
+        path_arg(&output),
+    ]);
+
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(
+        "HTTP/1.1 307 Temporary Redirect\r\n\
+         location: /b\r\n\
+         content-length: 0\r\n\
+         \r\n\
+         HTTP/1.1 200 OK\r\n\
+         set-cookie: a=***; Path=/\r\n\
+         set-cookie: b=***\r\n\
+         x-thing: yes\r\n\
+         content-length: 5\r\n\
+         \r\n",
+        fs::read_to_string(&headers).unwrap()
+    );
+}
+
+#[test]
+fn headers_not_dumped_on_failure() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let headers = dir.path().join("headers");
+
+    let server = serve(vec![response("404 Not Found", &[], b"")]);
+    let result = run(&[
+        "--dump-headers",
+        path_arg(&headers),
+        &server.url,
+        path_arg(&output),
+    ]);
+
+    assert!(!result.status.success(), "{:?}", result);
+    assert!(!headers.exists());
+}
+
+#[test]
+fn quiet_failures() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+
+    let server = serve(vec![
+        response("404 Not Found", &[], b""),
+        response("404 Not Found", &[], b""),
+    ]);
+
+    let result = run(&["-q", &server.url, path_arg(&output)]);
+    assert!(!result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert_eq!(1, stderr.lines().count(), "{}", stderr);
+    assert!(stderr.contains("404"), "{}", stderr);
+
+    let result = run(&["-qq", &server.url, path_arg(&output)]);
+    assert!(!result.status.success(), "{:?}", result);
+    assert!(result.stderr.is_empty(), "{:?}", result);
+}
+
+#[test]
+fn cron_mode() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+
+    let server = serve(vec![
+        response("200 OK", &[], b"abc"),
+        response("404 Not Found", &[], b""),
+    ]);
+
+    let result = run(&["--cron", "-v", &server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert!(result.stderr.is_empty(), "{:?}", result);
+    assert!(result.stdout.is_empty(), "{:?}", result);
+
+    let result = run(&["--cron", &server.url, path_arg(&output)]);
+    assert!(!result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert_eq!(1, stderr.lines().count(), "{}", stderr);
+    assert!(
+        stderr.starts_with(&format!(
+            "fetch-maybe: fetching {} to {:?} failed while ",
+            server.url, output
+        )) && stderr.contains("404"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn exit_statuses() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+
+    // nothing listens on the discard port
+    let result = run(&["http://127.0.0.1:9/", path_arg(&output)]);
+    assert_eq!(Some(4), result.status.code(), "{:?}", result);
+
+    let server = serve(vec![response("404 Not Found", &[], b"")]);
+    let result = run(&[&server.url, path_arg(&output)]);
+    assert_eq!(Some(7), result.status.code(), "{:?}", result);
+
+    let result = run(&[
+        "--min-age",
+        "soon",
+        "http://127.0.0.1:9/",
+        path_arg(&output),
+    ]);
+    assert_eq!(Some(2), result.status.code(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("--min-age"), "{}", stderr);
+
+    // a regular file in the way, as root can write to read-only directories
+    let file = dir.path().join("file");
+    fs::write(&file, b"").unwrap();
+    let result = run(&["http://127.0.0.1:9/", path_arg(&file.join("out"))]);
+    assert_eq!(Some(9), result.status.code(), "{:?}", result);
+}
+
+#[test]
+fn fail_if_unchanged() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let server = serve(vec![
+        response("200 OK", &[], b"new"),
+        response("304 Not Modified", &[], b""),
+        response("200 OK", &[], b"new"),
+        response("404 Not Found", &[], b""),
+    ]);
+    let fetch = || run(&["--fail-if-unchanged", &server.url, path_arg(&output)]);
+
+    let result = fetch();
+    assert_eq!(Some(0), result.status.code(), "{:?}", result);
+    for _ in 0..2 {
+        let result = fetch();
+        assert_eq!(Some(100), result.status.code(), "{:?}", result);
+        assert!(result.stderr.is_empty(), "{:?}", result);
+    }
+    let result = fetch();
+    assert_eq!(Some(7), result.status.code(), "{:?}", result);
+
+    let result = run(&[
+        "--fail-if-unchanged",
+        "--min-age",
+        "1h",
+        &server.url,
+        path_arg(&output),
+    ]);
+    assert_eq!(Some(100), result.status.code(), "{:?}", result);
+}
+
+/// Each class of status, and what it does to an existing output.
+#[test]
+fn status_classes() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+
+    let cases: &[(&str, i32, &[u8])] = &[
+        ("200 OK", 0, b"new"),
+        ("304 Not Modified", 0, b"old"),
+        ("302 Found", 8, b"old"),
+        ("403 Forbidden", 7, b"old"),
+        ("500 Internal Server Error", 8, b"old"),
+        ("503 Service Unavailable", 8, b"old"),
+    ];
+    for (status, code, contents) in cases {
+        fs::write(&output, b"old").unwrap();
+        let body: &[u8] = if status.starts_with("200") {
+            b"new"
+        } else {
+            b""
+        };
+        let server = serve(vec![response(status, &[], body)]);
+        let result = run(&[&server.url, path_arg(&output)]);
+        assert_eq!(
+            Some(*code),
+            result.status.code(),
+            "{}: {:?}",
+            status,
+            result
+        );
+        assert_eq!(
+            *contents,
+            fs::read(&output).unwrap().as_slice(),
+            "{}",
+            status
+        );
+    }
+}
+
+#[test]
+fn ok_status_replaces_the_default() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let server = serve(vec![
+        response("209 From Cache", &[], b"cached"),
+        response("201 Created", &[], b"created"),
+        response("410 Gone", &[], b"gone, and that's fine"),
+    ]);
+    let fetch = |output: &std::path::Path| {
+        run(&[
+            "--ok-status",
+            "200,209,400-410",
+            &server.url,
+            path_arg(output),
+        ])
+    };
+
+    let result = fetch(&output);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("cached", fs::read_to_string(&output).unwrap());
+
+    let created = dir.path().join("created");
+    let result = fetch(&created);
+    assert_eq!(Some(8), result.status.code(), "{:?}", result);
+    assert!(!created.exists());
+
+    let gone = dir.path().join("gone");
+    let result = fetch(&gone);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("gone, and that's fine", fs::read_to_string(&gone).unwrap());
+}
+
+#[test]
+fn ok_status_refuses_nonsense() {
+    let result = run(&["--ok-status", "299-200", "http://localhost/", "out"]);
+    assert_eq!(Some(2), result.status.code(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("backwards"), "{}", stderr);
+}
+
+#[test]
+fn interim_responses_skipped() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let early_hints = |then: Vec<u8>| {
+        let mut interim =
+            b"HTTP/1.1 103 Early Hints\r\nLink: </style.css>; rel=preload\r\n\r\n".to_vec();
+        interim.extend(then);
+        interim
+    };
+    let server = serve(vec![
+        early_hints(response("200 OK", &[], b"final body")),
+        early_hints(response("404 Not Found", &[], b"")),
+    ]);
+
+    let result = run(&["-vvv", &server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("final body", fs::read_to_string(&output).unwrap());
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains("interim: skipping \"HTTP/1.1 103 Early Hints\""),
+        "{}",
+        stderr
+    );
+
+    // judged on the final response, not the hints
+    let result = run(&[&server.url, path_arg(&output)]);
+    assert!(!result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("404 Not Found"), "{}", stderr);
+}
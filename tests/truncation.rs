@@ -0,0 +1,146 @@
+use std::fs;
+
+mod common;
+
+use common::path_arg;
+use common::response;
+use common::run;
+use common::serve;
+
+fn fetch_over_previous(body: &[u8], headers: &[&str]) -> (bool, String) {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    fs::write(&output, "previous").unwrap();
+
+    let server = serve(vec![response("200 OK", headers, body)]);
+    let result = run(&[&format!("{}/file", server.url), path_arg(&output)]);
+
+    (
+        result.status.success(),
+        fs::read_to_string(&output).unwrap(),
+    )
+}
+
+#[test]
+fn complete_chunked_body() {
+    let (ok, content) = fetch_over_previous(
+        b"5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n",
+        &["Transfer-Encoding: chunked"],
+    );
+    assert!(ok);
+    assert_eq!("hello world", content);
+}
+
+#[test]
+fn chunked_body_missing_final_chunk() {
+    let (ok, content) = fetch_over_previous(b"5\r\nhello\r\n", &["Transfer-Encoding: chunked"]);
+    assert!(!ok);
+    assert_eq!("previous", content);
+}
+
+#[test]
+fn chunked_body_closed_mid_chunk() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    fs::write(&output, "previous").unwrap();
+
+    // after an interim response, so the body's decoded by us: ureq's decoder, which isn't
+    // reachable, takes a close inside a chunk for the end of the body
+    let mut answer = b"HTTP/1.1 103 Early Hints\r\n\r\n".to_vec();
+    answer.extend(response(
+        "200 OK",
+        &["Transfer-Encoding: chunked"],
+        b"5\r\nhello\r\n6\r\n wo",
+    ));
+    let server = serve(vec![answer]);
+    let result = run(&[&format!("{}/file", server.url), path_arg(&output)]);
+
+    assert!(!result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("inside a chunk"), "{}", stderr);
+    assert_eq!("previous", fs::read_to_string(&output).unwrap());
+}
+
+#[test]
+fn short_content_length_body() {
+    let (ok, content) = fetch_over_previous(b"hel", &["Content-Length: 5"]);
+    assert!(!ok);
+    assert_eq!("previous", content);
+}
+
+#[test]
+fn empty_body_refused() {
+    let (ok, content) = fetch_over_previous(b"", &[]);
+    assert!(!ok);
+    assert_eq!("previous", content);
+}
+
+#[test]
+fn empty_body_allowed() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    fs::write(&output, "previous").unwrap();
+
+    let server = serve(vec![response("200 OK", &[], b"")]);
+    let result = run(&[
+        "--allow-empty",
+        &format!("{}/file", server.url),
+        path_arg(&output),
+    ]);
+
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("", fs::read_to_string(&output).unwrap());
+}
+
+#[test]
+fn empty_body_creates_new_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+
+    let server = serve(vec![response("200 OK", &[], b"")]);
+    let result = run(&[&format!("{}/file", server.url), path_arg(&output)]);
+
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("", fs::read_to_string(&output).unwrap());
+}
+
+#[test]
+fn shrunk_body_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    fs::write(&output, "0123456789").unwrap();
+
+    let server = serve(vec![response("200 OK", &[], b"012")]);
+    let result = run(&[
+        "--max-shrink",
+        "50",
+        &format!("{}/file", server.url),
+        path_arg(&output),
+    ]);
+
+    assert!(!result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("--max-shrink"), "{}", stderr);
+    assert_eq!("0123456789", fs::read_to_string(&output).unwrap());
+}
+
+#[test]
+fn short_body_after_preallocation() {
+    for how in &["fallocate", "truncate"] {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("out");
+        fs::write(&output, "previous").unwrap();
+
+        let server = serve(vec![
+            response("200 OK", &["Content-Length: 5"], b"hel"),
+            response("200 OK", &[], b"hello"),
+        ]);
+        let result = run(&["--preallocate", how, &server.url, path_arg(&output)]);
+        assert!(!result.status.success(), "{}: {:?}", how, result);
+        assert_eq!("previous", fs::read_to_string(&output).unwrap());
+
+        let result = run(&["--preallocate", how, &server.url, path_arg(&output)]);
+        assert!(result.status.success(), "{}: {:?}", how, result);
+        assert_eq!("hello", fs::read_to_string(&output).unwrap());
+    }
+}
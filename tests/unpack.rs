@@ -0,0 +1,232 @@
+use std::fs;
+use std::io::Write;
+
+mod common;
+use common::path_arg;
+use common::response;
+use common::run;
+use common::serve;
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+#[test]
+fn unpack_gzip() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("data.csv");
+    let body = gzip(b"a,b\n1,2\n");
+    let server = serve(vec![
+        response("200 OK", &[], &body),
+        response("200 OK", &[], &body),
+    ]);
+
+    // detected from the extension
+    let result = run(&[
+        "--unpack",
+        "auto",
+        &format!("{}/data.csv.gz", server.url),
+        path_arg(&output),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(b"a,b\n1,2\n", fs::read(&output).unwrap().as_slice());
+
+    // detected from the content, and sizes are of the decompressed output
+    fs::remove_file(&output).unwrap();
+    let result = run(&[
+        "--unpack",
+        "auto",
+        "--min-size",
+        "8",
+        &format!("{}/data", server.url),
+        path_arg(&output),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(b"a,b\n1,2\n", fs::read(&output).unwrap().as_slice());
+}
+
+#[test]
+fn unpack_corrupt_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("data.csv");
+    fs::write(&output, b"old").unwrap();
+    let body = gzip(b"a,b\n1,2\n");
+    let server = serve(vec![response("200 OK", &[], &body[..body.len() - 4])]);
+
+    let result = run(&["--unpack", "gzip", &server.url, path_arg(&output)]);
+    assert!(!result.status.success(), "{:?}", result);
+    assert_eq!(b"old", fs::read(&output).unwrap().as_slice());
+}
+
+#[test]
+fn compress_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("data.csv.gz");
+    let server = serve(vec![response("200 OK", &[], b"a,b\n1,2\n")]);
+
+    let result = run(&[
+        "--compress-output",
+        "gzip:9",
+        &server.url,
+        path_arg(&output),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+
+    let mut plain = Vec::new();
+    std::io::Read::read_to_end(
+        &mut flate2::read::GzDecoder::new(fs::File::open(&output).unwrap()),
+        &mut plain,
+    )
+    .unwrap();
+    assert_eq!(b"a,b\n1,2\n", plain.as_slice());
+}
+
+#[test]
+fn unpack_bomb_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("zeros");
+    // zeros pack about a thousand to one
+    let bomb = gzip(&vec![0; 64 << 20]);
+    assert!(bomb.len() < 100_000, "{}", bomb.len());
+    let server = serve(vec![
+        response("200 OK", &[], &bomb),
+        response("200 OK", &[], &bomb),
+    ]);
+    let url = format!("{}/zeros.gz", server.url);
+
+    let result = run(&["--unpack", "auto", &url, path_arg(&output)]);
+    assert_eq!(Some(10), result.status.code(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains("bytes of body unpacked to more than")
+            && stderr.contains("past --max-unpack-ratio 100"),
+        "{}",
+        stderr
+    );
+    // nothing kept, not even the temporary file; only the lock, as ever
+    let left = || -> Vec<String> {
+        fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect()
+    };
+    assert_eq!(vec!["zeros.lock"], left());
+
+    let result = run(&[
+        "--unpack",
+        "gzip",
+        "--max-unpack-ratio",
+        "0",
+        "--max-size",
+        "2M",
+        &url,
+        path_arg(&output),
+    ]);
+    assert_eq!(Some(10), result.status.code(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("past --max-size 2097152"), "{}", stderr);
+    assert_eq!(vec!["zeros.lock"], left());
+}
+
+#[test]
+fn max_size_without_unpacking() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let server = serve(vec![response("200 OK", &[], b"twelve bytes")]);
+
+    let result = run(&["--max-size", "10", &server.url, path_arg(&output)]);
+    assert_eq!(Some(10), result.status.code(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains("the body is 12 bytes, past --max-size 10"),
+        "{}",
+        stderr
+    );
+    assert!(!output.exists());
+}
+
+fn tar(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut archive = Vec::new();
+    for (name, data) in files {
+        let mut header = [0u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..107].copy_from_slice(b"0000644");
+        header[124..135].copy_from_slice(format!("{:011o}", data.len()).as_bytes());
+        header[136..147].copy_from_slice(b"14000000000");
+        header[148..156].copy_from_slice(b"        ");
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        let sum: u32 = header.iter().map(|&b| u32::from(b)).sum();
+        header[148..155].copy_from_slice(format!("{:06o}\0", sum).as_bytes());
+        archive.extend_from_slice(&header);
+        archive.extend_from_slice(data);
+        archive.resize(archive.len() + (512 - data.len() % 512) % 512, 0);
+    }
+    archive.extend_from_slice(&[0; 1024]);
+    archive
+}
+
+#[test]
+fn extract_replaces_the_tree() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("tree");
+    let first = gzip(&tar(&[("a.txt", b"one"), ("sub/b.txt", b"two")]));
+    let second = gzip(&tar(&[("c.txt", b"three")]));
+    let evil = gzip(&tar(&[("ok.txt", b"ok"), ("../evil.txt", b"evil")]));
+    let headers = &[
+        "ETag: \"v1\"",
+        "Last-Modified: Wed, 01 Jan 2020 00:00:00 GMT",
+    ];
+    let server = serve(vec![
+        response("200 OK", headers, &first),
+        response("304 Not Modified", &[], b""),
+        response("200 OK", &[], &second),
+        response("200 OK", &[], &evil),
+        response("200 OK", &[], &second[..second.len() / 2]),
+    ]);
+    let fetch = || run(&["--extract", "tar.gz", &server.url, path_arg(&output)]);
+
+    let result = fetch();
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("one", fs::read_to_string(output.join("a.txt")).unwrap());
+    assert_eq!("two", fs::read_to_string(output.join("sub/b.txt")).unwrap());
+
+    // asked about with what the .meta kept, and left alone
+    let result = fetch();
+    assert!(result.status.success(), "{:?}", result);
+    let requests = server.requests();
+    assert!(
+        requests[1].contains("If-None-Match: \"v1\""),
+        "{}",
+        requests[1]
+    );
+    assert!(
+        requests[1].contains("If-Modified-Since: Wed, 01 Jan 2020 00:00:00 GMT"),
+        "{}",
+        requests[1]
+    );
+    assert!(output.join("a.txt").exists());
+
+    // the old tree goes entirely
+    let result = fetch();
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!("three", fs::read_to_string(output.join("c.txt")).unwrap());
+    assert!(!output.join("a.txt").exists());
+
+    // neither an entry outside it, nor half an archive, touches what's there
+    for _ in 0..2 {
+        let result = fetch();
+        assert!(!result.status.success(), "{:?}", result);
+        assert_eq!("three", fs::read_to_string(output.join("c.txt")).unwrap());
+    }
+    assert!(!dir.path().join("evil.txt").exists());
+    // nor leaves its extraction, or the tree it replaced, behind
+    let left: Vec<_> = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.starts_with('.'))
+        .collect();
+    assert!(left.is_empty(), "{:?}", left);
+}
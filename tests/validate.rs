@@ -0,0 +1,58 @@
+use std::fs;
+
+mod common;
+use common::path_arg;
+use common::response;
+use common::run;
+use common::serve;
+
+#[test]
+fn validate_passes() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let server = serve(vec![response("200 OK", &[], b"abc")]);
+
+    let result = run(&[
+        "--validate-cmd",
+        "test \"$FETCH_MAYBE_SIZE\" = 3 && grep -q abc",
+        &server.url,
+        path_arg(&output),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(b"abc", fs::read(&output).unwrap().as_slice());
+}
+
+#[test]
+fn validate_fails() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    fs::write(&output, b"old").unwrap();
+    let server = serve(vec![response("200 OK", &[], b"abc")]);
+
+    let result = run(&[
+        "--validate-cmd",
+        "echo not today >&2; grep -q xyz {}",
+        &server.url,
+        path_arg(&output),
+    ]);
+    assert!(!result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("not today"), "{}", stderr);
+    assert_eq!(b"old", fs::read(&output).unwrap().as_slice());
+}
+
+#[test]
+fn paranoid_reads_back() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out");
+    let server = serve(vec![response(
+        "200 OK",
+        &["Last-Modified: Wed, 21 Oct 2015 07:28:00 GMT"],
+        b"abc",
+    )]);
+
+    let result = run(&["--paranoid", "-vvv", &server.url, path_arg(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("reads back as written"), "{}", stderr);
+}